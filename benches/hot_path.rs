@@ -0,0 +1,91 @@
+//! Throughput of the writers every byte of a transfer goes through, in each
+//! unit and over a range of write sizes.
+//!
+//! Run with `cargo bench --bench hot_path`; a filter argument runs only the
+//! cases whose names contain it, such as `cargo bench --bench hot_path --
+//! limited/line`. Save a baseline to compare later runs against with
+//! `--save-baseline NAME` and `--baseline NAME`.
+
+use std::{
+    hint::black_box,
+    io::{self, Write},
+    time::Duration,
+};
+
+use criterion::{
+    criterion_group,
+    criterion_main,
+    BenchmarkId,
+    Criterion,
+    Throughput,
+};
+
+use nonzero_ext::nonzero;
+
+use pvalve::{
+    syncio::{RateLimitedWriter, WriteExt as _},
+    unit::Unit,
+};
+
+/// Bytes written through each writer per iteration.
+const TOTAL: usize = 1 << 20;
+const CHUNK_SIZES: [usize; 3] = [64, 4 << 10, 64 << 10];
+const UNITS: [Unit; 3] = [Unit::Byte, Unit::Line, Unit::Null];
+
+/// Records of a few dozen bytes ending in the unit's delimiter, or text
+/// with neither delimiter in it when counting bytes.
+fn input(unit: Unit) -> Vec<u8> {
+    let record = b"the quick brown fox jumps over the lazy dog 0123456789";
+    let mut input = Vec::with_capacity(TOTAL + record.len());
+    while input.len() < TOTAL {
+        input.extend_from_slice(record);
+        input.push(unit.delimiter().unwrap_or(b' '));
+    }
+    input.truncate(TOTAL);
+    input
+}
+
+fn run(input: &[u8], chunk_size: usize, writer: &mut impl Write) {
+    for chunk in input.chunks(chunk_size) {
+        writer.write_all(black_box(chunk)).unwrap();
+    }
+    writer.flush().unwrap();
+}
+
+/// Bench `writer` in every unit and at every write size, as one group.
+fn bench<W: Write>(c: &mut Criterion, name: &str, writer: impl Fn(Unit) -> W) {
+    let mut group = c.benchmark_group(name);
+    group.throughput(Throughput::Bytes(TOTAL as u64));
+    for unit in UNITS {
+        let input = input(unit);
+        for chunk_size in CHUNK_SIZES {
+            let id = BenchmarkId::new(unit.to_string(), chunk_size);
+            let mut writer = writer(unit);
+            group.bench_with_input(id, &chunk_size, |b, &chunk_size| {
+                b.iter(|| run(&input, chunk_size, &mut writer));
+            });
+        }
+    }
+    group.finish();
+}
+
+fn progress(c: &mut Criterion) {
+    bench(c, "progress", |_| io::sink().progress());
+}
+
+fn instantaneous(c: &mut Criterion) {
+    bench(c, "instantaneous", |_| io::sink().instantaneous(Duration::from_secs(1)));
+}
+
+/// Charged for every unit but never held back, so only the cost of
+/// counting and acquiring is measured.
+fn limited(c: &mut Criterion) {
+    bench(c, "limited", |unit| RateLimitedWriter::with_rate_and_unit(
+        io::sink(),
+        nonzero!(1u64 << 40),
+        unit,
+    ));
+}
+
+criterion_group!(hot_path, progress, instantaneous, limited);
+criterion_main!(hot_path);
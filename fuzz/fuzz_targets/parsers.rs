@@ -0,0 +1,38 @@
+//! Every parser of command line and control socket text must turn arbitrary
+//! input into a value or an error, never a panic.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use pvalve::{
+    alarm::MinRate,
+    buffering::Buffering,
+    cli::Speed,
+    fanout::OutputSpec,
+    format::NumberFormat,
+    instantaneous::Smoothing,
+    ipc::Message,
+    range::ByteRange,
+    sample::SampleRate,
+    size::{self, ByteSize},
+    unit::Unit,
+};
+
+fuzz_target!(|text: &str| {
+    let _ = text.parse::<Speed>();
+    let _ = text.parse::<ByteSize>();
+    let _ = size::parse_nonzero(text);
+    let _ = text.parse::<ByteRange>();
+    let _ = text.parse::<MinRate>();
+    let _ = text.parse::<SampleRate>();
+    let _ = text.parse::<OutputSpec>();
+    let _ = text.parse::<Buffering>();
+    let _ = text.parse::<NumberFormat>();
+    let _ = text.parse::<Smoothing>();
+    let _ = text.parse::<Unit>();
+    let _ = text.parse::<Message>();
+    if let Ok(range) = text.parse::<ByteRange>() {
+        assert_eq!(range.to_string().parse::<ByteRange>().ok(), Some(range));
+    }
+});
@@ -0,0 +1,76 @@
+//! Scanning arbitrary data in every unit, split into arbitrary writes and
+//! granted arbitrary numbers of tokens, must neither panic nor lose or
+//! reorder a byte.
+
+#![no_main]
+
+use std::{
+    io::Write,
+    num::NonZeroU64,
+};
+
+use libfuzzer_sys::fuzz_target;
+
+use pvalve::{
+    config::{Config, ConfigMonitor},
+    syncio::{Grant, RateLimitedWriter, RateLimiter},
+    unit::{Scanner, Unit},
+};
+
+/// Grants tokens as the input dictates, cycling through its grant bytes.
+struct Grants<'a> {
+    grants: &'a [u8],
+    next: usize,
+}
+
+impl RateLimiter for Grants<'_> {
+    fn request(&mut self, tokens: u32) -> Grant {
+        let grant = self.grants.get(self.next % self.grants.len().max(1)).copied();
+        self.next += 1;
+        Grant::immediate(tokens.min(grant.map_or(tokens, u32::from)))
+    }
+    fn set_limit(&mut self, _: Option<NonZeroU64>) {}
+}
+
+fuzz_target!(|input: (u8, Vec<u8>, Vec<u8>, Vec<u8>)| {
+    let (unit, grants, splits, data) = input;
+    let unit = Unit::ALL[usize::from(unit) % Unit::ALL.len()];
+
+    let mut scanner = Scanner::default();
+    let mut counted = 0;
+    let mut rest = data.as_slice();
+    for &split in &splits {
+        let (chunk, tail) = rest.split_at(usize::from(split).min(rest.len()));
+        let points = scanner.annotate(unit, chunk);
+        assert!(points.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(points.iter().all(|&point| 0 < point && point <= chunk.len()));
+        let count = scanner.count(unit, chunk);
+        assert_eq!(points.len(), count);
+        counted += count;
+        rest = tail;
+    }
+    counted += scanner.count(unit, rest);
+    // Write calls are counted per call, so only they depend on the splits.
+    if unit != Unit::Op {
+        assert_eq!(counted, unit.count(&data));
+    }
+
+    let config = ConfigMonitor::fixed(Config {
+        limit: NonZeroU64::new(1).into(),
+        unit,
+        ..Default::default()
+    });
+    let limiter = Grants { grants: &grants, next: 0 };
+    let mut writer = RateLimitedWriter::writer_with_limiter(vec![], config, limiter);
+    let mut rest = data.as_slice();
+    for &split in splits.iter().cycle().take(data.len()) {
+        if rest.is_empty() {
+            break;
+        }
+        let (chunk, tail) = rest.split_at(usize::from(split).clamp(1, rest.len()));
+        writer.write_all(chunk).unwrap();
+        rest = tail;
+    }
+    writer.write_all(rest).unwrap();
+    assert_eq!(writer.get_ref(), &data);
+});
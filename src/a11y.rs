@@ -0,0 +1,288 @@
+use std::{
+    io::{self, Write},
+    time::{Duration, Instant},
+};
+
+use super::{
+    config::LatchMonitor,
+    control::Controls,
+    progress::FileProgress,
+    tui::Monitors,
+    unit::Unit,
+    widgets::TransferProgressView,
+    readahead::OccupancyMonitor,
+    window::WindowMonitor,
+};
+
+/// Progress read out as a short sentence on a line of its own every so
+/// often, for screen readers which cannot follow a line redrawn in place.
+pub struct Announcer<W = io::Stderr> {
+    out: W,
+    controls: Controls,
+    monitors: Monitors,
+    shutdown: LatchMonitor,
+    /// Set while a new rate is being typed, which nothing should interrupt.
+    editing: Option<LatchMonitor>,
+    interval: Duration,
+}
+
+impl Announcer {
+    pub fn new(controls: Controls, monitors: Monitors, shutdown: LatchMonitor) -> Self {
+        Self::with_output(io::stderr(), controls, monitors, shutdown)
+    }
+}
+
+impl <W: Write> Announcer<W> {
+    pub fn with_output(
+        out: W,
+        controls: Controls,
+        monitors: Monitors,
+        shutdown: LatchMonitor,
+    ) -> Self {
+        Self {
+            out,
+            controls,
+            monitors,
+            shutdown,
+            editing: None,
+            interval: Duration::from_secs(10),
+        }
+    }
+    /// Speak at most this often.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+    /// Hold back announcements while `editing` is active.
+    pub fn with_editing(mut self, editing: LatchMonitor) -> Self {
+        self.editing = Some(editing);
+        self
+    }
+
+    /// Announce the progress every interval until shutdown, then how much
+    /// was transferred in how long.
+    pub fn run(mut self) -> io::Result<()> {
+        while !self.shutdown.wait_timeout(self.interval) {
+            if self.editing.as_mut().is_some_and(LatchMonitor::active) {
+                continue;
+            }
+            let view = self.view();
+            let bytes = view.cumulative.progress.bytes_transferred;
+            match self.monitors.files.as_mut().and_then(|files| files.locate(bytes)) {
+                Some(file) => writeln!(self.out, "{}, {}", spoken_file(&file), sentence(&view))?,
+                None => writeln!(self.out, "{}", sentence(&view))?,
+            }
+        }
+        let cumulative = self.monitors.cumulative.get();
+        let unit = self.controls.config().shown_unit();
+        writeln!(
+            self.out,
+            "finished, {} in {}",
+            spoken_amount(unit.progress(&cumulative.progress), unit),
+            spoken_duration(cumulative.elapsed()),
+        )
+    }
+
+    fn view(&mut self) -> TransferProgressView {
+        let config = self.controls.config();
+        let Monitors {
+            cumulative,
+            instantaneous,
+            limiter,
+            forwarded,
+            gate,
+            space,
+            window,
+            buffer,
+            ..
+        } = &mut self.monitors;
+        let now = Instant::now();
+        TransferProgressView {
+            cumulative: cumulative.get(),
+            at: now,
+            expected_size: config.expected_size,
+            instantaneous: instantaneous.sample(),
+            limit: config.limit(),
+            paused: self.controls.paused(),
+            unit: config.shown_unit(),
+            limit_unit: config.unit,
+            format: config.number_format,
+            show_all_units: false,
+            below_minimum: false,
+            limiter_active: limiter.active_at(now),
+            forwarded: forwarded.as_mut().map(|forwarded| forwarded.get()),
+            mouse: false,
+            colored_gauge: false,
+            template: None,
+            gated: gate.as_mut().is_some_and(LatchMonitor::active),
+            waiting_for_space: space.as_mut().is_some_and(LatchMonitor::active),
+            window: window.as_mut().and_then(WindowMonitor::get),
+            buffered: buffer.as_mut().map(OccupancyMonitor::get),
+            rate_gauge: None,
+        }
+    }
+}
+
+/// What a screen reader should say about `view`, such as "42 percent, 3
+/// megabytes per second, 2 minutes remaining".
+pub fn sentence(view: &TransferProgressView) -> String {
+    let unit = view.unit;
+    let mut parts = vec![];
+    if view.paused {
+        parts.push("paused".to_owned());
+    }
+    match view.ratio() {
+        Some(_) if view.overrun().is_some() => parts.push("over 100 percent".to_owned()),
+        Some(ratio) => parts.push(format!("{} percent", (ratio * 100f64).floor())),
+        None => parts.push(format!(
+            "{} so far",
+            spoken_amount(unit.progress(&view.cumulative.progress), unit),
+        )),
+    }
+    parts.push(format!(
+        "{} per second",
+        spoken_amount(unit.progress(&view.instantaneous), unit),
+    ));
+    if let Some(excess) = view.overrun() {
+        parts.push(format!("{} more than expected", spoken_amount(excess, Unit::Byte)));
+    } else if let Some(eta) = view.eta() {
+        parts.push(format!("{} remaining", spoken_duration(eta)));
+    }
+    parts.join(", ")
+}
+
+/// Which of the input files is being read, such as "file 2 of 5, b.log".
+fn spoken_file(file: &FileProgress) -> String {
+    format!("file {} of {}, {}", file.index + 1, file.count, file.name)
+}
+
+/// `amount` of `unit` in words, with at most one decimal place.
+fn spoken_amount(amount: u64, unit: Unit) -> String {
+    let (base, multiples): (f64, &[&str]) = match unit {
+        Unit::Byte => (1024f64, &["", "kilo", "mega", "giga", "tera", "peta", "exa"]),
+        _ => (1000f64, &["", "thousand ", "million ", "billion ", "trillion ", "quadrillion ", "quintillion "]),
+    };
+    let noun = match unit {
+        Unit::Byte => "byte",
+        Unit::Line => "line",
+        Unit::Null => "null",
+        Unit::Word => "word",
+        Unit::Op => "write",
+    };
+    let mut scaled = amount as f64;
+    let mut power = 0;
+    while scaled >= base && power + 1 < multiples.len() {
+        scaled /= base;
+        power += 1;
+    }
+    let number = if power == 0 {
+        amount.to_string()
+    } else {
+        let number = format!("{:.1}", scaled);
+        number.strip_suffix(".0").map(str::to_owned).unwrap_or(number)
+    };
+    // A million lines, but a megabyte.
+    let plural = number != "1" || (power > 0 && unit != Unit::Byte);
+    format!(
+        "{} {}{}{}",
+        number,
+        multiples[power],
+        noun,
+        if plural { "s" } else { "" },
+    )
+}
+
+fn counted(count: u64, noun: &str) -> String {
+    format!("{} {}{}", count, noun, if count == 1 { "" } else { "s" })
+}
+
+/// `duration` in words, to the nearest second under a minute and the
+/// nearest minute beyond.
+fn spoken_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs_f64().round() as u64;
+    if seconds < 60 {
+        return counted(seconds, "second");
+    }
+    let minutes = (seconds + 30) / 60;
+    match (minutes / 60, minutes % 60) {
+        (0, minutes) => counted(minutes, "minute"),
+        (hours, 0) => counted(hours, "hour"),
+        (hours, minutes) => format!("{} {}", counted(hours, "hour"), counted(minutes, "minute")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::num::NonZeroUsize;
+    use crate::{
+        format::NumberFormat,
+        progress::{CumulativeTransferProgress, TransferProgress},
+    };
+
+    #[test]
+    fn amounts_and_durations_are_spoken_in_words() {
+        assert_eq!(spoken_amount(1, Unit::Byte), "1 byte");
+        assert_eq!(spoken_amount(3 * 1024 * 1024, Unit::Byte), "3 megabytes");
+        assert_eq!(spoken_amount(1536, Unit::Byte), "1.5 kilobytes");
+        assert_eq!(spoken_amount(1_000_000, Unit::Line), "1 million lines");
+        assert_eq!(spoken_amount(0, Unit::Op), "0 writes");
+        assert_eq!(spoken_duration(Duration::from_secs(1)), "1 second");
+        assert_eq!(spoken_duration(Duration::from_secs(110)), "2 minutes");
+        assert_eq!(spoken_duration(Duration::from_secs(3900)), "1 hour 5 minutes");
+        let file = FileProgress {
+            index: 0,
+            count: 2,
+            name: "a.log".into(),
+            transferred: 0,
+            size: None,
+        };
+        assert_eq!(spoken_file(&file), "file 1 of 2, a.log");
+    }
+
+    #[test]
+    fn sentence_reads_out_percent_rate_and_time_left() {
+        let start = Instant::now();
+        let mut cumulative = CumulativeTransferProgress::started(start);
+        cumulative.progress.bytes_transferred = 42 * 1024 * 1024;
+        let mut view = TransferProgressView {
+            cumulative,
+            at: start,
+            expected_size: NonZeroUsize::new(100 * 1024 * 1024),
+            instantaneous: TransferProgress {
+                bytes_transferred: 1024 * 1024,
+                ..Default::default()
+            },
+            limit: None,
+            paused: false,
+            unit: Unit::Byte,
+            limit_unit: Unit::Byte,
+            format: NumberFormat::Auto,
+            show_all_units: false,
+            below_minimum: false,
+            limiter_active: false,
+            forwarded: None,
+            mouse: false,
+            colored_gauge: false,
+            template: None,
+            gated: false,
+            waiting_for_space: false,
+            window: None,
+            buffered: None,
+            rate_gauge: None,
+        };
+        assert_eq!(
+            sentence(&view),
+            "42 percent, 1 megabyte per second, 58 seconds remaining",
+        );
+        view.expected_size = NonZeroUsize::new(40 * 1024 * 1024);
+        assert_eq!(
+            sentence(&view),
+            "over 100 percent, 1 megabyte per second, 2 megabytes more than expected",
+        );
+        view.expected_size = None;
+        view.paused = true;
+        assert_eq!(sentence(&view), "paused, 42 megabytes so far, 1 megabyte per second");
+    }
+
+}
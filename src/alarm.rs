@@ -0,0 +1,169 @@
+use std::{
+    fmt,
+    num::NonZeroU64,
+    str::FromStr,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use thiserror::Error;
+
+use super::{
+    progress::TransferProgress,
+    size::ByteSize,
+    unit::Unit,
+};
+
+/// Lowest acceptable rate and how long it may be undercut before alarming,
+/// written as `N[/UNIT][:SECS]`, e.g. `1M:10` or `100/l`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MinRate {
+    pub threshold: NonZeroU64,
+    /// Unit the threshold is measured in, the selected unit if unset.
+    pub unit: Option<Unit>,
+    pub period: Duration,
+    /// Ring the terminal bell when the alarm goes off.
+    pub bell: bool,
+}
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+#[error("invalid minimum rate {0:?}, expected N[/UNIT][:SECS] such as 1M:10 or 100/l")]
+pub struct ParseMinRateError(String);
+
+/// Tracks how long the rate has stayed below a [`MinRate`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateAlarm {
+    min_rate: MinRate,
+    below_since: Option<Instant>,
+    raised: bool,
+}
+
+impl MinRate {
+    pub const DEFAULT_PERIOD: Duration = Duration::from_secs(5);
+}
+
+impl FromStr for MinRate {
+    type Err = ParseMinRateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ParseMinRateError(s.to_owned());
+        let (rate, period) = match s.split_once(':') {
+            Some((rate, secs)) => {
+                let secs = secs.parse::<f64>()
+                    .ok()
+                    .filter(|secs| secs.is_finite() && *secs >= 0f64)
+                    .ok_or_else(invalid)?;
+                (rate, Duration::from_secs_f64(secs))
+            },
+            None => (s, Self::DEFAULT_PERIOD),
+        };
+        let (threshold, unit) = match rate.split_once('/') {
+            Some((threshold, unit)) => {
+                let unit = unit.parse::<Unit>().map_err(|_| invalid())?;
+                (threshold, Some(unit))
+            },
+            None => (rate, None),
+        };
+        let ByteSize(threshold) = threshold.parse().map_err(|_| invalid())?;
+        let threshold = NonZeroU64::new(threshold).ok_or_else(invalid)?;
+        Ok(Self {
+            threshold,
+            unit,
+            period,
+            bell: false,
+        })
+    }
+}
+
+impl fmt::Display for MinRate {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "{}", self.threshold)?;
+        if let Some(unit) = self.unit {
+            write!(fmt, "/{}", unit)?;
+        }
+        write!(fmt, ":{}", self.period.as_secs_f64())
+    }
+}
+
+impl RateAlarm {
+    pub fn new(min_rate: MinRate) -> Self {
+        Self {
+            min_rate,
+            below_since: None,
+            raised: false,
+        }
+    }
+    pub fn min_rate(&self) -> MinRate {
+        self.min_rate
+    }
+    /// Whether the rate has stayed too low for longer than allowed.
+    pub fn raised(&self) -> bool {
+        self.raised
+    }
+    /// Account for the rate observed at `now`, returning whether this
+    /// observation raised the alarm.
+    pub fn observe(
+        &mut self,
+        rate: &TransferProgress,
+        selected: Unit,
+        now: Instant,
+    ) -> bool {
+        let unit = self.min_rate.unit.unwrap_or(selected);
+        if unit.progress(rate) >= self.min_rate.threshold.get() {
+            self.reset();
+            return false;
+        }
+        let since = *self.below_since.get_or_insert(now);
+        let was_raised = self.raised;
+        self.raised = now.saturating_duration_since(since) >= self.min_rate.period;
+        self.raised && !was_raised
+    }
+    /// Forget about any low rate seen so far, such as while paused.
+    pub fn reset(&mut self) {
+        self.below_since = None;
+        self.raised = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nonzero_ext::nonzero;
+
+    #[test]
+    fn parses_threshold_unit_and_period() {
+        assert_eq!("1M:10".parse(), Ok(MinRate {
+            threshold: nonzero!(1_000_000u64),
+            unit: None,
+            period: Duration::from_secs(10),
+            bell: false,
+        }));
+        let rate: MinRate = "100/l".parse().unwrap();
+        assert_eq!(rate.unit, Some(Unit::Line));
+        assert_eq!(rate.period, MinRate::DEFAULT_PERIOD);
+        for bad in ["", "0", "1M:", "1M:x", "10/x", "x/l:1"] {
+            assert!(bad.parse::<MinRate>().is_err(), "{:?} parsed", bad);
+        }
+    }
+
+    #[test]
+    fn alarm_is_raised_once_the_rate_stays_low_long_enough() {
+        let mut alarm = RateAlarm::new("10:2".parse().unwrap());
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let rate = |bytes| TransferProgress {
+            bytes_transferred: bytes,
+            ..Default::default()
+        };
+        assert!(!alarm.observe(&rate(5), Unit::Byte, at(0)));
+        assert!(!alarm.observe(&rate(5), Unit::Byte, at(1)));
+        assert!(alarm.observe(&rate(5), Unit::Byte, at(2)));
+        assert!(!alarm.observe(&rate(5), Unit::Byte, at(3)));
+        assert!(alarm.raised());
+        assert!(!alarm.observe(&rate(10), Unit::Byte, at(4)));
+        assert!(!alarm.raised());
+    }
+
+}
@@ -0,0 +1,243 @@
+use std::{
+    fmt,
+    io::{
+        Result,
+        Write,
+    },
+    str::FromStr,
+    time::{
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+
+use thiserror::Error;
+
+use super::{
+    history::utc,
+    syncio::Finish,
+    unit::Unit,
+};
+
+/// How the time each record passes through is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampFormat {
+    /// UTC to the millisecond, as `2023-11-14T22:13:20.042Z`.
+    Iso,
+    /// Milliseconds since the Unix epoch.
+    EpochMs,
+}
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+#[error("unknown timestamp format {0:?}, expected iso or epoch-ms")]
+pub struct ParseTimestampFormatError(String);
+
+/// Writer which starts every record with its number, the time it was
+/// written or both, each followed by a tab, as `     1\ta` much as `cat -n`
+/// does.
+///
+/// A record's prefix goes out just ahead of its first byte, so a record
+/// split across several writes is annotated once, at the time it started,
+/// and nothing follows the last delimiter. Writes may be partial,
+/// reporting only how much of what was given went out, never the prefixes.
+/// With a unit which does not delimit records, or nothing to annotate them
+/// with, everything is passed on as it is.
+pub struct AnnotatingWriter<W> {
+    inner: W,
+    unit: Unit,
+    numbers: bool,
+    timestamps: Option<TimestampFormat>,
+    /// Number of the record last started.
+    record: u64,
+    /// The prefix of the current record is out and its bytes may follow.
+    in_record: bool,
+}
+
+impl TimestampFormat {
+    fn format(&self, at: SystemTime) -> String {
+        let since = at.duration_since(UNIX_EPOCH).unwrap_or_default();
+        match self {
+            Self::Iso => format!(
+                "{}.{:03}Z",
+                utc(since.as_secs()).replacen(' ', "T", 1),
+                since.subsec_millis(),
+            ),
+            Self::EpochMs => since.as_millis().to_string(),
+        }
+    }
+}
+
+impl FromStr for TimestampFormat {
+    type Err = ParseTimestampFormatError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "iso" => Ok(Self::Iso),
+            "epoch-ms" => Ok(Self::EpochMs),
+            _ => Err(ParseTimestampFormatError(s.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for TimestampFormat {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str(match self {
+            Self::Iso => "iso",
+            Self::EpochMs => "epoch-ms",
+        })
+    }
+}
+
+impl <W> AnnotatingWriter<W> {
+    /// Annotate the records of `unit`, with nothing until told what with.
+    pub fn new(inner: W, unit: Unit) -> Self {
+        Self {
+            inner,
+            unit,
+            numbers: false,
+            timestamps: None,
+            record: 0,
+            in_record: false,
+        }
+    }
+    /// Start each record with its number, counting from one.
+    pub fn with_numbers(mut self, enabled: bool) -> Self {
+        self.numbers = enabled;
+        self
+    }
+    /// Start each record with the time it was written, after its number.
+    pub fn with_timestamps(mut self, format: Option<TimestampFormat>) -> Self {
+        self.timestamps = format;
+        self
+    }
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+    fn delimiter(&self) -> Option<u8> {
+        self.unit.delimiter()
+            .filter(|_| self.numbers || self.timestamps.is_some())
+    }
+    fn prefix(&self) -> String {
+        let mut prefix = String::new();
+        if self.numbers {
+            prefix.push_str(&format!("{:>6}\t", self.record));
+        }
+        if let Some(format) = self.timestamps {
+            prefix.push_str(&format.format(SystemTime::now()));
+            prefix.push('\t');
+        }
+        prefix
+    }
+}
+
+impl <W: Write> Write for AnnotatingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let Some(delimiter) = self.delimiter() else {
+            return self.inner.write(buf);
+        };
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if !self.in_record {
+            self.record += 1;
+            let prefix = self.prefix();
+            self.inner.write_all(prefix.as_bytes())?;
+            self.in_record = true;
+        }
+        // Only as far as the end of this record, so that the next one is
+        // annotated before any of it goes out.
+        let end = buf.iter()
+            .position(|b| *b == delimiter)
+            .map_or(buf.len(), |at| at + 1);
+        let written = self.inner.write(&buf[..end])?;
+        if written > 0 && buf[written - 1] == delimiter {
+            self.in_record = false;
+        }
+        Ok(written)
+    }
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl <W: Finish> Finish for AnnotatingWriter<W> {
+    fn finish(&mut self) -> Result<()> {
+        self.inner.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    /// Accepts at most a few bytes per write.
+    struct Trickle(Vec<u8>, usize);
+
+    impl Write for Trickle {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            let written = buf.len().min(self.1);
+            self.0.extend_from_slice(&buf[..written]);
+            Ok(written)
+        }
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn records_split_across_writes_are_numbered_once() {
+        let mut writer = AnnotatingWriter::new(Vec::new(), Unit::Line).with_numbers(true);
+        writer.write_all(b"a1\nb").unwrap();
+        writer.write_all(b"2\n").unwrap();
+        writer.write_all(b"c3").unwrap();
+        assert_eq!(writer.get_ref(), b"     1\ta1\n     2\tb2\n     3\tc3");
+    }
+
+    #[test]
+    fn partial_writes_report_only_what_was_given() {
+        let mut writer = AnnotatingWriter::new(Trickle(vec![], 2), Unit::Null)
+            .with_numbers(true);
+        assert_eq!(writer.write(b"abc\0d").unwrap(), 2);
+        writer.write_all(b"c\0d\0").unwrap();
+        assert_eq!(writer.get_ref().0, b"     1\tabc\0     2\td\0");
+    }
+
+    #[test]
+    fn timestamps_follow_the_number() {
+        let mut writer = AnnotatingWriter::new(Vec::new(), Unit::Line)
+            .with_numbers(true)
+            .with_timestamps(Some(TimestampFormat::EpochMs));
+        writer.write_all(b"x\n").unwrap();
+        let text = String::from_utf8(writer.get_ref().clone()).unwrap();
+        let fields: Vec<_> = text.split('\t').collect();
+        assert_eq!(fields[0], "     1");
+        assert!(fields[1].parse::<u64>().is_ok(), "{}", text);
+        assert_eq!(fields[2], "x\n");
+    }
+
+    #[test]
+    fn timestamps_are_formatted_to_the_millisecond() {
+        let at = UNIX_EPOCH + Duration::from_millis(1_700_000_000_042);
+        assert_eq!(TimestampFormat::Iso.format(at), "2023-11-14T22:13:20.042Z");
+        assert_eq!(TimestampFormat::EpochMs.format(at), "1700000000042");
+        for format in [TimestampFormat::Iso, TimestampFormat::EpochMs] {
+            assert_eq!(format.to_string().parse(), Ok(format));
+        }
+        assert!("rfc2822".parse::<TimestampFormat>().is_err());
+    }
+
+    #[test]
+    fn everything_is_passed_on_without_records_to_annotate() {
+        let writers = [
+            AnnotatingWriter::new(Vec::new(), Unit::Byte).with_numbers(true),
+            AnnotatingWriter::new(Vec::new(), Unit::Line),
+        ];
+        for mut writer in writers {
+            writer.write_all(b"a\nb\n").unwrap();
+            assert_eq!(writer.get_ref(), b"a\nb\n");
+        }
+    }
+
+}
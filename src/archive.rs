@@ -0,0 +1,348 @@
+use std::{
+    fs::{self, File},
+    io::{
+        Read,
+        Result,
+    },
+    os::unix::{
+        ffi::OsStrExt as _,
+        fs::MetadataExt as _,
+    },
+    path::{Path, PathBuf},
+};
+
+use super::inputs::named;
+
+/// Size of a tar block; headers take one and file contents are padded to
+/// a whole number of them.
+const BLOCK: u64 = 512;
+
+/// What an entry of the archive holds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Kind {
+    File(u64),
+    Directory,
+    Symlink(Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    path: PathBuf,
+    /// Name within the archive, relative to the directory's parent.
+    name: Vec<u8>,
+    kind: Kind,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    mtime: u64,
+}
+
+impl Entry {
+    fn scan(path: PathBuf, name: Vec<u8>) -> Result<Option<Self>> {
+        let metadata = fs::symlink_metadata(&path).map_err(|e| named(&path, e))?;
+        let file_type = metadata.file_type();
+        let (kind, name) = if file_type.is_dir() {
+            let mut name = name;
+            name.push(b'/');
+            (Kind::Directory, name)
+        } else if file_type.is_file() {
+            (Kind::File(metadata.len()), name)
+        } else if file_type.is_symlink() {
+            let target = fs::read_link(&path).map_err(|e| named(&path, e))?;
+            (Kind::Symlink(target.as_os_str().as_bytes().to_vec()), name)
+        } else {
+            // Sockets, fifos and devices are left out, as tar leaves out
+            // sockets; reading a fifo could block forever.
+            return Ok(None);
+        };
+        Ok(Some(Self {
+            path,
+            name,
+            kind,
+            mode: metadata.mode() & 0o7777,
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            mtime: metadata.mtime().max(0) as u64,
+        }))
+    }
+
+    fn size(&self) -> u64 {
+        match self.kind {
+            Kind::File(size) => size,
+            _ => 0,
+        }
+    }
+
+    /// Bytes the entry takes up in the archive, headers and padding
+    /// included.
+    fn archived_size(&self) -> u64 {
+        let long = |text: &[u8], limit| match text.len() {
+            len if len < limit => 0,
+            len => BLOCK + padded(len as u64 + 1),
+        };
+        let link = match &self.kind {
+            Kind::Symlink(target) => long(target, 100),
+            _ => 0,
+        };
+        long(&self.name, 100) + link + BLOCK + padded(self.size())
+    }
+
+    /// The entry's header, preceded by GNU long name and long link headers
+    /// for a name or link target which will not fit in its field.
+    fn headers(&self) -> Vec<u8> {
+        let mut headers = vec![];
+        let target = match &self.kind {
+            Kind::Symlink(target) => target.as_slice(),
+            _ => &[],
+        };
+        for (text, kind) in [(self.name.as_slice(), b'L'), (target, b'K')] {
+            if text.len() >= 100 {
+                let mut data = text.to_vec();
+                data.push(0);
+                let mut long = header(b"././@LongLink", b"", kind, data.len() as u64);
+                long.resize(BLOCK as usize, 0);
+                data.resize(padded(data.len() as u64) as usize, 0);
+                headers.extend(checksummed(long));
+                headers.extend(data);
+            }
+        }
+        let kind = match self.kind {
+            Kind::File(_) => b'0',
+            Kind::Directory => b'5',
+            Kind::Symlink(_) => b'2',
+        };
+        let mut block = header(&self.name, target, kind, self.size());
+        numeric(&mut block[100..108], self.mode.into());
+        numeric(&mut block[108..116], self.uid.into());
+        numeric(&mut block[116..124], self.gid.into());
+        numeric(&mut block[136..148], self.mtime);
+        headers.extend(checksummed(block));
+        headers
+    }
+}
+
+/// A header block without its checksum, with as much of `name` and `link`
+/// as fits.
+fn header(name: &[u8], link: &[u8], kind: u8, size: u64) -> Vec<u8> {
+    let mut block = vec![0u8; BLOCK as usize];
+    let fit = |field: &mut [u8], text: &[u8]| {
+        let len = text.len().min(field.len());
+        field[..len].copy_from_slice(&text[..len]);
+    };
+    fit(&mut block[0..100], name);
+    numeric(&mut block[100..108], 0o644);
+    numeric(&mut block[108..116], 0);
+    numeric(&mut block[116..124], 0);
+    numeric(&mut block[124..136], size);
+    numeric(&mut block[136..148], 0);
+    block[156] = kind;
+    fit(&mut block[157..257], link);
+    block[257..265].copy_from_slice(b"ustar  \0");
+    block
+}
+
+fn checksummed(mut block: Vec<u8>) -> Vec<u8> {
+    block[148..156].fill(b' ');
+    let sum = block.iter().map(|&b| u64::from(b)).sum::<u64>();
+    let digits = format!("{:06o}\0 ", sum);
+    block[148..156].copy_from_slice(digits.as_bytes());
+    block
+}
+
+/// Write `value` into `field` in octal, or in GNU base-256 when too large.
+fn numeric(field: &mut [u8], value: u64) {
+    let digits = format!("{:0width$o}", value, width = field.len() - 1);
+    if digits.len() < field.len() {
+        field[..digits.len()].copy_from_slice(digits.as_bytes());
+        field[digits.len()] = 0;
+        return;
+    }
+    field.fill(0);
+    let bytes = value.to_be_bytes();
+    let len = field.len();
+    field[len - bytes.len()..].copy_from_slice(&bytes);
+    field[0] = 0x80;
+}
+
+fn padded(len: u64) -> u64 {
+    len.div_ceil(BLOCK) * BLOCK
+}
+
+/// Every entry below `path`, depth first in name order, with names
+/// starting with `name`.
+fn walk(path: PathBuf, name: Vec<u8>, entries: &mut Vec<Entry>) -> Result<()> {
+    let Some(entry) = Entry::scan(path, name)? else {
+        return Ok(());
+    };
+    let is_dir = entry.kind == Kind::Directory;
+    let (path, name) = (entry.path.clone(), entry.name.clone());
+    entries.push(entry);
+    if !is_dir {
+        return Ok(());
+    }
+    let mut children = fs::read_dir(&path)
+        .and_then(|children| children.collect::<Result<Vec<_>>>())
+        .map_err(|e| named(&path, e))?;
+    children.sort_by_key(|child| child.file_name());
+    for child in children {
+        let mut child_name = name.clone();
+        child_name.extend(child.file_name().as_bytes());
+        walk(child.path(), child_name, entries)?;
+    }
+    Ok(())
+}
+
+/// A file being archived, how much of it is still to come and the padding
+/// which follows it.
+struct Contents {
+    file: File,
+    path: PathBuf,
+    remaining: u64,
+    padding: usize,
+}
+
+/// Reader which streams a tar archive of a directory, in the GNU format
+/// `tar` and `bsdtar` both extract.
+///
+/// The whole tree is scanned up front so that the size of the archive is
+/// known before any of it is read. A file which grows after the scan is cut
+/// short and one which shrinks is padded with zeroes, so the archive is
+/// always exactly that size.
+pub struct TarStream {
+    entries: std::vec::IntoIter<Entry>,
+    size: u64,
+    /// Headers or padding waiting to be read.
+    pending: Vec<u8>,
+    consumed: usize,
+    current: Option<Contents>,
+    finished: bool,
+}
+
+impl TarStream {
+    pub fn open(dir: &Path) -> Result<Self> {
+        let root = fs::canonicalize(dir).map_err(|e| named(dir, e))?;
+        let name = root.file_name()
+            .map_or(b".".to_vec(), |name| name.as_bytes().to_vec());
+        let mut entries = vec![];
+        walk(root, name, &mut entries)?;
+        let size = entries.iter().map(Entry::archived_size).sum::<u64>() + 2 * BLOCK;
+        Ok(Self {
+            entries: entries.into_iter(),
+            size,
+            pending: vec![],
+            consumed: 0,
+            current: None,
+            finished: false,
+        })
+    }
+    /// Size of the whole archive.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+    fn queue(&mut self, bytes: Vec<u8>) {
+        self.pending = bytes;
+        self.consumed = 0;
+    }
+}
+
+impl Read for TarStream {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        loop {
+            if self.consumed < self.pending.len() {
+                let pending = &self.pending[self.consumed..];
+                let n = buf.len().min(pending.len());
+                buf[..n].copy_from_slice(&pending[..n]);
+                self.consumed += n;
+                return Ok(n);
+            }
+            if let Some(contents) = &mut self.current {
+                if contents.remaining == 0 {
+                    let padding = contents.padding;
+                    self.current = None;
+                    self.queue(vec![0; padding]);
+                    continue;
+                }
+                let limit = usize::try_from(contents.remaining)
+                    .map_or(buf.len(), |remaining| remaining.min(buf.len()));
+                let n = match contents.file.read(&mut buf[..limit]) {
+                    Ok(0) => {
+                        buf[..limit].fill(0);
+                        limit
+                    },
+                    Ok(n) => n,
+                    Err(e) => return Err(named(&contents.path, e)),
+                };
+                contents.remaining -= n as u64;
+                return Ok(n);
+            }
+            let Some(entry) = self.entries.next() else {
+                if self.finished {
+                    return Ok(0);
+                }
+                self.finished = true;
+                self.queue(vec![0; 2 * BLOCK as usize]);
+                continue;
+            };
+            if let Kind::File(size) = entry.kind {
+                let file = File::open(&entry.path).map_err(|e| named(&entry.path, e))?;
+                self.current = Some(Contents {
+                    file,
+                    path: entry.path.clone(),
+                    remaining: size,
+                    padding: (padded(size) - size) as usize,
+                });
+            }
+            self.queue(entry.headers());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn numbers_too_large_for_octal_switch_to_base_256() {
+        let mut field = [0u8; 12];
+        numeric(&mut field, 8 << 30);
+        assert_eq!(field[0], 0x80);
+        assert_eq!(&field[4..], &(8u64 << 30).to_be_bytes());
+        numeric(&mut field, 0o755);
+        assert_eq!(&field, b"00000000755\0");
+    }
+
+    #[test]
+    fn archive_is_exactly_the_size_scanned_and_lists_every_entry() {
+        let dir = std::env::temp_dir().join(format!("pvalve-tar-{}", std::process::id()));
+        let root = dir.join("tree");
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join("a.txt"), b"hello\n").unwrap();
+        fs::write(root.join("sub").join("empty"), b"").unwrap();
+        let long = "x".repeat(120);
+        fs::write(root.join(&long), vec![7u8; 1000]).unwrap();
+        symlink("a.txt", root.join("link")).unwrap();
+        let mut stream = TarStream::open(&root).unwrap();
+        let size = stream.size();
+        let mut archive = vec![];
+        stream.read_to_end(&mut archive).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(archive.len() as u64, size);
+        assert_eq!(size % BLOCK, 0);
+        assert!(archive.ends_with(&[0; 1024]));
+        let names = archive.chunks(BLOCK as usize)
+            .filter(|block| block[257..263] == *b"ustar ")
+            .map(|block| (block[156], String::from_utf8_lossy(&block[..100]).trim_end_matches('\0').to_owned()))
+            .collect::<Vec<_>>();
+        assert_eq!(names, [
+            (b'5', "tree/".to_owned()),
+            (b'0', "tree/a.txt".to_owned()),
+            (b'2', "tree/link".to_owned()),
+            (b'5', "tree/sub/".to_owned()),
+            (b'0', "tree/sub/empty".to_owned()),
+            (b'L', "././@LongLink".to_owned()),
+            (b'0', format!("tree/{}", long)[..100].to_owned()),
+        ]);
+    }
+
+}
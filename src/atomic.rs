@@ -0,0 +1,146 @@
+use std::io::{
+    Result,
+    Write,
+};
+
+use super::config::ConfigMonitor;
+use super::syncio::Finish;
+
+/// Writer which only ever passes whole records of the selected unit to the
+/// wrapped writer, holding back the unterminated tail of each write until
+/// the rest of its record arrives.
+///
+/// This only applies while [`Config::record_atomic`] is set and the unit
+/// delimits records; otherwise everything is passed straight through. A
+/// final record lacking its delimiter is only written once the writer is
+/// finished with [`Finish::finish`].
+///
+/// [`Config::record_atomic`]: crate::config::Config::record_atomic
+pub struct RecordAtomicWriter<W> {
+    inner: W,
+    config: ConfigMonitor,
+    pending: Vec<u8>,
+}
+
+impl <W> RecordAtomicWriter<W> {
+    pub fn new(inner: W, config: ConfigMonitor) -> Self {
+        Self {
+            inner,
+            config,
+            pending: vec![],
+        }
+    }
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+    /// Bytes of the current record which are being held back.
+    pub fn pending(&self) -> &[u8] {
+        &self.pending
+    }
+    fn delimiter(&mut self) -> Option<u8> {
+        self.config.refresh();
+        let config = self.config.config();
+        config.record_atomic
+            .then(|| config.unit.delimiter())
+            .flatten()
+    }
+}
+
+impl <W: Write> RecordAtomicWriter<W> {
+    fn write_pending(&mut self) -> Result<()> {
+        if !self.pending.is_empty() {
+            self.inner.write_all(&self.pending)?;
+            self.pending.clear();
+        }
+        Ok(())
+    }
+}
+
+impl <W: Write> Write for RecordAtomicWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let Some(delimiter) = self.delimiter() else {
+            self.write_pending()?;
+            return self.inner.write(buf);
+        };
+        let Some(last) = buf.iter().rposition(|b| *b == delimiter) else {
+            self.pending.extend_from_slice(buf);
+            return Ok(buf.len());
+        };
+        let (complete, tail) = buf.split_at(last + 1);
+        if self.pending.is_empty() {
+            self.inner.write_all(complete)?;
+        } else {
+            self.pending.extend_from_slice(complete);
+            self.write_pending()?;
+        }
+        self.pending.extend_from_slice(tail);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl <W: Finish> Finish for RecordAtomicWriter<W> {
+    /// Write out whatever is held back, once no more input will follow.
+    fn finish(&mut self) -> Result<()> {
+        self.write_pending()?;
+        self.inner.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Config,
+        unit::Unit,
+    };
+
+    /// Records every write it receives separately.
+    #[derive(Default)]
+    struct Writes(Vec<Vec<u8>>);
+
+    impl Write for Writes {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            self.0.push(buf.to_vec());
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Finish for Writes {}
+
+    fn writer(unit: Unit, record_atomic: bool) -> RecordAtomicWriter<Writes> {
+        let (_, config) = ConfigMonitor::new(Config {
+            unit,
+            record_atomic,
+            ..Default::default()
+        });
+        RecordAtomicWriter::new(Writes::default(), config)
+    }
+
+    #[test]
+    fn every_write_ends_on_a_record_boundary() {
+        let mut writer = writer(Unit::Line, true);
+        writer.write_all(b"a\nb").unwrap();
+        writer.write_all(b"c").unwrap();
+        writer.write_all(b"d\ne\nf").unwrap();
+        assert_eq!(writer.pending(), b"f");
+        writer.finish().unwrap();
+        let writes = &writer.get_ref().0;
+        assert_eq!(writes, &[b"a\n".to_vec(), b"bcd\ne\n".to_vec(), b"f".to_vec()]);
+    }
+
+    #[test]
+    fn writes_pass_through_unless_enabled_for_a_record_unit() {
+        for (unit, record_atomic) in [(Unit::Line, false), (Unit::Byte, true)] {
+            let mut writer = writer(unit, record_atomic);
+            writer.write_all(b"a\nb").unwrap();
+            assert_eq!(writer.get_ref().0, vec![b"a\nb".to_vec()]);
+        }
+    }
+
+}
@@ -0,0 +1,331 @@
+use std::{
+    fmt,
+    io::{
+        BufRead,
+        Result as IoResult,
+        Write,
+    },
+    num::NonZeroU64,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use thiserror::Error;
+
+use super::{
+    progress::TransferProgress,
+    syncio::Finish,
+    unit::{
+        Scanner,
+        Unit,
+    },
+};
+
+/// Writer which logs the time and size of every write passing through it.
+///
+/// Each line of the log holds the seconds elapsed since the writer was
+/// created followed by the bytes, lines, nulls and words written.
+pub struct AuditedWriter<W, L> {
+    inner: W,
+    log: Option<PacingLog<L>>,
+}
+
+struct PacingLog<L> {
+    log: L,
+    start: Instant,
+    scanner: Scanner,
+}
+
+/// One write recorded in a pacing log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PacingRecord {
+    pub at: Duration,
+    pub progress: TransferProgress,
+}
+
+#[derive(Debug, Error)]
+pub enum AuditError {
+    #[error("failed to read pacing log: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("line {line}: malformed pacing record {text:?}")]
+    Malformed {
+        line: usize,
+        text: String,
+    },
+}
+
+/// Achieved pacing of a transfer, measured in a single unit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditReport {
+    pub unit: Unit,
+    pub limit: Option<NonZeroU64>,
+    /// Amount written during each whole second of the transfer.
+    pub per_second: Vec<u64>,
+    pub largest_write: u64,
+    /// Writes which a token bucket holding one second's worth of the limit
+    /// would not have allowed.
+    pub violations: usize,
+    /// How far beyond the bucket's capacity the worst violation went.
+    pub worst_overdraft: f64,
+}
+
+impl <W, L> AuditedWriter<W, L> {
+    pub fn new(inner: W, log: Option<L>) -> Self {
+        Self {
+            inner,
+            log: log.map(|log| PacingLog {
+                log,
+                start: Instant::now(),
+                scanner: Scanner::default(),
+            }),
+        }
+    }
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+}
+
+impl <W: Write, L: Write> Write for AuditedWriter<W, L> {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        let bytes_transferred = self.inner.write(buf)?;
+        if let Some(log) = &mut self.log {
+            log.record(&buf[..bytes_transferred])?;
+        }
+        Ok(bytes_transferred)
+    }
+    fn flush(&mut self) -> IoResult<()> {
+        if let Some(PacingLog { log, .. }) = &mut self.log {
+            log.flush()?;
+        }
+        self.inner.flush()
+    }
+}
+
+impl <W: Finish, L: Write> Finish for AuditedWriter<W, L> {
+    fn finish(&mut self) -> IoResult<()> {
+        if let Some(PacingLog { log, .. }) = &mut self.log {
+            log.flush()?;
+        }
+        self.inner.finish()
+    }
+}
+
+impl <L: Write> PacingLog<L> {
+    fn record(&mut self, buf: &[u8]) -> IoResult<()> {
+        let at = self.start.elapsed();
+        let progress = TransferProgress::scan(&mut self.scanner, buf);
+        writeln!(
+            self.log,
+            "{:.9} {} {} {} {}",
+            at.as_secs_f64(),
+            progress.bytes_transferred,
+            progress.lines_transferred,
+            progress.nulls_transferred,
+            progress.words_transferred,
+        )
+    }
+}
+
+impl PacingRecord {
+    fn parse(line: usize, text: &str) -> Result<Self, AuditError> {
+        let malformed = || AuditError::Malformed {
+            line,
+            text: text.to_owned(),
+        };
+        let fields: Vec<&str> = text.split_whitespace().collect();
+        let [at, bytes, lines, nulls, words] = fields[..] else {
+            return Err(malformed());
+        };
+        let count = |s: &str| s.parse::<u64>().map_err(|_| malformed());
+        let at = at.parse::<f64>()
+            .ok()
+            .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+            .ok_or_else(malformed)?;
+        Ok(Self {
+            at,
+            progress: TransferProgress {
+                bytes_transferred: count(bytes)?,
+                lines_transferred: count(lines)?,
+                nulls_transferred: count(nulls)?,
+                words_transferred: count(words)?,
+                // Every record is a single write.
+                writes_transferred: 1,
+            },
+        })
+    }
+}
+
+/// Read every record of a pacing log, skipping blank lines.
+pub fn read_log(reader: impl BufRead) -> Result<Vec<PacingRecord>, AuditError> {
+    let mut records = vec![];
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        records.push(PacingRecord::parse(i + 1, &line)?);
+    }
+    Ok(records)
+}
+
+impl AuditReport {
+    pub fn analyze(
+        records: &[PacingRecord],
+        unit: Unit,
+        limit: Option<NonZeroU64>,
+    ) -> Self {
+        let mut per_second = vec![];
+        let mut largest_write = 0;
+        for record in records {
+            let amount = unit.progress(&record.progress);
+            let second = record.at.as_secs() as usize;
+            if per_second.len() <= second {
+                per_second.resize(second + 1, 0);
+            }
+            per_second[second] += amount;
+            largest_write = largest_write.max(amount);
+        }
+        let (violations, worst_overdraft) = limit
+            .map(|limit| Self::violations(records, unit, limit))
+            .unwrap_or_default();
+        Self {
+            unit,
+            limit,
+            per_second,
+            largest_write,
+            violations,
+            worst_overdraft,
+        }
+    }
+
+    /// Replay the writes against a full token bucket which refills at
+    /// `limit` units per second and holds at most one second's worth.
+    fn violations(
+        records: &[PacingRecord],
+        unit: Unit,
+        limit: NonZeroU64,
+    ) -> (usize, f64) {
+        /// Slack for rounding in the log's timestamps.
+        const TOLERANCE: f64 = 1f64;
+        let capacity = limit.get() as f64;
+        let mut tokens = capacity;
+        let mut last = Duration::ZERO;
+        let mut violations = 0;
+        let mut worst = 0f64;
+        for record in records {
+            let elapsed = record.at.saturating_sub(last).as_secs_f64();
+            last = last.max(record.at);
+            tokens = capacity.min(tokens + elapsed * capacity);
+            tokens -= unit.progress(&record.progress) as f64;
+            if tokens < -TOLERANCE {
+                violations += 1;
+                worst = worst.max(-tokens);
+                tokens = 0f64;
+            }
+        }
+        (violations, worst)
+    }
+
+    pub fn mean_rate(&self) -> f64 {
+        if self.per_second.is_empty() {
+            return 0f64;
+        }
+        let total: u64 = self.per_second.iter().sum();
+        total as f64 / self.per_second.len() as f64
+    }
+
+    pub fn peak_rate(&self) -> u64 {
+        self.per_second.iter().copied().max().unwrap_or_default()
+    }
+
+    /// Ratio of the busiest second to the average one.
+    pub fn burstiness(&self) -> f64 {
+        let mean = self.mean_rate();
+        if mean <= 0f64 {
+            0f64
+        } else {
+            self.peak_rate() as f64 / mean
+        }
+    }
+}
+
+impl fmt::Display for AuditReport {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unit = self.unit.abbreviation();
+        writeln!(fmt, "seconds: {}", self.per_second.len())?;
+        writeln!(fmt, "mean rate: {:.1}{}/s", self.mean_rate(), unit)?;
+        writeln!(fmt, "peak rate: {}{}/s", self.peak_rate(), unit)?;
+        writeln!(fmt, "largest write: {}{}", self.largest_write, unit)?;
+        writeln!(fmt, "burstiness: {:.2}", self.burstiness())?;
+        match self.limit {
+            Some(limit) => writeln!(
+                fmt,
+                "limit violations: {} (limit {}{}/s, worst overdraft {:.0}{})",
+                self.violations,
+                limit,
+                unit,
+                self.worst_overdraft,
+                unit,
+            )?,
+            None => writeln!(fmt, "limit violations: n/a (no limit given)")?,
+        }
+        writeln!(fmt, "per second:")?;
+        for (second, amount) in self.per_second.iter().enumerate() {
+            writeln!(fmt, "  {:>6} {}", second, amount)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nonzero_ext::nonzero;
+
+    #[test]
+    fn written_log_reads_back() {
+        let mut writer = AuditedWriter::new(Vec::new(), Some(Vec::new()));
+        writer.write_all(b"one\ntwo\n").unwrap();
+        writer.write_all(b"three").unwrap();
+        let log = writer.log.unwrap().log;
+        let records = read_log(&log[..]).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].progress, TransferProgress::of(b"one\ntwo\n"));
+        assert_eq!(records[1].progress.bytes_transferred, 5);
+        assert_eq!(writer.inner, b"one\ntwo\nthree");
+    }
+
+    #[test]
+    fn malformed_lines_are_reported_with_their_number() {
+        let log = b"0.1 1 0 0 0\n\n0.2 one 0 0 0\n";
+        match read_log(&log[..]) {
+            Err(AuditError::Malformed { line, .. }) => assert_eq!(line, 3),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn writes_beyond_the_bucket_are_violations() {
+        let record = |secs: f64, bytes| PacingRecord {
+            at: Duration::from_secs_f64(secs),
+            progress: TransferProgress {
+                bytes_transferred: bytes,
+                ..Default::default()
+            },
+        };
+        let paced = [record(0.0, 100), record(1.0, 100), record(2.0, 100)];
+        let report = AuditReport::analyze(&paced, Unit::Byte, Some(nonzero!(100u64)));
+        assert_eq!(report.per_second, vec![100, 100, 100]);
+        assert_eq!(report.violations, 0);
+        assert_eq!(report.burstiness(), 1f64);
+
+        let bursty = [record(0.0, 100), record(0.5, 100), record(2.0, 10)];
+        let report = AuditReport::analyze(&bursty, Unit::Byte, Some(nonzero!(100u64)));
+        assert_eq!(report.violations, 1);
+        assert_eq!(report.worst_overdraft, 50f64);
+        assert_eq!(report.largest_write, 100);
+    }
+
+}
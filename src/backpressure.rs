@@ -0,0 +1,259 @@
+use std::{
+    io::{
+        Read,
+        Result,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use serde::Serialize;
+
+use super::state::{
+    channel,
+    StateWatcher,
+    StateCell,
+};
+
+/// Time spent waiting on each part of the pipeline.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Backpressure {
+    /// Time spent waiting for the source to produce data.
+    pub reading: Duration,
+    /// Time spent waiting for the rate limiter to grant tokens.
+    pub limiting: Duration,
+    /// Time spent waiting for the sink to accept data.
+    pub writing: Duration,
+    /// Writes the rate limiter held back because it had no tokens to spare.
+    pub limiter_waits: u64,
+    /// Time spent paused with a write waiting to go out.
+    pub pausing: Duration,
+    /// Writes held back by a pause.
+    pub pauses: u64,
+    /// Writes the sink took longer than [`Backpressure::BLOCKED_WRITE`] to
+    /// accept, or stalls while it accepted nothing at all.
+    pub write_waits: u64,
+}
+
+/// How often and for how long the transfer waited on one cause.
+#[derive(Debug, Default, Clone, Copy, PartialEq, PartialOrd, Serialize)]
+pub struct Waits {
+    pub count: u64,
+    pub seconds: f64,
+}
+
+/// Waits on the limiter, on pauses and on the output, told apart for
+/// planning how long a scheduled transfer will take.
+#[derive(Debug, Default, Clone, Copy, PartialEq, PartialOrd, Serialize)]
+pub struct WaitBreakdown {
+    pub limiting: Waits,
+    pub pausing: Waits,
+    pub writing: Waits,
+}
+
+/// Share of the total waiting time attributed to each part of the pipeline.
+#[derive(Debug, Default, Clone, Copy, PartialEq, PartialOrd, Serialize)]
+pub struct BackpressureBreakdown {
+    pub reading: f64,
+    pub limiting: f64,
+    pub writing: f64,
+}
+
+impl Backpressure {
+    /// A write the sink takes longer than this to accept counts as a wait
+    /// for it, rather than as the time any write takes.
+    pub const BLOCKED_WRITE: Duration = Duration::from_millis(1);
+
+    pub fn total(&self) -> Duration {
+        self.reading + self.limiting + self.writing
+    }
+    pub fn breakdown(&self) -> BackpressureBreakdown {
+        let total = self.total().as_secs_f64();
+        if total <= 0f64 {
+            return BackpressureBreakdown::default();
+        }
+        let percent = |d: Duration| 100f64 * d.as_secs_f64() / total;
+        BackpressureBreakdown {
+            reading: percent(self.reading),
+            limiting: percent(self.limiting),
+            writing: percent(self.writing),
+        }
+    }
+    pub fn waits(&self) -> WaitBreakdown {
+        let waits = |count, duration: Duration| Waits {
+            count,
+            seconds: duration.as_secs_f64(),
+        };
+        WaitBreakdown {
+            limiting: waits(self.limiter_waits, self.limiting),
+            pausing: waits(self.pauses, self.pausing),
+            writing: waits(self.write_waits, self.writing),
+        }
+    }
+}
+
+impl std::ops::Add for Backpressure {
+    type Output = Self;
+    fn add(self, other: Self) -> Self {
+        Self {
+            reading: self.reading + other.reading,
+            limiting: self.limiting + other.limiting,
+            writing: self.writing + other.writing,
+            limiter_waits: self.limiter_waits + other.limiter_waits,
+            pausing: self.pausing + other.pausing,
+            pauses: self.pauses + other.pauses,
+            write_waits: self.write_waits + other.write_waits,
+        }
+    }
+}
+
+/// Run `f`, returning its result along with how long it took.
+pub fn timed<T>(f: impl FnOnce() -> T) -> (T, Duration) {
+    let start = Instant::now();
+    let result = f();
+    (result, start.elapsed())
+}
+
+/// Combines the backpressure reported by the reading and writing sides.
+#[derive(Clone)]
+pub struct BackpressureMonitor(Vec<StateWatcher<Backpressure>>);
+
+impl BackpressureMonitor {
+    pub fn new(rx: StateWatcher<Backpressure>) -> Self {
+        Self(vec![rx])
+    }
+    pub fn join(mut self, other: Self) -> Self {
+        self.0.extend(other.0);
+        self
+    }
+    pub fn get(&mut self) -> Backpressure {
+        self.0
+            .iter_mut()
+            .map(StateWatcher::get)
+            .fold(Backpressure::default(), |a, b| a + b)
+    }
+}
+
+/// Whether the rate limiter is what holds a transfer back, going by the
+/// waits it reports rather than by how close the rate is to the limit.
+#[derive(Clone)]
+pub struct LimiterActivity {
+    backpressure: BackpressureMonitor,
+    window: Duration,
+    waits: u64,
+    last_wait: Option<Instant>,
+}
+
+impl LimiterActivity {
+    pub fn new(backpressure: BackpressureMonitor, window: Duration) -> Self {
+        Self {
+            backpressure,
+            window,
+            waits: 0,
+            last_wait: None,
+        }
+    }
+    /// Whether the limiter blocked a write within the window before `now`.
+    pub fn active_at(&mut self, now: Instant) -> bool {
+        let waits = self.backpressure.get().limiter_waits;
+        self.observe(waits, now)
+    }
+    fn observe(&mut self, waits: u64, now: Instant) -> bool {
+        if waits > self.waits {
+            self.waits = waits;
+            self.last_wait = Some(now);
+        }
+        self.last_wait
+            .is_some_and(|at| now.saturating_duration_since(at) < self.window)
+    }
+}
+
+/// Reader which records how long it spends waiting for its source.
+pub struct TimedReader<R> {
+    inner: R,
+    backpressure: Backpressure,
+    tx: StateCell<Backpressure>,
+}
+
+impl <R> TimedReader<R> {
+    pub fn new(inner: R) -> Self {
+        let backpressure = Backpressure::default();
+        let (tx, _) = channel(backpressure);
+        Self {
+            inner,
+            backpressure,
+            tx,
+        }
+    }
+    pub fn backpressure(&mut self) -> BackpressureMonitor {
+        BackpressureMonitor::new(self.tx.subscribe())
+    }
+}
+
+impl <R: Read> Read for TimedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let (result, elapsed) = timed(|| self.inner.read(buf));
+        self.backpressure.reading += elapsed;
+        self.tx.send(self.backpressure);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn breakdown_is_zero_when_nothing_waited() {
+        let breakdown = Backpressure::default().breakdown();
+        assert_eq!(breakdown, BackpressureBreakdown::default());
+    }
+
+    #[test]
+    fn breakdown_splits_total_wait_into_percentages() {
+        let backpressure = Backpressure {
+            reading: Duration::from_millis(250),
+            limiting: Duration::from_millis(500),
+            writing: Duration::from_millis(250),
+            ..Default::default()
+        };
+        let breakdown = backpressure.breakdown();
+        assert_eq!(breakdown.reading, 25f64);
+        assert_eq!(breakdown.limiting, 50f64);
+        assert_eq!(breakdown.writing, 25f64);
+    }
+
+    #[test]
+    fn waits_are_counted_and_timed_by_cause() {
+        let backpressure = Backpressure {
+            limiting: Duration::from_millis(1500),
+            limiter_waits: 3,
+            pausing: Duration::from_secs(10),
+            pauses: 1,
+            ..Default::default()
+        };
+        let waits = (backpressure + backpressure).waits();
+        assert_eq!(waits.limiting, Waits { count: 6, seconds: 3f64 });
+        assert_eq!(waits.pausing, Waits { count: 2, seconds: 20f64 });
+        assert_eq!(waits.writing, Waits::default());
+    }
+
+    #[test]
+    fn limiter_stays_active_for_a_window_after_it_blocks() {
+        let (_, rx) = channel(Backpressure::default());
+        let mut activity = LimiterActivity::new(
+            BackpressureMonitor::new(rx),
+            Duration::from_secs(1),
+        );
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+        assert!(!activity.observe(0, at(0)));
+        assert!(activity.observe(3, at(100)));
+        assert!(activity.observe(3, at(900)));
+        assert!(!activity.observe(3, at(1100)));
+        assert!(activity.observe(4, at(1200)));
+    }
+
+}
@@ -0,0 +1,121 @@
+use std::{
+    fmt,
+    io::{
+        BufWriter,
+        LineWriter,
+        Result,
+        Write,
+    },
+    num::NonZeroUsize,
+    str::FromStr,
+};
+
+use nonzero_ext::nonzero;
+use thiserror::Error;
+
+use super::syncio::Finish;
+
+/// How output is buffered before reaching the underlying writer.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Buffering {
+    /// Every write goes straight through and is flushed immediately.
+    None,
+    /// Output is flushed whenever a line feed is written.
+    #[default]
+    Line,
+    /// Output is flushed whenever the buffer of the given size fills.
+    Block(NonZeroUsize),
+}
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+#[error("invalid buffering mode {0:?}, expected none, line, or block[:SIZE]")]
+pub struct ParseBufferingError(String);
+
+impl Buffering {
+    pub const DEFAULT_BLOCK_SIZE: NonZeroUsize = nonzero!(8192usize);
+}
+
+impl FromStr for Buffering {
+    type Err = ParseBufferingError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let error = || ParseBufferingError(s.to_owned());
+        match s.split_once(':') {
+            None => match s {
+                "none" => Ok(Self::None),
+                "line" => Ok(Self::Line),
+                "block" => Ok(Self::Block(Self::DEFAULT_BLOCK_SIZE)),
+                _ => Err(error()),
+            },
+            Some(("block", size)) => size.parse()
+                .map(Self::Block)
+                .map_err(|_| error()),
+            Some(_) => Err(error()),
+        }
+    }
+}
+
+impl fmt::Display for Buffering {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::None => write!(fmt, "none"),
+            Self::Line => write!(fmt, "line"),
+            Self::Block(size) => write!(fmt, "block:{}", size),
+        }
+    }
+}
+
+pub enum BufferedWriter<W: Write> {
+    Unbuffered(W),
+    Line(LineWriter<W>),
+    Block(BufWriter<W>),
+}
+
+impl <W: Write> BufferedWriter<W> {
+    pub fn new(inner: W, buffering: Buffering) -> Self {
+        match buffering {
+            Buffering::None => Self::Unbuffered(inner),
+            Buffering::Line => Self::Line(LineWriter::new(inner)),
+            Buffering::Block(size) => Self::Block(
+                BufWriter::with_capacity(size.get(), inner)
+            ),
+        }
+    }
+}
+
+impl <W: Write> Write for BufferedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        match self {
+            Self::Unbuffered(inner) => {
+                let n = inner.write(buf)?;
+                inner.flush()?;
+                Ok(n)
+            },
+            Self::Line(inner) => inner.write(buf),
+            Self::Block(inner) => inner.write(buf),
+        }
+    }
+    fn flush(&mut self) -> Result<()> {
+        match self {
+            Self::Unbuffered(inner) => inner.flush(),
+            Self::Line(inner) => inner.flush(),
+            Self::Block(inner) => inner.flush(),
+        }
+    }
+}
+
+impl <W: Finish> Finish for BufferedWriter<W> {
+    fn finish(&mut self) -> Result<()> {
+        match self {
+            Self::Unbuffered(inner) => inner.finish(),
+            Self::Line(inner) => {
+                inner.flush()?;
+                inner.get_mut().finish()
+            },
+            Self::Block(inner) => {
+                inner.flush()?;
+                inner.get_mut().finish()
+            },
+        }
+    }
+}
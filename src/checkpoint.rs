@@ -0,0 +1,366 @@
+use std::{
+    fs::{
+        self,
+        File,
+    },
+    io::{
+        self,
+        ErrorKind,
+        Seek,
+        SeekFrom,
+        Write,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use thiserror::Error;
+
+use super::{
+    progress::TransferProgress,
+    syncio::Finish,
+    unit::Scanner,
+};
+
+/// How far an interrupted transfer got, saved so that it can be resumed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// Everything written to the output so far; the byte count doubles as
+    /// the offset to resume from in both the input and the output.
+    pub progress: TransferProgress,
+    /// Size of the input when it is a regular file, to tell whether it was
+    /// replaced before resuming.
+    pub input_size: Option<u64>,
+}
+
+/// On-disk form of a [`Checkpoint`], with a checksum guarding against files
+/// which were truncated or edited.
+#[derive(Debug, Serialize, Deserialize)]
+struct Stored {
+    version: u32,
+    checkpoint: Checkpoint,
+    checksum: u64,
+}
+
+/// Where checkpoints of a transfer are kept.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateFile {
+    path: PathBuf,
+}
+
+#[derive(Debug, Error)]
+pub enum StateError {
+    #[error("I/O error accessing state file")]
+    Io(#[from] io::Error),
+    #[error("state file is malformed")]
+    Malformed(#[from] serde_json::Error),
+    #[error("state file version {0} is not supported")]
+    Version(u32),
+    #[error("state file checksum does not match its contents")]
+    Checksum,
+    #[error("cannot resume: {0} is not seekable")]
+    NotSeekable(&'static str),
+    #[error("cannot resume: input is {actual} bytes but was {expected} bytes when checkpointed")]
+    InputChanged {
+        expected: u64,
+        actual: u64,
+    },
+    #[error("cannot resume: {which} holds {actual} bytes but {expected} were checkpointed")]
+    TooShort {
+        which: &'static str,
+        expected: u64,
+        actual: u64,
+    },
+}
+
+/// Writer which keeps a [`StateFile`] up to date with how much has reached
+/// the wrapped writer, at most every [`CheckpointWriter::INTERVAL`] and
+/// once more when finished, however the transfer ended.
+///
+/// Place it beneath any buffering so that only data which has actually been
+/// handed to the output is counted.
+pub struct CheckpointWriter<W> {
+    inner: W,
+    state: Option<StateFile>,
+    checkpoint: Checkpoint,
+    scanner: Scanner,
+    stored: Instant,
+    bytes_only: bool,
+}
+
+impl Checkpoint {
+    const VERSION: u32 = 1;
+
+    pub fn offset(&self) -> u64 {
+        self.progress.bytes_transferred
+    }
+    fn checksum(&self) -> Result<u64, serde_json::Error> {
+        let text = serde_json::to_string(self)?;
+        Ok(fnv1a(text.as_bytes()))
+    }
+}
+
+/// 64-bit FNV-1a, which is plenty to notice accidental damage.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+impl StateFile {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+        }
+    }
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    /// The saved checkpoint, or `None` if there is none yet.
+    pub fn load(&self) -> Result<Option<Checkpoint>, StateError> {
+        let text = match fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let Stored { version, checkpoint, checksum } = serde_json::from_str(&text)?;
+        if version != Checkpoint::VERSION {
+            return Err(StateError::Version(version));
+        }
+        if checkpoint.checksum()? != checksum {
+            return Err(StateError::Checksum);
+        }
+        Ok(Some(checkpoint))
+    }
+    /// Replace the saved checkpoint, atomically so that an interruption
+    /// leaves either the old or the new one behind.
+    pub fn store(&self, checkpoint: &Checkpoint) -> io::Result<()> {
+        let stored = Stored {
+            version: Checkpoint::VERSION,
+            checkpoint: *checkpoint,
+            checksum: checkpoint.checksum()?,
+        };
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(".tmp");
+        fs::write(&temporary, serde_json::to_vec(&stored)?)?;
+        fs::rename(&temporary, &self.path)
+    }
+    /// Forget the saved checkpoint, once the transfer has completed.
+    pub fn remove(&self) -> io::Result<()> {
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Size of `file` if it is a regular file.
+pub fn input_size(file: &File) -> Option<u64> {
+    file.metadata()
+        .ok()
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+}
+
+/// Seek `input` and `output` to where `checkpoint` left off, discarding
+/// anything written to `output` after it.
+pub fn resume(
+    checkpoint: &Checkpoint,
+    input: &mut File,
+    output: &mut File,
+) -> Result<(), StateError> {
+    let offset = checkpoint.offset();
+    let input_len = input_size(input).ok_or(StateError::NotSeekable("input"))?;
+    match checkpoint.input_size {
+        Some(expected) if expected != input_len => {
+            return Err(StateError::InputChanged {
+                expected,
+                actual: input_len,
+            });
+        },
+        _ if input_len < offset => {
+            return Err(StateError::TooShort {
+                which: "input",
+                expected: offset,
+                actual: input_len,
+            });
+        },
+        _ => {},
+    }
+    let output_len = input_size(output).ok_or(StateError::NotSeekable("output"))?;
+    if output_len < offset {
+        return Err(StateError::TooShort {
+            which: "output",
+            expected: offset,
+            actual: output_len,
+        });
+    }
+    input.seek(SeekFrom::Start(offset))?;
+    output.set_len(offset)?;
+    output.seek(SeekFrom::Start(offset))?;
+    Ok(())
+}
+
+impl <W> CheckpointWriter<W> {
+    pub const INTERVAL: Duration = Duration::from_secs(1);
+
+    /// Count from `resumed`, saving checkpoints to `state` if given.
+    pub fn new(inner: W, state: Option<StateFile>, resumed: Checkpoint) -> Self {
+        Self {
+            inner,
+            state,
+            checkpoint: resumed,
+            scanner: Scanner::default(),
+            stored: Instant::now(),
+            bytes_only: false,
+        }
+    }
+    /// Count only bytes and writes, which is all resuming needs.
+    pub fn count_bytes_only(mut self, enabled: bool) -> Self {
+        self.bytes_only = enabled;
+        self
+    }
+    pub fn checkpoint(&self) -> Checkpoint {
+        self.checkpoint
+    }
+    fn store_if_due(&mut self) -> io::Result<()> {
+        if self.stored.elapsed() >= Self::INTERVAL {
+            self.store()?;
+        }
+        Ok(())
+    }
+    fn store(&mut self) -> io::Result<()> {
+        if let Some(state) = &self.state {
+            state.store(&self.checkpoint)?;
+            self.stored = Instant::now();
+        }
+        Ok(())
+    }
+}
+
+impl <W: Write> Write for CheckpointWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let bytes_transferred = self.inner.write(buf)?;
+        let written = &buf[..bytes_transferred];
+        let sample = if self.bytes_only {
+            TransferProgress::bytes_only(written)
+        } else {
+            TransferProgress::scan(&mut self.scanner, written)
+        };
+        self.checkpoint.progress = self.checkpoint.progress + sample;
+        self.store_if_due()?;
+        Ok(bytes_transferred)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()?;
+        self.store_if_due()
+    }
+}
+
+impl <W: Finish> Finish for CheckpointWriter<W> {
+    /// Save how far the transfer got even if finishing the wrapped writer
+    /// fails, so that a failed or cancelled copy can still be resumed.
+    fn finish(&mut self) -> io::Result<()> {
+        let finished = self.inner.finish();
+        self.store()?;
+        finished
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "pvalve-checkpoint-{}-{}",
+            std::process::id(),
+            name,
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn checkpoints_roundtrip_and_damage_is_detected() {
+        let dir = scratch("roundtrip");
+        let state = StateFile::new(dir.join("state"));
+        assert!(state.load().unwrap().is_none());
+        let checkpoint = Checkpoint {
+            progress: TransferProgress::of(b"a\nb\n"),
+            input_size: Some(10),
+        };
+        state.store(&checkpoint).unwrap();
+        assert_eq!(state.load().unwrap(), Some(checkpoint));
+        let damaged = fs::read_to_string(state.path())
+            .unwrap()
+            .replace("\"bytes_transferred\":4", "\"bytes_transferred\":5");
+        fs::write(state.path(), damaged).unwrap();
+        assert!(matches!(state.load(), Err(StateError::Checksum)));
+        state.remove().unwrap();
+        state.remove().unwrap();
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn finishing_saves_what_reached_the_output() {
+        let dir = scratch("finish");
+        let state = StateFile::new(dir.join("state"));
+        let resumed = Checkpoint {
+            progress: TransferProgress::of(b"ab\n"),
+            input_size: None,
+        };
+        let mut writer = CheckpointWriter::new(Vec::new(), Some(state.clone()), resumed);
+        writer.write_all(b"cd\n").unwrap();
+        // Too soon after the last checkpoint to save another.
+        writer.flush().unwrap();
+        assert!(state.load().unwrap().is_none());
+        writer.finish().unwrap();
+        let saved = state.load().unwrap().unwrap();
+        assert_eq!(saved.offset(), 6);
+        assert_eq!(saved.progress.lines_transferred, 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn resuming_seeks_both_ends_and_drops_the_unsaved_tail() {
+        let dir = scratch("resume");
+        fs::write(dir.join("input"), b"0123456789").unwrap();
+        fs::write(dir.join("output"), b"012345").unwrap();
+        let mut input = File::open(dir.join("input")).unwrap();
+        let mut output = File::options()
+            .read(true)
+            .write(true)
+            .open(dir.join("output"))
+            .unwrap();
+        let checkpoint = Checkpoint {
+            progress: TransferProgress::of(b"0123"),
+            input_size: Some(10),
+        };
+        resume(&checkpoint, &mut input, &mut output).unwrap();
+        assert_eq!(input.stream_position().unwrap(), 4);
+        assert_eq!(output.stream_position().unwrap(), 4);
+        assert_eq!(fs::read(dir.join("output")).unwrap(), b"0123");
+        let replaced = Checkpoint {
+            input_size: Some(11),
+            ..checkpoint
+        };
+        assert!(matches!(
+            resume(&replaced, &mut input, &mut output),
+            Err(StateError::InputChanged { expected: 11, actual: 10 }),
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+}
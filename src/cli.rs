@@ -1,45 +1,308 @@
-use std::num::{
-    NonZeroU32,
-    NonZeroUsize,
+use std::{
+    num::{
+        NonZeroU64,
+        NonZeroUsize,
+    },
+    os::fd::RawFd,
+    path::PathBuf,
+    time::Duration,
 };
 
-use clap::Parser;
+use clap::{
+    builder::{
+        PossibleValue,
+        PossibleValuesParser,
+        TypedValueParser,
+    },
+    Args,
+    Parser,
+    Subcommand,
+};
 
-use super::unit::Unit;
+use super::{
+    alarm::MinRate,
+    annotate::TimestampFormat,
+    buffering::Buffering,
+    completions::Shell,
+    delay::Latency,
+    fanout::OutputSpec,
+    filter::LimitSide,
+    format::NumberFormat,
+    hooks::Hooks,
+    instantaneous::Smoothing,
+    range::ByteRange,
+    readahead::Watermarks,
+    sample::SampleRate,
+    status::Render,
+    status_line::RateScale,
+    template::ProgressTemplate,
+    window::Windows,
+    size::{
+        self,
+        ParseByteSizeError,
+    },
+    unit::Unit,
+};
 
+/// A limit per second, which may be given in bits such as `10Mbit` to
+/// match a network link.
 #[derive(Debug, Clone, Copy, PartialOrd, Ord, PartialEq, Eq)]
-pub struct Speed(pub NonZeroU32);
+pub struct Speed {
+    /// Units per second, bytes when given in bits.
+    pub per_second: NonZeroU64,
+    /// Given in bits, which only apply to bytes.
+    pub bits: bool,
+}
 
 impl std::str::FromStr for Speed {
-    type Err = std::num::ParseIntError;
+    type Err = ParseByteSizeError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        NonZeroU32::from_str(s).map(Self)
+        let trimmed = s.trim();
+        let Some(bits) = trimmed.strip_suffix("bit/s").or_else(|| trimmed.strip_suffix("bit")) else {
+            return size::parse_nonzero(s).map(Self::from);
+        };
+        // Round to the nearest byte, but never down to nothing.
+        let bytes = size::parse_nonzero(bits)?.get().saturating_add(4) / 8;
+        Ok(Self {
+            per_second: NonZeroU64::new(bytes).unwrap_or(NonZeroU64::MIN),
+            bits: true,
+        })
+    }
+}
+
+impl From<NonZeroU64> for Speed {
+    fn from(per_second: NonZeroU64) -> Self {
+        Self {
+            per_second,
+            bits: false,
+        }
     }
 }
 
-impl From<&Speed> for NonZeroU32 {
+impl From<&Speed> for NonZeroU64 {
     fn from(val: &Speed) -> Self {
-        val.0
+        val.per_second
     }
 }
 
-#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+/// What the process was asked to do.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Copy(Invocation),
+    Watch(Invocation),
+    /// Copy with the timing of a recording rather than a limit.
+    Replay(Invocation),
+    Audit(AuditInvocation),
+    SelfTest(SelfTestInvocation),
+    /// Print the progress another process publishes with --shm-progress.
+    ReadShm(String),
+    /// Print the most recent transfers recorded with --history.
+    History(usize),
+    Completions(Shell),
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub struct Invocation {
     pub speed: Option<Speed>,
+    /// How long the transfer should take, adjusting the limit to suit.
+    pub target_duration: Option<Duration>,
+    /// Limits by time of day, which take the place of the limit within them.
+    pub windows: Option<Windows>,
+    /// Pause between records, whatever the limit.
+    pub latency: Option<Latency>,
     pub unit: Unit,
+    /// Unit to show amounts and rates in, when not the one limited.
+    pub display_unit: Option<Unit>,
     pub expected_size: Option<NonZeroUsize>,
+    pub max_chunk: Option<NonZeroUsize>,
+    /// Size of the buffer the input is read ahead into, and when it stops.
+    pub buffer_size: Option<NonZeroUsize>,
+    pub buffer_watermarks: Watermarks,
+    pub flush_every_record: bool,
+    pub record_atomic: bool,
+    pub output_buffering: Buffering,
+    /// Longest a single write to stdout may block before giving up.
+    pub write_timeout: Option<Duration>,
+    /// Die of SIGPIPE once stdout is closed, after restoring the terminal.
+    pub pipe_default: bool,
+    pub json_summary: bool,
+    pub rate_window: Duration,
+    pub rate_smoothing: Smoothing,
+    pub count_final_partial: bool,
+    pub audit_pacing: Option<PathBuf>,
+    pub no_altscreen: bool,
+    pub render: Render,
+    /// Interval between spoken progress updates, when announcing them.
+    pub a11y: Option<Duration>,
+    pub mouse: bool,
+    /// Ask for Ctrl-C twice before aborting from the full-screen interface.
+    pub confirm_abort: bool,
+    /// Pause the transfer when Ctrl-Z suspends the full-screen interface.
+    pub pause_on_suspend: bool,
+    pub keys: bool,
+    pub headless: bool,
+    pub control: Option<PathBuf>,
+    pub progress_fd: Option<RawFd>,
+    pub shm_progress: Option<String>,
+    /// CSV file to log the rate to every second.
+    pub rate_log: Option<PathBuf>,
+    /// How often to write a cumulative report line, and where to, stderr
+    /// when no file is given.
+    pub report_interval: Option<Duration>,
+    pub report_file: Option<PathBuf>,
+    /// Record the transfer in the history file once it ends.
+    pub history: bool,
+    pub idle_io: bool,
+    /// Count only bytes, for display-only runs which need nothing else.
+    pub lightweight: bool,
+    pub dry_run: bool,
+    pub refresh_rate: Duration,
+    pub number_format: NumberFormat,
+    pub show_all_units: bool,
+    /// Count payload bytes as well, leaving out delimiters and this many
+    /// header bytes of each record.
+    pub net_bytes: Option<usize>,
+    pub colored_gauge: bool,
+    /// What the gauge drawn next to the rate is full at, if there is one.
+    pub rate_gauge_max: Option<RateScale>,
+    /// Layout of the status line, with the name `%N` stands for.
+    pub progress_format: Option<ProgressTemplate>,
+    pub name: Option<String>,
+    pub min_rate: Option<MinRate>,
+    /// Command to pause the transfer while it fails, and how often to run it.
+    pub gate: Option<String>,
+    pub gate_interval: Duration,
+    /// Bytes to keep free on the filesystems written to, pausing otherwise.
+    pub min_free: Option<NonZeroU64>,
+    pub hooks: Hooks,
+    pub state_file: Option<PathBuf>,
+    pub range: Option<ByteRange>,
+    pub outputs: Vec<OutputSpec>,
+    pub sample: Option<SampleRate>,
+    /// Start each record with its number, the time or both.
+    pub number_lines: bool,
+    pub timestamp_records: Option<TimestampFormat>,
+    /// Shell command the stream is passed through on its way out.
+    pub filter: Option<String>,
+    pub limit_side: LimitSide,
+    pub inputs: Vec<PathBuf>,
+    /// Directory to stream as a tar archive, with the `archive` feature.
+    pub tar: Option<PathBuf>,
+    /// Timing file to replay the stream with.
+    pub timing: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct AuditInvocation {
+    pub log: PathBuf,
+    pub unit: Unit,
+    pub speed: Option<Speed>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SelfTestInvocation {
+    pub size: NonZeroU64,
+    pub skip_limiter: bool,
 }
 
 /// Pipe Valve - Monitor and control pipe throughput.
-#[derive(Debug, Default, Clone, Copy, Parser)]
-#[clap(version)]
+///
+/// Without a subcommand, behaves as `pvalve copy`.
+#[derive(Debug, Default, Clone, Parser)]
+#[clap(version, args_conflicts_with_subcommands = true)]
 pub struct Opts {
+    #[clap(subcommand)]
+    command: Option<Subcommands>,
+    #[clap(flatten)]
+    copy: CopyOpts,
+}
+
+/// Options for transfers which may be throttled.
+#[derive(Debug, Default, Clone, Args)]
+struct CopyOpts {
     #[clap(
         short = 'L',
-        help = "Limit the throughput of the transfer.",
+        value_name = "RATE",
+        help = "Limit the throughput of the transfer, in units per second (e.g. 1000, 10M, 1.5Gi) or bits per second (e.g. 10Mbit).",
     )]
     speed_limit: Option<Speed>,
+    #[clap(
+        long = "target-duration",
+        value_name = "DURATION",
+        value_parser = parse_duration,
+        requires = "expected_size",
+        conflicts_with = "speed_limit",
+        help = "Keep adjusting the limit so that -s finishes in about DURATION (e.g. 90s, 30m, 1h30m).",
+    )]
+    target_duration: Option<Duration>,
+    #[clap(
+        long = "window",
+        value_name = "HH:MM-HH:MM=RATE,...",
+        conflicts_with = "target_duration",
+        help = "Limit to RATE, or unlimited, between two local times of day (e.g. '22:00-06:00=unlimited,06:00-22:00=500k'); -L applies outside every window.",
+    )]
+    windows: Option<Windows>,
+    #[clap(
+        long = "delay-per-record",
+        value_name = "MS",
+        value_parser = parse_millis,
+        help = "Pause for MS milliseconds between records of the unit, whatever the limit.",
+    )]
+    delay_per_record: Option<Duration>,
+    #[clap(
+        long = "jitter",
+        value_name = "MS",
+        value_parser = parse_millis,
+        requires = "delay_per_record",
+        help = "Vary each --delay-per-record pause at random by up to MS milliseconds either way.",
+    )]
+    jitter: Option<Duration>,
+    #[clap(
+        long = "flush-every-record",
+        help = "Flush the output after every record passes the limiter, or after every write for units without a delimiter.",
+    )]
+    flush_every_record: bool,
+    #[clap(
+        long = "record-atomic",
+        help = "Only pass whole records to the output, holding back a partial record until it is complete.",
+    )]
+    record_atomic: bool,
+    #[clap(
+        long = "audit-pacing",
+        value_name = "FILE",
+        hide = true,
+        help = "Log the time and size of every write to FILE.",
+    )]
+    audit_pacing: Option<PathBuf>,
+    #[clap(flatten)]
+    measure: MeasureOpts,
+}
+
+/// Options for transfers which are only monitored.
+#[derive(Debug, Default, Clone, Args)]
+struct WatchOpts {
+    #[clap(flatten)]
+    measure: MeasureOpts,
+}
+
+/// Options for replaying a recorded stream with its original timing.
+#[derive(Debug, Default, Clone, Args)]
+struct ReplayOpts {
+    #[clap(
+        long = "timing",
+        value_name = "FILE",
+        value_hint = clap::ValueHint::FilePath,
+        help = "Timing to reproduce, as written by script -t, script -T or --rate-log. Typescripts start with a header line script does not time, so leave it out first (e.g. tail -n +2).",
+    )]
+    timing: PathBuf,
+    #[clap(flatten)]
+    measure: MeasureOpts,
+}
+
+/// Options shared by every mode which measures a transfer.
+#[derive(Debug, Default, Clone, Args)]
+struct MeasureOpts {
     #[clap(
         short = 'l',
         long,
@@ -52,42 +315,798 @@ pub struct Opts {
         help = "Measurements apply to null-separated records.",
     )]
     null_mode: bool,
+    #[clap(
+        short = 'u',
+        long = "unit",
+        value_parser = unit_parser(),
+        help = "Unit measurements apply to: b (bytes), l (lines), 0 (nulls), w (words), o (write calls).",
+    )]
+    unit: Option<Unit>,
+    #[clap(
+        long = "display-unit",
+        value_name = "UNIT",
+        value_parser = unit_parser(),
+        help = "Show amounts and rates in UNIT while the limit still applies to the selected unit; Shift-Tab cycles it.",
+    )]
+    display_unit: Option<Unit>,
     #[clap(
         short = 's',
         long = "expected-size",
-        help = "Expected size of input stream in bytes.",
+        value_name = "SIZE",
+        value_parser = parse_expected_size,
+        help = "Expected size of input stream in bytes (e.g. 700M, 4.7Gi).",
     )]
     expected_size: Option<NonZeroUsize>,
+    #[clap(
+        long = "max-chunk",
+        value_name = "SIZE",
+        value_parser = parse_expected_size,
+        help = "Read and write at most SIZE bytes at a time (default 8Ki), which bounds each op with -u o.",
+    )]
+    max_chunk: Option<NonZeroUsize>,
+    #[clap(
+        long = "buffer-size",
+        value_name = "SIZE",
+        value_parser = parse_expected_size,
+        help = "Read the input ahead on a separate thread into a buffer of SIZE bytes (e.g. 64Mi), so that a bursty source keeps being read while the output drains.",
+    )]
+    buffer_size: Option<NonZeroUsize>,
+    #[clap(
+        long = "buffer-watermarks",
+        value_name = "LOW:HIGH",
+        default_value_t,
+        requires = "buffer_size",
+        help = "Stop reading ahead once the buffer is HIGH percent full, and start again once it drains to LOW percent.",
+    )]
+    buffer_watermarks: Watermarks,
+    #[clap(
+        long = "output-buffering",
+        value_name = "none|line|block[:SIZE]",
+        default_value_t,
+        help = "How output is buffered before it is written.",
+    )]
+    output_buffering: Buffering,
+    #[clap(
+        long = "write-timeout",
+        value_name = "SECS",
+        value_parser = parse_seconds,
+        help = "Give up with exit status 124 if a single write to stdout blocks for more than SECS seconds.",
+    )]
+    write_timeout: Option<Duration>,
+    #[clap(
+        long = "pipe-default",
+        help = "Die of SIGPIPE, silently, when whatever reads stdout goes away, as classic filters do, rather than reporting the broken pipe and exiting with status 141.",
+    )]
+    pipe_default: bool,
+    #[clap(
+        long = "json-summary",
+        help = "Print a JSON summary of the transfer to stderr when done.",
+    )]
+    json_summary: bool,
+    #[clap(
+        long = "rate-window",
+        value_name = "SECS",
+        default_value = "1",
+        value_parser = parse_seconds,
+        help = "Period over which the instantaneous rate is measured.",
+    )]
+    rate_window: Duration,
+    #[clap(
+        long = "rate-smoothing",
+        value_name = "mean|ewma",
+        default_value_t,
+        help = "How samples within the rate window are combined.",
+    )]
+    rate_smoothing: Smoothing,
+    #[clap(
+        long = "count-final-partial",
+        overrides_with = "no_count_final_partial",
+        help = "Count a trailing unterminated record of the unit once the input ends, the default.",
+    )]
+    count_final_partial: bool,
+    #[clap(
+        long = "no-count-final-partial",
+        overrides_with = "count_final_partial",
+        help = "Leave a trailing unterminated record uncounted.",
+    )]
+    no_count_final_partial: bool,
+    #[clap(
+        long = "no-altscreen",
+        help = "Report progress on a single line of stderr instead of a full-screen interface.",
+    )]
+    no_altscreen: bool,
+    #[clap(
+        long = "render",
+        value_name = "auto|full|simple",
+        default_value_t,
+        help = "Draw the full-screen interface or a single redrawn line; auto picks the line inside tmux or screen.",
+    )]
+    render: Render,
+    #[clap(
+        long = "a11y",
+        conflicts_with_all = ["no_altscreen", "render", "mouse"],
+        help = "Announce progress as a short sentence on its own line of stderr, for screen readers.",
+    )]
+    a11y: bool,
+    #[clap(
+        long = "a11y-interval",
+        value_name = "SECS",
+        value_parser = parse_seconds,
+        default_value = "10",
+        requires = "a11y",
+        help = "Seconds between announcements with --a11y.",
+    )]
+    a11y_interval: Duration,
+    #[clap(
+        long = "mouse",
+        conflicts_with = "no_altscreen",
+        help = "Click the pause marker, scroll over the rate and point along the gauge with the mouse.",
+    )]
+    mouse: bool,
+    #[clap(
+        long = "confirm-abort",
+        conflicts_with = "no_altscreen",
+        help = "Ask for Ctrl-C to be pressed again within 3s before aborting from the full-screen interface.",
+    )]
+    confirm_abort: bool,
+    #[clap(
+        long = "pause-on-suspend",
+        conflicts_with = "no_altscreen",
+        help = "Pause the transfer when Ctrl-Z suspends the full-screen interface, so that it stays paused after fg.",
+    )]
+    pause_on_suspend: bool,
+    #[clap(
+        long = "keys",
+        help = "Read the pause and rate keys from the terminal even without the full-screen interface.",
+    )]
+    keys: bool,
+    #[clap(
+        long = "headless",
+        conflicts_with_all = ["no_altscreen", "render", "a11y", "mouse", "confirm_abort", "pause_on_suspend", "keys"],
+        help = "Never touch the terminal; control the transfer through --control instead.",
+    )]
+    headless: bool,
+    #[clap(
+        long = "control",
+        value_name = "PATH",
+        help = "Accept pause, resume, toggle, limit N|off, abort and status, one per line, on a Unix socket at PATH.",
+    )]
+    control: Option<PathBuf>,
+    #[clap(
+        long = "progress-fd",
+        value_name = "FD",
+        value_parser = clap::value_parser!(RawFd).range(3..),
+        help = "Write progress as one line of JSON per refresh to file descriptor FD, 3 or above, for wrappers drawing their own progress.",
+    )]
+    progress_fd: Option<RawFd>,
+    #[clap(
+        long = "shm-progress",
+        value_name = "NAME",
+        help = "Publish progress in the shared memory object NAME as it happens, for pvalve read-shm.",
+    )]
+    shm_progress: Option<String>,
+    #[clap(
+        long = "rate-log",
+        value_name = "FILE",
+        value_hint = clap::ValueHint::FilePath,
+        help = "Write progress, the rate, the limit and whether paused to FILE as one CSV row per second, for graphing afterwards.",
+    )]
+    rate_log: Option<PathBuf>,
+    #[clap(
+        long = "report-interval",
+        value_name = "DURATION",
+        value_parser = parse_duration,
+        help = "Append a line with the time, the total so far, the elapsed time, the average rate and the state to --report-file every DURATION (e.g. 10s, 5m) and once at the end, for batch job logs.",
+    )]
+    report_interval: Option<Duration>,
+    #[clap(
+        long = "report-file",
+        value_name = "FILE",
+        value_hint = clap::ValueHint::FilePath,
+        requires = "report_interval",
+        help = "Append --report-interval lines to FILE rather than writing them to stderr.",
+    )]
+    report_file: Option<PathBuf>,
+    #[clap(
+        long = "history",
+        help = "Append the command, totals, rate and outcome to ~/.local/state/pvalve/history.jsonl once the transfer ends.",
+    )]
+    history: bool,
+    #[clap(
+        long = "idle-io",
+        help = "Run at the lowest CPU priority and, on Linux, the idle I/O class.",
+    )]
+    idle_io: bool,
+    #[clap(
+        long = "lightweight",
+        conflicts_with_all = ["line_mode", "null_mode", "unit", "show_all_units", "sample"],
+        help = "Count bytes alone, skipping the scan for lines and other records, to copy nearly as fast as cat.",
+    )]
+    lightweight: bool,
+    #[clap(
+        long = "dry-run",
+        help = "Print the resolved plan as JSON and exit without copying anything.",
+    )]
+    dry_run: bool,
+    #[clap(
+        long = "refresh-rate",
+        value_name = "MS",
+        default_value = "1000",
+        value_parser = parse_millis,
+        help = "Redraw progress at most every MS milliseconds.",
+    )]
+    refresh_rate: Duration,
+    #[clap(
+        long = "number-format",
+        value_name = "auto|binary|si|raw|bits",
+        default_value_t,
+        help = "How amounts and rates are written: binary (KiB), SI (kB), raw integers or rates in bits.",
+    )]
+    number_format: NumberFormat,
+    #[clap(
+        long = "plain-numbers",
+        help = "Write amounts and rates as raw integers; same as --number-format raw.",
+    )]
+    plain_numbers: bool,
+    #[clap(
+        long = "show-all-units",
+        help = "Show every counter at once; the limit still applies to the selected unit, and the a key toggles it.",
+    )]
+    show_all_units: bool,
+    #[clap(
+        long = "net-bytes",
+        value_name = "HEADER_LEN",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "0",
+        help = "Also count payload bytes, leaving out the delimiter and the first HEADER_LEN bytes (none by default) of every record, lines unless -0 is given; shown in the details view and the --json-summary.",
+    )]
+    net_bytes: Option<usize>,
+    #[clap(
+        long = "colored-gauge",
+        help = "Color the gauge green below the limit, amber near it and red while paused.",
+    )]
+    colored_gauge: bool,
+    #[clap(
+        long = "rate-gauge-max",
+        value_name = "RATE|limit",
+        help = "Draw a small gauge next to the rate, full at RATE per second in the shown unit (e.g. 10M) or at the limit.",
+    )]
+    rate_gauge_max: Option<RateScale>,
+    #[clap(
+        short = 'F',
+        long = "format",
+        value_name = "FORMAT",
+        help = "Lay the status line out as FORMAT, where %b is the amount transferred, %t the time elapsed, %r the rate, %e the ETA, %p the percentage, %T how full the --buffer-size buffer is, %N the name and %% a percent sign.",
+    )]
+    progress_format: Option<ProgressTemplate>,
+    #[clap(
+        short = 'N',
+        long = "name",
+        value_name = "NAME",
+        help = "Name the transfer NAME, for %N in --format.",
+    )]
+    name: Option<String>,
+    #[clap(
+        long = "min-rate",
+        value_name = "N[/UNIT][:SECS]",
+        help = "Flag the rate once it stays below N per second for SECS seconds (default 5).",
+    )]
+    min_rate: Option<MinRate>,
+    #[clap(
+        long = "gate-cmd",
+        value_name = "CMD",
+        help = "Run the shell command CMD every --gate-interval, pausing the transfer while it fails and resuming once it succeeds.",
+    )]
+    gate_cmd: Option<String>,
+    #[clap(
+        long = "gate-interval",
+        value_name = "DURATION",
+        value_parser = parse_duration,
+        default_value = "30s",
+        requires = "gate_cmd",
+        help = "How often to run --gate-cmd (e.g. 30s, 5m).",
+    )]
+    gate_interval: Duration,
+    #[clap(
+        long = "min-free",
+        value_name = "SIZE",
+        value_parser = size::parse_nonzero,
+        help = "Pause while less than SIZE is free on the filesystem of the output file or any --out file (e.g. 5Gi), resuming once there is room again.",
+    )]
+    min_free: Option<NonZeroU64>,
+    #[clap(
+        long = "min-rate-bell",
+        requires = "min_rate",
+        help = "Ring the terminal bell when the rate falls below --min-rate.",
+    )]
+    min_rate_bell: bool,
+    #[clap(
+        long = "on-complete",
+        value_name = "CMD",
+        help = "Run CMD through the shell once the input has been copied in full.",
+    )]
+    on_complete: Option<String>,
+    #[clap(
+        long = "on-abort",
+        value_name = "CMD",
+        help = "Run CMD through the shell if the transfer is cancelled or fails.",
+    )]
+    on_abort: Option<String>,
+    #[clap(
+        long = "on-stall",
+        value_name = "CMD",
+        help = "Run CMD through the shell when the rate stays below --min-rate, or at zero.",
+    )]
+    on_stall: Option<String>,
+    #[clap(
+        long = "state-file",
+        value_name = "PATH",
+        value_hint = clap::ValueHint::FilePath,
+        help = "Checkpoint progress to PATH and resume from it when seekable input and output allow.",
+    )]
+    state_file: Option<PathBuf>,
+    #[clap(
+        long = "range",
+        value_name = "START-END",
+        allow_hyphen_values = true,
+        conflicts_with = "state_file",
+        help = "Copy only bytes START up to END of the input (e.g. 1Gi-2Gi, 100M-, -4k).",
+    )]
+    range: Option<ByteRange>,
+    #[clap(
+        long = "out",
+        value_name = "PATH[:RATE][:lossy]",
+        conflicts_with = "state_file",
+        help = "Also write the stream to PATH, at most RATE units per second; lossy outputs drop data instead of holding the transfer back. May be repeated.",
+    )]
+    outputs: Vec<OutputSpec>,
+    #[clap(
+        long = "sample",
+        value_name = "K/N",
+        conflicts_with = "state_file",
+        help = "Forward only K of every N line or null records, while still counting them all (e.g. 1/100).",
+    )]
+    sample: Option<SampleRate>,
+    #[clap(
+        long = "number-lines",
+        conflicts_with = "state_file",
+        help = "Start each line or null record forwarded with its number and a tab, as cat -n does.",
+    )]
+    number_lines: bool,
+    #[clap(
+        long = "timestamp-records",
+        value_name = "iso|epoch-ms",
+        conflicts_with = "state_file",
+        help = "Start each line or null record forwarded with the time it went out and a tab, after any --number-lines number.",
+    )]
+    timestamp_records: Option<TimestampFormat>,
+    #[clap(
+        long = "filter",
+        value_name = "CMD",
+        conflicts_with_all = ["state_file", "sample"],
+        help = "Pass the stream through the shell command CMD (e.g. 'gzip -9'), showing what it writes as forwarded.",
+    )]
+    filter: Option<String>,
+    #[clap(
+        long = "limit-side",
+        value_name = "in|out",
+        default_value_t,
+        requires = "filter",
+        help = "Apply the limit to what goes into the --filter command, or to what comes out of it.",
+    )]
+    limit_side: LimitSide,
+    #[clap(
+        value_name = "FILE",
+        value_hint = clap::ValueHint::FilePath,
+        conflicts_with_all = ["state_file", "range"],
+        help = "Read each FILE in turn instead of stdin, showing progress through the current one.",
+    )]
+    inputs: Vec<PathBuf>,
+    #[cfg(feature = "archive")]
+    #[clap(
+        long = "tar",
+        value_name = "DIR",
+        value_hint = clap::ValueHint::DirPath,
+        conflicts_with_all = ["state_file", "range", "inputs"],
+        help = "Read a tar archive of DIR instead of stdin, sized up front for the gauge.",
+    )]
+    tar: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+enum Subcommands {
+    /// Copy stdin to stdout, monitoring and optionally throttling it.
+    Copy(CopyOpts),
+    /// Copy stdin to stdout, monitoring it without any throttling.
+    Watch(WatchOpts),
+    /// Copy stdin to stdout as fast as it originally went, following a
+    /// timing file recorded alongside it.
+    Replay(ReplayOpts),
+    /// Analyze a pacing log written with --audit-pacing.
+    Audit(AuditOpts),
+    /// Measure how fast pvalve itself can copy, count and limit.
+    #[clap(name = "selftest")]
+    SelfTest(SelfTestOpts),
+    /// Print the progress of a transfer run with --shm-progress as JSON.
+    #[clap(name = "read-shm")]
+    ReadShm {
+        #[clap(value_name = "NAME", help = "Shared memory object the transfer publishes to.")]
+        name: String,
+    },
+    /// Print the most recent transfers recorded with --history.
+    History {
+        #[clap(
+            short = 'n',
+            long = "count",
+            value_name = "N",
+            default_value = "20",
+            help = "How many of the most recent transfers to print.",
+        )]
+        count: usize,
+    },
+    /// Print a shell completion script.
+    Completions {
+        #[clap(value_enum)]
+        shell: Shell,
+    },
+}
+
+#[derive(Debug, Clone, Args)]
+struct SelfTestOpts {
+    #[clap(
+        long = "size",
+        value_name = "SIZE",
+        default_value = "256Mi",
+        value_parser = size::parse_nonzero,
+        help = "Bytes to push through each throughput benchmark.",
+    )]
+    size: NonZeroU64,
+    #[clap(
+        long = "skip-limiter",
+        help = "Skip checking the limiter, which takes a few seconds.",
+    )]
+    skip_limiter: bool,
+}
+
+#[derive(Debug, Clone, Args)]
+struct AuditOpts {
+    #[clap(help = "Pacing log to analyze.")]
+    log: PathBuf,
+    #[clap(
+        short = 'u',
+        long = "unit",
+        default_value_t,
+        value_parser = unit_parser(),
+        help = "Unit to measure pacing in: b (bytes), l (lines), 0 (nulls), w (words), o (write calls).",
+    )]
+    unit: Unit,
+    #[clap(
+        short = 'L',
+        help = "Limit the transfer was expected to respect.",
+    )]
+    speed_limit: Option<Speed>,
+}
+
+/// Parses a [`Unit`] while advertising the accepted values to completions.
+fn unit_parser() -> impl TypedValueParser<Value = Unit> {
+    PossibleValuesParser::new([
+        PossibleValue::new("b").aliases(["byte", "bytes"]),
+        PossibleValue::new("l").aliases(["line", "lines"]),
+        PossibleValue::new("0").aliases(["null", "nulls"]),
+        PossibleValue::new("w").aliases(["word", "words"]),
+        PossibleValue::new("o").aliases(["op", "ops"]),
+    ])
+        .map(|s| s.parse::<Unit>().expect("every possible value is a unit"))
+}
+
+fn parse_expected_size(s: &str) -> Result<NonZeroUsize, String> {
+    let size = size::parse_nonzero(s).map_err(|e| e.to_string())?;
+    NonZeroUsize::try_from(size)
+        .map_err(|_| format!("{:?} is too large for this platform", s))
+}
+
+fn parse_millis(s: &str) -> Result<Duration, String> {
+    s.parse::<u64>()
+        .ok()
+        .filter(|millis| *millis > 0)
+        .map(Duration::from_millis)
+        .ok_or_else(|| format!("{:?} is not a positive number of milliseconds", s))
+}
+
+fn parse_seconds(s: &str) -> Result<Duration, String> {
+    s.parse::<f64>()
+        .ok()
+        .filter(|secs| secs.is_finite() && *secs > 0f64)
+        .map(Duration::from_secs_f64)
+        .ok_or_else(|| format!("{:?} is not a positive number of seconds", s))
+}
+
+/// A duration such as `90`, `90s`, `30m`, `2h`, `1d` or `1h30m`.
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let invalid = || format!("{:?} is not a duration such as 90s, 30m or 1h30m", s);
+    let mut seconds = 0f64;
+    let mut rest = s;
+    while !rest.is_empty() {
+        let end = rest.find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(rest.len());
+        let (number, suffix) = rest.split_at(end);
+        let number = number.parse::<f64>().map_err(|_| invalid())?;
+        let mut suffix = suffix.chars();
+        let scale = match suffix.next() {
+            None | Some('s') => 1f64,
+            Some('m') => 60f64,
+            Some('h') => 3600f64,
+            Some('d') => 86400f64,
+            Some(_) => return Err(invalid()),
+        };
+        seconds += number * scale;
+        rest = suffix.as_str();
+    }
+    Some(seconds)
+        .filter(|secs| secs.is_finite() && *secs > 0f64)
+        .map(Duration::from_secs_f64)
+        .ok_or_else(invalid)
+}
+
+impl Invocation {
+    /// Whether the copy can run on the evented engine: it was built in, it
+    /// reads stdin, and every writer in the chain passes each write straight
+    /// on, so none loses track of one the output turns away partway.
+    pub fn evented(&self) -> bool {
+        cfg!(feature = "evented")
+            && self.latency.is_none()
+            && !self.record_atomic
+            && self.audit_pacing.is_none()
+            && self.state_file.is_none()
+            && self.outputs.is_empty()
+            && self.sample.is_none()
+            && !self.number_lines
+            && self.timestamp_records.is_none()
+            && self.filter.is_none()
+            && self.inputs.is_empty()
+            && self.tar.is_none()
+            && self.timing.is_none()
+            && self.buffer_size.is_none()
+            && self.net_bytes.is_none()
+    }
 }
 
 impl Opts {
-    pub fn parse_process_args() -> Invocation {
+    pub fn parse_process_args() -> Command {
         let opts = Self::parse();
         opts.into()
     }
 }
 
-impl From<&Opts> for Unit {
-    fn from(opts: &Opts) -> Self {
+impl From<Opts> for Command {
+    fn from(opts: Opts) -> Self {
+        match opts.command {
+            None => Self::Copy(opts.copy.into()),
+            Some(Subcommands::Copy(copy)) => Self::Copy(copy.into()),
+            Some(Subcommands::Watch(watch)) => Self::Watch(watch.into()),
+            Some(Subcommands::Replay(replay)) => Self::Replay(replay.into()),
+            Some(Subcommands::Audit(AuditOpts { log, unit, speed_limit })) => {
+                Self::Audit(AuditInvocation {
+                    log,
+                    unit,
+                    speed: speed_limit,
+                })
+            },
+            Some(Subcommands::SelfTest(SelfTestOpts { size, skip_limiter })) => {
+                Self::SelfTest(SelfTestInvocation {
+                    size,
+                    skip_limiter,
+                })
+            },
+            Some(Subcommands::ReadShm { name }) => Self::ReadShm(name),
+            Some(Subcommands::History { count }) => Self::History(count),
+            Some(Subcommands::Completions { shell }) => Self::Completions(shell),
+        }
+    }
+}
+
+impl From<&MeasureOpts> for Unit {
+    fn from(opts: &MeasureOpts) -> Self {
         if opts.null_mode {
             Self::Null
         } else if opts.line_mode {
             Self::Line
         } else {
-            Self::Byte
+            opts.unit.unwrap_or_default()
         }
     }
 }
 
-impl From<Opts> for Invocation {
-    fn from(opts: Opts) -> Self {
+impl From<MeasureOpts> for Invocation {
+    fn from(opts: MeasureOpts) -> Self {
         let unit = Unit::from(&opts);
-        let Opts {
-            speed_limit: speed,
+        let MeasureOpts {
+            display_unit,
             expected_size,
+            max_chunk,
+            buffer_size,
+            buffer_watermarks,
+            output_buffering,
+            write_timeout,
+            pipe_default,
+            json_summary,
+            rate_window,
+            rate_smoothing,
+            count_final_partial: _,
+            no_count_final_partial,
+            no_altscreen,
+            render,
+            a11y,
+            a11y_interval,
+            mouse,
+            confirm_abort,
+            pause_on_suspend,
+            keys,
+            headless,
+            control,
+            progress_fd,
+            shm_progress,
+            rate_log,
+            report_interval,
+            report_file,
+            history,
+            idle_io,
+            lightweight,
+            dry_run,
+            refresh_rate,
+            number_format,
+            plain_numbers,
+            show_all_units,
+            net_bytes,
+            colored_gauge,
+            rate_gauge_max,
+            progress_format,
+            name,
+            min_rate,
+            min_rate_bell,
+            gate_cmd,
+            gate_interval,
+            min_free,
+            on_complete,
+            on_abort,
+            on_stall,
+            state_file,
+            range,
+            outputs,
+            sample,
+            number_lines,
+            timestamp_records,
+            filter,
+            limit_side,
+            inputs,
+            #[cfg(feature = "archive")]
+            tar,
             ..
         } = opts;
-        Self { unit, speed, expected_size }
+        let number_format = if plain_numbers {
+            NumberFormat::Raw
+        } else {
+            number_format
+        };
+        Self {
+            unit,
+            display_unit,
+            expected_size,
+            max_chunk,
+            buffer_size,
+            buffer_watermarks,
+            output_buffering,
+            write_timeout,
+            pipe_default,
+            json_summary,
+            rate_window,
+            rate_smoothing,
+            count_final_partial: !no_count_final_partial,
+            no_altscreen: no_altscreen || render == Render::Simple,
+            render,
+            a11y: a11y.then_some(a11y_interval),
+            mouse,
+            confirm_abort,
+            pause_on_suspend,
+            keys,
+            headless,
+            control,
+            progress_fd,
+            shm_progress,
+            rate_log,
+            report_interval,
+            report_file,
+            history,
+            idle_io,
+            lightweight,
+            dry_run,
+            refresh_rate,
+            number_format,
+            show_all_units,
+            net_bytes,
+            colored_gauge,
+            rate_gauge_max,
+            progress_format: progress_format
+                .map(|format| format.with_name(name.clone())),
+            name,
+            min_rate: min_rate.map(|min_rate| MinRate {
+                bell: min_rate_bell,
+                ..min_rate
+            }),
+            gate: gate_cmd,
+            gate_interval,
+            min_free,
+            hooks: Hooks {
+                on_complete,
+                on_abort,
+                on_stall,
+            },
+            state_file,
+            range,
+            outputs,
+            sample,
+            number_lines,
+            timestamp_records,
+            filter,
+            limit_side,
+            inputs,
+            #[cfg(feature = "archive")]
+            tar,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<CopyOpts> for Invocation {
+    fn from(opts: CopyOpts) -> Self {
+        let CopyOpts {
+            speed_limit: speed,
+            target_duration,
+            windows,
+            delay_per_record,
+            jitter,
+            flush_every_record,
+            record_atomic,
+            audit_pacing,
+            measure,
+        } = opts;
+        let measured = Self::from(measure);
+        // Limits given in bits are shown in bits unless asked otherwise.
+        let number_format = match (speed, measured.number_format) {
+            (Some(Speed { bits: true, .. }), NumberFormat::Auto) => NumberFormat::Bits,
+            (_, number_format) => number_format,
+        };
+        Self {
+            speed,
+            target_duration,
+            windows,
+            number_format,
+            latency: delay_per_record.map(|delay| Latency {
+                delay,
+                jitter: jitter.unwrap_or_default(),
+            }),
+            flush_every_record,
+            record_atomic,
+            audit_pacing,
+            ..measured
+        }
+    }
+}
+
+impl From<WatchOpts> for Invocation {
+    fn from(opts: WatchOpts) -> Self {
+        opts.measure.into()
+    }
+}
+
+impl From<ReplayOpts> for Invocation {
+    fn from(opts: ReplayOpts) -> Self {
+        let ReplayOpts { timing, measure } = opts;
+        Self {
+            timing: Some(timing),
+            ..measure.into()
+        }
     }
 }
 
@@ -97,10 +1116,17 @@ mod tests {
     use super::*;
     type Result = anyhow::Result<()>;
 
-    fn parse(args: &[&str]) -> anyhow::Result<Invocation> {
+    fn command(args: &[&str]) -> anyhow::Result<Command> {
         let args = [&["pvalve"][..], args].concat();
-        let invo = Opts::try_parse_from(args)?;
-        Ok(invo.into())
+        let opts = Opts::try_parse_from(args)?;
+        Ok(opts.into())
+    }
+
+    fn parse(args: &[&str]) -> anyhow::Result<Invocation> {
+        match command(args)? {
+            Command::Copy(invo) => Ok(invo),
+            other => anyhow::bail!("expected a copy, got {:?}", other),
+        }
     }
 
     #[test]
@@ -110,6 +1136,13 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn when__display_unit_selected__then__limit_unit_is_kept() -> Result {
+        let Invocation { unit, display_unit, .. } = parse(&["--display-unit", "l"])?;
+        assert_eq!((unit, display_unit), (Unit::Byte, Some(Unit::Line)));
+        Ok(())
+    }
+
     #[test]
     fn when__line_unit_selected__then__line_is_used() -> Result {
         let Invocation { unit, .. } = parse(&["-l"])?;
@@ -138,6 +1171,668 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn when__unit_option_supplied__then__supplied_unit_is_used() -> Result {
+        let Invocation { unit, .. } = parse(&["--unit", "l"])?;
+        assert_eq!(unit, Unit::Line);
+        let Invocation { unit, .. } = parse(&["-u", "0"])?;
+        assert_eq!(unit, Unit::Null);
+        let Invocation { unit, .. } = parse(&["-u", "w"])?;
+        assert_eq!(unit, Unit::Word);
+        let Invocation { unit, .. } = parse(&["-u", "ops"])?;
+        assert_eq!(unit, Unit::Op);
+        Ok(())
+    }
+
+    #[test]
+    fn when__unknown_unit_supplied__then__parse_fails() -> Result {
+        parse(&["--unit", "x"])
+            .expect_err("parse should have failed");
+        Ok(())
+    }
+
+    #[test]
+    fn when__speed_exceeds_u32__then__it_is_accepted() -> Result {
+        let Invocation { speed, .. } = parse(&["-L", "8000000000"])?;
+        assert_eq!(speed.map(|s| s.per_second.get()), Some(8_000_000_000));
+        Ok(())
+    }
+
+    #[test]
+    fn when__speed_given_in_bits__then__it_is_rounded_to_bytes_and_shown_in_bits() -> Result {
+        let invo = parse(&["-L", "10Mbit"])?;
+        assert_eq!(invo.speed.map(|s| s.per_second.get()), Some(1_250_000));
+        assert_eq!(invo.number_format, NumberFormat::Bits);
+        let invo = parse(&["-L", "12bit/s", "--number-format", "si"])?;
+        assert_eq!(invo.speed.map(|s| s.per_second.get()), Some(2));
+        assert_eq!(invo.number_format, NumberFormat::Si);
+        assert_eq!(parse(&["-L", "1bit"])?.speed.map(|s| s.per_second.get()), Some(1));
+        parse(&["-L", "0bit"])
+            .expect_err("a limit must be positive");
+        Ok(())
+    }
+
+    #[test]
+    fn when__count_final_partial_not_supplied__then__it_is_enabled() -> Result {
+        let Invocation { count_final_partial, .. } = parse(&[])?;
+        assert!(count_final_partial);
+        let Invocation { count_final_partial, .. } =
+            parse(&["--no-count-final-partial"])?;
+        assert!(!count_final_partial);
+        let Invocation { count_final_partial, .. } =
+            parse(&["--no-count-final-partial", "--count-final-partial"])?;
+        assert!(count_final_partial);
+        Ok(())
+    }
+
+    #[test]
+    fn when__audit_subcommand_supplied__then__audit_is_invoked() -> Result {
+        let audit = command(&["audit", "-u", "l", "-L", "10", "pacing.log"])?;
+        assert_eq!(audit, Command::Audit(AuditInvocation {
+            log: "pacing.log".into(),
+            unit: Unit::Line,
+            speed: Some(Speed::from(nonzero_ext::nonzero!(10u64))),
+        }));
+        Ok(())
+    }
+
+    #[test]
+    fn when__selftest_subcommand_supplied__then__selftest_is_invoked() -> Result {
+        let selftest = command(&["selftest", "--size", "1Mi", "--skip-limiter"])?;
+        assert_eq!(selftest, Command::SelfTest(SelfTestInvocation {
+            size: nonzero_ext::nonzero!(1048576u64),
+            skip_limiter: true,
+        }));
+        Ok(())
+    }
+
+    #[test]
+    fn when__rate_log_supplied__then__path_is_set() -> Result {
+        assert_eq!(parse(&[])?.rate_log, None);
+        let Invocation { rate_log, .. } = parse(&["--rate-log", "rate.csv"])?;
+        assert_eq!(rate_log, Some("rate.csv".into()));
+        Ok(())
+    }
+
+    #[test]
+    fn when__pipe_default_supplied__then__sigpipe_is_left_to_kill() -> Result {
+        assert!(!parse(&[])?.pipe_default);
+        assert!(parse(&["--pipe-default"])?.pipe_default);
+        Ok(())
+    }
+
+    #[test]
+    fn when__report_interval_supplied__then__reports_go_to_stderr_unless_a_file_is_given() -> Result {
+        let Invocation { report_interval, report_file, .. } = parse(&["--report-interval", "1m30s"])?;
+        assert_eq!(report_interval, Some(Duration::from_secs(90)));
+        assert_eq!(report_file, None);
+        let Invocation { report_file, .. } = parse(&["--report-interval", "10s", "--report-file", "job.log"])?;
+        assert_eq!(report_file, Some("job.log".into()));
+        assert!(parse(&["--report-file", "job.log"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn when__shm_progress_supplied__then__readers_can_name_it() -> Result {
+        assert_eq!(parse(&["--shm-progress", "backup"])?.shm_progress.as_deref(), Some("backup"));
+        assert_eq!(command(&["read-shm", "backup"])?, Command::ReadShm("backup".into()));
+        command(&["read-shm"])
+            .expect_err("read-shm needs a name");
+        Ok(())
+    }
+
+    #[test]
+    fn when__history_supplied__then__transfers_are_recorded_and_listed() -> Result {
+        assert!(!parse(&[])?.history);
+        assert!(parse(&["--history"])?.history);
+        assert_eq!(command(&["history"])?, Command::History(20));
+        assert_eq!(command(&["history", "-n", "5"])?, Command::History(5));
+        Ok(())
+    }
+
+    #[test]
+    fn when__no_subcommand_supplied__then__copy_is_implied() -> Result {
+        let implicit = command(&["-l", "-L", "10"])?;
+        let explicit = command(&["copy", "-l", "-L", "10"])?;
+        assert_eq!(implicit, explicit);
+        assert!(matches!(implicit, Command::Copy(_)));
+        Ok(())
+    }
+
+    #[test]
+    fn when__watch_subcommand_supplied__then__no_limit_is_accepted() -> Result {
+        let watch = command(&["watch", "-l"])?;
+        assert_eq!(watch, Command::Watch(Invocation {
+            unit: Unit::Line,
+            ..parse(&[])?
+        }));
+        command(&["watch", "-L", "10"])
+            .expect_err("watch should not accept a limit");
+        Ok(())
+    }
+
+    #[test]
+    fn when__replay_subcommand_supplied__then__timing_is_followed() -> Result {
+        let replay = command(&["replay", "--timing", "session.tm", "-l"])?;
+        assert_eq!(replay, Command::Replay(Invocation {
+            unit: Unit::Line,
+            timing: Some("session.tm".into()),
+            ..parse(&[])?
+        }));
+        command(&["replay"])
+            .expect_err("replay should need a timing file");
+        command(&["replay", "--timing", "session.tm", "-L", "10"])
+            .expect_err("replay should not accept a limit");
+        Ok(())
+    }
+
+    #[test]
+    fn when__completions_subcommand_supplied__then__shell_is_selected() -> Result {
+        assert_eq!(command(&["completions", "zsh"])?, Command::Completions(Shell::Zsh));
+        command(&["completions", "tcsh"])
+            .expect_err("unsupported shell should be rejected");
+        Ok(())
+    }
+
+    #[test]
+    fn when__no_altscreen_supplied__then__flag_is_set() -> Result {
+        assert!(!parse(&[])?.no_altscreen);
+        assert!(parse(&["--no-altscreen"])?.no_altscreen);
+        assert!(parse(&["--mouse"])?.mouse);
+        assert!(parse(&["--render", "simple"])?.no_altscreen);
+        assert_eq!(parse(&["--render", "full"])?.render, Render::Full);
+        parse(&["--mouse", "--no-altscreen"])
+            .expect_err("mouse needs the full-screen interface");
+        Ok(())
+    }
+
+    #[test]
+    fn when__confirm_abort_supplied__then__the_full_screen_interface_asks_twice() -> Result {
+        assert!(!parse(&[])?.confirm_abort);
+        assert!(parse(&["--confirm-abort"])?.confirm_abort);
+        parse(&["--confirm-abort", "--headless"])
+            .expect_err("only the full-screen interface can ask");
+        Ok(())
+    }
+
+    #[test]
+    fn when__pause_on_suspend_supplied__then__flag_is_set() -> Result {
+        assert!(!parse(&[])?.pause_on_suspend);
+        assert!(parse(&["--pause-on-suspend"])?.pause_on_suspend);
+        parse(&["--pause-on-suspend", "--no-altscreen"])
+            .expect_err("only the full-screen interface suspends on Ctrl-Z");
+        Ok(())
+    }
+
+    #[test]
+    fn when__delay_per_record_supplied__then__jitter_may_vary_it() -> Result {
+        assert_eq!(parse(&[])?.latency, None);
+        assert_eq!(
+            parse(&["--delay-per-record", "20"])?.latency,
+            Some(Latency {
+                delay: Duration::from_millis(20),
+                jitter: Duration::ZERO,
+            }),
+        );
+        assert_eq!(
+            parse(&["--delay-per-record", "20", "--jitter", "5"])?.latency,
+            Some(Latency {
+                delay: Duration::from_millis(20),
+                jitter: Duration::from_millis(5),
+            }),
+        );
+        parse(&["--jitter", "5"])
+            .expect_err("jitter needs a delay to vary");
+        Ok(())
+    }
+
+    #[test]
+    fn when__write_timeout_supplied__then__it_must_be_positive() -> Result {
+        assert_eq!(parse(&[])?.write_timeout, None);
+        assert_eq!(
+            parse(&["--write-timeout", "2.5"])?.write_timeout,
+            Some(Duration::from_millis(2500)),
+        );
+        parse(&["--write-timeout", "0"])
+            .expect_err("a write cannot finish in no time");
+        Ok(())
+    }
+
+    #[test]
+    fn when__target_duration_supplied__then__it_needs_an_expected_size() -> Result {
+        assert_eq!(parse(&[])?.target_duration, None);
+        assert_eq!(
+            parse(&["-s", "1G", "--target-duration", "1h30m"])?.target_duration,
+            Some(Duration::from_secs(5400)),
+        );
+        assert_eq!(
+            parse(&["-s", "1G", "--target-duration", "90"])?.target_duration,
+            Some(Duration::from_secs(90)),
+        );
+        parse(&["--target-duration", "2h"])
+            .expect_err("pacing needs to know how much is coming");
+        parse(&["-s", "1G", "--target-duration", "2h", "-L", "1M"])
+            .expect_err("pacing sets the limit itself");
+        for invalid in ["", "0s", "2x", "h", "1.2.3m"] {
+            parse(&["-s", "1G", "--target-duration", invalid])
+                .expect_err(invalid);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn when__a11y_supplied__then__announcements_are_spaced_by_the_interval() -> Result {
+        assert_eq!(parse(&[])?.a11y, None);
+        assert_eq!(parse(&["--a11y"])?.a11y, Some(Duration::from_secs(10)));
+        assert_eq!(
+            parse(&["--a11y", "--a11y-interval", "2.5"])?.a11y,
+            Some(Duration::from_millis(2500)),
+        );
+        parse(&["--a11y-interval", "5"])
+            .expect_err("interval needs --a11y");
+        parse(&["--a11y", "--mouse"])
+            .expect_err("mouse needs the full-screen interface");
+        Ok(())
+    }
+
+    #[test]
+    fn when__number_format_supplied__then__it_is_used() -> Result {
+        assert_eq!(parse(&[])?.number_format, NumberFormat::Auto);
+        let Invocation { number_format, .. } = parse(&["--number-format", "si"])?;
+        assert_eq!(number_format, NumberFormat::Si);
+        let Invocation { number_format, .. } = parse(&["--plain-numbers"])?;
+        assert_eq!(number_format, NumberFormat::Raw);
+        Ok(())
+    }
+
+    #[test]
+    fn when__show_all_units_supplied__then__flag_is_set() -> Result {
+        assert!(!parse(&[])?.show_all_units);
+        assert!(parse(&["-l", "--show-all-units"])?.show_all_units);
+        Ok(())
+    }
+
+    #[test]
+    fn when__net_bytes_supplied__then__header_length_defaults_to_none() -> Result {
+        assert_eq!(parse(&[])?.net_bytes, None);
+        assert_eq!(parse(&["-l", "--net-bytes"])?.net_bytes, Some(0));
+        assert_eq!(parse(&["-l", "--net-bytes=4"])?.net_bytes, Some(4));
+        assert!(parse(&["--net-bytes=x"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn when__keys_supplied__then__flag_is_set() -> Result {
+        assert!(!parse(&[])?.keys);
+        assert!(parse(&["--keys", "--no-altscreen"])?.keys);
+        Ok(())
+    }
+
+    #[test]
+    fn when__control_supplied__then__socket_path_is_set() -> Result {
+        let Invocation { headless, control, progress_fd, .. } = parse(&[
+            "--headless",
+            "--control", "/run/pvalve.sock",
+            "--progress-fd", "3",
+        ])?;
+        assert!(headless);
+        assert_eq!(control, Some(PathBuf::from("/run/pvalve.sock")));
+        assert_eq!(progress_fd, Some(3));
+        assert!(parse(&["--control", "/run/pvalve.sock"])?.control.is_some());
+        parse(&["--headless", "--no-altscreen"])
+            .expect_err("headless mode draws nothing");
+        Ok(())
+    }
+
+    #[test]
+    fn when__progress_fd_is_a_standard_stream__then__parse_fails() {
+        for fd in ["-1", "0", "1", "2"] {
+            parse(&["--progress-fd", fd])
+                .expect_err("progress would mix with the transfer");
+        }
+    }
+
+    #[test]
+    fn when__idle_io_supplied__then__flag_is_set() -> Result {
+        assert!(!parse(&[])?.idle_io);
+        assert!(parse(&["--idle-io"])?.idle_io);
+        Ok(())
+    }
+
+    #[test]
+    fn when__lightweight_supplied__then__only_bytes_may_be_measured() -> Result {
+        assert!(!parse(&[])?.lightweight);
+        assert!(parse(&["--lightweight"])?.lightweight);
+        parse(&["--lightweight", "-l"])
+            .expect_err("parse should have failed");
+        Ok(())
+    }
+
+    #[test]
+    fn when__dry_run_supplied__then__flag_is_set() -> Result {
+        assert!(!parse(&[])?.dry_run);
+        assert!(parse(&["--dry-run"])?.dry_run);
+        Ok(())
+    }
+
+    #[test]
+    fn when__colored_gauge_supplied__then__flag_is_set() -> Result {
+        assert!(!parse(&[])?.colored_gauge);
+        assert!(parse(&["--colored-gauge"])?.colored_gauge);
+        Ok(())
+    }
+
+    #[test]
+    fn when__rate_gauge_max_supplied__then__scale_is_set() -> Result {
+        assert_eq!(parse(&[])?.rate_gauge_max, None);
+        assert_eq!(parse(&["--rate-gauge-max", "limit"])?.rate_gauge_max, Some(RateScale::Limit));
+        assert_eq!(
+            parse(&["--rate-gauge-max", "10M"])?.rate_gauge_max,
+            Some(RateScale::Fixed(nonzero_ext::nonzero!(10_000_000u64))),
+        );
+        parse(&["--rate-gauge-max", "0"]).expect_err("parse should have failed");
+        Ok(())
+    }
+
+    #[test]
+    fn when__format_supplied__then__template_is_named() -> Result {
+        assert_eq!(parse(&[])?.progress_format, None);
+        let Invocation { progress_format, name, .. } = parse(&["-F", "%N %b", "-N", "backup"])?;
+        let expected = "%N %b".parse::<ProgressTemplate>()?.with_name(Some("backup".into()));
+        assert_eq!(progress_format, Some(expected));
+        assert_eq!(name.as_deref(), Some("backup"));
+        assert!(parse(&["--format", "%q"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn when__min_rate_supplied__then__threshold_and_bell_are_set() -> Result {
+        assert_eq!(parse(&[])?.min_rate, None);
+        let Invocation { min_rate, .. } =
+            parse(&["--min-rate", "1M/l:10", "--min-rate-bell"])?;
+        assert_eq!(min_rate, Some(MinRate {
+            threshold: nonzero_ext::nonzero!(1_000_000u64),
+            unit: Some(Unit::Line),
+            period: Duration::from_secs(10),
+            bell: true,
+        }));
+        parse(&["--min-rate-bell"])
+            .expect_err("bell should require a minimum rate");
+        parse(&["--min-rate", "0"])
+            .expect_err("zero minimum rate should be rejected");
+        Ok(())
+    }
+
+    #[test]
+    fn when__hooks_supplied__then__each_is_set() -> Result {
+        assert!(parse(&[])?.hooks.is_empty());
+        let Invocation { hooks, .. } = parse(&[
+            "--on-complete", "echo done",
+            "--on-stall", "echo stuck",
+        ])?;
+        assert_eq!(hooks, Hooks {
+            on_complete: Some("echo done".into()),
+            on_abort: None,
+            on_stall: Some("echo stuck".into()),
+        });
+        Ok(())
+    }
+
+    #[test]
+    fn when__state_file_supplied__then__path_is_set() -> Result {
+        assert_eq!(parse(&[])?.state_file, None);
+        let Invocation { state_file, .. } =
+            parse(&["--state-file", "copy.state"])?;
+        assert_eq!(state_file, Some("copy.state".into()));
+        Ok(())
+    }
+
+    #[test]
+    fn when__files_supplied__then__they_are_read_instead_of_stdin() -> Result {
+        assert!(parse(&[])?.inputs.is_empty());
+        let Invocation { inputs, .. } = parse(&["-l", "a.log", "b.log"])?;
+        assert_eq!(inputs, [PathBuf::from("a.log"), PathBuf::from("b.log")]);
+        let Invocation { inputs, .. } = parse(&["copy", "-L", "1M", "a.log"])?;
+        assert_eq!(inputs, [PathBuf::from("a.log")]);
+        assert!(matches!(
+            command(&["watch", "a.log"])?,
+            Command::Watch(Invocation { inputs, .. }) if inputs.len() == 1,
+        ));
+        parse(&["--range", "1-2", "a.log"])
+            .expect_err("ranges only apply to stdin");
+        Ok(())
+    }
+
+    #[cfg(feature = "archive")]
+    #[test]
+    fn when__tar_supplied__then__directory_is_archived() -> Result {
+        assert_eq!(parse(&[])?.tar, None);
+        let Invocation { tar, .. } = parse(&["--tar", "photos"])?;
+        assert_eq!(tar, Some("photos".into()));
+        parse(&["--tar", "photos", "a.log"])
+            .expect_err("a directory is read instead of files");
+        Ok(())
+    }
+
+    #[test]
+    fn when__range_supplied__then__bounds_are_set() -> Result {
+        let Invocation { range, .. } = parse(&["--range", "1Gi-2Gi"])?;
+        assert_eq!(range.and_then(|r| r.len()), Some(1 << 30));
+        let Invocation { range, .. } = parse(&["--range", "-4k"])?;
+        assert_eq!(range.and_then(|r| r.len()), Some(4000));
+        parse(&["--range", "2-1"])
+            .expect_err("backwards range should be rejected");
+        parse(&["--range", "1-2", "--state-file", "copy.state"])
+            .expect_err("ranges cannot be resumed");
+        Ok(())
+    }
+
+    #[test]
+    fn when__outputs_supplied__then__each_is_kept_in_order() -> Result {
+        assert!(parse(&[])?.outputs.is_empty());
+        let Invocation { outputs, .. } = parse(&[
+            "--out", "a.log:1M",
+            "--out", "b.log:10k:lossy",
+        ])?;
+        let paths: Vec<_> = outputs.iter().map(|o| o.path.clone()).collect();
+        assert_eq!(paths, vec![PathBuf::from("a.log"), PathBuf::from("b.log")]);
+        assert!(!outputs[0].lossy && outputs[1].lossy);
+        Ok(())
+    }
+
+    #[test]
+    fn when__sample_supplied__then__rate_is_set() -> Result {
+        let Invocation { sample, .. } = parse(&["-l", "--sample", "1/100"])?;
+        assert_eq!(sample.map(|s| s.every.get()), Some(100));
+        parse(&["--sample", "3/2"])
+            .expect_err("more than every record should be rejected");
+        Ok(())
+    }
+
+    #[test]
+    fn when__filter_supplied__then__limit_applies_to_the_chosen_side() -> Result {
+        let Invocation { filter, limit_side, .. } = parse(&["--filter", "gzip -9"])?;
+        assert_eq!(filter.as_deref(), Some("gzip -9"));
+        assert_eq!(limit_side, LimitSide::In);
+        let Invocation { limit_side, .. } =
+            parse(&["--filter", "gzip", "--limit-side", "out"])?;
+        assert_eq!(limit_side, LimitSide::Out);
+        parse(&["--limit-side", "out"])
+            .expect_err("a limit side without a filter should be rejected");
+        parse(&["--filter", "gzip", "--sample", "1/10", "-l"])
+            .expect_err("sampling shares the forwarded counter with the filter");
+        Ok(())
+    }
+
+    #[test]
+    fn when__window_supplied__then__limits_follow_the_time_of_day() -> Result {
+        assert_eq!(parse(&[])?.windows, None);
+        let Invocation { windows, .. } =
+            parse(&["--window", "22:00-06:00=unlimited,06:00-22:00=500k"])?;
+        let windows = windows.expect("windows");
+        assert_eq!(windows.0.len(), 2);
+        assert_eq!(windows.0[0].limit, None);
+        parse(&["--window", "22:00-06:00"])
+            .expect_err("a window without a limit should be rejected");
+        parse(&["watch", "--window", "22:00-06:00=1k"])
+            .expect_err("watch should not accept windows");
+        Ok(())
+    }
+
+    #[test]
+    fn when__gate_cmd_supplied__then__it_runs_every_interval() -> Result {
+        let Invocation { gate, gate_interval, .. } = parse(&["--gate-cmd", "check_quota.sh"])?;
+        assert_eq!(gate.as_deref(), Some("check_quota.sh"));
+        assert_eq!(gate_interval, Duration::from_secs(30));
+        let invo = parse(&["--gate-cmd", "true", "--gate-interval", "5m"])?;
+        assert_eq!(invo.gate_interval, Duration::from_secs(300));
+        parse(&["--gate-interval", "5m"])
+            .expect_err("an interval without a command should be rejected");
+        Ok(())
+    }
+
+    #[test]
+    fn when__min_free_supplied__then__size_is_set() -> Result {
+        assert_eq!(parse(&[])?.min_free, None);
+        let Invocation { min_free, .. } = parse(&["--min-free", "5Gi"])?;
+        assert_eq!(min_free.map(NonZeroU64::get), Some(5 << 30));
+        parse(&["--min-free", "0"])
+            .expect_err("nothing free should be rejected");
+        Ok(())
+    }
+
+    #[test]
+    fn when__number_lines_supplied__then__records_are_numbered() -> Result {
+        let Invocation { number_lines, timestamp_records, .. } = parse(&["-l", "--number-lines"])?;
+        assert!(number_lines);
+        assert_eq!(timestamp_records, None);
+        assert!(!parse(&["-l", "--number-lines"])?.evented());
+        Ok(())
+    }
+
+    #[test]
+    fn when__timestamp_records_supplied__then__format_is_set() -> Result {
+        let Invocation { number_lines, timestamp_records, .. } =
+            parse(&["-l", "--timestamp-records", "epoch-ms"])?;
+        assert!(!number_lines);
+        assert_eq!(timestamp_records, Some(TimestampFormat::EpochMs));
+        parse(&["--timestamp-records", "rfc2822"])
+            .expect_err("unknown timestamp formats should be rejected");
+        Ok(())
+    }
+
+    #[test]
+    fn when__audit_pacing_supplied__then__log_path_is_set() -> Result {
+        let Invocation { audit_pacing, .. } =
+            parse(&["--audit-pacing", "pacing.log"])?;
+        assert_eq!(audit_pacing, Some("pacing.log".into()));
+        Ok(())
+    }
+
+    #[test]
+    fn when__flush_every_record_supplied__then__flag_is_set() -> Result {
+        let Invocation { flush_every_record, .. } = parse(&[])?;
+        assert!(!flush_every_record);
+        let Invocation { flush_every_record, .. } =
+            parse(&["--flush-every-record"])?;
+        assert!(flush_every_record);
+        Ok(())
+    }
+
+    #[test]
+    fn when__record_atomic_supplied__then__flag_is_set() -> Result {
+        assert!(!parse(&[])?.record_atomic);
+        assert!(parse(&["-l", "--record-atomic"])?.record_atomic);
+        Ok(())
+    }
+
+    #[test]
+    fn when__output_buffering_supplied__then__supplied_mode_is_used() -> Result {
+        let Invocation { output_buffering, .. } = parse(&[])?;
+        assert_eq!(output_buffering, Buffering::Line);
+        let Invocation { output_buffering, .. } =
+            parse(&["--output-buffering", "none"])?;
+        assert_eq!(output_buffering, Buffering::None);
+        let Invocation { output_buffering, .. } =
+            parse(&["--output-buffering", "block"])?;
+        assert_eq!(output_buffering, Buffering::Block(Buffering::DEFAULT_BLOCK_SIZE));
+        let Invocation { output_buffering, .. } =
+            parse(&["--output-buffering", "block:65536"])?;
+        assert_eq!(
+            output_buffering,
+            Buffering::Block(nonzero_ext::nonzero!(65536usize)),
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn when__invalid_output_buffering_supplied__then__parse_fails() -> Result {
+        for mode in ["", "full", "block:0", "block:x", "line:10"] {
+            parse(&["--output-buffering", mode])
+                .expect_err("parse should have failed");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn when__rate_window_supplied__then__fractional_seconds_are_accepted() -> Result {
+        let Invocation { rate_window, rate_smoothing, .. } = parse(&[])?;
+        assert_eq!(rate_window, Duration::from_secs(1));
+        assert_eq!(rate_smoothing, Smoothing::Mean);
+        let Invocation { rate_window, rate_smoothing, .. } = parse(&[
+            "--rate-window", "0.25",
+            "--rate-smoothing", "ewma",
+        ])?;
+        assert_eq!(rate_window, Duration::from_millis(250));
+        assert_eq!(rate_smoothing, Smoothing::Exponential);
+        Ok(())
+    }
+
+    #[test]
+    fn when__refresh_rate_supplied__then__positive_milliseconds_are_accepted() -> Result {
+        assert_eq!(parse(&[])?.refresh_rate, Duration::from_secs(1));
+        let Invocation { refresh_rate, .. } = parse(&["--refresh-rate", "250"])?;
+        assert_eq!(refresh_rate, Duration::from_millis(250));
+        for refresh in ["0", "-1", "0.5", "x"] {
+            parse(&["--refresh-rate", refresh])
+                .expect_err("parse should have failed");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn when__non_positive_rate_window_supplied__then__parse_fails() -> Result {
+        for window in ["0", "-1", "nan", "x"] {
+            parse(&["--rate-window", window])
+                .expect_err("parse should have failed");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn when__buffer_size_supplied__then__input_is_read_ahead() -> Result {
+        let Invocation { buffer_size, buffer_watermarks, .. } = parse(&[])?;
+        assert_eq!(buffer_size, None);
+        assert_eq!(buffer_watermarks, Watermarks::DEFAULT);
+        let invo = parse(&["--buffer-size", "64Mi", "--buffer-watermarks", "25:75"])?;
+        assert_eq!(invo.buffer_size, NonZeroUsize::new(64 << 20));
+        assert_eq!(invo.buffer_watermarks, Watermarks { low: 25, high: 75 });
+        assert!(!invo.evented());
+        parse(&["--buffer-watermarks", "25:75"])
+            .expect_err("watermarks without a buffer should be rejected");
+        parse(&["--buffer-size", "1Mi", "--buffer-watermarks", "75:25"])
+            .expect_err("a low watermark above the high one should be rejected");
+        Ok(())
+    }
+
+    #[test]
+    fn when__max_chunk_supplied__then__it_bounds_the_chunk_size() -> Result {
+        assert_eq!(parse(&[])?.max_chunk, None);
+        assert_eq!(parse(&["--max-chunk", "512"])?.max_chunk, NonZeroUsize::new(512));
+        parse(&["--max-chunk", "0"]).expect_err("parse should have failed");
+        Ok(())
+    }
+
     #[test]
     fn when__no_expected_size_supplied__then__none_is_used() -> Result {
         let Invocation { expected_size, .. } = parse(&[])?;
@@ -152,6 +1847,15 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn when__sizes_have_suffixes__then__they_are_expanded() -> Result {
+        let Invocation { expected_size, speed, .. } =
+            parse(&["-s", "700M", "-L", "1.5Ki"])?;
+        assert_eq!(expected_size, Some(nonzero_ext::nonzero!(700_000_000usize)));
+        assert_eq!(speed.map(|s| s.per_second.get()), Some(1536));
+        Ok(())
+    }
+
     #[test]
     fn when__zero_expected_size_supplied__then__parse_fails() -> Result {
         parse(&["-s", "0"])
@@ -0,0 +1,63 @@
+use std::io::{
+    Result,
+    Write,
+};
+
+const ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Put `text` on the system clipboard by asking the terminal `out` leads to
+/// to do it, with an OSC 52 escape sequence.
+///
+/// Going through the terminal rather than a display server means it also
+/// works over ssh and inside multiplexers which pass the sequence on, but
+/// quietly does nothing in terminals which ignore it.
+pub fn copy(out: &mut impl Write, text: &str) -> Result<()> {
+    out.write_all(osc52(text).as_bytes())?;
+    out.flush()
+}
+
+/// Escape sequence setting the clipboard to `text`.
+pub fn osc52(text: &str) -> String {
+    format!("\x1b]52;c;{}\x07", base64(text.as_bytes()))
+}
+
+/// Standard, padded base64 of `bytes`.
+fn base64(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter()
+            .enumerate()
+            .fold(0u32, |group, (i, &b)| group | u32::from(b) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                let sextet = (group >> (18 - 6 * i)) & 0x3f;
+                encoded.push(ALPHABET[sextet as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64_matches_the_standard_alphabet_and_padding() {
+        let encoded = ["", "f", "fo", "foo", "foob", "fooba", "foobar"]
+            .map(|text| base64(text.as_bytes()));
+        assert_eq!(encoded, ["", "Zg==", "Zm8=", "Zm9v", "Zm9vYg==", "Zm9vYmE=", "Zm9vYmFy"]);
+        assert_eq!(base64(&[0xff, 0xfe]), "//4=");
+    }
+
+    #[test]
+    fn copying_writes_one_osc_52_sequence() {
+        let mut out = vec![];
+        copy(&mut out, "1.00MiB").unwrap();
+        assert_eq!(out, b"\x1b]52;c;MS4wME1pQg==\x07");
+    }
+
+}
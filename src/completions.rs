@@ -0,0 +1,66 @@
+use std::io::{
+    Result,
+    Write,
+};
+
+use clap::{
+    Command,
+    ValueEnum,
+};
+
+/// Shells which completion scripts can be generated for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// Write a completion script for `cmd` to `out`.
+///
+/// The script is written all at once, so that a closed `out` is an error
+/// rather than a panic part way through.
+pub fn generate(shell: Shell, cmd: &mut Command, out: &mut impl Write) -> Result<()> {
+    let generator = match shell {
+        Shell::Bash => clap_complete::Shell::Bash,
+        Shell::Zsh => clap_complete::Shell::Zsh,
+        Shell::Fish => clap_complete::Shell::Fish,
+    };
+    let name = cmd.get_name().to_owned();
+    let mut script = vec![];
+    clap_complete::generate(generator, cmd, name, &mut script);
+    out.write_all(&script)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+    use crate::cli::Opts;
+
+    fn script(shell: Shell) -> String {
+        let mut out = vec![];
+        generate(shell, &mut Opts::command(), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn unit_values_are_offered_in_every_shell() {
+        assert!(script(Shell::Bash).contains("COMPREPLY=($(compgen -W \"b l 0 w o\""));
+        assert!(script(Shell::Zsh).contains("'--unit=[Unit measurements apply to"));
+        assert!(script(Shell::Zsh).contains(":UNIT:(b l 0 w o)'"));
+        assert!(script(Shell::Fish).contains("-s u -l unit"));
+    }
+
+    #[test]
+    fn subcommands_and_file_values_are_offered() {
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish] {
+            let script = script(shell);
+            for sub in ["copy", "watch", "audit", "completions"] {
+                assert!(script.contains(sub), "{:?} lacks {}", shell, sub);
+            }
+        }
+        assert!(script(Shell::Zsh).contains(":FILE:_files' \\\n"));
+    }
+
+}
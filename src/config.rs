@@ -1,102 +1,168 @@
-use std::num::{NonZeroU32, NonZeroUsize};
-
-use nonzero_ext::nonzero;
+use std::{
+    num::{NonZeroU64, NonZeroUsize},
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
 
-use watch::{
-    WatchReceiver,
-    WatchSender,
+use super::state::{
+    StateWatcher,
+    StateCell,
     channel,
 };
 
-use super::unit::Unit;
+use super::{
+    alarm::MinRate,
+    format::NumberFormat,
+    status_line::RateScale,
+    unit::Unit,
+};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+/// A limit which can be switched off and on again, remembering its value.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct SpeedLimit {
-    limit: NonZeroU32,
+    /// `None` until a limit has been configured.
+    limit: Option<NonZeroU64>,
     enabled: bool,
 }
 
 #[derive(Default, Debug, Clone, Copy)]
 pub struct Config {
     pub limit: SpeedLimit,
+    /// Unit the limit is charged in.
     pub unit: Unit,
+    /// Unit amounts and rates are shown in, when not the limiting unit.
+    pub display_unit: Option<Unit>,
     pub expected_size: Option<NonZeroUsize>,
+    pub flush_every_record: bool,
+    pub record_atomic: bool,
+    pub number_format: NumberFormat,
+    pub show_all_units: bool,
+    pub colored_gauge: bool,
+    /// What the gauge next to the rate is full at, if there is one.
+    pub rate_gauge: Option<RateScale>,
+    pub min_rate: Option<MinRate>,
 }
 
+/// Receiving end of configuration updates.
+///
+/// The most recently received [`Config`] is cached so that reading it on the
+/// write path does not touch the channel; only [`ConfigMonitor::refresh`]
+/// checks for updates. A [`ConfigMonitor::fixed`] monitor has no channel
+/// and never sees any.
 #[derive(Clone)]
-pub struct ConfigMonitor(WatchReceiver<Config>);
+pub struct ConfigMonitor {
+    rx: Option<StateWatcher<Config>>,
+    cached: Config,
+}
 
 #[derive(Clone)]
 pub struct Latch {
     active: bool,
-    tx: WatchSender<bool>,
+    tx: StateCell<bool>,
+    notifiers: Notifiers,
 }
 #[derive(Clone)]
-pub struct LatchMonitor(WatchReceiver<bool>);
-
-impl Default for SpeedLimit {
-    fn default() -> Self {
-        Self {
-            limit: nonzero!(1u32),
-            enabled: false,
-        }
-    }
+pub struct LatchMonitor {
+    rx: StateWatcher<bool>,
+    notifiers: Notifiers,
 }
 
+/// Callbacks run whenever a latch is set, registered through
+/// [`LatchMonitor::notify`].
+#[derive(Clone, Default)]
+struct Notifiers(Arc<Mutex<Vec<Notifier>>>);
+
+type Notifier = Box<dyn Fn(bool) + Send>;
+
 impl SpeedLimit {
-    fn limit(&self) -> Option<NonZeroU32> {
-        if self.enabled {
-            Some(self.limit)
-        } else {
-            None
-        }
+    fn limit(&self) -> Option<NonZeroU64> {
+        self.limit.filter(|_| self.enabled)
     }
-    fn toggle(&mut self) -> bool {
+    /// Switch the limit off, or back on, starting from `observed` if no
+    /// limit was ever configured. Stays off if neither is known.
+    fn toggle(&mut self, observed: Option<NonZeroU64>) -> bool {
         let enabled = self.enabled;
-        self.enabled = !enabled;
+        self.limit = self.limit.or(observed);
+        self.enabled = !enabled && self.limit.is_some();
         enabled
     }
 }
 
-impl From<Option<NonZeroU32>> for SpeedLimit {
-    fn from(limit: Option<NonZeroU32>) -> Self {
-        if let Some(limit) = limit {
-            Self {
-                limit,
-                enabled: true,
-            }
-        } else {
-            Self::default()
+impl From<Option<NonZeroU64>> for SpeedLimit {
+    fn from(limit: Option<NonZeroU64>) -> Self {
+        Self {
+            limit,
+            enabled: limit.is_some(),
         }
     }
 }
 
 impl Config {
-    pub fn limit(&self) -> Option<NonZeroU32> {
+    pub fn limit(&self) -> Option<NonZeroU64> {
         self.limit.limit()
     }
-    pub fn toggle_limit(&mut self) -> bool {
-        self.limit.toggle()
+    /// Switch the limit off or on, returning whether it was on; see
+    /// [`SpeedLimit::toggle`] for what `observed` is for.
+    pub fn toggle_limit(&mut self, observed: Option<NonZeroU64>) -> bool {
+        self.limit.toggle(observed)
+    }
+    /// Unit amounts and rates are shown in.
+    pub fn shown_unit(&self) -> Unit {
+        self.display_unit.unwrap_or(self.unit)
+    }
+    /// Show the next unit along, going back to following the limiting unit
+    /// once it comes round to it.
+    pub fn cycle_display_unit(&mut self) {
+        let mut shown = self.shown_unit();
+        shown.cycle();
+        self.display_unit = (shown != self.unit).then_some(shown);
     }
 }
 
 impl ConfigMonitor {
-    pub fn new(config: Config) -> (WatchSender<Config>, Self) {
+    pub fn new(config: Config) -> (StateCell<Config>, Self) {
         let (tx, rx) = channel(config);
-        (tx, Self(rx))
+        (tx, Self { rx: Some(rx), cached: config })
+    }
+    /// A monitor for `config` alone, for embedders whose configuration
+    /// never changes.
+    pub fn fixed(config: Config) -> Self {
+        Self {
+            rx: None,
+            cached: config,
+        }
+    }
+    /// Pick up the latest configuration, returning whether it changed.
+    pub fn refresh(&mut self) -> bool {
+        if let Some(config) = self.rx.as_mut().and_then(StateWatcher::get_if_changed) {
+            self.cached = config;
+            true
+        } else {
+            false
+        }
+    }
+    pub fn config(&self) -> Config {
+        self.cached
+    }
+    /// Pick up the latest configuration, returning the limit only if it or
+    /// the unit it is in changed, so that changes to how the transfer is
+    /// shown leave the limiter alone.
+    pub fn limit_if_new(&mut self) -> Option<NonZeroU64> {
+        let (limit, unit) = (self.limit(), self.unit());
+        if self.refresh() && (self.limit(), self.unit()) != (limit, unit) {
+            self.cached.limit()
+        } else {
+            None
+        }
     }
-    pub fn limit_if_new(&mut self) -> Option<NonZeroU32> {
-        self.0
-            .get_if_new()
-            .and_then(|config| config.limit())
+    pub fn limit(&self) -> Option<NonZeroU64> {
+        self.cached.limit()
     }
-    pub fn limit(&mut self) -> Option<NonZeroU32> {
-        self.0
-            .get()
-            .limit()
+    pub fn unit(&self) -> Unit {
+        self.cached.unit
     }
-    pub fn unit(&mut self) -> Unit {
-        self.0.get().unit
+    pub fn flush_every_record(&self) -> bool {
+        self.cached.flush_every_record
     }
 }
 
@@ -107,6 +173,7 @@ impl Latch {
         Self {
             active,
             tx,
+            notifiers: Notifiers::default(),
         }
     }
     pub fn active(&self) -> bool {
@@ -126,9 +193,13 @@ impl Latch {
     }
     fn tx(&mut self) {
         self.tx.send(self.active);
+        self.notifiers.notify(self.active);
     }
     pub fn watch(&mut self) -> LatchMonitor {
-        LatchMonitor(self.tx.subscribe())
+        LatchMonitor {
+            rx: self.tx.subscribe(),
+            notifiers: self.notifiers.clone(),
+        }
     }
 }
 
@@ -140,6 +211,156 @@ impl Default for Latch {
 
 impl LatchMonitor {
     pub fn active(&mut self) -> bool {
-        self.0.get()
+        self.rx.get()
+    }
+    /// Block until the latch changes or `timeout` passes, returning whether
+    /// it is active.
+    pub fn wait_timeout(&mut self, timeout: Duration) -> bool {
+        match self.rx.wait_changed(timeout) {
+            Some(active) => active,
+            None => self.rx.get(),
+        }
+    }
+    /// Block until the latch is `state` or `timeout` passes, returning
+    /// whether it is. A timeout too long to reach waits for good.
+    pub fn wait_for(&mut self, state: bool, timeout: Duration) -> bool {
+        let deadline = Instant::now().checked_add(timeout);
+        let mut active = self.rx.get();
+        while active != state {
+            active = match deadline {
+                Some(deadline) => {
+                    let left = deadline.saturating_duration_since(Instant::now());
+                    if left.is_zero() {
+                        return false;
+                    }
+                    self.wait_timeout(left)
+                },
+                None => self.rx.wait(),
+            };
+        }
+        true
+    }
+    /// Call `notify` with the state of the latch every time it is set, on
+    /// the thread setting it, for consumers which cannot block on it.
+    ///
+    /// `notify` must not set the latch itself.
+    pub fn notify(&self, notify: impl Fn(bool) + Send + 'static) {
+        self.notifiers.lock().push(Box::new(notify));
+    }
+}
+
+impl Notifiers {
+    fn lock(&self) -> MutexGuard<'_, Vec<Notifier>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+    fn notify(&self, active: bool) {
+        for notify in self.lock().iter() {
+            notify(active);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nonzero_ext::nonzero;
+
+    #[test]
+    fn monitor_only_sees_updates_after_refresh() {
+        let (tx, mut monitor) = ConfigMonitor::new(Config::default());
+        tx.send(Config {
+            unit: Unit::Line,
+            ..Default::default()
+        });
+        assert_eq!(monitor.unit(), Unit::Byte);
+        assert!(monitor.refresh());
+        assert_eq!(monitor.unit(), Unit::Line);
+        assert!(!monitor.refresh());
+        let mut config = monitor.config();
+        config.cycle_display_unit();
+        assert_eq!((config.unit, config.shown_unit()), (Unit::Line, Unit::Null));
+        for _ in 0..4 {
+            config.cycle_display_unit();
+        }
+        assert_eq!((config.display_unit, config.shown_unit()), (None, Unit::Line));
+        let mut fixed = ConfigMonitor::fixed(monitor.config());
+        assert!(!fixed.refresh());
+        assert_eq!(fixed.unit(), Unit::Line);
+    }
+
+    #[test]
+    fn only_limit_and_unit_changes_are_new_limits() {
+        let config = Config {
+            limit: Some(nonzero!(100u64)).into(),
+            ..Default::default()
+        };
+        let (tx, mut monitor) = ConfigMonitor::new(config);
+        tx.send(Config {
+            display_unit: Some(Unit::Line),
+            colored_gauge: true,
+            ..config
+        });
+        assert_eq!(monitor.limit_if_new(), None);
+        assert_eq!(monitor.config().display_unit, Some(Unit::Line));
+        tx.send(Config {
+            unit: Unit::Line,
+            ..config
+        });
+        assert_eq!(monitor.limit_if_new(), Some(nonzero!(100u64)));
+        tx.send(Config {
+            limit: Some(nonzero!(200u64)).into(),
+            unit: Unit::Line,
+            ..config
+        });
+        assert_eq!(monitor.limit_if_new(), Some(nonzero!(200u64)));
+        assert_eq!(monitor.limit_if_new(), None);
+    }
+
+    #[test]
+    fn latch_waits_time_out_unless_it_changes() {
+        let mut latch = Latch::new();
+        let mut monitor = latch.watch();
+        assert!(!monitor.wait_timeout(Duration::from_millis(10)));
+        latch.on();
+        assert!(monitor.wait_timeout(Duration::from_secs(10)));
     }
+
+    #[test]
+    fn latch_waits_for_the_state_asked_for() {
+        let mut latch = Latch::new();
+        let mut monitor = latch.watch();
+        assert!(monitor.wait_for(false, Duration::ZERO));
+        assert!(!monitor.wait_for(true, Duration::from_millis(10)));
+        let mut waiter = latch.watch();
+        let waiting = std::thread::spawn(move || waiter.wait_for(true, Duration::MAX));
+        latch.off();
+        latch.on();
+        assert!(waiting.join().unwrap());
+        assert!(monitor.wait_for(true, Duration::ZERO));
+    }
+
+    #[test]
+    fn latch_notifies_every_time_it_is_set() {
+        let mut latch = Latch::new();
+        let (tx, rx) = std::sync::mpsc::channel();
+        latch.watch().notify(move |active| tx.send(active).unwrap());
+        latch.on();
+        latch.toggle();
+        latch.clone().on();
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), [true, false, true]);
+    }
+
+    #[test]
+    fn toggling_an_unconfigured_limit_starts_from_the_observed_rate() {
+        let mut config = Config::default();
+        config.toggle_limit(None);
+        assert_eq!(config.limit(), None);
+        config.toggle_limit(NonZeroU64::new(5000));
+        assert_eq!(config.limit(), NonZeroU64::new(5000));
+        config.toggle_limit(NonZeroU64::new(7000));
+        assert_eq!(config.limit(), None);
+        config.toggle_limit(NonZeroU64::new(7000));
+        assert_eq!(config.limit(), NonZeroU64::new(5000));
+    }
+
 }
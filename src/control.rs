@@ -0,0 +1,207 @@
+use std::{
+    num::NonZeroU64,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use super::state::StateCell;
+
+use super::{
+    config::{Config, Latch},
+    ipc::Message,
+    progress::TransferProgress,
+};
+
+fn checked_add(value: Option<NonZeroU64>, increment: u64) -> Option<NonZeroU64> {
+    value?.get()
+        .checked_add(increment)
+        .or(Some(0))
+        .map(|n| n - (n % increment))
+        .map(|n| 1.max(n))
+        .and_then(NonZeroU64::new)
+}
+
+fn checked_sub(value: Option<NonZeroU64>, increment: u64) -> Option<NonZeroU64> {
+    value?.get()
+        .checked_sub(increment)
+        .or(Some(0))
+        .map(|n| n - (n % increment))
+        .map(|n| 1.max(n))
+        .and_then(NonZeroU64::new)
+}
+
+/// Sole owner of the configuration and the latches of a running transfer,
+/// changing them only as [`Message`]s ask.
+struct Controller {
+    config: Config,
+    config_tx: StateCell<Config>,
+    paused: Latch,
+    aborted: Latch,
+}
+
+/// Handle on the [`Controller`] which the interfaces, the key listener and
+/// the control socket each hold a clone of.
+///
+/// Messages are applied before [`Controls::send`] returns, so whoever sent
+/// one sees its effect straight away.
+#[derive(Clone)]
+pub struct Controls(Arc<Mutex<Controller>>);
+
+impl Controls {
+    pub fn new(
+        config: Config,
+        config_tx: StateCell<Config>,
+        paused: Latch,
+        aborted: Latch,
+    ) -> Self {
+        Self(Arc::new(Mutex::new(Controller {
+            config,
+            config_tx,
+            paused,
+            aborted,
+        })))
+    }
+    fn controller(&self) -> MutexGuard<'_, Controller> {
+        self.0.lock().expect("controller panicked")
+    }
+    pub fn send(&self, message: Message) {
+        self.controller().apply(message);
+    }
+    pub fn config(&self) -> Config {
+        self.controller().config
+    }
+    pub fn paused(&self) -> bool {
+        self.controller().paused.active()
+    }
+    pub fn aborted(&self) -> bool {
+        self.controller().aborted.active()
+    }
+}
+
+impl Controller {
+    fn apply(&mut self, message: Message) {
+        match message {
+            Message::Pause => self.paused.on(),
+            Message::Resume => self.paused.off(),
+            Message::TogglePause => self.paused.toggle(),
+            Message::Abort => self.aborted.on(),
+            Message::Limit(limit) => self.update(|config| config.limit = limit.into()),
+            Message::ToggleLimit(observed) => {
+                let observed = NonZeroU64::new(self.config.unit.progress(&observed));
+                self.update(|config| {
+                    config.toggle_limit(observed);
+                });
+            },
+            Message::Faster(observed) => {
+                let limit = checked_add(self.adjustable_limit(&observed), 10);
+                self.update(|config| config.limit = limit.into());
+            },
+            Message::Slower(observed) => {
+                let limit = checked_sub(self.adjustable_limit(&observed), 10);
+                self.update(|config| config.limit = limit.into());
+            },
+            Message::RestoreLimit(limit) => self.update(|config| config.limit = limit),
+            // Only an interface knows what it changed, and restores it itself.
+            Message::Undo => {},
+            Message::CycleUnit => self.update(|config| config.unit.cycle()),
+            Message::CycleDisplayUnit => self.update(Config::cycle_display_unit),
+            Message::ToggleAllUnits => self.update(|config| {
+                config.show_all_units = !config.show_all_units;
+            }),
+        }
+    }
+    fn update(&mut self, f: impl FnOnce(&mut Config)) {
+        f(&mut self.config);
+        self.config_tx.send(self.config);
+    }
+    /// The limit to adjust, starting from the observed rate when there is
+    /// none so that stepping the rate also switches the limiter on.
+    fn adjustable_limit(&self, observed: &TransferProgress) -> Option<NonZeroU64> {
+        self.config.limit()
+            .or_else(|| NonZeroU64::new(self.config.unit.progress(observed)))
+            .or(NonZeroU64::new(1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nonzero_ext::nonzero;
+    use crate::{
+        config::{ConfigMonitor, LatchMonitor},
+        unit::Unit,
+    };
+
+    fn controls(limit: Option<NonZeroU64>) -> (Controls, ConfigMonitor, LatchMonitor) {
+        let config = Config {
+            limit: limit.into(),
+            ..Default::default()
+        };
+        let (config_tx, monitor) = ConfigMonitor::new(config);
+        let mut paused = Latch::new();
+        let paused_monitor = paused.watch();
+        (
+            Controls::new(config, config_tx, paused, Latch::new()),
+            monitor,
+            paused_monitor,
+        )
+    }
+
+    #[test]
+    fn messages_drive_the_latches_and_the_config() {
+        let (controls, mut config, mut paused) = controls(Some(nonzero!(100u64)));
+        let observed = TransferProgress::default();
+        controls.send(Message::TogglePause);
+        assert!(paused.active());
+        assert!(controls.paused());
+        controls.send(Message::Faster(observed));
+        config.refresh();
+        assert_eq!(config.limit(), Some(nonzero!(110u64)));
+        controls.send(Message::CycleUnit);
+        config.refresh();
+        assert_eq!(config.unit(), Unit::Line);
+        controls.send(Message::CycleDisplayUnit);
+        assert_eq!(controls.config().shown_unit(), Unit::Null);
+        assert_eq!(controls.config().unit, Unit::Line);
+        let before = controls.config().limit;
+        controls.send(Message::ToggleLimit(observed));
+        assert_eq!(controls.config().limit(), None);
+        controls.send(Message::RestoreLimit(before));
+        assert_eq!(controls.config().limit(), Some(nonzero!(110u64)));
+        controls.send(Message::Abort);
+        assert!(controls.aborted());
+    }
+
+    #[test]
+    fn enabling_an_unconfigured_limit_starts_from_the_observed_rate() {
+        let (controls, mut config, _) = controls(None);
+        let observed = TransferProgress {
+            bytes_transferred: 1234,
+            ..Default::default()
+        };
+        controls.send(Message::ToggleLimit(observed));
+        config.refresh();
+        assert_eq!(config.limit(), Some(nonzero!(1234u64)));
+    }
+
+    #[test]
+    fn every_clone_controls_the_same_transfer() {
+        let (controls, mut config, _) = controls(None);
+        controls.clone().send(Message::Limit(Some(nonzero!(4096u64))));
+        config.refresh();
+        assert_eq!(config.limit(), Some(nonzero!(4096u64)));
+        assert_eq!(controls.config().limit(), Some(nonzero!(4096u64)));
+    }
+
+    #[test]
+    fn stepping_starts_from_the_observed_rate_without_a_limit() {
+        let (controls, mut config, _) = controls(None);
+        let observed = TransferProgress {
+            bytes_transferred: 1234,
+            ..Default::default()
+        };
+        controls.send(Message::Slower(observed));
+        config.refresh();
+        assert_eq!(config.limit(), Some(nonzero!(1220u64)));
+    }
+
+}
@@ -0,0 +1,163 @@
+use std::{
+    fmt,
+    io::{
+        Result,
+        Write,
+    },
+    iter,
+    thread::sleep,
+    time::{
+        Duration,
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+
+use super::syncio::Finish;
+use super::unit::{
+    Scanner,
+    Unit,
+};
+
+/// Artificial pause between records, spread at random by up to `jitter`
+/// either way.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Latency {
+    pub delay: Duration,
+    pub jitter: Duration,
+}
+
+impl Latency {
+    /// The delay with `sample`, uniform over `0..=u64::MAX`, picking how
+    /// much jitter to add or take away.
+    fn at(&self, sample: u64) -> Duration {
+        let jitter = self.jitter.as_secs_f64();
+        let offset = (sample as f64 / u64::MAX as f64 * 2f64 - 1f64) * jitter;
+        Duration::from_secs_f64((self.delay.as_secs_f64() + offset).max(0f64))
+    }
+}
+
+impl fmt::Display for Latency {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "{}ms", self.delay.as_millis())?;
+        if !self.jitter.is_zero() {
+            write!(fmt, "±{}ms", self.jitter.as_millis())?;
+        }
+        Ok(())
+    }
+}
+
+/// Writer which pauses before each record of a unit after the first, to
+/// simulate a slow producer regardless of any limit.
+///
+/// Every unit counts as a record, so with bytes the pause comes between
+/// every byte and with write calls between every write.
+pub struct DelayWriter<W> {
+    inner: W,
+    unit: Unit,
+    latency: Option<Latency>,
+    scanner: Scanner,
+    /// The last byte written ended a record.
+    record_ended: bool,
+    /// State of the xorshift generator behind the jitter.
+    seed: u64,
+}
+
+impl <W> DelayWriter<W> {
+    pub fn new(inner: W, unit: Unit, latency: Option<Latency>) -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_nanos() as u64)
+            .unwrap_or_default();
+        Self {
+            inner,
+            unit,
+            latency,
+            scanner: Scanner::default(),
+            record_ended: false,
+            seed: seed | 1,
+        }
+    }
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+    fn next_sample(&mut self) -> u64 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 7;
+        self.seed ^= self.seed << 17;
+        self.seed
+    }
+}
+
+impl <W: Write> Write for DelayWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let Some(latency) = self.latency else {
+            return self.inner.write(buf);
+        };
+        let ends = self.scanner.annotate(self.unit, buf);
+        self.scanner.advance(buf);
+        let mut start = 0;
+        let parts = ends.into_iter()
+            .map(|end| (end, true))
+            .chain(iter::once((buf.len(), false)));
+        for (end, ends_record) in parts {
+            if end == start {
+                continue;
+            }
+            if self.record_ended {
+                let sample = self.next_sample();
+                sleep(latency.at(sample));
+            }
+            self.inner.write_all(&buf[start..end])?;
+            self.record_ended = ends_record;
+            start = end;
+        }
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl <W: Finish> Finish for DelayWriter<W> {
+    fn finish(&mut self) -> Result<()> {
+        self.inner.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn jitter_stays_within_bounds_and_never_goes_negative() {
+        let latency = Latency {
+            delay: Duration::from_millis(10),
+            jitter: Duration::from_millis(4),
+        };
+        assert_eq!(latency.at(0), Duration::from_millis(6));
+        assert_eq!(latency.at(u64::MAX), Duration::from_millis(14));
+        let wide = Latency {
+            jitter: Duration::from_millis(20),
+            ..latency
+        };
+        assert_eq!(wide.at(0), Duration::ZERO);
+        assert_eq!(latency.to_string(), "10ms±4ms");
+    }
+
+    #[test]
+    fn records_are_passed_on_whole_with_pauses_between_them() {
+        let latency = Latency {
+            delay: Duration::from_millis(5),
+            jitter: Duration::ZERO,
+        };
+        let mut writer = DelayWriter::new(Vec::new(), Unit::Line, Some(latency));
+        let start = Instant::now();
+        writer.write_all(b"a\nb").unwrap();
+        writer.write_all(b"b\nc\n").unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(10));
+        assert_eq!(writer.get_ref(), b"a\nbb\nc\n");
+    }
+
+}
@@ -0,0 +1,438 @@
+use std::{
+    io::{
+        Error,
+        ErrorKind,
+        Read,
+        Result,
+        Write,
+    },
+    num::NonZeroUsize,
+    os::fd::{
+        AsFd,
+        AsRawFd,
+        OwnedFd,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use super::state::{
+    channel,
+    StateCell,
+};
+
+use super::{
+    backpressure::{
+        timed,
+        Backpressure,
+        BackpressureMonitor,
+    },
+    config::LatchMonitor,
+    pump::{
+        ChunkMeta,
+        ChunkWrite,
+        Pump,
+    },
+    sink::ProgressSink,
+    syncio::cancelled_error,
+    throttled::Throttled,
+    unit::Scanner,
+    watchdog::WriteTimedOut,
+};
+
+/// Leaves a descriptor in non-blocking mode until dropped, then puts back
+/// whatever mode it was in.
+///
+/// The mode belongs to the open file, which stdin and stdout share with
+/// the shell and the rest of the pipeline, so it must not outlive the copy.
+struct NonBlocking {
+    fd: OwnedFd,
+    flags: libc::c_int,
+}
+
+impl NonBlocking {
+    fn enable(fd: &OwnedFd) -> Result<Self> {
+        let fd = fd.try_clone()?;
+        // SAFETY: fcntl(2) on a descriptor the guard owns.
+        let flags = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GETFL) };
+        if flags < 0 {
+            return Err(Error::last_os_error());
+        }
+        // SAFETY: as above.
+        if unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(Self { fd, flags })
+    }
+}
+
+impl Drop for NonBlocking {
+    fn drop(&mut self) {
+        // SAFETY: as in `enable`.
+        unsafe { libc::fcntl(self.fd.as_raw_fd(), libc::F_SETFL, self.flags) };
+    }
+}
+
+/// Wait until one of `fds` is ready or `timeout` passes, see poll(2).
+///
+/// The timeout is rounded up to the next millisecond, so that a wait for
+/// less than one does not spin.
+fn poll(fds: &mut [libc::pollfd], timeout: Duration) -> Result<()> {
+    let millis = timeout.as_nanos()
+        .div_ceil(1_000_000)
+        .min(libc::c_int::MAX as u128) as libc::c_int;
+    // SAFETY: `fds` is valid for as many entries as it is told to hold.
+    let result = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, millis) };
+    match result {
+        -1 => match Error::last_os_error() {
+            e if e.kind() == ErrorKind::Interrupted => Ok(()),
+            e => Err(e),
+        },
+        _ => Ok(()),
+    }
+}
+
+fn pollfd(fd: &OwnedFd, events: libc::c_short) -> libc::pollfd {
+    libc::pollfd {
+        fd: fd.as_raw_fd(),
+        events,
+        revents: 0,
+    }
+}
+
+/// What a write turned away with [`ErrorKind::WouldBlock`] is waiting for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Wait {
+    Paused,
+    Limiter(Instant),
+    Output,
+}
+
+/// Copies like a [`Pump`], but with both ends of the pipe in non-blocking
+/// mode, so that every wait happens in one place: waiting for input, for
+/// the output to take more, for the limiter and while paused.
+///
+/// None of those waits is longer than [`Self::TICK`], so cancellation, a
+/// pause or a new limit take effect within one even at very low rates,
+/// where a blocking copy would sit out the wait for its next token first.
+/// A write the output does not take within the write timeout fails the
+/// copy with [`WriteTimedOut`] inside an [`ErrorKind::TimedOut`] error.
+///
+/// Only a [`Throttled`] chain which does nothing but pass each write on
+/// can be driven this way, since writers which keep state of their own
+/// lose track of a write the output turns away partway.
+pub struct EventedPump {
+    input: OwnedFd,
+    output: OwnedFd,
+    chunk_size: NonZeroUsize,
+    cancelled: Option<LatchMonitor>,
+    write_timeout: Option<Duration>,
+    backpressure: Backpressure,
+    backpressure_tx: StateCell<Backpressure>,
+    /// The last write turned away was held back by a pause, which is still
+    /// the same pause until a write goes out.
+    in_pause: bool,
+    bytes_only: bool,
+}
+
+impl EventedPump {
+    /// Longest the copy waits before looking for cancellation, a pause or a
+    /// new limit again.
+    pub const TICK: Duration = Duration::from_millis(50);
+
+    /// Copy between whatever `input` and `output` read from and write to,
+    /// which are only used to wait on.
+    pub fn new(input: &impl AsFd, output: &impl AsFd) -> Result<Self> {
+        let (backpressure_tx, _) = channel(Backpressure::default());
+        Ok(Self {
+            input: input.as_fd().try_clone_to_owned()?,
+            output: output.as_fd().try_clone_to_owned()?,
+            chunk_size: Pump::DEFAULT_CHUNK_SIZE,
+            cancelled: None,
+            write_timeout: None,
+            backpressure: Backpressure::default(),
+            backpressure_tx,
+            in_pause: false,
+            bytes_only: false,
+        })
+    }
+    /// Read at most `chunk_size` bytes at a time.
+    pub fn chunk_size(mut self, chunk_size: NonZeroUsize) -> Self {
+        self.chunk_size = chunk_size;
+        self
+    }
+    /// Count only bytes and writes, as [`Pump::count_bytes_only`] does.
+    pub fn count_bytes_only(mut self, enabled: bool) -> Self {
+        self.bytes_only = enabled;
+        self
+    }
+    /// Stop with an error at the next wait once `cancelled` is active.
+    pub fn cancel_on(mut self, cancelled: LatchMonitor) -> Self {
+        self.cancelled = Some(cancelled);
+        self
+    }
+    /// Fail once the output has taken nothing for `timeout`.
+    pub fn with_write_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.write_timeout = timeout;
+        self
+    }
+    /// Time spent waiting on each part of the pipeline, which the chain
+    /// itself no longer sees.
+    pub fn backpressure(&mut self) -> BackpressureMonitor {
+        BackpressureMonitor::new(self.backpressure_tx.subscribe())
+    }
+
+    /// Copy until `reader` runs out and `writer` has flushed everything,
+    /// returning how many bytes were copied.
+    pub fn copy<R, W, S>(&mut self, reader: &mut R, writer: &mut Throttled<W, S>) -> Result<u64>
+    where
+        R: Read + ?Sized,
+        W: Write,
+        S: ProgressSink,
+    {
+        let _input = NonBlocking::enable(&self.input)?;
+        let _output = NonBlocking::enable(&self.output)?;
+        writer.set_nonblocking(true);
+        let result = self.pump(reader, writer);
+        writer.set_nonblocking(false);
+        result
+    }
+
+    fn pump<R, W, S>(&mut self, reader: &mut R, writer: &mut Throttled<W, S>) -> Result<u64>
+    where
+        R: Read + ?Sized,
+        W: Write,
+        S: ProgressSink,
+    {
+        let mut chunk = vec![0u8; self.chunk_size.get()];
+        let mut scanner = Scanner::default();
+        let mut copied = 0;
+        // When the output last took anything, while it is not taking more.
+        let mut stalled = None;
+        loop {
+            self.check_cancelled()?;
+            let n = match reader.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    let mut fds = [pollfd(&self.input, libc::POLLIN)];
+                    let (result, elapsed) = timed(|| poll(&mut fds, Self::TICK));
+                    self.wait_over(|backpressure| backpressure.reading += elapsed);
+                    result?;
+                    continue;
+                },
+                Err(e) => return Err(e),
+            };
+            let buf = &chunk[..n];
+            let meta = ChunkMeta::scan(&mut scanner, buf, self.bytes_only);
+            let mut written = 0;
+            while written < n {
+                let result = if written == 0 {
+                    writer.write_chunk(buf, &meta)
+                } else {
+                    writer.write(&buf[written..])
+                };
+                match result {
+                    Ok(0) => return Err(Error::new(
+                        ErrorKind::WriteZero,
+                        "failed to write whole buffer",
+                    )),
+                    Ok(k) => {
+                        written += k;
+                        stalled = None;
+                        self.in_pause = false;
+                    },
+                    Err(e) if e.kind() == ErrorKind::Interrupted => {},
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                        let wait = Self::waiting_for(writer);
+                        self.wait(wait, &mut stalled)?;
+                    },
+                    Err(e) => return Err(e),
+                }
+            }
+            copied += n as u64;
+        }
+        loop {
+            match writer.flush() {
+                Ok(()) => return Ok(copied),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {},
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    self.wait(Wait::Output, &mut stalled)?;
+                },
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn waiting_for<W: Write, S: ProgressSink>(writer: &mut Throttled<W, S>) -> Wait {
+        if writer.is_paused() {
+            Wait::Paused
+        } else if let Some(ready_at) = writer.ready_at() {
+            Wait::Limiter(ready_at)
+        } else {
+            Wait::Output
+        }
+    }
+
+    /// Wait for whatever turned a write away, at most for one tick, failing
+    /// if cancelled or if the output has stalled for too long.
+    fn wait(&mut self, wait: Wait, stalled: &mut Option<Instant>) -> Result<()> {
+        self.check_cancelled()?;
+        let now = Instant::now();
+        match wait {
+            Wait::Paused => {
+                *stalled = None;
+                let pause = !std::mem::replace(&mut self.in_pause, true);
+                let (result, elapsed) = timed(|| poll(&mut [], Self::TICK));
+                self.wait_over(|backpressure| {
+                    backpressure.pausing += elapsed;
+                    backpressure.pauses += u64::from(pause);
+                });
+                result
+            },
+            Wait::Limiter(ready_at) => {
+                *stalled = None;
+                self.in_pause = false;
+                let timeout = ready_at.saturating_duration_since(now).min(Self::TICK);
+                let (result, elapsed) = timed(|| poll(&mut [], timeout));
+                self.wait_over(|backpressure| backpressure.limiting += elapsed);
+                result
+            },
+            Wait::Output => {
+                self.in_pause = false;
+                let stall = stalled.is_none();
+                let since = *stalled.get_or_insert(now);
+                let mut timeout = Self::TICK;
+                if let Some(limit) = self.write_timeout {
+                    let left = limit.checked_sub(now.saturating_duration_since(since))
+                        .filter(|left| !left.is_zero())
+                        .ok_or_else(|| Error::new(ErrorKind::TimedOut, WriteTimedOut(limit)))?;
+                    timeout = timeout.min(left);
+                }
+                let mut fds = [pollfd(&self.output, libc::POLLOUT)];
+                let (result, elapsed) = timed(|| poll(&mut fds, timeout));
+                self.wait_over(|backpressure| {
+                    backpressure.writing += elapsed;
+                    backpressure.write_waits += u64::from(stall);
+                });
+                result
+            },
+        }
+    }
+
+    fn wait_over(&mut self, f: impl FnOnce(&mut Backpressure)) {
+        f(&mut self.backpressure);
+        self.backpressure_tx.send(self.backpressure);
+    }
+
+    fn check_cancelled(&mut self) -> Result<()> {
+        if self.cancelled.as_mut().is_some_and(LatchMonitor::active) {
+            return Err(cancelled_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        fs::File,
+        os::fd::FromRawFd as _,
+        thread,
+    };
+    use nonzero_ext::nonzero;
+    use crate::config::{
+        Config,
+        ConfigMonitor,
+        Latch,
+    };
+
+    fn pipe() -> (File, File) {
+        let mut fds = [0; 2];
+        // SAFETY: pipe(2) fills in both descriptors, which are owned from
+        // then on by the files.
+        unsafe {
+            assert_eq!(libc::pipe(fds.as_mut_ptr()), 0);
+            (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1]))
+        }
+    }
+
+    fn throttled(output: File, config: Config) -> Throttled<File> {
+        let (_, config) = ConfigMonitor::new(config);
+        Throttled::new(output, config)
+    }
+
+    #[test]
+    fn copies_everything_through_pipes_and_restores_blocking_mode() {
+        let (input_rx, mut input_tx) = pipe();
+        let (mut output_rx, output_tx) = pipe();
+        let data: Vec<u8> = (0..200_000u32).map(|i| i as u8).collect();
+        let feeder = {
+            let data = data.clone();
+            thread::spawn(move || input_tx.write_all(&data))
+        };
+        let drainer = thread::spawn(move || {
+            let mut drained = vec![];
+            output_rx.read_to_end(&mut drained).map(|_| drained)
+        });
+        let mut pump = EventedPump::new(&input_rx, &output_tx).unwrap();
+        let mut writer = throttled(output_tx, Config::default());
+        let mut progress = writer.sink().transfer_progress();
+        let copied = pump.copy(&mut &input_rx, &mut writer).unwrap();
+        // SAFETY: F_GETFL on a descriptor still owned by the writer.
+        let flags = unsafe { libc::fcntl(writer.get_ref().as_raw_fd(), libc::F_GETFL) };
+        assert_eq!(flags & libc::O_NONBLOCK, 0);
+        drop((pump, writer));
+        feeder.join().unwrap().unwrap();
+        assert_eq!(copied, data.len() as u64);
+        assert_eq!(progress.get().bytes_transferred, data.len() as u64);
+        assert_eq!(drainer.join().unwrap().unwrap(), data);
+    }
+
+    #[test]
+    fn cancellation_interrupts_a_wait_for_the_limiter() {
+        let (input_rx, mut input_tx) = pipe();
+        let (_output_rx, output_tx) = pipe();
+        input_tx.write_all(b"abc").unwrap();
+        drop(input_tx);
+        let mut cancelled = Latch::new();
+        let mut pump = EventedPump::new(&input_rx, &output_tx)
+            .unwrap()
+            .cancel_on(cancelled.watch());
+        let mut writer = throttled(output_tx, Config {
+            limit: Some(nonzero!(1u64)).into(),
+            ..Default::default()
+        });
+        let start = Instant::now();
+        let canceller = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            cancelled.on();
+        });
+        let error = pump.copy(&mut &input_rx, &mut writer).unwrap_err();
+        canceller.join().unwrap();
+        assert_eq!(error.kind(), ErrorKind::BrokenPipe);
+        assert!(start.elapsed() < Duration::from_millis(500));
+    }
+
+    #[test]
+    fn a_stalled_output_times_out() {
+        let (input_rx, mut input_tx) = pipe();
+        let (_output_rx, output_tx) = pipe();
+        let feeder = thread::spawn(move || {
+            let _ = input_tx.write_all(&[0; 1 << 20]);
+        });
+        let mut pump = EventedPump::new(&input_rx, &output_tx)
+            .unwrap()
+            .with_write_timeout(Some(Duration::from_millis(100)));
+        let mut writer = throttled(output_tx, Config::default());
+        let error = pump.copy(&mut &input_rx, &mut writer).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::TimedOut);
+        drop((pump, input_rx));
+        feeder.join().unwrap();
+    }
+
+}
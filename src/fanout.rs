@@ -0,0 +1,542 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{
+        self,
+        Write,
+    },
+    num::NonZeroU64,
+    path::PathBuf,
+    str::FromStr,
+    sync::{
+        mpsc::{
+            sync_channel,
+            Receiver,
+            SyncSender,
+            TrySendError,
+        },
+        atomic::{
+            AtomicBool,
+            Ordering,
+        },
+        Arc,
+    },
+    thread::{
+        self,
+        JoinHandle,
+    },
+    time::Duration,
+};
+
+use thiserror::Error;
+
+use super::state::{
+    channel,
+    StateCell,
+};
+
+use super::{
+    backpressure::LimiterActivity,
+    config::{
+        Config,
+        ConfigMonitor,
+    },
+    instantaneous::{
+        RateEstimator,
+        RateSampler,
+    },
+    progress::{
+        TransferProgress,
+        TransferProgressMonitor,
+    },
+    size,
+    syncio::{
+        Finish,
+        WriteExt as _,
+    },
+    unit::{
+        Scanner,
+        Unit,
+    },
+};
+
+/// An extra destination for the stream, written `PATH[:RATE][:lossy]`.
+///
+/// A lossless output holds the whole transfer back while it catches up; a
+/// lossy one drops whatever it cannot keep up with instead, along with
+/// whatever it still has queued once the transfer finishes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputSpec {
+    pub path: PathBuf,
+    pub limit: Option<NonZeroU64>,
+    pub lossy: bool,
+}
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+#[error("{0:?} is not an output such as out.log, out.log:10M or out.log:1M:lossy")]
+pub struct ParseOutputSpecError(String);
+
+enum Message {
+    Data(Arc<[u8]>),
+    /// Flush, then acknowledge once everything before it was written.
+    Flush(SyncSender<()>),
+}
+
+struct Output {
+    name: String,
+    lossy: bool,
+    tx: SyncSender<Message>,
+    scanner: Scanner,
+    dropped_tx: StateCell<TransferProgress>,
+    /// Set once the transfer finishes, for a lossy output to drop its
+    /// backlog rather than write it out.
+    abandoned: Arc<AtomicBool>,
+}
+
+/// Sending ends of every extra output, to be handed to a [`FanOut`].
+#[derive(Default)]
+pub struct Outputs(Vec<Output>);
+
+/// Threads writing to the extra outputs, which finish once the [`FanOut`]
+/// feeding them is dropped.
+#[derive(Default)]
+pub struct OutputThreads(Vec<(String, JoinHandle<io::Result<()>>)>);
+
+/// What the interface shows about one extra output.
+pub struct OutputMonitor {
+    pub name: String,
+    pub limit: Option<NonZeroU64>,
+    pub lossy: bool,
+    pub written: TransferProgressMonitor,
+    pub rate: RateSampler,
+    pub dropped: TransferProgressMonitor,
+    pub limiter: LimiterActivity,
+}
+
+/// Writer which passes everything to `primary` and a copy of what it
+/// accepted to each of the [`Outputs`].
+pub struct FanOut<W> {
+    primary: W,
+    outputs: Outputs,
+}
+
+impl OutputSpec {
+    const LOSSY: &'static str = "lossy";
+}
+
+impl FromStr for OutputSpec {
+    type Err = ParseOutputSpecError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut path = s;
+        let mut lossy = false;
+        if let Some((rest, Self::LOSSY)) = path.rsplit_once(':') {
+            path = rest;
+            lossy = true;
+        }
+        let mut limit = None;
+        if let Some((rest, rate)) = path.rsplit_once(':') {
+            if let Ok(rate) = size::parse_nonzero(rate) {
+                path = rest;
+                limit = Some(rate);
+            }
+        }
+        if path.is_empty() {
+            return Err(ParseOutputSpecError(s.to_owned()));
+        }
+        Ok(Self {
+            path: path.into(),
+            limit,
+            lossy,
+        })
+    }
+}
+
+impl fmt::Display for OutputSpec {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "{}", self.path.display())?;
+        if let Some(limit) = self.limit {
+            write!(fmt, ":{}", limit)?;
+        }
+        if self.lossy {
+            write!(fmt, ":{}", Self::LOSSY)?;
+        }
+        Ok(())
+    }
+}
+
+impl Outputs {
+    /// Chunks each output may fall behind by before it holds the transfer
+    /// back, or drops data if lossy.
+    const DEPTH: usize = 16;
+
+    /// Create every output in `specs` and start writing to each of them, at
+    /// its own rate in `unit`s per second.
+    pub fn open(
+        specs: &[OutputSpec],
+        unit: Unit,
+    ) -> io::Result<(Self, OutputThreads, Vec<OutputMonitor>)> {
+        let mut outputs = Self::default();
+        let mut threads = OutputThreads::default();
+        let mut monitors = vec![];
+        for spec in specs {
+            let file = File::create(&spec.path)?;
+            let (_, config) = ConfigMonitor::new(Config {
+                limit: spec.limit.into(),
+                unit,
+                ..Default::default()
+            });
+            let name = spec.path.display().to_string();
+            let (tx, rx) = sync_channel(Self::DEPTH);
+            let (written_tx, _) = channel(TransferProgress::default());
+            let (dropped_tx, _) = channel(TransferProgress::default());
+            let mut writer = file.limited(config);
+            monitors.push(OutputMonitor {
+                name: name.clone(),
+                limit: spec.limit,
+                lossy: spec.lossy,
+                written: TransferProgressMonitor::new(written_tx.subscribe()),
+                rate: RateSampler::new(
+                    TransferProgressMonitor::new(written_tx.subscribe()),
+                    RateEstimator::new(Duration::from_secs(1)),
+                ),
+                dropped: TransferProgressMonitor::new(dropped_tx.subscribe()),
+                limiter: LimiterActivity::new(writer.backpressure(), Duration::from_secs(1)),
+            });
+            let abandoned = Arc::new(AtomicBool::new(false));
+            let drained = Drained {
+                written: written_tx,
+                dropped: dropped_tx.clone(),
+                abandoned: abandoned.clone(),
+            };
+            threads.0.push((
+                name.clone(),
+                thread::spawn(move || drain(rx, writer, drained)),
+            ));
+            outputs.0.push(Output {
+                name,
+                lossy: spec.lossy,
+                tx,
+                scanner: Scanner::default(),
+                dropped_tx,
+                abandoned,
+            });
+        }
+        Ok((outputs, threads, monitors))
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Where an output thread reports what became of the chunks it was sent.
+struct Drained {
+    written: StateCell<TransferProgress>,
+    dropped: StateCell<TransferProgress>,
+    abandoned: Arc<AtomicBool>,
+}
+
+fn drain(
+    rx: Receiver<Message>,
+    mut writer: impl Write,
+    drained: Drained,
+) -> io::Result<()> {
+    let mut scanner = Scanner::default();
+    let mut written = TransferProgress::default();
+    for message in rx {
+        match message {
+            Message::Data(chunk) => {
+                // Written a grant at a time, to give up partway once abandoned.
+                let mut rest = &chunk[..];
+                while !rest.is_empty() && !drained.abandoned.load(Ordering::Relaxed) {
+                    let n = match writer.write(rest) {
+                        Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                        Ok(n) => n,
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                        Err(e) => return Err(e),
+                    };
+                    written = written + TransferProgress::scan(&mut scanner, &rest[..n]);
+                    drained.written.send(written);
+                    rest = &rest[n..];
+                }
+                if !rest.is_empty() {
+                    let dropped = TransferProgress::scan(&mut scanner, rest);
+                    drained.dropped.update(|total| *total = *total + dropped);
+                }
+            },
+            Message::Flush(ack) => {
+                writer.flush()?;
+                let _ = ack.send(());
+            },
+        }
+    }
+    writer.flush()
+}
+
+fn stopped(name: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::BrokenPipe,
+        format!("output {} stopped", name),
+    )
+}
+
+impl Output {
+    fn send(&mut self, chunk: &Arc<[u8]>) -> io::Result<()> {
+        let message = Message::Data(chunk.clone());
+        let result = if self.lossy {
+            match self.tx.try_send(message) {
+                Err(TrySendError::Full(_)) => {
+                    let dropped = TransferProgress::scan(&mut self.scanner, chunk);
+                    self.dropped_tx.update(|total| *total = *total + dropped);
+                    return Ok(());
+                },
+                Err(TrySendError::Disconnected(_)) => Err(()),
+                Ok(()) => Ok(()),
+            }
+        } else {
+            self.tx.send(message).map_err(|_| ())
+        };
+        result.map_err(|_| stopped(&self.name))
+    }
+}
+
+impl OutputThreads {
+    /// Wait for every output to finish, returning the first failure.
+    pub fn join(self) -> io::Result<()> {
+        let mut result = Ok(());
+        for (name, thread) in self.0 {
+            let outcome = thread.join()
+                .unwrap_or_else(|_| Err(stopped(&name)))
+                .map_err(|e| io::Error::new(e.kind(), format!("output {}: {}", name, e)));
+            result = result.and(outcome);
+        }
+        result
+    }
+}
+
+impl <W> FanOut<W> {
+    pub fn new(primary: W, outputs: Outputs) -> Self {
+        Self {
+            primary,
+            outputs,
+        }
+    }
+    pub fn get_ref(&self) -> &W {
+        &self.primary
+    }
+    /// Wait for every lossless output to write out what it was sent so far.
+    ///
+    /// Lossy outputs are only asked to flush if they have room for it, and
+    /// never waited for.
+    fn flush_outputs(&self) -> io::Result<()> {
+        let mut acks = vec![];
+        for output in &self.outputs.0 {
+            let (ack_tx, ack_rx) = sync_channel(1);
+            let message = Message::Flush(ack_tx);
+            if output.lossy {
+                match output.tx.try_send(message) {
+                    Err(TrySendError::Disconnected(_)) => return Err(stopped(&output.name)),
+                    _ => continue,
+                }
+            }
+            output.tx
+                .send(message)
+                .map_err(|_| stopped(&output.name))?;
+            acks.push((&output.name, ack_rx));
+        }
+        for (name, ack) in acks {
+            ack.recv().map_err(|_| stopped(name))?;
+        }
+        Ok(())
+    }
+}
+
+impl <W: Write> Write for FanOut<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let bytes_transferred = self.primary.write(buf)?;
+        if self.outputs.is_empty() || bytes_transferred == 0 {
+            return Ok(bytes_transferred);
+        }
+        let chunk: Arc<[u8]> = Arc::from(&buf[..bytes_transferred]);
+        for output in &mut self.outputs.0 {
+            output.send(&chunk)?;
+        }
+        Ok(bytes_transferred)
+    }
+    /// Flush the primary writer, then wait for every output to write out
+    /// what it was sent so far.
+    fn flush(&mut self) -> io::Result<()> {
+        self.primary.flush()?;
+        self.flush_outputs()
+    }
+}
+
+impl <W: Finish> Finish for FanOut<W> {
+    /// Finish the primary writer, drop whatever lossy outputs still have
+    /// queued, then wait for the lossless ones to catch up.
+    fn finish(&mut self) -> io::Result<()> {
+        self.primary.finish()?;
+        for output in self.outputs.0.iter().filter(|output| output.lossy) {
+            output.abandoned.store(true, Ordering::Relaxed);
+        }
+        self.flush_outputs()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        fs,
+        time::Instant,
+    };
+    use nonzero_ext::nonzero;
+
+    /// A fresh directory for the outputs of the test called `name`.
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir()
+            .join(format!("pvalve-fanout-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// An output taking 1000 bytes a second, a tenth of a second's worth of
+    /// which go through at once.
+    fn slow(path: PathBuf, lossy: bool) -> OutputSpec {
+        OutputSpec { path, limit: Some(nonzero!(1000u64)), lossy }
+    }
+
+    #[test]
+    fn specs_take_an_optional_rate_and_lossy_marker() {
+        let spec = |s: &str| s.parse::<OutputSpec>().unwrap();
+        assert_eq!(spec("out.log"), OutputSpec {
+            path: "out.log".into(),
+            limit: None,
+            lossy: false,
+        });
+        assert_eq!(spec("out.log:10M").limit.map(NonZeroU64::get), Some(10_000_000));
+        assert!(spec("out.log:1k:lossy").lossy);
+        assert!(spec("out.log:lossy").lossy);
+        assert_eq!(spec("a:b").path, PathBuf::from("a:b"));
+        assert!("".parse::<OutputSpec>().is_err());
+        assert!(":lossy".parse::<OutputSpec>().is_err());
+    }
+
+    #[test]
+    fn every_output_receives_a_copy() {
+        let dir = dir("copy");
+        let specs = [
+            OutputSpec { path: dir.join("a"), limit: None, lossy: false },
+            OutputSpec { path: dir.join("b"), limit: None, lossy: false },
+        ];
+        let (outputs, threads, mut monitors) =
+            Outputs::open(&specs, Unit::Byte).unwrap();
+        let mut writer = FanOut::new(Vec::new(), outputs);
+        writer.write_all(b"hello\n").unwrap();
+        writer.write_all(b"world\n").unwrap();
+        writer.flush().unwrap();
+        assert_eq!(monitors[1].written.get().lines_transferred, 2);
+        assert_eq!(writer.get_ref(), b"hello\nworld\n");
+        drop(writer);
+        threads.join().unwrap();
+        for spec in &specs {
+            assert_eq!(fs::read(&spec.path).unwrap(), b"hello\nworld\n");
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn outputs_are_written_at_their_own_rate() {
+        let dir = dir("rate");
+        let specs = [
+            slow(dir.join("slow"), false),
+            OutputSpec { path: dir.join("fast"), limit: None, lossy: false },
+        ];
+        let (outputs, threads, mut monitors) =
+            Outputs::open(&specs, Unit::Byte).unwrap();
+        let mut writer = FanOut::new(Vec::new(), outputs);
+        let started = Instant::now();
+        writer.write_all(&[b'x'; 300]).unwrap();
+        writer.flush().unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(150), "{:?}", started.elapsed());
+        assert_eq!(monitors[0].written.get().bytes_transferred, 300);
+        drop(writer);
+        threads.join().unwrap();
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn slow_lossless_outputs_hold_the_transfer_back() {
+        let dir = dir("lossless");
+        let specs = [
+            slow(dir.join("slow"), false),
+        ];
+        let (outputs, threads, _) = Outputs::open(&specs, Unit::Byte).unwrap();
+        let mut writer = FanOut::new(Vec::new(), outputs);
+        let started = Instant::now();
+        // Far more chunks than the output may fall behind by.
+        for _ in 0..60 {
+            writer.write_all(&[b'x'; 10]).unwrap();
+        }
+        assert!(started.elapsed() >= Duration::from_millis(200), "{:?}", started.elapsed());
+        assert_eq!(writer.get_ref().len(), 600);
+        drop(writer);
+        threads.join().unwrap();
+        assert_eq!(fs::read(dir.join("slow")).unwrap().len(), 600);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn lossy_outputs_drop_what_they_cannot_keep_up_with() {
+        let dir = dir("lossy");
+        let specs = [
+            slow(dir.join("slow"), true),
+        ];
+        let (outputs, threads, mut monitors) =
+            Outputs::open(&specs, Unit::Byte).unwrap();
+        let mut writer = FanOut::new(Vec::new(), outputs);
+        let started = Instant::now();
+        for _ in 0..100 {
+            writer.write_all(&[b'x'; 10]).unwrap();
+            // As a limited writer does after every short write.
+            writer.flush().unwrap();
+        }
+        // The transfer goes on at its own pace.
+        assert!(started.elapsed() < Duration::from_millis(200), "{:?}", started.elapsed());
+        assert_eq!(writer.get_ref().len(), 1000);
+        drop(writer);
+        threads.join().unwrap();
+        let written = monitors[0].written.get().bytes_transferred;
+        let dropped = monitors[0].dropped.get().bytes_transferred;
+        assert!(dropped > 0);
+        assert_eq!(written + dropped, 1000);
+        assert_eq!(fs::read(dir.join("slow")).unwrap().len() as u64, written);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn finishing_drops_what_lossy_outputs_still_have_queued() {
+        let dir = dir("abandon");
+        let specs = [
+            slow(dir.join("slow"), true),
+        ];
+        let (outputs, threads, mut monitors) =
+            Outputs::open(&specs, Unit::Byte).unwrap();
+        let mut writer = FanOut::new(Vec::new(), outputs);
+        for _ in 0..Outputs::DEPTH {
+            writer.write_all(&[b'x'; 100]).unwrap();
+        }
+        let started = Instant::now();
+        writer.finish().unwrap();
+        drop(writer);
+        threads.join().unwrap();
+        // Draining the whole backlog at this rate would take over a second.
+        assert!(started.elapsed() < Duration::from_millis(500), "{:?}", started.elapsed());
+        let written = monitors[0].written.get().bytes_transferred;
+        let dropped = monitors[0].dropped.get().bytes_transferred;
+        assert!(written < 1600);
+        assert_eq!(written + dropped, 1600);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+}
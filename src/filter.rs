@@ -0,0 +1,182 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{
+        self,
+        Write,
+    },
+    os::fd::OwnedFd,
+    process::{
+        Child,
+        Command,
+        ExitStatus,
+        Stdio,
+    },
+    str::FromStr,
+    thread::{
+        self,
+        JoinHandle,
+    },
+};
+
+use thiserror::Error;
+
+/// Which side of a [`Filter`] the rate limit applies to.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LimitSide {
+    /// What is fed to the filter, as read from the input.
+    #[default]
+    In,
+    /// What the filter writes, on its way to the output.
+    Out,
+}
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+#[error("unknown limit side {0:?}, expected in or out")]
+pub struct ParseLimitSideError(String);
+
+/// The filter exited unsuccessfully, so what it wrote cannot be trusted.
+#[derive(Debug, Error)]
+#[error("filter {command:?} failed: {status}")]
+pub struct FilterFailed {
+    pub command: String,
+    pub status: ExitStatus,
+}
+
+/// Command the stream passes through on its way to the output, run by the
+/// shell with the stream on its stdin.
+///
+/// Whatever it writes is copied to the output on a thread of its own, so a
+/// filter which holds its output back, as compressors do, cannot stall the
+/// copy feeding it.
+pub struct Filter {
+    command: String,
+    child: Child,
+    output: JoinHandle<io::Result<()>>,
+}
+
+impl FromStr for LimitSide {
+    type Err = ParseLimitSideError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "in" => Ok(Self::In),
+            "out" => Ok(Self::Out),
+            _ => Err(ParseLimitSideError(s.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for LimitSide {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str(match self {
+            Self::In => "in",
+            Self::Out => "out",
+        })
+    }
+}
+
+impl Filter {
+    /// Start `command`, copying everything it writes to `output`, and return
+    /// it along with its stdin.
+    ///
+    /// The filter only sees the end of its input once the returned file and
+    /// every copy of it are closed.
+    pub fn spawn(
+        command: &str,
+        mut output: impl Write + Send + 'static,
+    ) -> io::Result<(Self, File)> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| io::Error::new(e.kind(), format!("filter {:?}: {}", command, e)))?;
+        let stdin = child.stdin.take().expect("filter stdin is piped");
+        let mut stdout = child.stdout.take().expect("filter stdout is piped");
+        let output = thread::spawn(move || {
+            io::copy(&mut stdout, &mut output)?;
+            output.flush()
+        });
+        let filter = Self {
+            command: command.to_owned(),
+            child,
+            output,
+        };
+        Ok((filter, File::from(OwnedFd::from(stdin))))
+    }
+    /// Wait for the filter to write out the last of its output and exit.
+    ///
+    /// Failing to write its output is reported ahead of how it exited, since
+    /// a filter whose output was cut short usually fails for that reason.
+    pub fn wait(mut self) -> io::Result<()> {
+        let copied = self.output.join()
+            .unwrap_or_else(|_| Err(io::Error::other("filter output stopped")))
+            .map_err(|e| io::Error::new(
+                e.kind(),
+                format!("filter {:?} output: {}", self.command, e),
+            ));
+        // Reap the filter even if its output failed, which it will soon
+        // notice itself.
+        let status = self.child.wait()?;
+        copied?;
+        if !status.success() {
+            return Err(io::Error::other(FilterFailed {
+                command: self.command,
+                status,
+            }));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn limit_sides_round_trip() {
+        for side in [LimitSide::In, LimitSide::Out] {
+            assert_eq!(side.to_string().parse(), Ok(side));
+        }
+        assert!("both".parse::<LimitSide>().is_err());
+    }
+
+    #[test]
+    fn filter_output_is_drained_once_its_input_is_closed() {
+        let output = SharedBuffer::default();
+        let (filter, mut stdin) = Filter::spawn("tr a-z A-Z", output.clone()).unwrap();
+        stdin.write_all(b"hello\nworld\n").unwrap();
+        drop(stdin);
+        filter.wait().unwrap();
+        assert_eq!(*output.0.lock().unwrap(), b"HELLO\nWORLD\n");
+    }
+
+    #[test]
+    fn failing_filters_are_reported_with_their_status() {
+        let (filter, stdin) = Filter::spawn("cat >/dev/null; exit 3", io::sink()).unwrap();
+        drop(stdin);
+        let e = filter.wait().unwrap_err();
+        let failed = e.get_ref()
+            .and_then(|e| e.downcast_ref::<FilterFailed>())
+            .expect("filter failure");
+        assert_eq!(failed.status.code(), Some(3));
+    }
+
+}
@@ -0,0 +1,135 @@
+use std::{
+    fmt,
+    str::FromStr,
+};
+
+use size_format::{
+    SizeFormatterBinary,
+    SizeFormatterSI,
+};
+
+use thiserror::Error;
+
+use super::unit::Unit;
+
+/// How amounts and rates are written for people to read.
+///
+/// Every human-readable number, whether in the full-screen interface, the
+/// stderr status line or the JSON summary, goes through this type.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum NumberFormat {
+    /// Binary multiples for bytes and SI multiples for every other unit.
+    #[default]
+    Auto,
+    /// Binary multiples such as `KiB`.
+    Binary,
+    /// SI multiples such as `kB`.
+    Si,
+    /// Plain integers.
+    Raw,
+    /// Like auto, except that rates of bytes are given in SI multiples of
+    /// bits, as network links are.
+    Bits,
+}
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+#[error("unknown number format {0:?}, expected auto, binary, si, raw or bits")]
+pub struct ParseNumberFormatError(String);
+
+impl NumberFormat {
+    /// `amount` of `unit`, with at most `precision` decimal places when a
+    /// multiple is used.
+    pub fn amount(&self, amount: u64, unit: Unit, precision: Option<usize>) -> String {
+        let abbreviation = unit.abbreviation();
+        let binary = match self {
+            Self::Raw => return format!("{}{}", amount, abbreviation),
+            Self::Auto | Self::Bits => unit == Unit::Byte,
+            Self::Binary => true,
+            Self::Si => false,
+        };
+        match (binary, precision) {
+            (true, Some(p)) => format!("{:.*}{}", p, SizeFormatterBinary::new(amount), abbreviation),
+            (true, None) => format!("{}{}", SizeFormatterBinary::new(amount), abbreviation),
+            (false, Some(p)) => format!("{:.*}{}", p, SizeFormatterSI::new(amount), abbreviation),
+            (false, None) => format!("{}{}", SizeFormatterSI::new(amount), abbreviation),
+        }
+    }
+    /// `amount` of `unit` per second.
+    pub fn rate(&self, amount: u64, unit: Unit) -> String {
+        if *self == Self::Bits && unit == Unit::Byte {
+            return format!("{}bit/s", SizeFormatterSI::new(amount.saturating_mul(8)));
+        }
+        format!("{}/s", self.amount(amount, unit, None))
+    }
+}
+
+impl FromStr for NumberFormat {
+    type Err = ParseNumberFormatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "binary" => Ok(Self::Binary),
+            "si" => Ok(Self::Si),
+            "raw" | "plain" => Ok(Self::Raw),
+            "bits" => Ok(Self::Bits),
+            _ => Err(ParseNumberFormatError(s.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for NumberFormat {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Auto => "auto",
+            Self::Binary => "binary",
+            Self::Si => "si",
+            Self::Raw => "raw",
+            Self::Bits => "bits",
+        };
+        fmt.write_str(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auto_uses_binary_for_bytes_only() {
+        let format = NumberFormat::Auto;
+        assert_eq!(format.amount(2048, Unit::Byte, Some(2)), "2.00KiB");
+        assert_eq!(format.amount(2000, Unit::Line, Some(2)), "2.00kL");
+    }
+
+    #[test]
+    fn explicit_formats_apply_to_every_unit() {
+        assert_eq!(NumberFormat::Si.amount(2000, Unit::Byte, Some(2)), "2.00kB");
+        assert_eq!(NumberFormat::Binary.amount(2048, Unit::Line, Some(2)), "2.00KiL");
+        assert_eq!(NumberFormat::Raw.amount(2048, Unit::Byte, Some(2)), "2048B");
+        assert_eq!(NumberFormat::Raw.rate(7, Unit::Word), "7W/s");
+    }
+
+    #[test]
+    fn bits_apply_to_rates_of_bytes_only() {
+        let format = NumberFormat::Bits;
+        assert_eq!(format.rate(1_250_000, Unit::Byte), "10.0Mbit/s");
+        assert_eq!(format.rate(2000, Unit::Line), "2.0kL/s");
+        assert_eq!(format.amount(2048, Unit::Byte, Some(2)), "2.00KiB");
+    }
+
+    #[test]
+    fn parse_roundtrips_display() {
+        for format in [
+            NumberFormat::Auto,
+            NumberFormat::Binary,
+            NumberFormat::Si,
+            NumberFormat::Raw,
+            NumberFormat::Bits,
+        ] {
+            assert_eq!(format.to_string().parse(), Ok(format));
+        }
+        assert!("metric".parse::<NumberFormat>().is_err());
+    }
+
+}
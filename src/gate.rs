@@ -0,0 +1,154 @@
+use std::{
+    io,
+    os::fd::AsFd as _,
+    process::{
+        Command,
+        Stdio,
+    },
+    time::Duration,
+};
+
+use super::{
+    config::{
+        Latch,
+        LatchMonitor,
+    },
+    control::Controls,
+    ipc::Message,
+};
+
+/// Pauses the transfer while a check fails and resumes it once the check
+/// passes again, checking every so often, for `--gate-cmd` and
+/// `--min-free`.
+///
+/// Only a pause the gate made is lifted by it, so a transfer paused by hand
+/// stays paused when the gate opens.
+pub struct Gate {
+    check: Box<dyn FnMut() -> bool + Send>,
+    interval: Duration,
+    controls: Controls,
+    closed: Latch,
+    /// The gate paused the transfer and has yet to resume it.
+    paused: bool,
+    shutdown: LatchMonitor,
+}
+
+impl Gate {
+    pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(30);
+
+    /// Gate which is open while `check` passes.
+    pub fn new(
+        check: impl FnMut() -> bool + Send + 'static,
+        controls: Controls,
+        shutdown: LatchMonitor,
+    ) -> Self {
+        Self {
+            check: Box::new(check),
+            interval: Self::DEFAULT_INTERVAL,
+            controls,
+            closed: Latch::new(),
+            paused: false,
+            shutdown,
+        }
+    }
+    /// Gate which is open while the shell `command` succeeds. A command
+    /// which cannot be run at all counts as failing.
+    pub fn command(command: String, controls: Controls, shutdown: LatchMonitor) -> Self {
+        Self::new(move || succeeds(&command), controls, shutdown)
+    }
+    /// Check this often.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+    /// Whether the check failed when last made.
+    pub fn closed(&mut self) -> LatchMonitor {
+        self.closed.watch()
+    }
+
+    /// Check straight away, then every interval until shutdown.
+    pub fn run(mut self) {
+        loop {
+            let open = (self.check)();
+            self.apply(open);
+            if self.shutdown.wait_timeout(self.interval) {
+                break;
+            }
+        }
+    }
+
+    fn apply(&mut self, open: bool) {
+        if open {
+            self.closed.off();
+            if self.paused {
+                self.paused = false;
+                self.controls.send(Message::Resume);
+            }
+        } else {
+            self.closed.on();
+            if !self.controls.paused() {
+                self.paused = true;
+                self.controls.send(Message::Pause);
+            }
+        }
+    }
+}
+
+/// Run `command` with its output on stderr, to keep it out of the stream.
+fn succeeds(command: &str) -> bool {
+    let status = io::stderr().as_fd().try_clone_to_owned().and_then(|stderr| {
+        Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::null())
+            .stdout(Stdio::from(stderr))
+            .status()
+    });
+    status.is_ok_and(|status| status.success())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::{
+        Config,
+        ConfigMonitor,
+    };
+
+    fn gate() -> (Gate, Controls, LatchMonitor) {
+        let config = Config::default();
+        let (config_tx, _) = ConfigMonitor::new(config);
+        let controls = Controls::new(config, config_tx, Latch::new(), Latch::new());
+        let mut gate = Gate::new(|| true, controls.clone(), Latch::new().watch());
+        let closed = gate.closed();
+        (gate, controls, closed)
+    }
+
+    #[test]
+    fn commands_open_the_gate_by_succeeding() {
+        assert!(succeeds("true"));
+        assert!(!succeeds("exit 3"));
+    }
+
+    #[test]
+    fn closing_pauses_and_opening_resumes() {
+        let (mut gate, controls, mut closed) = gate();
+        gate.apply(false);
+        assert!(closed.active() && controls.paused());
+        gate.apply(false);
+        assert!(controls.paused());
+        gate.apply(true);
+        assert!(!closed.active() && !controls.paused());
+    }
+
+    #[test]
+    fn pauses_made_by_hand_outlast_the_gate() {
+        let (mut gate, controls, _) = gate();
+        controls.send(Message::Pause);
+        gate.apply(false);
+        gate.apply(true);
+        assert!(controls.paused());
+    }
+
+}
@@ -0,0 +1,255 @@
+use std::{
+    env,
+    fmt,
+    fs::{self, OpenOptions},
+    io::{
+        self,
+        BufRead,
+        BufReader,
+        Write,
+    },
+    path::PathBuf,
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    format::NumberFormat,
+    progress::{
+        CumulativeTransferProgress,
+        TransferProgress,
+    },
+    unit::Unit,
+};
+
+/// One finished transfer, as appended to the history file by `--history`
+/// in a single line of JSON such as
+///
+/// ```text
+/// {"finished_at":1700000000,"command_line":["pvalve","-L","1M"],"bytes_transferred":4096,"lines_transferred":12,"nulls_transferred":0,"words_transferred":30,"writes_transferred":1,"elapsed_seconds":1.5,"unit":"byte","average_rate":2730,"exit_status":0}
+/// ```
+///
+/// As with progress messages, fields may be added but never removed or
+/// renamed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// Seconds since the Unix epoch.
+    pub finished_at: u64,
+    pub command_line: Vec<String>,
+    #[serde(flatten)]
+    pub progress: TransferProgress,
+    pub elapsed_seconds: f64,
+    pub unit: String,
+    /// Average rate per second in `unit`, leaving out time spent paused.
+    pub average_rate: u64,
+    pub exit_status: i32,
+}
+
+/// File of [`HistoryEntry`]s, one per line, the oldest first.
+pub struct History {
+    path: PathBuf,
+}
+
+impl HistoryEntry {
+    /// Entry for a transfer ending now after `progress`, measured in `unit`.
+    pub fn new(
+        command_line: Vec<String>,
+        progress: &CumulativeTransferProgress,
+        unit: Unit,
+        exit_status: i32,
+    ) -> Self {
+        let active_seconds = progress.active_duration().as_secs_f64();
+        let amount = unit.progress(&progress.progress);
+        let average_rate = if active_seconds > 0f64 {
+            (amount as f64 / active_seconds).round() as u64
+        } else {
+            0
+        };
+        Self {
+            finished_at: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            command_line,
+            progress: progress.progress,
+            elapsed_seconds: progress.elapsed().as_secs_f64(),
+            unit: unit.to_string(),
+            average_rate,
+            exit_status,
+        }
+    }
+}
+
+impl fmt::Display for HistoryEntry {
+    /// When it finished in UTC, how it ended, the totals and the command.
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unit: Unit = self.unit.parse().unwrap_or_default();
+        let format = NumberFormat::default();
+        let status = match self.exit_status {
+            0 => "ok".to_owned(),
+            code => format!("exit {}", code),
+        };
+        let command_line = self.command_line.iter()
+            .map(|arg| if arg.is_empty() || arg.contains(char::is_whitespace) {
+                format!("'{}'", arg)
+            } else {
+                arg.clone()
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        write!(
+            fmt,
+            "{}  {:<7}  {} in {:.1}s at {}  {}",
+            utc(self.finished_at),
+            status,
+            format.amount(unit.progress(&self.progress), unit, Some(2)),
+            self.elapsed_seconds,
+            format.rate(self.average_rate, unit),
+            command_line,
+        )
+    }
+}
+
+impl History {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+        }
+    }
+    /// `pvalve/history.jsonl` under `$XDG_STATE_HOME`, or under
+    /// `~/.local/state` when that is not set.
+    pub fn default_path() -> Option<PathBuf> {
+        let state = env::var_os("XDG_STATE_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| {
+                let home = env::var_os("HOME").filter(|dir| !dir.is_empty())?;
+                Some(PathBuf::from(home).join(".local/state"))
+            })?;
+        Some(state.join("pvalve/history.jsonl"))
+    }
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+    /// Add `entry` to the end, creating the file and its directory first if
+    /// need be.
+    ///
+    /// The line goes out in a single write to a file opened for appending,
+    /// so that transfers ending together do not interleave their entries.
+    pub fn append(&self, entry: &HistoryEntry) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?
+            .write_all(line.as_bytes())
+    }
+    /// The last `count` entries, the oldest first, or none if nothing was
+    /// recorded yet.
+    pub fn recent(&self, count: usize) -> io::Result<Vec<HistoryEntry>> {
+        let file = match fs::File::open(&self.path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            file => file?,
+        };
+        let mut entries = vec![];
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry = serde_json::from_str(&line)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            entries.push(entry);
+        }
+        let skipped = entries.len().saturating_sub(count);
+        entries.drain(..skipped);
+        Ok(entries)
+    }
+}
+
+/// `secs` since the Unix epoch as a UTC date and time.
+pub(crate) fn utc(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let time = secs % 86400;
+    // Civil date from days since the epoch, in 400 year eras of 146097
+    // days starting on the first of March.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524
+        - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(finished_at: u64) -> HistoryEntry {
+        HistoryEntry {
+            finished_at,
+            command_line: vec!["pvalve".into(), "--filter".into(), "gzip -9".into()],
+            progress: TransferProgress {
+                bytes_transferred: 3 << 20,
+                ..Default::default()
+            },
+            elapsed_seconds: 1.5,
+            unit: "byte".into(),
+            average_rate: 2 << 20,
+            exit_status: 0,
+        }
+    }
+
+    #[test]
+    fn utc_dates_match_the_calendar() {
+        assert_eq!(utc(0), "1970-01-01 00:00:00");
+        assert_eq!(utc(951_782_400), "2000-02-29 00:00:00");
+        assert_eq!(utc(1_700_000_000), "2023-11-14 22:13:20");
+    }
+
+    #[test]
+    fn entries_show_when_how_much_and_what_ran() {
+        assert_eq!(
+            entry(1_700_000_000).to_string(),
+            "2023-11-14 22:13:20  ok       3.00MiB in 1.5s at 2.0MiB/s  pvalve --filter 'gzip -9'",
+        );
+        let failed = HistoryEntry {
+            exit_status: 124,
+            ..entry(0)
+        };
+        assert!(failed.to_string().contains("exit 124"));
+    }
+
+    #[test]
+    fn recent_entries_are_read_back_oldest_first() {
+        let dir = env::temp_dir().join(format!("pvalve-history-{}", std::process::id()));
+        let history = History::new(dir.join("nested/history.jsonl"));
+        assert!(history.recent(10).unwrap().is_empty());
+        for finished_at in 1..=3 {
+            history.append(&entry(finished_at)).unwrap();
+        }
+        let recent = history.recent(2).unwrap();
+        let finished: Vec<_> = recent.iter().map(|entry| entry.finished_at).collect();
+        assert_eq!(finished, [2, 3]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+}
@@ -0,0 +1,282 @@
+use std::{
+    fmt,
+    io,
+    os::fd::AsFd as _,
+    process::{
+        Command,
+        ExitStatus,
+        Stdio,
+    },
+    thread::sleep,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use serde::Serialize;
+use super::state::StateWatcher;
+
+use super::{
+    alarm::{
+        MinRate,
+        RateAlarm,
+    },
+    instantaneous::RateSampler,
+    progress::{
+        CumulativeProgressMonitor,
+        CumulativeTransferProgress,
+        TransferProgress,
+    },
+    sink::TransferState,
+    unit::Unit,
+};
+
+/// Shell commands to run when the transfer reaches a milestone.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct Hooks {
+    pub on_complete: Option<String>,
+    pub on_abort: Option<String>,
+    pub on_stall: Option<String>,
+}
+
+/// Milestones which hooks can be attached to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookEvent {
+    /// The input was copied in full.
+    Complete,
+    /// The transfer was cancelled or failed.
+    Abort,
+    /// The rate stayed below the minimum for too long.
+    Stall,
+}
+
+/// Everything a hook is told about the transfer, through `PVALVE_*`
+/// environment variables.
+#[derive(Debug, Clone, Copy)]
+pub struct HookContext {
+    pub event: HookEvent,
+    pub reason: &'static str,
+    pub progress: CumulativeTransferProgress,
+    pub rate: TransferProgress,
+    pub unit: Unit,
+}
+
+/// Runs [`Hooks`] as the transfer state and rate change, until the transfer
+/// ends.
+///
+/// Without a minimum rate, the transfer is considered stalled once nothing
+/// has been transferred for [`MinRate::DEFAULT_PERIOD`].
+pub struct HookRunner {
+    hooks: Hooks,
+    state: StateWatcher<TransferState>,
+    cumulative: CumulativeProgressMonitor,
+    rate: RateSampler,
+    alarm: RateAlarm,
+    unit: Unit,
+}
+
+impl HookEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Complete => "complete",
+            Self::Abort => "abort",
+            Self::Stall => "stall",
+        }
+    }
+}
+
+impl fmt::Display for HookEvent {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str(self.name())
+    }
+}
+
+impl Hooks {
+    pub fn is_empty(&self) -> bool {
+        self.on_complete.is_none()
+            && self.on_abort.is_none()
+            && self.on_stall.is_none()
+    }
+    pub fn command(&self, event: HookEvent) -> Option<&str> {
+        match event {
+            HookEvent::Complete => self.on_complete.as_deref(),
+            HookEvent::Abort => self.on_abort.as_deref(),
+            HookEvent::Stall => self.on_stall.as_deref(),
+        }
+    }
+    /// Run the hook for `context.event`, if any, and wait for it to exit.
+    ///
+    /// The hook's output goes to stderr so that it cannot end up in the
+    /// transferred data.
+    pub fn run(&self, context: &HookContext) -> io::Result<Option<ExitStatus>> {
+        let Some(command) = self.command(context.event) else {
+            return Ok(None);
+        };
+        let stderr = io::stderr().as_fd().try_clone_to_owned()?;
+        Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .envs(context.environment())
+            .stdin(Stdio::null())
+            .stdout(Stdio::from(stderr))
+            .status()
+            .map(Some)
+    }
+}
+
+impl HookContext {
+    pub fn environment(&self) -> Vec<(&'static str, String)> {
+        let TransferProgress {
+            bytes_transferred,
+            lines_transferred,
+            nulls_transferred,
+            words_transferred,
+            writes_transferred,
+        } = self.progress.progress;
+        vec![
+            ("PVALVE_EVENT", self.event.to_string()),
+            ("PVALVE_REASON", self.reason.to_owned()),
+            ("PVALVE_BYTES", bytes_transferred.to_string()),
+            ("PVALVE_LINES", lines_transferred.to_string()),
+            ("PVALVE_NULLS", nulls_transferred.to_string()),
+            ("PVALVE_WORDS", words_transferred.to_string()),
+            ("PVALVE_WRITES", writes_transferred.to_string()),
+            ("PVALVE_ELAPSED", format!("{:.3}", self.progress.elapsed().as_secs_f64())),
+            ("PVALVE_ACTIVE", format!("{:.3}", self.progress.active_duration().as_secs_f64())),
+            ("PVALVE_UNIT", self.unit.to_string()),
+            ("PVALVE_RATE", self.unit.progress(&self.rate).to_string()),
+        ]
+    }
+}
+
+impl HookRunner {
+    const POLL: Duration = Duration::from_millis(100);
+    const SAMPLE: Duration = Duration::from_secs(1);
+
+    pub fn new(
+        hooks: Hooks,
+        state: StateWatcher<TransferState>,
+        cumulative: CumulativeProgressMonitor,
+        rate: RateSampler,
+        min_rate: Option<MinRate>,
+        unit: Unit,
+    ) -> Self {
+        let min_rate = min_rate.unwrap_or(MinRate {
+            threshold: nonzero_ext::nonzero!(1u64),
+            unit: None,
+            period: MinRate::DEFAULT_PERIOD,
+            bell: false,
+        });
+        Self {
+            hooks,
+            state,
+            cumulative,
+            rate,
+            alarm: RateAlarm::new(min_rate),
+            unit,
+        }
+    }
+
+    /// Watch the transfer until it ends, running hooks along the way.
+    pub fn run(mut self) -> io::Result<()> {
+        let mut rate = TransferProgress::default();
+        let mut sampled = Instant::now();
+        loop {
+            sleep(Self::POLL);
+            let (event, reason) = match self.state.get() {
+                TransferState::Finished => (HookEvent::Complete, "finished"),
+                TransferState::Cancelled => (HookEvent::Abort, "cancelled"),
+                TransferState::Failed => (HookEvent::Abort, "failed"),
+                TransferState::Paused => {
+                    self.alarm.reset();
+                    continue;
+                },
+                TransferState::Running => {
+                    let now = Instant::now();
+                    if now.duration_since(sampled) < Self::SAMPLE {
+                        continue;
+                    }
+                    sampled = now;
+                    rate = self.rate.sample();
+                    if !self.alarm.observe(&rate, self.unit, now) {
+                        continue;
+                    }
+                    self.fire(HookEvent::Stall, "stalled", rate)?;
+                    continue;
+                },
+            };
+            self.fire(event, reason, rate)?;
+            return Ok(());
+        }
+    }
+
+    fn fire(
+        &mut self,
+        event: HookEvent,
+        reason: &'static str,
+        rate: TransferProgress,
+    ) -> io::Result<()> {
+        self.hooks.run(&HookContext {
+            event,
+            reason,
+            progress: self.cumulative.get(),
+            rate,
+            unit: self.unit,
+        })?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context(event: HookEvent) -> HookContext {
+        let mut progress = CumulativeTransferProgress::started(Instant::now());
+        progress.progress = TransferProgress {
+            bytes_transferred: 12,
+            lines_transferred: 3,
+            ..Default::default()
+        };
+        HookContext {
+            event,
+            reason: "finished",
+            progress,
+            rate: TransferProgress {
+                lines_transferred: 2,
+                ..Default::default()
+            },
+            unit: Unit::Line,
+        }
+    }
+
+    #[test]
+    fn environment_describes_the_transfer() {
+        let environment = context(HookEvent::Complete).environment();
+        let var = |name| environment.iter()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value.as_str());
+        assert_eq!(var("PVALVE_EVENT"), Some("complete"));
+        assert_eq!(var("PVALVE_REASON"), Some("finished"));
+        assert_eq!(var("PVALVE_BYTES"), Some("12"));
+        assert_eq!(var("PVALVE_LINES"), Some("3"));
+        assert_eq!(var("PVALVE_UNIT"), Some("line"));
+        assert_eq!(var("PVALVE_RATE"), Some("2"));
+    }
+
+    #[test]
+    fn only_the_matching_hook_runs() {
+        let hooks = Hooks {
+            on_complete: Some("test \"$PVALVE_BYTES\" = 12".into()),
+            on_abort: Some("exit 3".into()),
+            ..Default::default()
+        };
+        let status = hooks.run(&context(HookEvent::Complete)).unwrap();
+        assert!(status.unwrap().success());
+        let status = hooks.run(&context(HookEvent::Abort)).unwrap();
+        assert_eq!(status.unwrap().code(), Some(3));
+        assert!(hooks.run(&context(HookEvent::Stall)).unwrap().is_none());
+    }
+
+}
@@ -0,0 +1,141 @@
+use std::{
+    fs::{self, File},
+    io::{
+        self,
+        Read,
+        Result,
+    },
+    path::{Path, PathBuf},
+};
+
+use super::state::{
+    channel,
+    StateCell,
+};
+
+use super::progress::{
+    InputFile,
+    InputFilesMonitor,
+};
+
+/// Reader which reads several files one after another as if they were one,
+/// publishing how big each turned out to be.
+///
+/// Every file is looked up when the reader is made, so that a missing one
+/// is reported before anything is transferred, but each is only opened
+/// once the one before it has been read to its end.
+pub struct InputFiles {
+    paths: Vec<PathBuf>,
+    files: Vec<InputFile>,
+    current: Option<File>,
+    index: usize,
+    /// Bytes read from the current file so far.
+    read: u64,
+    tx: StateCell<Vec<InputFile>>,
+}
+
+impl InputFiles {
+    pub fn open(paths: &[PathBuf]) -> Result<Self> {
+        let files = paths.iter()
+            .map(|path| {
+                let metadata = fs::metadata(path).map_err(|e| named(path, e))?;
+                Ok(InputFile {
+                    name: path.display().to_string(),
+                    size: metadata.is_file().then_some(metadata.len()),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let (tx, _) = channel(files.clone());
+        Ok(Self {
+            paths: paths.to_vec(),
+            files,
+            current: None,
+            index: 0,
+            read: 0,
+            tx,
+        })
+    }
+    /// Size of all the files together, if every one of them is known.
+    pub fn total_size(&self) -> Option<u64> {
+        self.files.iter()
+            .map(|file| file.size)
+            .try_fold(0u64, |total, size| total.checked_add(size?))
+    }
+    pub fn monitor(&self) -> InputFilesMonitor {
+        InputFilesMonitor::new(self.tx.subscribe())
+    }
+}
+
+impl Read for InputFiles {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        loop {
+            let file = match &mut self.current {
+                Some(file) => file,
+                None => {
+                    let Some(path) = self.paths.get(self.index) else {
+                        return Ok(0);
+                    };
+                    self.read = 0;
+                    let file = File::open(path).map_err(|e| named(path, e))?;
+                    self.current.insert(file)
+                },
+            };
+            let n = file.read(buf).map_err(|e| named(&self.paths[self.index], e))?;
+            if n > 0 || buf.is_empty() {
+                self.read += n as u64;
+                return Ok(n);
+            }
+            let finished = &mut self.files[self.index];
+            if finished.size != Some(self.read) {
+                finished.size = Some(self.read);
+                self.tx.send(self.files.clone());
+            }
+            self.current = None;
+            self.index += 1;
+        }
+    }
+}
+
+/// Name the file an error came from, keeping its kind.
+pub(crate) fn named(path: &Path, e: io::Error) -> io::Error {
+    io::Error::new(e.kind(), format!("{}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn files_are_read_in_turn_and_sizes_corrected_at_their_end() {
+        let dir = std::env::temp_dir().join(format!("pvalve-inputs-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let paths = ["a", "empty", "b"].map(|name| dir.join(name));
+        fs::write(&paths[0], b"first\n").unwrap();
+        fs::write(&paths[1], b"").unwrap();
+        fs::write(&paths[2], b"second\n").unwrap();
+        let mut inputs = InputFiles::open(&paths).unwrap();
+        let mut monitor = inputs.monitor();
+        assert_eq!(inputs.total_size(), Some(13));
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&paths[0])
+            .and_then(|mut file| file.write_all(b"grown\n"))
+            .unwrap();
+        let mut read = String::new();
+        inputs.read_to_string(&mut read).unwrap();
+        assert_eq!(read, "first\ngrown\nsecond\n");
+        let sizes = monitor.get().into_iter().map(|file| file.size).collect::<Vec<_>>();
+        assert_eq!(sizes, [Some(12), Some(0), Some(7)]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_missing_file_is_reported_by_name_up_front() {
+        let path = PathBuf::from("/nonexistent/pvalve-input");
+        let e = InputFiles::open(&[path]).err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+        assert!(e.to_string().starts_with("/nonexistent/pvalve-input: "));
+    }
+
+}
@@ -1,70 +1,271 @@
 use std::{
+    collections::VecDeque,
+    fmt,
     io::{
         Result,
         Write,
     },
-    time::Duration,
+    str::FromStr,
+    time::{
+        Duration,
+        Instant,
+    },
 };
 
-use watch::{
+use super::state::{
     channel,
-    WatchSender,
+    StateCell,
 };
 
-use sum_queue::{
-    SumQueue,
-    QueueStats,
-};
+use thiserror::Error;
 
+use super::unit::Scanner;
+use super::sink::ProgressSink;
+use super::syncio::Finish;
 use super::progress::{
     TransferProgress,
     TransferProgressMonitor,
 };
 
-fn mean(data: &mut QueueStats<TransferProgress>, window: Duration) -> TransferProgress {
-    let TransferProgress {
-        bytes_transferred,
-        lines_transferred,
-        nulls_transferred,
-    } = data.sum.unwrap_or_default();
-    let window = window.as_secs_f64();
-    let bytes_transferred = bytes_transferred as f64 / window;
-    let lines_transferred = lines_transferred as f64 / window;
-    let nulls_transferred = nulls_transferred as f64 / window;
-    TransferProgress {
-        bytes_transferred: bytes_transferred as usize,
-        lines_transferred: lines_transferred as usize,
-        nulls_transferred: nulls_transferred as usize,
+/// How recent samples are combined into an instantaneous rate.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Smoothing {
+    /// Everything transferred within the window, divided by its length.
+    #[default]
+    Mean,
+    /// Exponentially weighted moving average with the window as its time
+    /// constant, favoring the most recent samples.
+    Exponential,
+}
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+#[error("unknown smoothing {0:?}, expected mean or ewma")]
+pub struct ParseSmoothingError(String);
+
+impl FromStr for Smoothing {
+    type Err = ParseSmoothingError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "mean" => Ok(Self::Mean),
+            "ewma" | "exponential" => Ok(Self::Exponential),
+            _ => Err(ParseSmoothingError(s.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for Smoothing {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Mean => write!(fmt, "mean"),
+            Self::Exponential => write!(fmt, "ewma"),
+        }
+    }
+}
+
+/// Amount transferred between two instants.
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    start: Instant,
+    end: Instant,
+    progress: TransferProgress,
+}
+
+/// Per-second rate of every unit, kept in floating point between samples.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct Rate([f64; 5]);
+
+impl Rate {
+    fn of(progress: TransferProgress, seconds: f64) -> Self {
+        let TransferProgress {
+            bytes_transferred,
+            lines_transferred,
+            nulls_transferred,
+            words_transferred,
+            writes_transferred,
+        } = progress;
+        Self([
+            bytes_transferred as f64 / seconds,
+            lines_transferred as f64 / seconds,
+            nulls_transferred as f64 / seconds,
+            words_transferred as f64 / seconds,
+            writes_transferred as f64 / seconds,
+        ])
+    }
+    fn approach(&mut self, target: Self, alpha: f64) {
+        for (current, target) in self.0.iter_mut().zip(target.0) {
+            *current += alpha * (target - *current);
+        }
+    }
+}
+
+impl From<Rate> for TransferProgress {
+    fn from(Rate([bytes, lines, nulls, words, writes]): Rate) -> Self {
+        Self {
+            bytes_transferred: bytes.round() as u64,
+            lines_transferred: lines.round() as u64,
+            nulls_transferred: nulls.round() as u64,
+            words_transferred: words.round() as u64,
+            writes_transferred: writes.round() as u64,
+        }
+    }
+}
+
+/// Instantaneous transfer rate estimated from timestamped samples.
+pub struct RateEstimator {
+    window: Duration,
+    smoothing: Smoothing,
+    started: Instant,
+    samples: VecDeque<Sample>,
+    sum: TransferProgress,
+    ewma: Rate,
+    last: Option<Instant>,
+}
+
+impl RateEstimator {
+    pub const DEFAULT_WINDOW: Duration = Duration::from_secs(1);
+    const RESOLUTION: Duration = Duration::from_millis(10);
+
+    pub fn new(window: Duration) -> Self {
+        Self::with_smoothing(window, Smoothing::default())
+    }
+    pub fn with_smoothing(window: Duration, smoothing: Smoothing) -> Self {
+        Self {
+            window,
+            smoothing,
+            started: Instant::now(),
+            samples: VecDeque::new(),
+            sum: TransferProgress::default(),
+            ewma: Rate::default(),
+            last: None,
+        }
+    }
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+    pub fn smoothing(&self) -> Smoothing {
+        self.smoothing
+    }
+    /// Record a sample and return the updated rate per second.
+    pub fn push(&mut self, sample: TransferProgress) -> TransferProgress {
+        self.push_at(Instant::now(), sample)
+    }
+    /// Record a sample taken at `now` and return the updated rate per second.
+    pub fn push_at(
+        &mut self,
+        now: Instant,
+        sample: TransferProgress,
+    ) -> TransferProgress {
+        match self.smoothing {
+            Smoothing::Mean => self.push_mean(now, sample),
+            Smoothing::Exponential => self.push_exponential(now, sample),
+        }
+    }
+    fn push_mean(
+        &mut self,
+        now: Instant,
+        sample: TransferProgress,
+    ) -> TransferProgress {
+        // Each sample covers the time since the one before it, which is the
+        // refresh interval when a RateSampler takes them and may well be
+        // longer than the window, so the rate is worked out over the time
+        // the kept samples cover rather than over the window.
+        let start = self.last.unwrap_or(self.started);
+        self.last = Some(now);
+        self.samples.push_back(Sample {
+            start,
+            end: now,
+            progress: sample,
+        });
+        self.sum = self.sum + sample;
+        while let Some(oldest) = self.samples.front().copied() {
+            if now.duration_since(oldest.end) <= self.window
+                || self.samples.len() == 1
+            {
+                break;
+            }
+            self.samples.pop_front();
+            self.sum = self.sum - oldest.progress;
+        }
+        let span = self.samples
+            .front()
+            .map(|oldest| now.duration_since(oldest.start))
+            .unwrap_or_default()
+            .max(Self::RESOLUTION);
+        Rate::of(self.sum, span.as_secs_f64()).into()
+    }
+    fn push_exponential(
+        &mut self,
+        now: Instant,
+        sample: TransferProgress,
+    ) -> TransferProgress {
+        let since = self.last.unwrap_or(self.started);
+        let elapsed = now.duration_since(since).max(Self::RESOLUTION);
+        let alpha = 1f64 - (
+            -elapsed.as_secs_f64() / self.window.as_secs_f64()
+        ).exp();
+        self.ewma.approach(Rate::of(sample, elapsed.as_secs_f64()), alpha);
+        self.last = Some(now);
+        self.ewma.into()
+    }
+}
+
+/// Derives the instantaneous rate by periodically sampling cumulative
+/// progress, keeping rate computation off the write path entirely.
+pub struct RateSampler {
+    cumulative: TransferProgressMonitor,
+    last: TransferProgress,
+    estimator: RateEstimator,
+}
+
+impl RateSampler {
+    /// Rates are measured from the progress `cumulative` holds now, so that
+    /// a resumed transfer does not start with a spike.
+    pub fn new(
+        mut cumulative: TransferProgressMonitor,
+        estimator: RateEstimator,
+    ) -> Self {
+        let last = cumulative.get();
+        Self {
+            cumulative,
+            last,
+            estimator,
+        }
+    }
+    /// Take a sample now and return the updated rate per second.
+    pub fn sample(&mut self) -> TransferProgress {
+        let current = self.cumulative.get();
+        let delta = current - self.last;
+        self.last = current;
+        self.estimator.push(delta)
+    }
+    /// Take a sample now and report the updated rate to `sink`.
+    pub fn sample_into(&mut self, sink: &mut impl ProgressSink) {
+        let rate = self.sample();
+        sink.on_rate_change(rate);
     }
 }
 
 pub struct InstantaneousProgressWriter<W> {
     inner: W,
-    tx: WatchSender<TransferProgress>,
-    q: SumQueue<TransferProgress>,
+    tx: StateCell<TransferProgress>,
+    rate: RateEstimator,
+    scanner: Scanner,
 }
 
 impl <W> InstantaneousProgressWriter<W> {
     pub fn new(inner: W, window: Duration) -> Self {
         let (tx, _) = channel(TransferProgress::default());
-        let q = SumQueue::new(window);
         Self {
             inner,
             tx,
-            q,
+            rate: RateEstimator::new(window),
+            scanner: Scanner::default(),
         }
     }
     fn update(&mut self, buf: &[u8]) {
-        let sample = TransferProgress {
-            bytes_transferred: count_bytes(buf),
-            lines_transferred: count_lines(buf),
-            nulls_transferred: count_nulls(buf),
-        };
-        let mut stats = self.q.push_and_stats(sample);
-        let mean = mean(
-            &mut stats,
-            self.q.max_age(),
-        );
+        let sample = TransferProgress::scan(&mut self.scanner, buf);
+        let mean = self.rate.push(sample);
         self.tx.send(mean);
     }
     pub fn transfer_progress(&mut self) -> TransferProgressMonitor {
@@ -84,18 +285,64 @@ impl <W: Write> Write for InstantaneousProgressWriter<W> {
     }
 }
 
-fn count_bytes(buf: &[u8]) -> usize {
-    buf.len()
+impl <W: Finish> Finish for InstantaneousProgressWriter<W> {
+    fn finish(&mut self) -> Result<()> {
+        self.inner.finish()
+    }
 }
 
-fn count_lines(buf: &[u8]) -> usize {
-    buf.iter()
-        .filter(|b| 0xAu8 == **b)
-        .count()
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(n: u64) -> TransferProgress {
+        TransferProgress {
+            bytes_transferred: n,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn mean_evicts_samples_older_than_the_window() {
+        let mut rate = RateEstimator::new(Duration::from_millis(500));
+        let start = rate.started;
+        rate.push_at(start + Duration::from_millis(500), bytes(1000));
+        let later = rate.push_at(start + Duration::from_millis(1100), bytes(100));
+        // 100 over the 600ms since the evicted sample.
+        assert_eq!(later.bytes_transferred, 167);
+    }
+
+    #[test]
+    fn mean_of_samples_a_window_apart_is_the_steady_rate() {
+        let mut rate = RateEstimator::new(Duration::from_secs(1));
+        let start = rate.started;
+        for i in 1..=5 {
+            let now = rate.push_at(start + Duration::from_secs(i), bytes(1000));
+            assert_eq!(now.bytes_transferred, 1000, "after {}s", i);
+        }
+    }
+
+    #[test]
+    fn mean_spans_the_whole_interval_of_a_single_sample() {
+        let mut rate = RateEstimator::new(Duration::from_millis(250));
+        let start = rate.started;
+        let later = rate.push_at(start + Duration::from_secs(2), bytes(1000));
+        assert_eq!(later.bytes_transferred, 500);
+    }
+
+    #[test]
+    fn exponential_converges_on_a_steady_rate() {
+        let mut rate = RateEstimator::with_smoothing(
+            Duration::from_millis(500),
+            Smoothing::Exponential,
+        );
+        let start = rate.started;
+        let mut last = TransferProgress::default();
+        for i in 1..=100 {
+            let now = start + Duration::from_millis(100 * i);
+            last = rate.push_at(now, bytes(100));
+        }
+        assert_eq!(last.bytes_transferred, 1000);
+    }
 
-fn count_nulls(buf: &[u8]) -> usize {
-    buf.iter()
-        .filter(|b| 0x0u8 == **b)
-        .count()
 }
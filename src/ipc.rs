@@ -0,0 +1,405 @@
+use std::{
+    fs,
+    io::{
+        self,
+        BufRead,
+        BufReader,
+        ErrorKind,
+        Write,
+    },
+    num::{NonZeroU64, NonZeroUsize},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use super::state::StateWatcher;
+
+use super::{
+    config::{LatchMonitor, SpeedLimit},
+    control::Controls,
+    instantaneous::RateSampler,
+    progress::{
+        CumulativeProgressMonitor,
+        TransferProgress,
+    },
+    readahead::OccupancyMonitor,
+    sink::{EndCause, ProgressRegistry, TransferState},
+    size::{self, ParseByteSizeError},
+    unit::Unit,
+};
+
+/// A request to change the running transfer, whether from a key, the mouse
+/// or a line sent to a [`ControlSocket`].
+///
+/// Only the messages a socket client can send are parsed from text; the
+/// rest carry what the interface saw when they were made.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Message {
+    Pause,
+    Resume,
+    TogglePause,
+    /// Limit the rate, or stop limiting it when `None`.
+    Limit(Option<NonZeroU64>),
+    /// Switch the limit off or on, starting from the observed rate if no
+    /// limit was ever configured.
+    ToggleLimit(TransferProgress),
+    /// Step the limit up, starting from the observed rate when there is no
+    /// limit yet.
+    Faster(TransferProgress),
+    /// Step the limit down, starting from the observed rate when there is
+    /// no limit yet.
+    Slower(TransferProgress),
+    /// Put back a limit as it was before a change, switched off or not.
+    RestoreLimit(SpeedLimit),
+    /// Put back the limit as it was before the latest change made from the
+    /// interface, which alone remembers them.
+    Undo,
+    CycleUnit,
+    /// Show amounts and rates in the next unit along, leaving the limit
+    /// charged in the unit it was.
+    CycleDisplayUnit,
+    ToggleAllUnits,
+    Abort,
+}
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum ParseMessageError {
+    #[error("unknown command {0:?}, expected one of: pause, resume, toggle, limit N|off, abort, status")]
+    Unknown(String),
+    #[error(transparent)]
+    Limit(#[from] ParseByteSizeError),
+}
+
+impl FromStr for Message {
+    type Err = ParseMessageError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let message = match (words.next(), words.next(), words.next()) {
+            (Some("pause"), None, _) => Self::Pause,
+            (Some("resume"), None, _) => Self::Resume,
+            (Some("toggle"), None, _) => Self::TogglePause,
+            (Some("abort"), None, _) => Self::Abort,
+            (Some("limit"), Some("off"), None) => Self::Limit(None),
+            (Some("limit"), Some(limit), None) => {
+                Self::Limit(Some(size::parse_nonzero(limit)?))
+            },
+            _ => return Err(ParseMessageError::Unknown(s.trim().to_owned())),
+        };
+        Ok(message)
+    }
+}
+
+/// Unix socket accepting [`Message`]s, one per line, from one client at a
+/// time. Each is answered with `ok` or `error: ` and the reason.
+///
+/// Given [`ControlSocket::with_status`], a `status` line is answered with a
+/// [`ProgressMessage`] on how the transfer stands, its `rate` being the
+/// average so far since a single question has no window to measure over.
+///
+/// A socket file created by [`ControlSocket::bind`] is removed again when
+/// this is dropped.
+pub struct ControlSocket {
+    listener: UnixListener,
+    path: Option<PathBuf>,
+    status: Option<Status>,
+}
+
+/// What a `status` command reports on.
+struct Status {
+    registry: ProgressRegistry,
+    unit: Unit,
+}
+
+impl ControlSocket {
+    /// How long to wait for a client or a command before checking for
+    /// shutdown again.
+    const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+    pub fn bind(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_owned();
+        let listener = UnixListener::bind(&path)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            path: Some(path),
+            status: None,
+        })
+    }
+    /// Serve on a socket somebody else bound, such as a service manager.
+    pub fn from_listener(listener: UnixListener) -> io::Result<Self> {
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            path: None,
+            status: None,
+        })
+    }
+    /// Answer `status` with progress from `registry`, in `unit`.
+    pub fn with_status(mut self, registry: ProgressRegistry, unit: Unit) -> Self {
+        self.status = Some(Status {
+            registry,
+            unit,
+        });
+        self
+    }
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+    /// Answer clients until shutdown or until the transfer is aborted.
+    pub fn serve(self, controls: Controls, mut shutdown: LatchMonitor) -> io::Result<()> {
+        while !shutdown.active() && !controls.aborted() {
+            match self.listener.accept() {
+                // A client going away mid-session is its own business.
+                Ok((stream, _)) => {
+                    let _ = self.session(stream, &controls, &mut shutdown);
+                },
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    shutdown.wait_timeout(Self::POLL_INTERVAL);
+                },
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+    fn session(
+        &self,
+        stream: UnixStream,
+        controls: &Controls,
+        shutdown: &mut LatchMonitor,
+    ) -> io::Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(Self::POLL_INTERVAL))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = stream;
+        let mut line = vec![];
+        while !shutdown.active() && !controls.aborted() {
+            match reader.read_until(b'\n', &mut line) {
+                Ok(0) => break,
+                Ok(_) => {},
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    continue;
+                },
+                Err(e) => return Err(e),
+            }
+            let request = String::from_utf8_lossy(&line);
+            let reply = match (request.trim(), &self.status) {
+                ("status", Some(status)) => serde_json::to_string(&status.report())?,
+                _ => match request.parse::<Message>() {
+                    Ok(message) => {
+                        controls.send(message);
+                        "ok".to_owned()
+                    },
+                    Err(e) => format!("error: {}", e),
+                },
+            };
+            writeln!(writer, "{}", reply)?;
+            line.clear();
+        }
+        Ok(())
+    }
+}
+
+impl Status {
+    /// Subscribe afresh, so that the totals are as they are now.
+    fn report(&self) -> ProgressMessage {
+        let cumulative = self.registry.cumulative_progress().get();
+        ProgressMessage {
+            state: self.registry.transfer_state().get(),
+            progress: cumulative.progress,
+            elapsed_seconds: cumulative.elapsed().as_secs_f64(),
+            unit: self.unit.to_string(),
+            rate: self.unit.progress(&cumulative.average_rate_at(Instant::now())),
+            expected_size: None,
+            buffer_percent: None,
+            end: self.registry.end_cause().get(),
+        }
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        if let Some(path) = &self.path {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// One line of JSON written by a [`ProgressReporter`], such as
+///
+/// ```text
+/// {"state":"running","bytes_transferred":4096,"lines_transferred":12,"nulls_transferred":0,"words_transferred":30,"writes_transferred":1,"elapsed_seconds":1.5,"unit":"line","rate":8,"expected_size":8192,"buffer_percent":null}
+/// ```
+///
+/// `state` is one of `running`, `paused`, `cancelled`, `finished` or
+/// `failed`, and the last line written always has the final state, along
+/// with why the transfer ended in `end`: `eof`, `cancelled`,
+/// `downstream_closed`, `quota_reached` or `error`. Fields
+/// may be added but never removed or renamed, so readers should ignore any
+/// they do not know; [`crate::progress_fd`] parses these lines back.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProgressMessage {
+    pub state: TransferState,
+    #[serde(flatten)]
+    pub progress: TransferProgress,
+    pub elapsed_seconds: f64,
+    pub unit: String,
+    /// Instantaneous rate per second in `unit`.
+    pub rate: u64,
+    /// Bytes the transfer is expected to amount to, if known.
+    #[serde(default)]
+    pub expected_size: Option<u64>,
+    /// How full the `--buffer-size` buffer is, from 0 to 100, when reading
+    /// ahead. A buffer which stays full means the output is holding the
+    /// transfer back, and one which stays empty means the input is.
+    #[serde(default)]
+    pub buffer_percent: Option<f64>,
+    /// Why the transfer ended, once it has.
+    #[serde(default)]
+    pub end: Option<EndCause>,
+}
+
+impl ProgressMessage {
+    /// Fraction of the expected size transferred, if it is known.
+    pub fn ratio(&self) -> Option<f64> {
+        self.expected_size
+            .filter(|expected| *expected > 0)
+            .map(|expected| self.progress.bytes_transferred as f64 / expected as f64)
+    }
+}
+
+/// Writes a [`ProgressMessage`] every refresh until shutdown, and once more
+/// after it.
+pub struct ProgressReporter<W> {
+    out: W,
+    state: StateWatcher<TransferState>,
+    end: Option<StateWatcher<Option<EndCause>>>,
+    cumulative: CumulativeProgressMonitor,
+    rate: RateSampler,
+    unit: Unit,
+    expected_size: Option<NonZeroUsize>,
+    buffer: Option<OccupancyMonitor>,
+    shutdown: LatchMonitor,
+    refresh: Duration,
+}
+
+impl <W: Write> ProgressReporter<W> {
+    pub fn new(
+        out: W,
+        state: StateWatcher<TransferState>,
+        cumulative: CumulativeProgressMonitor,
+        rate: RateSampler,
+        unit: Unit,
+        shutdown: LatchMonitor,
+    ) -> Self {
+        Self {
+            out,
+            state,
+            end: None,
+            cumulative,
+            rate,
+            unit,
+            expected_size: None,
+            buffer: None,
+            shutdown,
+            refresh: Duration::from_secs(1),
+        }
+    }
+    /// Include the size the transfer is expected to amount to.
+    pub fn with_expected_size(mut self, expected_size: Option<NonZeroUsize>) -> Self {
+        self.expected_size = expected_size;
+        self
+    }
+    /// Include why the transfer ended, once it has.
+    pub fn with_end_cause(mut self, end: StateWatcher<Option<EndCause>>) -> Self {
+        self.end = Some(end);
+        self
+    }
+    /// Include how full the read-ahead buffer is.
+    pub fn with_buffer(mut self, buffer: Option<OccupancyMonitor>) -> Self {
+        self.buffer = buffer;
+        self
+    }
+    /// Report at most this often.
+    pub fn with_refresh(mut self, refresh: Duration) -> Self {
+        self.refresh = refresh;
+        self
+    }
+    pub fn run(mut self) -> io::Result<()> {
+        self.report()?;
+        while !self.shutdown.wait_timeout(self.refresh) {
+            self.report()?;
+        }
+        self.report()
+    }
+    fn report(&mut self) -> io::Result<()> {
+        let cumulative = self.cumulative.get();
+        let message = ProgressMessage {
+            state: self.state.get(),
+            progress: cumulative.progress,
+            elapsed_seconds: cumulative.elapsed().as_secs_f64(),
+            unit: self.unit.to_string(),
+            rate: self.unit.progress(&self.rate.sample()),
+            expected_size: self.expected_size.map(|size| size.get() as u64),
+            buffer_percent: self.buffer.as_mut()
+                .map(|buffer| buffer.get().percentage()),
+            end: self.end.as_mut().and_then(StateWatcher::get),
+        };
+        serde_json::to_writer(&mut self.out, &message)?;
+        writeln!(self.out)?;
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nonzero_ext::nonzero;
+
+    #[test]
+    fn messages_parse_from_single_lines() {
+        assert_eq!("pause\n".parse(), Ok(Message::Pause));
+        assert_eq!(" resume ".parse(), Ok(Message::Resume));
+        assert_eq!("limit 4Ki".parse(), Ok(Message::Limit(Some(nonzero!(4096u64)))));
+        assert_eq!("limit off".parse(), Ok(Message::Limit(None)));
+        for line in ["", "limit", "limit 0", "pause now", "stop"] {
+            line.parse::<Message>().expect_err("parse should have failed");
+        }
+    }
+
+    #[test]
+    fn status_reports_the_transfer_so_far() {
+        use std::io::Read;
+        use crate::{
+            config::{Config, ConfigMonitor, Latch},
+            sink::{SinkWriter, WatchSink},
+        };
+
+        let sink = WatchSink::new();
+        let registry = sink.registry();
+        let mut writer = SinkWriter::new(vec![], sink);
+        writer.write_all(b"one\ntwo\n").unwrap();
+        let path = std::env::temp_dir().join(format!("pvalve-status-{}", std::process::id()));
+        let socket = ControlSocket::bind(&path).unwrap().with_status(registry, Unit::Line);
+        let (config_tx, _) = ConfigMonitor::new(Config::default());
+        let controls = Controls::new(Config::default(), config_tx, Latch::new(), Latch::new());
+        let (server, mut client) = UnixStream::pair().unwrap();
+        client.write_all(b"status\npause\n").unwrap();
+        client.shutdown(std::net::Shutdown::Write).unwrap();
+        socket.session(server, &controls, &mut Latch::new().watch()).unwrap();
+        let mut replies = String::new();
+        client.read_to_string(&mut replies).unwrap();
+        let mut replies = replies.lines();
+        let status: ProgressMessage = serde_json::from_str(replies.next().unwrap()).unwrap();
+        assert_eq!(status.state, TransferState::Running);
+        assert_eq!(status.progress.lines_transferred, 2);
+        assert_eq!(status.unit, "line");
+        assert_eq!(replies.next(), Some("ok"));
+        assert!(controls.paused());
+    }
+
+}
@@ -0,0 +1,314 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    io::{
+        Result,
+        Write,
+    },
+    num::{
+        NonZeroU32,
+        NonZeroU64,
+    },
+    sync::{
+        Arc,
+        Mutex,
+    },
+};
+
+use governor::{
+    clock::DefaultClock,
+    state::keyed::DefaultKeyedStateStore,
+    NegativeMultiDecision,
+    NotUntil,
+    RateLimiter as GovernorRateLimiter,
+};
+
+use crate::{
+    config::ConfigMonitor,
+    progress::{
+        TransferProgress,
+        TransferProgressMonitor,
+    },
+    syncio::{
+        scaled_quota,
+        DefaultInstant,
+        Grant,
+        RateLimitedWriter,
+        RateLimiter,
+        Scaled,
+        TokenBucket,
+    },
+    unit::Scanner,
+    valve::SharedValve,
+};
+
+type KeyedRateLimiter<K> =
+    GovernorRateLimiter<K, DefaultKeyedStateStore<K>, DefaultClock>;
+
+/// Throttles many named streams, each to the same per-key limit, under an
+/// overall cap shared by all of them.
+///
+/// The overall cap follows the [`ConfigMonitor`] the valve was created with,
+/// while every key is held to the fixed per-key limit, each drawing on a
+/// budget of its own.
+pub struct KeyedValve<K: Hash + Eq + Clone>(Arc<KeyedShared<K>>);
+
+struct KeyedShared<K: Hash + Eq + Clone> {
+    per_key: KeyedRateLimiter<K>,
+    per_key_scale: NonZeroU64,
+    global: SharedValve,
+    progress: Mutex<HashMap<K, TransferProgress>>,
+}
+
+/// Writer for a single key of a [`KeyedValve`].
+pub struct KeyedValveWriter<K: Hash + Eq + Clone, W> {
+    inner: RateLimitedWriter<W, KeyedLimiter<K>>,
+    valve: KeyedValve<K>,
+    key: K,
+    scanner: Scanner,
+}
+
+/// Token source which charges both the key's budget and the overall cap.
+pub struct KeyedLimiter<K: Hash + Eq + Clone> {
+    valve: KeyedValve<K>,
+    global: SharedValve,
+    key: K,
+    /// Tokens charged to the key which the overall cap has yet to let
+    /// through, spent before the key is charged again.
+    credit: u32,
+}
+
+struct KeyedBucket<'a, K: Hash + Eq + Clone> {
+    limiter: &'a KeyedRateLimiter<K>,
+    key: &'a K,
+}
+
+impl <K: Hash + Eq + Clone> Clone for KeyedValve<K> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl <K: Hash + Eq + Clone> KeyedValve<K> {
+    pub fn new(config: ConfigMonitor, per_key: NonZeroU64) -> Self {
+        let (quota, per_key_scale) = scaled_quota(per_key);
+        Self(Arc::new(KeyedShared {
+            per_key: KeyedRateLimiter::keyed(quota),
+            per_key_scale,
+            global: SharedValve::new(config),
+            progress: Mutex::new(HashMap::new()),
+        }))
+    }
+    /// Wrap `writer` into one throttled under `key`.
+    pub fn writer<W>(&self, key: K, writer: W) -> KeyedValveWriter<K, W> {
+        let limiter = self.limiter(key.clone());
+        let config = self.0.global.config();
+        KeyedValveWriter {
+            inner: RateLimitedWriter::writer_with_limiter(
+                writer,
+                config,
+                limiter,
+            ),
+            valve: self.clone(),
+            key,
+            scanner: Scanner::default(),
+        }
+    }
+    /// Progress of every key which has written anything so far.
+    pub fn progress(&self) -> Vec<(K, TransferProgress)> {
+        self.0.progress
+            .lock()
+            .expect("keyed progress poisoned")
+            .iter()
+            .map(|(key, progress)| (key.clone(), *progress))
+            .collect()
+    }
+    /// Progress of a single key.
+    pub fn progress_of(&self, key: &K) -> TransferProgress {
+        self.0.progress
+            .lock()
+            .expect("keyed progress poisoned")
+            .get(key)
+            .copied()
+            .unwrap_or_default()
+    }
+    /// Combined progress of every key.
+    pub fn transfer_progress(&self) -> TransferProgressMonitor {
+        self.0.global.transfer_progress()
+    }
+    fn limiter(&self, key: K) -> KeyedLimiter<K> {
+        KeyedLimiter {
+            valve: self.clone(),
+            global: self.0.global.clone(),
+            key,
+            credit: 0,
+        }
+    }
+    fn add_progress(&self, key: &K, progress: TransferProgress) {
+        let mut all = self.0.progress.lock().expect("keyed progress poisoned");
+        let entry = all.entry(key.clone()).or_default();
+        *entry = *entry + progress;
+        drop(all);
+        self.0.global.add_progress(progress);
+    }
+}
+
+impl <K: Hash + Eq + Clone> RateLimiter for KeyedLimiter<K> {
+    fn request(&mut self, tokens: u32) -> Grant {
+        if tokens < 1 {
+            return Grant::default();
+        }
+        let charged = if self.credit > 0 {
+            let spent = self.credit.min(tokens);
+            self.credit -= spent;
+            Grant::immediate(spent)
+        } else {
+            let bucket = KeyedBucket {
+                limiter: &self.valve.0.per_key,
+                key: &self.key,
+            };
+            Scaled::new(bucket, self.valve.0.per_key_scale)
+                .wait_for_at_most(tokens)
+        };
+        let global = self.global.request(charged.tokens);
+        // Whatever the overall cap refused is still the key's to spend.
+        self.credit += charged.tokens.saturating_sub(global.tokens);
+        Grant {
+            blocked: charged.blocked || global.blocked,
+            ..global
+        }
+    }
+    fn set_limit(&mut self, limit: Option<NonZeroU64>) {
+        self.global.set_limit(limit);
+    }
+}
+
+impl <'a, K: Hash + Eq + Clone> TokenBucket for KeyedBucket<'a, K> {
+    fn check_one(&self) -> std::result::Result<(), NotUntil<DefaultInstant>> {
+        self.limiter.check_key(self.key)
+    }
+    fn check_many(
+        &self,
+        n: NonZeroU32,
+    ) -> std::result::Result<(), NegativeMultiDecision<NotUntil<DefaultInstant>>> {
+        self.limiter.check_key_n(self.key, n)
+    }
+}
+
+impl <K: Hash + Eq + Clone, W> KeyedValveWriter<K, W> {
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+    pub fn get_ref(&self) -> &W {
+        self.inner.get_ref()
+    }
+}
+
+impl <K: Hash + Eq + Clone, W: Write> Write for KeyedValveWriter<K, W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let bytes_transferred = self.inner.write(buf)?;
+        let slice = &buf[..bytes_transferred];
+        let progress = TransferProgress::scan(&mut self.scanner, slice);
+        self.valve.add_progress(&self.key, progress);
+        Ok(bytes_transferred)
+    }
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        thread,
+        time::{
+            Duration,
+            Instant,
+        },
+    };
+    use nonzero_ext::nonzero;
+    use crate::config::Config;
+
+    fn valve(limit: NonZeroU64, per_key: NonZeroU64) -> KeyedValve<&'static str> {
+        let (_tx, config) = ConfigMonitor::new(Config {
+            limit: Some(limit).into(),
+            ..Default::default()
+        });
+        KeyedValve::new(config, per_key)
+    }
+
+    #[test]
+    fn progress_is_tracked_per_key_and_overall() {
+        let (_tx, config) = ConfigMonitor::new(Config {
+            limit: Some(nonzero!(100_000u64)).into(),
+            ..Default::default()
+        });
+        let valve = KeyedValve::new(config, nonzero!(10_000u64));
+        let mut total = valve.transfer_progress();
+        let mut a = valve.writer("a", Vec::new());
+        let mut b = valve.writer("b", Vec::new());
+        a.write_all(b"one\ntwo\n").unwrap();
+        b.write_all(b"three\n").unwrap();
+        assert_eq!(valve.progress_of(&"a").lines_transferred, 2);
+        assert_eq!(valve.progress_of(&"b").bytes_transferred, 6);
+        assert_eq!(valve.progress_of(&"c"), TransferProgress::default());
+        let mut keys: Vec<_> = valve.progress()
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        keys.sort();
+        assert_eq!(keys, vec!["a", "b"]);
+        assert_eq!(total.get().bytes_transferred, 14);
+    }
+
+    #[test]
+    fn each_key_is_held_to_the_per_key_limit() {
+        // A tenth of a second's worth goes through at once, the rest at
+        // the per-key rate.
+        let valve = valve(nonzero!(10_000_000u64), nonzero!(10_000u64));
+        let mut a = valve.writer("a", Vec::new());
+        let mut b = valve.writer("b", Vec::new());
+        let started = Instant::now();
+        a.write_all(&[b'x'; 6000]).unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(400), "{:?}", started.elapsed());
+        // The other key has a budget of its own.
+        let started = Instant::now();
+        b.write_all(&[b'x'; 1000]).unwrap();
+        assert!(started.elapsed() < Duration::from_millis(200), "{:?}", started.elapsed());
+    }
+
+    #[test]
+    fn all_keys_together_are_held_to_the_overall_cap() {
+        let valve = valve(nonzero!(10_000u64), nonzero!(10_000_000u64));
+        let started = Instant::now();
+        let threads: Vec<_> = ["a", "b"]
+            .into_iter()
+            .map(|key| {
+                let mut writer = valve.writer(key, Vec::new());
+                thread::spawn(move || writer.write_all(&[b'x'; 3000]).unwrap())
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert!(started.elapsed() >= Duration::from_millis(400), "{:?}", started.elapsed());
+        assert_eq!(valve.transfer_progress().get().bytes_transferred, 6000);
+    }
+
+    #[test]
+    fn tokens_refused_by_the_overall_cap_stay_with_the_key() {
+        let valve = valve(nonzero!(10_000u64), nonzero!(10_000_000u64));
+        let mut limiter = valve.limiter("a");
+        let mut granted = limiter.request(3000).tokens;
+        assert!(granted < 3000);
+        assert_eq!(limiter.credit, 3000 - granted);
+        // The rest goes through the overall cap without charging the key
+        // a second time.
+        while limiter.credit > 0 {
+            granted += limiter.request(3000 - granted).tokens;
+        }
+        assert_eq!(granted, 3000);
+    }
+
+}
@@ -0,0 +1,171 @@
+use std::{
+    io,
+    time::Duration,
+};
+
+use crossterm::{
+    event::{
+        poll,
+        read,
+        Event,
+        KeyCode,
+        KeyEvent,
+        KeyEventKind,
+        KeyModifiers,
+    },
+    terminal,
+};
+
+use super::{
+    config::{Latch, LatchMonitor},
+    control::Controls,
+    instantaneous::RateSampler,
+    ipc::Message,
+    progress::TransferProgress,
+    widgets::{EditRateResponse, EditRateState, KeyboardInput as _},
+};
+
+/// The message one of the keys every interface shares stands for, if `key`
+/// is one of them.
+///
+/// `observed` is the rate the arrow keys start from when there is no limit
+/// yet.
+pub fn message(key: KeyEvent, observed: &TransferProgress) -> Option<Message> {
+    let message = match key {
+        KeyEvent { code: KeyCode::Char(' '), .. } => Message::TogglePause,
+        KeyEvent { code: KeyCode::Left, .. } => Message::Slower(*observed),
+        KeyEvent { code: KeyCode::Right, .. } => Message::Faster(*observed),
+        KeyEvent { code: KeyCode::Char('`'), .. } => Message::ToggleLimit(*observed),
+        KeyEvent { code: KeyCode::Tab, .. } => Message::CycleUnit,
+        KeyEvent { code: KeyCode::BackTab, .. } => Message::CycleDisplayUnit,
+        KeyEvent { code: KeyCode::Char('a'), .. } => Message::ToggleAllUnits,
+        KeyEvent { code: KeyCode::Char('u'), .. } => Message::Undo,
+        KeyEvent {
+            code: KeyCode::Char('c'),
+            modifiers: KeyModifiers::CONTROL,
+            ..
+        } => Message::Abort,
+        _ => return None,
+    };
+    Some(message)
+}
+
+/// Reads the pause and rate keys from the terminal in raw mode, without
+/// drawing anything, for when there is no full-screen interface to take
+/// them.
+///
+/// Only usable while stdin is not the terminal, since otherwise the keys
+/// would be taken out of the data being copied.
+///
+/// As in the full-screen interface, `e` starts typing a new rate, which
+/// enter applies and escape abandons.
+pub struct KeyListener {
+    controls: Controls,
+    shutdown: LatchMonitor,
+    instantaneous: RateSampler,
+    /// The rate being typed, if any.
+    edit: Option<EditRateState>,
+    editing: Latch,
+}
+
+/// Leaves raw mode once the listener stops, however it stops.
+struct RawMode;
+
+impl RawMode {
+    fn enable() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        Ok(Self)
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+impl KeyListener {
+    /// How long to wait for a key before checking for shutdown again.
+    const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+    pub fn new(
+        controls: Controls,
+        shutdown: LatchMonitor,
+        instantaneous: RateSampler,
+    ) -> Self {
+        Self {
+            controls,
+            shutdown,
+            instantaneous,
+            edit: None,
+            editing: Latch::new(),
+        }
+    }
+    /// Active while a new rate is being typed.
+    pub fn editing(&mut self) -> LatchMonitor {
+        self.editing.watch()
+    }
+    /// Act on keys until shutdown or until the transfer is aborted.
+    pub fn run(mut self) -> io::Result<()> {
+        let _raw = RawMode::enable()?;
+        while !self.shutdown.active() && !self.controls.aborted() {
+            if !poll(Self::POLL_INTERVAL)? {
+                continue;
+            }
+            let event = read()?;
+            if matches!(event, Event::Key(key) if key.kind == KeyEventKind::Release) {
+                continue;
+            }
+            if let Some(edit) = &mut self.edit {
+                match edit.input(event) {
+                    Some(EditRateResponse::NewRate(rate)) => {
+                        self.controls.send(Message::Limit(Some(rate)));
+                        self.stop_editing();
+                    },
+                    Some(EditRateResponse::Cancelled) => self.stop_editing(),
+                    None => {},
+                }
+            } else if let Event::Key(key) = event {
+                if key.code == KeyCode::Char('e') {
+                    self.edit = Some(EditRateState::new());
+                    self.editing.on();
+                    continue;
+                }
+                let observed = self.instantaneous.sample();
+                if let Some(message) = message(key, &observed) {
+                    self.controls.send(message);
+                }
+            }
+        }
+        Ok(())
+    }
+    fn stop_editing(&mut self) {
+        self.edit = None;
+        self.editing.off();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    #[test]
+    fn shared_keys_stand_for_messages() {
+        let observed = TransferProgress {
+            bytes_transferred: 1234,
+            ..Default::default()
+        };
+        assert_eq!(message(press(KeyCode::Char(' ')), &observed), Some(Message::TogglePause));
+        assert_eq!(message(press(KeyCode::Left), &observed), Some(Message::Slower(observed)));
+        assert_eq!(message(press(KeyCode::Char('u')), &observed), Some(Message::Undo));
+        assert_eq!(message(press(KeyCode::Char('a')), &observed), Some(Message::ToggleAllUnits));
+        assert_eq!(message(press(KeyCode::Char('e')), &observed), None);
+        let ctrl_c = KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL);
+        assert_eq!(message(ctrl_c, &observed), Some(Message::Abort));
+    }
+
+}
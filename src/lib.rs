@@ -1,8 +1,73 @@
+#[cfg(feature = "full")]
 pub mod cli;
+#[cfg(feature = "full")]
+pub mod completions;
 pub mod unit;
+pub mod size;
+pub mod range;
+pub mod format;
+pub mod status_line;
+pub mod template;
+pub mod alarm;
+pub mod state;
 pub mod config;
 pub mod progress;
 pub mod syncio;
+pub mod pump;
+pub mod inputs;
+pub mod readahead;
+#[cfg(feature = "archive")]
+pub mod archive;
+pub mod throttled;
+pub mod valve;
+pub mod keyed;
+pub mod buffering;
+pub mod backpressure;
+pub mod summary;
+#[cfg(feature = "full")]
+pub mod plan;
+pub mod selftest;
+pub mod audit;
+pub mod checkpoint;
+pub mod fanout;
+pub mod sample;
+pub mod annotate;
+pub mod filter;
+pub mod delay;
+pub mod replay;
+pub mod atomic;
+pub mod payload;
+pub mod hooks;
 pub mod instantaneous;
+pub mod sink;
+pub mod control;
+pub mod pacing;
+pub mod gate;
+pub mod space;
+pub mod window;
+pub mod watchdog;
+#[cfg(feature = "evented")]
+pub mod evented;
+#[cfg(feature = "full")]
+pub mod keys;
+pub mod ipc;
+pub mod progress_fd;
+pub mod rate_log;
+pub mod report;
+pub mod history;
+pub mod shm;
+pub mod platform;
+#[cfg(feature = "systemd")]
+pub mod systemd;
+#[cfg(feature = "full")]
 pub mod widgets;
+#[cfg(feature = "full")]
 pub mod tui;
+#[cfg(feature = "clipboard")]
+pub mod clipboard;
+#[cfg(feature = "full")]
+pub mod status;
+#[cfg(feature = "full")]
+pub mod a11y;
+#[cfg(not(feature = "full"))]
+pub mod minimal;
@@ -1,66 +1,619 @@
+#[cfg(feature = "full")]
 use std::{
-    io::{self, copy},
-    time::Instant,
+    fs::{File, OpenOptions},
+    io::{self, BufReader, BufWriter, Read, Write},
+    num::NonZeroUsize,
+    os::fd::AsFd as _,
     thread,
+    time::Instant,
 };
 
+#[cfg(feature = "full")]
+use clap::CommandFactory as _;
+#[cfg(feature = "full")]
 use crossterm::tty::IsTty;
+#[cfg(feature = "full")]
 use pvalve::{
     config::{
         Config,
         ConfigMonitor,
         Latch,
     },
-    cli::Opts,
-    syncio::WriteExt as _,
-    tui::{Cleanup, UserInterface},
+    cli::{AuditInvocation, Command, Invocation, Opts, SelfTestInvocation},
+    audit::{read_log, AuditReport},
+    checkpoint::{self, Checkpoint, StateFile},
+    fanout::Outputs,
+    filter::{Filter, LimitSide},
+    inputs::InputFiles,
+    completions,
+    backpressure::{LimiterActivity, TimedReader},
+    history::{History, HistoryEntry},
+    hooks::HookRunner,
+    plan::Plan,
+    selftest::SelfTest,
+    platform,
+    ipc::{ControlSocket, ProgressReporter},
+    shm::{SharedProgress, ShmPublisher},
+    control::Controls,
+    pacing::{self, Pacer},
+    payload::{PayloadMonitor, PayloadScanner},
+    gate::Gate,
+    space::FreeSpace,
+    window::WindowScheduler,
+    watchdog::{Watchdog, WriteTimedOut},
+    unit::Unit,
+    progress::TransferProgress,
+    rate_log::RateLog,
+    report::IntervalReport,
+    replay::Timing,
+    keys::KeyListener,
+    instantaneous::{RateEstimator, RateSampler},
+    pump::Pump,
+    readahead::ReadAhead,
+    sink::EndCause,
+    summary::Summary,
+    syncio::{RateLimitedWriter, WriteExt as _},
+    throttled::{Features, Throttled},
+    status::StatusLine,
+    a11y::Announcer,
+    tui::{Cleanup, Monitors, Screen, UserInterface},
 };
 
+#[cfg(all(feature = "full", feature = "evented"))]
+use pvalve::evented::EventedPump;
+#[cfg(all(feature = "full", feature = "archive"))]
+use pvalve::archive::TarStream;
+
+#[cfg(feature = "full")]
 fn main() -> anyhow::Result<()> {
-    let invo = Opts::parse_process_args();
+    match Opts::parse_process_args() {
+        Command::Copy(invo) => run_copy(invo, Features::default()),
+        Command::Watch(invo) | Command::Replay(invo) => run_copy(invo, Features {
+            limiting: false,
+            ..Features::default()
+        }),
+        Command::Audit(invo) => run_audit(invo),
+        Command::SelfTest(invo) => run_selftest(invo),
+        Command::ReadShm(name) => {
+            println!("{}", SharedProgress::open(&name)?.read()?.to_json());
+            Ok(())
+        },
+        Command::History(count) => {
+            let history = History::default_path()
+                .map(History::new)
+                .ok_or_else(|| anyhow::anyhow!("no home directory to find the history in"))?;
+            for entry in history.recent(count)? {
+                println!("{}", entry);
+            }
+            Ok(())
+        },
+        Command::Completions(shell) => {
+            let mut stdout = io::stdout().lock();
+            completions::generate(shell, &mut Opts::command(), &mut stdout)?;
+            Ok(())
+        },
+    }
+}
 
-    let limit = invo.speed.map(|s| s.0).into();
-    let unit = invo.unit;
-    let expected_size = invo.expected_size;
+#[cfg(feature = "full")]
+fn run_audit(invo: AuditInvocation) -> anyhow::Result<()> {
+    let log = BufReader::new(File::open(&invo.log)?);
+    let records = read_log(log)?;
+    let limit = invo.speed.map(|s| s.per_second);
+    print!("{}", AuditReport::analyze(&records, invo.unit, limit));
+    Ok(())
+}
 
-    let config = Config { limit, unit, expected_size };
+#[cfg(feature = "full")]
+fn run_selftest(invo: SelfTestInvocation) -> anyhow::Result<()> {
+    let defaults = SelfTest::default();
+    let selftest = SelfTest {
+        size: invo.size.get(),
+        rates: if invo.skip_limiter { vec![] } else { defaults.rates },
+    };
+    print!("{}", selftest.run()?);
+    Ok(())
+}
 
-    let (config_tx, config_rx) = ConfigMonitor::new(config);
+#[cfg(feature = "full")]
+fn run_copy(mut invo: Invocation, features: Features) -> anyhow::Result<()> {
+
+    if invo.sample.is_some() && invo.unit.delimiter().is_none() {
+        anyhow::bail!("--sample needs records, select lines or nulls as the unit");
+    }
+    if invo.number_lines && invo.unit.delimiter().is_none() {
+        anyhow::bail!("--number-lines needs records, select lines or nulls as the unit");
+    }
+    if invo.timestamp_records.is_some() && invo.unit.delimiter().is_none() {
+        anyhow::bail!("--timestamp-records needs records, select lines or nulls as the unit");
+    }
+    if invo.speed.is_some_and(|speed| speed.bits) && invo.unit != Unit::Byte {
+        anyhow::bail!("limits in bits only apply to bytes");
+    }
+    if !invo.headless && invo.render.is_simple() {
+        invo.no_altscreen = true;
+    }
+    if invo.dry_run {
+        println!("{}", Plan::new(&invo, features.limiting).to_json());
+        return Ok(());
+    }
+    if invo.idle_io {
+        platform::idle_priority()?;
+    }
 
     let stdin = io::stdin();
     let stdout = io::stdout();
 
+    let inputs = match invo.inputs.as_slice() {
+        [] => None,
+        paths => Some(InputFiles::open(paths)?),
+    };
+    let input_is_tty = inputs.is_none() && invo.tar.is_none() && stdin.is_tty();
+    let interactive_mode = !input_is_tty && !stdout.is_tty();
+    let mut input = File::from(stdin.as_fd().try_clone_to_owned()?);
+    if let Some(range) = invo.range {
+        range.seek(&mut input)?;
+    }
+    let range_size = invo.range.and_then(|range| {
+        match checkpoint::input_size(&input) {
+            Some(size) => Some(range.len_within(size)),
+            None => range.len(),
+        }
+    });
+    #[cfg(feature = "archive")]
+    let archive = invo.tar.as_deref().map(TarStream::open).transpose()?;
+    let files = inputs.as_ref().map(InputFiles::monitor);
+    let input_size = inputs.as_ref()
+        .map_or(range_size, InputFiles::total_size);
+    #[cfg(feature = "archive")]
+    let input_size = archive.as_ref().map(TarStream::size).or(input_size);
+    let reader: Box<dyn Read + Send> = match inputs {
+        Some(inputs) => Box::new(inputs),
+        None => Box::new(stdin.take(range_size.unwrap_or(u64::MAX))),
+    };
+    #[cfg(feature = "archive")]
+    let reader = match archive {
+        Some(archive) => Box::new(archive),
+        None => reader,
+    };
+    let (reader, occupancy) = match invo.buffer_size {
+        Some(size) => {
+            let read_ahead = ReadAhead::spawn(reader, size, invo.buffer_watermarks)?;
+            let occupancy = read_ahead.occupancy();
+            (Box::new(read_ahead) as Box<dyn Read>, Some(occupancy))
+        },
+        None => (reader as Box<dyn Read>, None),
+    };
+    let mut stdin = TimedReader::new(reader);
+    let mut stdout = File::from(stdout.as_fd().try_clone_to_owned()?);
+
+    let timing = invo.timing
+        .as_deref()
+        .map(|path| {
+            File::open(path)
+                .map(BufReader::new)
+                .and_then(Timing::read)
+                .map_err(|e| io::Error::new(
+                    e.kind(),
+                    format!("timing {}: {}", path.display(), e),
+                ))
+        })
+        .transpose()?;
+
+    let unit = invo.unit;
+    // A replay is as long as its recording.
+    let expected_size = invo.expected_size.or_else(|| {
+        input_size
+            .or_else(|| timing.as_ref().map(Timing::size))
+            .and_then(|size| usize::try_from(size).ok())
+            .and_then(NonZeroUsize::new)
+    });
+    // Paced transfers start out at the pace, not flat out.
+    let base_limit = invo.speed
+        .map(|s| s.per_second)
+        .or_else(|| {
+            let (target, expected) = invo.target_duration.zip(expected_size)?;
+            pacing::pace(expected.get() as u64, &TransferProgress::default(), unit, target)
+        });
+    // As do transfers within a window.
+    let window = invo.windows.as_ref().and_then(|windows| {
+        let time = platform::local_time_of_day().ok()?;
+        windows.at((time.as_secs() / 60) as u16)
+    });
+    let limit = window.map_or(base_limit, |window| window.limit).into();
+    let flush_every_record = invo.flush_every_record;
+    let number_format = invo.number_format;
+    let shown_unit = invo.display_unit.unwrap_or(unit);
+
+    let config = Config {
+        limit,
+        unit,
+        display_unit: invo.display_unit,
+        expected_size,
+        flush_every_record,
+        record_atomic: invo.record_atomic,
+        number_format,
+        show_all_units: invo.show_all_units,
+        colored_gauge: invo.colored_gauge,
+        rate_gauge: invo.rate_gauge_max,
+        min_rate: invo.min_rate,
+    };
+
+    let (config_tx, config_rx) = ConfigMonitor::new(config);
+
     let mut shutdown = Latch::new();
     let mut paused = Latch::new();
     let mut aborted = Latch::new();
 
-    let interactive_mode = !stdin.is_tty() && !stdout.is_tty();
-    let mut stdout = stdout.limited(config_rx)
+    let state_file = invo.state_file.clone().map(StateFile::new);
+    let mut resumed = Checkpoint {
+        input_size: checkpoint::input_size(&input),
+        ..Checkpoint::default()
+    };
+    if let Some(checkpoint) = state_file.as_ref().map(StateFile::load).transpose()?.flatten() {
+        checkpoint::resume(&checkpoint, &mut input, &mut stdout)?;
+        resumed = checkpoint;
+    }
+    // A filter takes the place of stdout, which gets whatever it writes.
+    let limit_filter_output = invo.limit_side == LimitSide::Out && features.limiting;
+    let mut filter = None;
+    let mut filter_progress = None;
+    if let Some(command) = &invo.filter {
+        let output = if limit_filter_output {
+            stdout.limited(config_rx.clone())
+        } else {
+            RateLimitedWriter::unlimited(stdout)
+        };
+        let mut output = output.progress();
+        filter_progress = Some((output.transfer_progress(), output.transfer_progress()));
+        let (spawned, stdin) = Filter::spawn(command, output)?;
+        filter = Some(spawned);
+        stdout = stdin;
+    }
+    let audit_log = invo.audit_pacing
+        .as_ref()
+        .map(File::create)
+        .transpose()?
+        .map(BufWriter::new);
+    let (outputs, output_threads, output_monitors) =
+        Outputs::open(&invo.outputs, unit)?;
+    let stdout = stdout.watched();
+    // The evented engine times out stalled writes itself.
+    let watchdog = invo.write_timeout.filter(|_| !invo.evented()).map(|timeout| {
+        let watchdog = Watchdog::new(stdout.in_flight(), timeout, shutdown.watch());
+        thread::spawn(move || {
+            // The blocked write never returns, so there is no unwinding
+            // the copy, only leaving the terminal as it was found.
+            if let Err(e) = watchdog.run() {
+                Cleanup();
+                eprintln!("Error: {}", e);
+                std::process::exit(WriteTimedOut::EXIT_CODE);
+            }
+        })
+    });
+    let sampled = stdout
+        .checkpointed(state_file.clone(), resumed)
+        .count_bytes_only(invo.lightweight)
+        .fan_out(outputs)
+        .sampled(unit, invo.sample)
+        .count_bytes_only(invo.lightweight);
+    let (forwarded, forwarded_summary) = filter_progress.unzip();
+    let forwarded = forwarded
+        .or_else(|| invo.sample.map(|_| sampled.forwarded_progress()));
+    let mut forwarded_summary = forwarded_summary
+        .or_else(|| invo.sample.map(|_| sampled.forwarded_progress()));
+    let mut stdout = Throttled::new(
+        sampled
+            .annotated(unit)
+            .with_numbers(invo.number_lines)
+            .with_timestamps(invo.timestamp_records)
+            .delayed(unit, invo.latency)
+            .replayed(timing)
+            .buffered(invo.output_buffering)
+            .audited(audit_log)
+            .record_atomic(config_rx.clone())
+            .net_bytes(invo.net_bytes.map(|header| PayloadScanner::for_unit(unit, header))),
+        config_rx,
+    )
         .pauseable(paused.watch())
         .cancellable(aborted.watch())
-        .instantaneous(std::time::Duration::from_secs(1));
-    let instantaneous_progress = stdout.transfer_progress();
-    let mut stdout = stdout.progress();
-    let absolute_progress = stdout.transfer_progress();
-    let ui = if interactive_mode {
-        let ui = UserInterface::new(
-            paused,
-            aborted,
+        .count_final_partial(invo.count_final_partial.then_some(unit))
+        .count_bytes_only(invo.lightweight)
+        .resumed_from(resumed.progress);
+    stdout.set_features(features);
+    if limit_filter_output && filter.is_some() {
+        stdout.set_limiting(false);
+    }
+    let mut pump = Pump::default()
+        .chunk_size(invo.max_chunk.unwrap_or(Pump::DEFAULT_CHUNK_SIZE))
+        .count_bytes_only(invo.lightweight)
+        .cancel_on(aborted.watch());
+    let mut cancelled = aborted.watch();
+    let mut backpressure = stdin.backpressure().join(stdout.backpressure());
+    #[cfg(feature = "evented")]
+    let mut evented = invo.evented()
+        .then(|| EventedPump::new(&io::stdin(), &io::stdout()))
+        .transpose()?
+        .map(|evented| {
+            evented
+                .chunk_size(invo.max_chunk.unwrap_or(Pump::DEFAULT_CHUNK_SIZE))
+                .count_bytes_only(invo.lightweight)
+                .cancel_on(aborted.watch())
+                .with_write_timeout(invo.write_timeout)
+        });
+    #[cfg(feature = "evented")]
+    if let Some(evented) = &mut evented {
+        backpressure = backpressure.join(evented.backpressure());
+    }
+    let instantaneous_progress = RateSampler::new(
+        stdout.sink().transfer_progress(),
+        RateEstimator::with_smoothing(invo.rate_window, invo.rate_smoothing),
+    );
+    let absolute_progress = stdout.sink().cumulative_progress();
+    let mut summary_progress = stdout.sink().cumulative_progress();
+    let payload = stdout.get_ref().payload();
+    let mut payload_summary = payload.clone();
+    let mut monitors = Monitors {
+        cumulative: absolute_progress,
+        instantaneous: instantaneous_progress,
+        backpressure: backpressure.clone(),
+        limiter: LimiterActivity::new(stdout.backpressure(), invo.rate_window),
+        outputs: output_monitors,
+        forwarded,
+        files,
+        gate: None,
+        space: None,
+        window: None,
+        buffer: occupancy.clone(),
+        payload,
+    };
+    let hooks = (!invo.hooks.is_empty()).then(|| {
+        let runner = HookRunner::new(
+            invo.hooks.clone(),
+            stdout.sink().transfer_state(),
+            stdout.sink().cumulative_progress(),
+            RateSampler::new(
+                stdout.sink().transfer_progress(),
+                RateEstimator::with_smoothing(invo.rate_window, invo.rate_smoothing),
+            ),
+            invo.min_rate,
+            unit,
+        );
+        thread::spawn(move || runner.run())
+    });
+    let controls = Controls::new(config, config_tx, paused, aborted);
+    let pacer = invo.target_duration
+        .zip(expected_size)
+        .map(|(target, expected_size)| {
+            let pacer = Pacer::new(
+                controls.clone(),
+                stdout.sink().cumulative_progress(),
+                expected_size,
+                Instant::now() + target,
+                shutdown.watch(),
+            )
+                .with_refresh(invo.refresh_rate);
+            thread::spawn(move || pacer.run())
+        });
+    let mut gate = invo.gate.clone().map(|command| {
+        Gate::command(command, controls.clone(), shutdown.watch())
+            .with_interval(invo.gate_interval)
+    });
+    monitors.gate = gate.as_mut().map(Gate::closed);
+    let gate = gate.map(|gate| thread::spawn(move || gate.run()));
+    let mut space_gate = None;
+    if let Some(min_free) = invo.min_free {
+        let mut space = FreeSpace::default()
+            .with_file(File::from(io::stdout().as_fd().try_clone_to_owned()?));
+        for output in &invo.outputs {
+            if let Ok(file) = File::open(&output.path) {
+                space = space.with_file(file);
+            }
+        }
+        if space.is_empty() {
+            eprintln!("!!! --min-free IGNORED: not writing to any file !!!");
+        } else {
+            space_gate = Some(
+                Gate::new(move || space.at_least(min_free.get()), controls.clone(), shutdown.watch())
+                    .with_interval(FreeSpace::INTERVAL)
+            );
+        }
+    }
+    monitors.space = space_gate.as_mut().map(Gate::closed);
+    let space_gate = space_gate.map(|gate| thread::spawn(move || gate.run()));
+    let scheduler = invo.windows.clone().map(|windows| {
+        WindowScheduler::new(windows, base_limit, controls.clone(), shutdown.watch())
+    });
+    monitors.window = scheduler.as_ref().map(WindowScheduler::active);
+    let scheduler = scheduler.map(|scheduler| thread::spawn(move || scheduler.run()));
+    let rate_log = invo.rate_log
+        .as_ref()
+        .map(File::create)
+        .transpose()?
+        .map(|log| {
+            let log = RateLog::new(
+                BufWriter::new(log),
+                stdout.sink().cumulative_progress(),
+                RateSampler::new(
+                    stdout.sink().transfer_progress(),
+                    RateEstimator::with_smoothing(invo.rate_window, invo.rate_smoothing),
+                ),
+                controls.clone(),
+                shutdown.watch(),
+            );
+            thread::spawn(move || log.run())
+        });
+    let report = invo.report_interval
+        .map(|interval| -> io::Result<_> {
+            let out: Box<dyn Write + Send> = match &invo.report_file {
+                Some(path) => Box::new(OpenOptions::new().create(true).append(true).open(path)?),
+                None => Box::new(io::stderr()),
+            };
+            let report = IntervalReport::new(
+                out,
+                stdout.sink().cumulative_progress(),
+                stdout.sink().transfer_state(),
+                shown_unit,
+                interval,
+                shutdown.watch(),
+            )
+                .with_format(number_format);
+            Ok(thread::spawn(move || report.run()))
+        })
+        .transpose()?;
+    let full_screen = interactive_mode
+        && !invo.no_altscreen
+        && !invo.headless
+        && invo.a11y.is_none();
+    if invo.keys && !full_screen && input_is_tty {
+        anyhow::bail!("--keys needs stdin to be something other than the terminal");
+    }
+    // Announcements take the keys wherever the full-screen interface would.
+    let listening = invo.keys || (invo.a11y.is_some() && interactive_mode);
+    let mut editing = None;
+    // Keys go to the listener or to the full-screen interface, never both.
+    let keys = (listening && !full_screen).then(|| {
+        let mut listener = KeyListener::new(
+            controls.clone(),
             shutdown.watch(),
-            config,
-            absolute_progress,
-            instantaneous_progress,
-            config_tx,
-        )?;
-        Some(thread::spawn(|| ui.run(Instant::now())))
-    } else {
-        eprintln!(
-            "!!! INTERACTIVE MODE DISABLED: \
-            either stdin or stdout is not a tty !!!"
+            RateSampler::new(
+                stdout.sink().transfer_progress(),
+                RateEstimator::with_smoothing(invo.rate_window, invo.rate_smoothing),
+            ),
         );
+        editing = Some(listener.editing());
+        thread::spawn(move || listener.run())
+    });
+    #[cfg(feature = "systemd")]
+    let activated = invo.headless
+        .then(pvalve::systemd::activated_listener)
+        .flatten()
+        .map(ControlSocket::from_listener)
+        .transpose()?;
+    #[cfg(not(feature = "systemd"))]
+    let activated = None;
+    let socket = match &invo.control {
+        Some(path) => Some(ControlSocket::bind(path)?),
+        None => activated,
+    };
+    let control = socket.map(|socket| {
+        let socket = socket.with_status(stdout.sink().registry(), shown_unit);
+        let controls = controls.clone();
+        let shutdown = shutdown.watch();
+        thread::spawn(move || socket.serve(controls, shutdown))
+    });
+    #[cfg(feature = "systemd")]
+    let notifier = {
+        use pvalve::systemd::{Notifier, StatusNotifier};
+        let notifier = StatusNotifier::new(
+            Notifier::from_env()?,
+            stdout.sink().cumulative_progress(),
+            shown_unit,
+            number_format,
+            shutdown.watch(),
+        )
+            .with_refresh(invo.refresh_rate);
+        thread::spawn(move || notifier.run())
+    };
+    let progress_report = invo.progress_fd.map(|fd| {
+        let out = File::from(platform::duplicate_inherited(fd)?);
+        let reporter = ProgressReporter::new(
+            out,
+            stdout.sink().transfer_state(),
+            stdout.sink().cumulative_progress(),
+            RateSampler::new(
+                stdout.sink().transfer_progress(),
+                RateEstimator::with_smoothing(invo.rate_window, invo.rate_smoothing),
+            ),
+            unit,
+            shutdown.watch(),
+        )
+            .with_expected_size(expected_size)
+            .with_end_cause(stdout.sink().end_cause())
+            .with_buffer(occupancy.clone())
+            .with_refresh(invo.refresh_rate);
+        io::Result::Ok(thread::spawn(move || reporter.run()))
+    }).transpose()?;
+    let shm_publisher = invo.shm_progress
+        .as_deref()
+        .map(SharedProgress::create)
+        .transpose()?
+        .map(|shared| {
+            let publisher = ShmPublisher::new(
+                shared,
+                stdout.sink().cumulative_progress(),
+                shutdown.watch(),
+            );
+            thread::spawn(move || publisher.run())
+        });
+    let screen = if !full_screen {
+        None
+    } else {
+        Screen::enter()
+            .map_err(|e| eprintln!(
+                "!!! INTERACTIVE MODE DISABLED: {}; \
+                reporting progress on stderr instead !!!",
+                e,
+            ))
+            .ok()
+    };
+    let mut status = None;
+    let ui = if invo.headless {
+        None
+    } else if let Some(interval) = invo.a11y {
+        let mut announcer = Announcer::new(controls, monitors, shutdown.watch())
+            .with_interval(interval);
+        if let Some(editing) = editing {
+            announcer = announcer.with_editing(editing);
+        }
+        status = Some(thread::spawn(move || announcer.run()));
+        None
+    } else if let Some(screen) = screen {
+        let ui = UserInterface::new(screen, controls, shutdown.watch(), monitors)?
+            .with_mouse(invo.mouse)?
+            .with_confirm_abort(invo.confirm_abort)
+            .with_pause_on_suspend(invo.pause_on_suspend)
+            .with_template(invo.progress_format.clone())
+            .with_refresh(invo.refresh_rate);
+        Some(thread::spawn(move || ui.run()))
+    } else if invo.no_altscreen || interactive_mode {
+        let line = StatusLine::new(config, monitors, shutdown.watch())
+            .with_template(invo.progress_format.clone())
+            .with_refresh(invo.refresh_rate);
+        status = Some(thread::spawn(move || line.run()));
+        None
+    } else {
+        if keys.is_none() {
+            eprintln!(
+                "!!! INTERACTIVE MODE DISABLED: \
+                either stdin or stdout is not a tty !!!"
+            );
+        }
         None
     };
-    let copy_result = copy(&mut stdin.lock(), &mut stdout);
+    #[cfg(feature = "evented")]
+    let copied = match &mut evented {
+        Some(evented) => evented.copy(&mut stdin, &mut stdout),
+        None => pump.copy(&mut stdin, &mut stdout),
+    };
+    #[cfg(not(feature = "evented"))]
+    let copied = pump.copy(&mut stdin, &mut stdout);
+    let copy_result = copied.and_then(|copied| stdout.drain().map(|_| copied));
+    if copy_result.is_err() {
+        // Drain what every writer still holds, so that none of it is lost
+        // and a later run can resume from whatever made it out.
+        let _ = stdout.drain();
+    }
+    let end = EndCause::classify(
+        &copy_result,
+        cancelled.active(),
+        invo.range.and_then(|range| range.len()),
+    );
+    stdout.end(end);
+    // Closing the filter's stdin lets it finish, and whatever it still
+    // holds is written out before the interface goes.
+    drop(stdout);
+    let filtered = filter.map(Filter::wait).transpose();
     shutdown.on();
     if let Some(ui) = ui {
         match ui.join() {
@@ -68,6 +621,123 @@ fn main() -> anyhow::Result<()> {
             _ => {}
         }
     }
-    copy_result?;
+    if let Some(control) = control {
+        control.join().expect("control socket panicked")?;
+    }
+    #[cfg(feature = "systemd")]
+    notifier.join().expect("status notifier panicked")?;
+    if let Some(progress_report) = progress_report {
+        progress_report.join().expect("progress reporter panicked")?;
+    }
+    if let Some(shm_publisher) = shm_publisher {
+        shm_publisher.join().expect("shared memory publisher panicked");
+    }
+    if let Some(rate_log) = rate_log {
+        rate_log.join().expect("rate log panicked")?;
+    }
+    if let Some(report) = report {
+        report.join().expect("interval report panicked")?;
+    }
+    if let Some(keys) = keys {
+        let listened = keys.join().expect("key listener panicked");
+        // Without a terminal to read, announcements simply go without keys.
+        if invo.keys {
+            listened?;
+        }
+    }
+    if let Some(status) = status {
+        status.join().expect("status line panicked")?;
+    }
+    if let Some(hooks) = hooks {
+        hooks.join().expect("hook runner panicked")?;
+    }
+    if let Some(pacer) = pacer {
+        pacer.join().expect("pacer panicked");
+    }
+    if let Some(gate) = gate {
+        gate.join().expect("gate panicked");
+    }
+    if let Some(space_gate) = space_gate {
+        space_gate.join().expect("free space gate panicked");
+    }
+    if let Some(scheduler) = scheduler {
+        scheduler.join().expect("window scheduler panicked");
+    }
+    if let Some(watchdog) = watchdog {
+        watchdog.join().expect("write watchdog panicked");
+    }
+    let outputs_result = output_threads.join();
+    let timed_out = copy_result.as_ref()
+        .err()
+        .and_then(|e| e.get_ref())
+        .and_then(|e| e.downcast_ref::<WriteTimedOut>());
+    let exit_status = if timed_out.is_some() {
+        WriteTimedOut::EXIT_CODE
+    } else if outputs_result.is_err() || filtered.is_err() {
+        1
+    } else {
+        end.exit_code()
+    };
+    if invo.history {
+        let entry = HistoryEntry::new(
+            std::env::args().collect(),
+            &summary_progress.get(),
+            unit,
+            exit_status,
+        );
+        // Losing the record is no reason to report the transfer as failed.
+        if let Some(history) = History::default_path().map(History::new) {
+            if let Err(e) = history.append(&entry) {
+                eprintln!("!!! could not record history in {}: {} !!!", history.path().display(), e);
+            }
+        }
+    }
+    // However the transfer ended, the summary says how far it got and why.
+    if invo.json_summary {
+        let summary = Summary::new(
+            summary_progress.get(),
+            backpressure.get(),
+        )
+            .with_forwarded(forwarded_summary.as_mut().map(|f| f.get()))
+            .with_end(end)
+            .with_payload(payload_summary.as_mut().map(PayloadMonitor::get))
+            .with_display(number_format, shown_unit);
+        eprintln!("{}", summary.to_json());
+    }
+    // A failed output also fails the copy, but its own error says why.
+    outputs_result?;
+    if let Some(timed_out) = timed_out {
+        eprintln!("Error: {}", timed_out);
+        std::process::exit(WriteTimedOut::EXIT_CODE);
+    }
+    // A filter which failed usually broke the copy feeding it too.
+    filtered?;
+    if let Err(e) = copy_result {
+        // Only now that the terminal is restored and every thread is done.
+        if invo.pipe_default && end == EndCause::DownstreamClosed {
+            platform::die_of_sigpipe()?;
+        }
+        eprintln!("Error: {}", e);
+        std::process::exit(exit_status);
+    }
+    if let Some(state_file) = &state_file {
+        state_file.remove()?;
+    }
     Ok(())
 }
+
+#[cfg(not(feature = "full"))]
+fn main() -> anyhow::Result<()> {
+    use pvalve::minimal::{self, Invocation};
+    match Invocation::parse(std::env::args().skip(1)) {
+        Ok(Some(invo)) => Ok(minimal::run(&invo)?),
+        Ok(None) => {
+            print!("{}", minimal::USAGE);
+            Ok(())
+        },
+        Err(e) => {
+            eprint!("pvalve: {}\n\n{}", e, minimal::USAGE);
+            std::process::exit(2);
+        },
+    }
+}
@@ -0,0 +1,196 @@
+use std::{
+    fs::File,
+    io::{self, Write},
+    num::{NonZeroU64, NonZeroUsize},
+    os::fd::AsFd as _,
+    thread,
+    time::Duration,
+};
+
+use thiserror::Error;
+
+use super::{
+    config::{Config, ConfigMonitor, Latch, LatchMonitor},
+    format::NumberFormat,
+    instantaneous::{RateEstimator, RateSampler},
+    progress::CumulativeProgressMonitor,
+    pump::Pump,
+    size::{self, ParseByteSizeError},
+    throttled::Throttled,
+    unit::Unit,
+};
+
+pub const USAGE: &str = "\
+Usage: pvalve [-l | -0] [-L RATE] [-s SIZE]
+
+  -l         Measure and limit lines
+  -0         Measure and limit null-separated records
+  -L RATE    Limit the throughput to RATE units per second (e.g. 1000, 10M, 1.5Gi)
+  -s SIZE    Expected size of the input in bytes (e.g. 700M, 4.7Gi)
+  -h         Print this help
+";
+
+/// What a minimal build was asked to do, out of the few options it takes.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Invocation {
+    pub speed: Option<NonZeroU64>,
+    pub unit: Unit,
+    pub expected_size: Option<NonZeroUsize>,
+}
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum UsageError {
+    #[error("unknown option {0:?}")]
+    Unknown(String),
+    #[error("{0} needs a value")]
+    Missing(String),
+    #[error(transparent)]
+    Size(#[from] ParseByteSizeError),
+}
+
+impl Invocation {
+    /// Parse the arguments following the program name, or None if they ask
+    /// for help.
+    ///
+    /// Values may follow their option as the next argument or be attached
+    /// to it, as in `-L10M`.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Option<Self>, UsageError> {
+        let mut invo = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let (option, attached) = match arg.char_indices().nth(2) {
+                Some((split, _)) if !arg.starts_with("--") => {
+                    (&arg[..split], Some(arg[split..].to_owned()))
+                },
+                _ => (arg.as_str(), None),
+            };
+            let mut value = || attached.clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| UsageError::Missing(option.to_owned()));
+            match option {
+                "-L" => invo.speed = Some(size::parse_nonzero(&value()?)?),
+                "-s" => {
+                    let text = value()?;
+                    let size = size::parse_nonzero(&text)?;
+                    let size = usize::try_from(size.get())
+                        .ok()
+                        .and_then(NonZeroUsize::new)
+                        .ok_or(ParseByteSizeError::TooLarge(text))?;
+                    invo.expected_size = Some(size);
+                },
+                _ if attached.is_some() => return Err(UsageError::Unknown(arg)),
+                "-l" => invo.unit = Unit::Line,
+                "-0" => invo.unit = Unit::Null,
+                "-h" | "--help" => return Ok(None),
+                _ => return Err(UsageError::Unknown(arg)),
+            }
+        }
+        Ok(Some(invo))
+    }
+}
+
+/// Copy stdin to stdout as `invo` asks, redrawing a line of progress on
+/// stderr every second.
+pub fn run(invo: &Invocation) -> io::Result<()> {
+    let config = Config {
+        limit: invo.speed.into(),
+        unit: invo.unit,
+        expected_size: invo.expected_size,
+        ..Default::default()
+    };
+    let (_config_tx, config_rx) = ConfigMonitor::new(config);
+    let mut shutdown = Latch::new();
+    let stdin = io::stdin();
+    let mut stdin = stdin.lock();
+    let stdout = File::from(io::stdout().as_fd().try_clone_to_owned()?);
+    let mut stdout = Throttled::new(stdout, config_rx);
+    let progress = ProgressLine {
+        cumulative: stdout.sink().cumulative_progress(),
+        rate: RateSampler::new(
+            stdout.sink().transfer_progress(),
+            RateEstimator::new(Duration::from_secs(1)),
+        ),
+        config,
+        shutdown: shutdown.watch(),
+    };
+    let progress = thread::spawn(move || progress.run());
+    let copy_result = Pump::default()
+        .copy(&mut stdin, &mut stdout)
+        .and_then(|_| stdout.drain());
+    if copy_result.is_ok() {
+        stdout.finish();
+    } else {
+        stdout.fail();
+    }
+    shutdown.on();
+    progress.join().expect("progress line panicked")?;
+    copy_result.map(drop)
+}
+
+/// Totals, rate and percentage on a line of stderr redrawn in place.
+struct ProgressLine {
+    cumulative: CumulativeProgressMonitor,
+    rate: RateSampler,
+    config: Config,
+    shutdown: LatchMonitor,
+}
+
+impl ProgressLine {
+    fn run(mut self) -> io::Result<()> {
+        let mut stderr = io::stderr().lock();
+        while !self.shutdown.wait_timeout(Duration::from_secs(1)) {
+            write!(stderr, "\r{}\x1b[K", self.line())?;
+        }
+        writeln!(stderr, "\r{}\x1b[K", self.line())
+    }
+    fn line(&mut self) -> String {
+        let Config { unit, expected_size, .. } = self.config;
+        let format = NumberFormat::Auto;
+        let cumulative = self.cumulative.get();
+        let elapsed = cumulative.elapsed().as_secs();
+        let mut line = format!(
+            "{} {}:{:02}:{:02} [{}]",
+            format.amount(unit.progress(&cumulative.progress), unit, Some(2)),
+            elapsed / 3600,
+            elapsed / 60 % 60,
+            elapsed % 60,
+            format.rate(unit.progress(&self.rate.sample()), unit),
+        );
+        if let Some(expected) = expected_size {
+            let percent = cumulative.progress.bytes_transferred as f64 * 100f64
+                / expected.get() as f64;
+            line.push_str(&format!(" {}%", percent as u64));
+        }
+        line
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nonzero_ext::nonzero;
+
+    fn parse(args: &[&str]) -> Result<Option<Invocation>, UsageError> {
+        Invocation::parse(args.iter().map(ToString::to_string))
+    }
+
+    #[test]
+    fn only_the_essential_options_are_taken() {
+        assert_eq!(parse(&[]), Ok(Some(Invocation::default())));
+        assert_eq!(
+            parse(&["-l", "-L", "10k", "-s700M"]),
+            Ok(Some(Invocation {
+                speed: Some(nonzero!(10000u64)),
+                unit: Unit::Line,
+                expected_size: Some(nonzero!(700_000_000usize)),
+            })),
+        );
+        assert_eq!(parse(&["-0"]).map(|invo| invo.unwrap().unit), Ok(Unit::Null));
+        assert_eq!(parse(&["-h"]), Ok(None));
+        assert_eq!(parse(&["-L"]), Err(UsageError::Missing("-L".into())));
+        assert_eq!(parse(&["--headless"]), Err(UsageError::Unknown("--headless".into())));
+        assert_eq!(parse(&["-lx"]), Err(UsageError::Unknown("-lx".into())));
+        assert!(matches!(parse(&["-L", "0"]), Err(UsageError::Size(_))));
+    }
+
+}
@@ -0,0 +1,148 @@
+use std::{
+    num::{NonZeroU64, NonZeroUsize},
+    time::{Duration, Instant},
+};
+
+use super::{
+    config::LatchMonitor,
+    control::Controls,
+    ipc::Message,
+    progress::{CumulativeProgressMonitor, TransferProgress},
+    unit::Unit,
+};
+
+/// The limit in `unit` per second which moves what is left of `expected`
+/// bytes in `remaining`.
+///
+/// A new limit lets a second's worth through straight away before holding
+/// to the rate, so that second is spread over as well.
+///
+/// Records are assumed to stay the size they have been so far, so there is
+/// no limit in a record unit until some have been counted. There is none
+/// either once time is up or everything expected has arrived.
+pub fn pace(
+    expected: u64,
+    progress: &TransferProgress,
+    unit: Unit,
+    remaining: Duration,
+) -> Option<NonZeroU64> {
+    let seconds = remaining.as_secs_f64();
+    if seconds <= 0f64 {
+        return None;
+    }
+    let bytes = progress.bytes_transferred;
+    let units_per_byte = match unit {
+        Unit::Byte => 1f64,
+        unit => match (unit.progress(progress), bytes) {
+            (0, _) | (_, 0) => return None,
+            (units, bytes) => units as f64 / bytes as f64,
+        },
+    };
+    let left = expected.saturating_sub(bytes) as f64;
+    NonZeroU64::new((left * units_per_byte / (seconds + 1f64)).ceil() as u64)
+}
+
+/// Whether `limit` is far enough from the `current` one to be worth
+/// changing to, since every change starts the limiter over with a fresh
+/// burst.
+fn worth_changing(current: Option<NonZeroU64>, limit: Option<NonZeroU64>) -> bool {
+    match (current, limit) {
+        (Some(current), Some(limit)) => {
+            let drift = limit.get().abs_diff(current.get()) as f64 / current.get() as f64;
+            drift > Pacer::TOLERANCE
+        },
+        (current, limit) => current != limit,
+    }
+}
+
+/// Keeps the limit at whatever finishes the expected size by a deadline,
+/// for `--target-duration`.
+pub struct Pacer {
+    controls: Controls,
+    cumulative: CumulativeProgressMonitor,
+    expected_size: NonZeroUsize,
+    deadline: Instant,
+    shutdown: LatchMonitor,
+    refresh: Duration,
+}
+
+impl Pacer {
+    /// How far the pace may drift from the limit before it is changed.
+    const TOLERANCE: f64 = 0.1;
+
+    pub fn new(
+        controls: Controls,
+        cumulative: CumulativeProgressMonitor,
+        expected_size: NonZeroUsize,
+        deadline: Instant,
+        shutdown: LatchMonitor,
+    ) -> Self {
+        Self {
+            controls,
+            cumulative,
+            expected_size,
+            deadline,
+            shutdown,
+            refresh: Duration::from_secs(1),
+        }
+    }
+    /// Adjust the limit at most this often.
+    pub fn with_refresh(mut self, refresh: Duration) -> Self {
+        self.refresh = refresh;
+        self
+    }
+
+    pub fn run(mut self) {
+        loop {
+            self.adjust(Instant::now());
+            if self.shutdown.wait_timeout(self.refresh) {
+                break;
+            }
+        }
+    }
+
+    fn adjust(&mut self, now: Instant) {
+        let config = self.controls.config();
+        let limit = pace(
+            self.expected_size.get() as u64,
+            &self.cumulative.get().progress,
+            config.unit,
+            self.deadline.saturating_duration_since(now),
+        );
+        if worth_changing(config.limit(), limit) {
+            self.controls.send(Message::Limit(limit));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nonzero_ext::nonzero;
+
+    #[test]
+    fn pace_spreads_what_is_left_over_the_time_left() {
+        let progress = TransferProgress {
+            bytes_transferred: 4000,
+            lines_transferred: 40,
+            ..Default::default()
+        };
+        let minute = Duration::from_secs(59);
+        assert_eq!(pace(10000, &progress, Unit::Byte, minute), Some(nonzero!(100u64)));
+        assert_eq!(pace(10000, &progress, Unit::Line, minute), Some(nonzero!(1u64)));
+        assert_eq!(pace(10000, &progress, Unit::Word, minute), None);
+        assert_eq!(pace(10000, &progress, Unit::Byte, Duration::ZERO), None);
+        assert_eq!(pace(4000, &progress, Unit::Byte, minute), None);
+    }
+
+    #[test]
+    fn small_drifts_leave_the_limit_alone() {
+        let limit = Some(nonzero!(1000u64));
+        assert!(!worth_changing(limit, Some(nonzero!(1050u64))));
+        assert!(worth_changing(limit, Some(nonzero!(1200u64))));
+        assert!(worth_changing(limit, None));
+        assert!(worth_changing(None, limit));
+        assert!(!worth_changing(None, None));
+    }
+
+}
@@ -0,0 +1,155 @@
+use std::io::{
+    Result,
+    Write,
+};
+
+use super::{
+    state::{
+        channel,
+        StateCell,
+        StateWatcher,
+    },
+    syncio::Finish,
+    unit::Unit,
+};
+
+/// Counts the payload bytes of delimited records, leaving out each record's
+/// delimiter and its first `header` bytes, carrying on across writes from
+/// wherever the last one left off.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PayloadScanner {
+    delimiter: u8,
+    header: usize,
+    /// Bytes of the current record seen so far.
+    offset: usize,
+}
+
+/// Writer which counts the payload bytes of what passes through it, for
+/// `--net-bytes`, without changing any of it.
+pub struct PayloadWriter<W> {
+    inner: W,
+    scanner: Option<PayloadScanner>,
+    payload: u64,
+    tx: StateCell<u64>,
+}
+
+/// Payload bytes counted so far.
+#[derive(Clone)]
+pub struct PayloadMonitor(StateWatcher<u64>);
+
+impl PayloadScanner {
+    pub fn new(delimiter: u8, header: usize) -> Self {
+        Self {
+            delimiter,
+            header,
+            offset: 0,
+        }
+    }
+    /// Records of `unit`, or lines when it does not delimit records.
+    pub fn for_unit(unit: Unit, header: usize) -> Self {
+        Self::new(unit.delimiter().unwrap_or(b'\n'), header)
+    }
+    /// Payload bytes in `buf`, consuming it.
+    pub fn count(&mut self, buf: &[u8]) -> u64 {
+        let mut payload = 0;
+        let mut records = buf.split(|b| *b == self.delimiter).peekable();
+        while let Some(record) = records.next() {
+            let header = self.header.saturating_sub(self.offset).min(record.len());
+            payload += (record.len() - header) as u64;
+            self.offset += record.len();
+            // Every piece but the last ends in a delimiter.
+            if records.peek().is_some() {
+                self.offset = 0;
+            }
+        }
+        payload
+    }
+}
+
+impl <W> PayloadWriter<W> {
+    /// Count through `scanner`, if there is one, or pass everything
+    /// straight through.
+    pub fn new(inner: W, scanner: Option<PayloadScanner>) -> Self {
+        let (tx, _) = channel(0);
+        Self {
+            inner,
+            scanner,
+            payload: 0,
+            tx,
+        }
+    }
+    /// The count, when there is one.
+    pub fn payload(&self) -> Option<PayloadMonitor> {
+        self.scanner.map(|_| PayloadMonitor(self.tx.subscribe()))
+    }
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+}
+
+impl <W: Write> Write for PayloadWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let written = self.inner.write(buf)?;
+        if let Some(scanner) = &mut self.scanner {
+            self.payload = self.payload.saturating_add(scanner.count(&buf[..written]));
+            self.tx.send(self.payload);
+        }
+        Ok(written)
+    }
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl <W: Finish> Finish for PayloadWriter<W> {
+    fn finish(&mut self) -> Result<()> {
+        self.inner.finish()
+    }
+}
+
+impl PayloadMonitor {
+    pub fn get(&mut self) -> u64 {
+        self.0.get()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delimiters_and_headers_are_left_out_across_writes() {
+        let mut scanner = PayloadScanner::new(b'\n', 2);
+        assert_eq!(scanner.count(b"h:abc\nh:de"), 5);
+        assert_eq!(scanner.count(b"f\n\nh"), 1);
+        assert_eq!(scanner.count(b":xyz\n"), 3);
+        let mut lines = PayloadScanner::for_unit(Unit::Byte, 0);
+        assert_eq!(lines.count(b"one\ntwo\n"), 6);
+        let mut nulls = PayloadScanner::for_unit(Unit::Null, 1);
+        assert_eq!(nulls.count(b"#a\0#bc\0\n"), 3);
+    }
+
+    #[test]
+    fn only_what_the_inner_writer_took_is_counted() {
+        struct Short(Vec<u8>);
+
+        impl Write for Short {
+            fn write(&mut self, buf: &[u8]) -> Result<usize> {
+                let n = buf.len().min(3);
+                self.0.extend_from_slice(&buf[..n]);
+                Ok(n)
+            }
+            fn flush(&mut self) -> Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut writer = PayloadWriter::new(Short(vec![]), Some(PayloadScanner::new(b'\n', 0)));
+        let mut payload = writer.payload().unwrap();
+        writer.write_all(b"ab\ncd\n").unwrap();
+        assert_eq!(payload.get(), 4);
+        assert_eq!(writer.get_ref().0, b"ab\ncd\n");
+        assert!(PayloadWriter::new(Vec::<u8>::new(), None).payload().is_none());
+    }
+
+}
@@ -0,0 +1,201 @@
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+use super::{
+    cli::Invocation,
+    history::History,
+    hooks::Hooks,
+    pump::Pump,
+};
+
+/// Machine-readable description of what a copy would do, printed instead
+/// of doing it by `--dry-run`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Plan {
+    /// Limit per second in `unit`, if any applies.
+    pub limit: Option<u64>,
+    /// The limit as the interface would show it.
+    pub limit_display: Option<String>,
+    /// How long the transfer is paced to take, adjusting the limit to suit.
+    pub target_duration_seconds: Option<f64>,
+    pub unit: String,
+    /// Unit amounts and rates are shown in.
+    pub display_unit: String,
+    /// Header bytes left out of each record's payload, when counting it.
+    pub net_bytes_header: Option<usize>,
+    pub rate_window_seconds: f64,
+    pub rate_smoothing: String,
+    pub max_chunk: usize,
+    pub buffer_size: Option<usize>,
+    pub buffer_watermarks: Option<String>,
+    pub expected_size: Option<usize>,
+    pub source: Source,
+    pub sinks: Vec<String>,
+    pub output_buffering: String,
+    pub write_timeout_seconds: Option<f64>,
+    pub pipe_default: bool,
+    /// "evented" when the copy waits on both ends in a poll loop,
+    /// "blocking" otherwise.
+    pub engine: &'static str,
+    pub record_atomic: bool,
+    /// Only bytes are counted.
+    pub lightweight: bool,
+    pub delay_per_record: Option<String>,
+    pub sample: Option<String>,
+    pub number_lines: bool,
+    /// Timing file the stream is replayed with.
+    pub timing: Option<PathBuf>,
+    pub timestamp_records: Option<String>,
+    /// Command the stream is passed through, and which side of it is limited.
+    pub filter: Option<String>,
+    pub limit_side: Option<String>,
+    pub min_rate: Option<String>,
+    pub gate: Option<String>,
+    /// Limits by time of day, given with `--window`.
+    pub windows: Option<String>,
+    pub gate_interval_seconds: Option<f64>,
+    pub min_free: Option<u64>,
+    pub hooks: Hooks,
+    pub state_file: Option<PathBuf>,
+    pub audit_pacing: Option<PathBuf>,
+    pub interface: &'static str,
+    /// Layout of the status line given with `-F`, and the name for `%N`.
+    pub format: Option<String>,
+    pub name: Option<String>,
+    pub rate_gauge_max: Option<String>,
+    pub control: Option<PathBuf>,
+    pub progress_fd: Option<i32>,
+    pub shm_progress: Option<String>,
+    pub rate_log: Option<PathBuf>,
+    pub report_interval_seconds: Option<f64>,
+    /// Where report lines are appended, `None` meaning stderr.
+    pub report_file: Option<PathBuf>,
+    /// History file the transfer is recorded in once it ends.
+    pub history: Option<PathBuf>,
+}
+
+/// Where the data comes from.
+#[derive(Debug, Clone, Serialize)]
+pub struct Source {
+    /// "stdin", "files" when `files` are read in turn instead, or "tar"
+    /// when `tar` is archived.
+    pub path: &'static str,
+    pub files: Vec<PathBuf>,
+    pub tar: Option<PathBuf>,
+    pub range: Option<String>,
+}
+
+impl Plan {
+    /// Resolve `invo` the way a copy would, limiting only when `limiting`.
+    pub fn new(invo: &Invocation, limiting: bool) -> Self {
+        let unit = invo.unit;
+        let limit = invo.speed
+            .filter(|_| limiting)
+            .map(|speed| speed.per_second.get());
+        let interface = if invo.headless {
+            "headless"
+        } else if invo.a11y.is_some() {
+            "a11y"
+        } else if invo.no_altscreen {
+            "status-line"
+        } else {
+            "full-screen"
+        };
+        Self {
+            limit,
+            limit_display: limit.map(|limit| invo.number_format.rate(limit, unit)),
+            target_duration_seconds: invo.target_duration
+                .filter(|_| limiting)
+                .map(|target| target.as_secs_f64()),
+            unit: unit.to_string(),
+            display_unit: invo.display_unit.unwrap_or(unit).to_string(),
+            net_bytes_header: invo.net_bytes,
+            rate_window_seconds: invo.rate_window.as_secs_f64(),
+            rate_smoothing: invo.rate_smoothing.to_string(),
+            max_chunk: invo.max_chunk.unwrap_or(Pump::DEFAULT_CHUNK_SIZE).get(),
+            buffer_size: invo.buffer_size.map(|size| size.get()),
+            buffer_watermarks: invo.buffer_size
+                .map(|_| invo.buffer_watermarks.to_string()),
+            expected_size: invo.expected_size.map(|size| size.get()),
+            source: Source {
+                path: if invo.tar.is_some() {
+                    "tar"
+                } else if !invo.inputs.is_empty() {
+                    "files"
+                } else {
+                    "stdin"
+                },
+                files: invo.inputs.clone(),
+                tar: invo.tar.clone(),
+                range: invo.range.map(|range| range.to_string()),
+            },
+            sinks: std::iter::once("stdout".to_owned())
+                .chain(invo.outputs.iter().map(ToString::to_string))
+                .collect(),
+            output_buffering: invo.output_buffering.to_string(),
+            write_timeout_seconds: invo.write_timeout.map(|timeout| timeout.as_secs_f64()),
+            pipe_default: invo.pipe_default,
+            engine: if invo.evented() { "evented" } else { "blocking" },
+            record_atomic: invo.record_atomic,
+            lightweight: invo.lightweight,
+            delay_per_record: invo.latency.map(|latency| latency.to_string()),
+            sample: invo.sample.map(|sample| sample.to_string()),
+            number_lines: invo.number_lines,
+            timing: invo.timing.clone(),
+            timestamp_records: invo.timestamp_records.map(|format| format.to_string()),
+            filter: invo.filter.clone(),
+            limit_side: invo.filter.as_ref().map(|_| invo.limit_side.to_string()),
+            min_rate: invo.min_rate.map(|min_rate| min_rate.to_string()),
+            gate: invo.gate.clone(),
+            min_free: invo.min_free.map(|min_free| min_free.get()),
+            windows: invo.windows.as_ref().map(ToString::to_string),
+            gate_interval_seconds: invo.gate
+                .as_ref()
+                .map(|_| invo.gate_interval.as_secs_f64()),
+            hooks: invo.hooks.clone(),
+            state_file: invo.state_file.clone(),
+            audit_pacing: invo.audit_pacing.clone(),
+            interface,
+            format: invo.progress_format.as_ref().map(ToString::to_string),
+            name: invo.name.clone(),
+            rate_gauge_max: invo.rate_gauge_max.map(|scale| scale.to_string()),
+            control: invo.control.clone(),
+            progress_fd: invo.progress_fd,
+            shm_progress: invo.shm_progress.clone(),
+            rate_log: invo.rate_log.clone(),
+            report_interval_seconds: invo.report_interval.map(|interval| interval.as_secs_f64()),
+            report_file: invo.report_file.clone(),
+            history: invo.history.then(History::default_path).flatten(),
+        }
+    }
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self)
+            .expect("plan is always serializable")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nonzero_ext::nonzero;
+    use crate::{
+        cli::Speed,
+        unit::Unit,
+    };
+
+    #[test]
+    fn watching_never_limits() {
+        let invo = Invocation {
+            speed: Some(Speed::from(nonzero!(2000u64))),
+            unit: Unit::Line,
+            ..Default::default()
+        };
+        let plan = Plan::new(&invo, true);
+        assert_eq!(plan.limit, Some(2000));
+        assert_eq!(plan.limit_display.as_deref(), Some("2.0kL/s"));
+        assert_eq!(plan.sinks, vec!["stdout"]);
+        assert_eq!(Plan::new(&invo, false).limit, None);
+    }
+
+}
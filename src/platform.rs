@@ -0,0 +1,118 @@
+use std::{
+    io,
+    mem,
+    os::fd::{AsFd, AsRawFd as _, BorrowedFd, OwnedFd, RawFd},
+    ptr,
+    time::Duration,
+};
+
+/// Lowest CPU scheduling priority, see setpriority(2).
+const LOWEST_NICE: libc::c_int = 19;
+
+/// Stop the whole process, as the suspend key does outside raw mode,
+/// returning once it is continued, as by `fg`.
+pub fn suspend() -> io::Result<()> {
+    // SAFETY: a plain system call taking no pointers.
+    if unsafe { libc::raise(libc::SIGTSTP) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Die of SIGPIPE, as a filter which never stopped ignoring it would have
+/// on writing to a pipe nobody reads anymore, so that whatever started the
+/// process sees the signal rather than an exit status. Only returns if the
+/// signal could not be delivered.
+pub fn die_of_sigpipe() -> io::Result<()> {
+    // SAFETY: restoring the default disposition installs no handler.
+    if unsafe { libc::signal(libc::SIGPIPE, libc::SIG_DFL) } == libc::SIG_ERR {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: a plain system call taking no pointers.
+    if unsafe { libc::raise(libc::SIGPIPE) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// A descriptor of our own for `fd`, which was inherited from whatever
+/// started the process, failing if nothing is open there. The inherited
+/// descriptor is left open.
+pub fn duplicate_inherited(fd: RawFd) -> io::Result<OwnedFd> {
+    // SAFETY: a plain system call taking no pointers.
+    if unsafe { libc::fcntl(fd, libc::F_GETFD) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: fcntl has just found fd open, and it is only borrowed for as
+    // long as it takes to duplicate it.
+    unsafe { BorrowedFd::borrow_raw(fd) }.try_clone_to_owned()
+}
+
+/// Time since midnight in the local time zone.
+pub fn local_time_of_day() -> io::Result<Duration> {
+    // SAFETY: time is given no pointer to write to, and localtime_r only
+    // writes to the tm it is given, which any bit pattern is valid for.
+    let local = unsafe {
+        let now = libc::time(ptr::null_mut());
+        let mut local: libc::tm = mem::zeroed();
+        if libc::localtime_r(&now, &mut local).is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        local
+    };
+    let seconds = local.tm_hour * 3600 + local.tm_min * 60 + local.tm_sec;
+    Ok(Duration::from_secs(seconds as u64))
+}
+
+/// Bytes an unprivileged process may still write to the filesystem `file`
+/// is on, see fstatvfs(3).
+pub fn available_space(file: impl AsFd) -> io::Result<u64> {
+    // SAFETY: fstatvfs only writes to the statvfs it is given, which any
+    // bit pattern is valid for.
+    let stats = unsafe {
+        let mut stats: libc::statvfs = mem::zeroed();
+        if libc::fstatvfs(file.as_fd().as_raw_fd(), &mut stats) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        stats
+    };
+    // Both are narrower than u64 on some platforms.
+    #[allow(clippy::unnecessary_cast)]
+    Ok(stats.f_bavail as u64 * stats.f_frsize as u64)
+}
+
+/// Give up CPU and disk time to anything else that wants it.
+///
+/// Both priorities only apply to the calling thread and to the threads it
+/// starts afterwards, so this belongs before any thread is spawned.
+pub fn idle_priority() -> io::Result<()> {
+    // SAFETY: plain system calls on the calling thread, taking no pointers.
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, LOWEST_NICE) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    idle_io()
+}
+
+#[cfg(target_os = "linux")]
+fn idle_io() -> io::Result<()> {
+    /// See ioprio_set(2).
+    const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+    const IOPRIO_CLASS_IDLE: libc::c_int = 3;
+    const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+    let priority = IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT;
+    // SAFETY: as above.
+    let result = unsafe {
+        libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, priority)
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Only Linux has a separate I/O priority; elsewhere the nice value is all
+/// there is.
+#[cfg(not(target_os = "linux"))]
+fn idle_io() -> io::Result<()> {
+    Ok(())
+}
@@ -1,45 +1,153 @@
-use std::time::{
-    Duration,
-    Instant,
+use std::{
+    sync::{
+        atomic::{
+            AtomicU64,
+            Ordering,
+        },
+        Arc,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use super::state::StateWatcher;
+
+use super::unit::{
+    Scanner,
+    Unit,
 };
-use watch::WatchReceiver;
 
-#[derive(Debug, Default, Clone, Copy, PartialEq, PartialOrd, Ord, Eq)]
+/// Amount transferred, counted in every unit at once.
+///
+/// Counters are 64 bits wide regardless of the target's pointer width and
+/// saturate rather than wrap; [`TransferProgress::saturated`] reports when
+/// that has happened.
+#[derive(Debug, Default, Clone, Copy, PartialEq, PartialOrd, Ord, Eq, Serialize, Deserialize)]
 pub struct TransferProgress {
-    pub bytes_transferred: usize,
-    pub lines_transferred: usize,
-    pub nulls_transferred: usize,
+    pub bytes_transferred: u64,
+    pub lines_transferred: u64,
+    pub nulls_transferred: u64,
+    pub words_transferred: u64,
+    /// Calls to write; missing from state files made before it was counted.
+    #[serde(default)]
+    pub writes_transferred: u64,
 }
 
 impl TransferProgress {
-    pub fn add_bytes(&mut self, n: usize) {
-        self.bytes_transferred += n;
+    /// Count every unit in `buf`.
+    pub fn of(buf: &[u8]) -> Self {
+        Self::scan(&mut Scanner::default(), buf)
+    }
+    /// Count every unit in `buf`, continuing from where `scanner` left off.
+    pub fn scan(scanner: &mut Scanner, buf: &[u8]) -> Self {
+        let progress = Self {
+            bytes_transferred: scanner.count(Unit::Byte, buf) as u64,
+            lines_transferred: scanner.count(Unit::Line, buf) as u64,
+            nulls_transferred: scanner.count(Unit::Null, buf) as u64,
+            words_transferred: scanner.count(Unit::Word, buf) as u64,
+            writes_transferred: scanner.count(Unit::Op, buf) as u64,
+        };
+        scanner.observe_end(buf);
+        progress
+    }
+    /// Count only the bytes in `buf` and the write of it, for when nothing
+    /// needs the other units.
+    pub fn bytes_only(buf: &[u8]) -> Self {
+        Self {
+            bytes_transferred: buf.len() as u64,
+            writes_transferred: !buf.is_empty() as u64,
+            ..Default::default()
+        }
+    }
+    pub fn add_bytes(&mut self, n: u64) {
+        self.bytes_transferred = self.bytes_transferred.saturating_add(n);
+    }
+    pub fn add_lines(&mut self, n: u64) {
+        self.lines_transferred = self.lines_transferred.saturating_add(n);
+    }
+    pub fn add_nulls(&mut self, n: u64) {
+        self.nulls_transferred = self.nulls_transferred.saturating_add(n);
+    }
+    pub fn add_words(&mut self, n: u64) {
+        self.words_transferred = self.words_transferred.saturating_add(n);
     }
-    pub fn add_lines(&mut self, n: usize) {
-        self.lines_transferred += n;
+    pub fn add_writes(&mut self, n: u64) {
+        self.writes_transferred = self.writes_transferred.saturating_add(n);
     }
-    pub fn add_nulls(&mut self, n: usize) {
-        self.nulls_transferred += n;
+    /// Sum of `self` and `other`, or `None` if any counter would overflow.
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        Some(Self {
+            bytes_transferred: self.bytes_transferred.checked_add(other.bytes_transferred)?,
+            lines_transferred: self.lines_transferred.checked_add(other.lines_transferred)?,
+            nulls_transferred: self.nulls_transferred.checked_add(other.nulls_transferred)?,
+            words_transferred: self.words_transferred.checked_add(other.words_transferred)?,
+            writes_transferred: self.writes_transferred.checked_add(other.writes_transferred)?,
+        })
+    }
+    /// Difference of `self` and `other`, or `None` if any counter would
+    /// underflow.
+    pub fn checked_sub(self, other: Self) -> Option<Self> {
+        Some(Self {
+            bytes_transferred: self.bytes_transferred.checked_sub(other.bytes_transferred)?,
+            lines_transferred: self.lines_transferred.checked_sub(other.lines_transferred)?,
+            nulls_transferred: self.nulls_transferred.checked_sub(other.nulls_transferred)?,
+            words_transferred: self.words_transferred.checked_sub(other.words_transferred)?,
+            writes_transferred: self.writes_transferred.checked_sub(other.writes_transferred)?,
+        })
+    }
+    /// Whether any counter has reached its maximum and stopped counting.
+    pub fn saturated(&self) -> bool {
+        [
+            self.bytes_transferred,
+            self.lines_transferred,
+            self.nulls_transferred,
+            self.words_transferred,
+            self.writes_transferred,
+        ].contains(&u64::MAX)
     }
 }
 
+/// Saturating addition; see [`TransferProgress::checked_add`].
 impl std::ops::Add for TransferProgress {
     type Output = Self;
     fn add(self, other: Self) -> Self {
         Self {
-            bytes_transferred: self.bytes_transferred + other.bytes_transferred,
-            lines_transferred: self.lines_transferred + other.lines_transferred,
-            nulls_transferred: self.nulls_transferred + other.nulls_transferred,
+            bytes_transferred: self.bytes_transferred.saturating_add(other.bytes_transferred),
+            lines_transferred: self.lines_transferred.saturating_add(other.lines_transferred),
+            nulls_transferred: self.nulls_transferred.saturating_add(other.nulls_transferred),
+            words_transferred: self.words_transferred.saturating_add(other.words_transferred),
+            writes_transferred: self.writes_transferred.saturating_add(other.writes_transferred),
         }
     }
 }
 
-impl std::ops::Div<usize> for TransferProgress {
+/// Saturating subtraction; see [`TransferProgress::checked_sub`].
+impl std::ops::Sub for TransferProgress {
     type Output = Self;
-    fn div(mut self, rhs: usize) -> Self::Output {
+    fn sub(self, other: Self) -> Self {
+        Self {
+            bytes_transferred: self.bytes_transferred.saturating_sub(other.bytes_transferred),
+            lines_transferred: self.lines_transferred.saturating_sub(other.lines_transferred),
+            nulls_transferred: self.nulls_transferred.saturating_sub(other.nulls_transferred),
+            words_transferred: self.words_transferred.saturating_sub(other.words_transferred),
+            writes_transferred: self.writes_transferred.saturating_sub(other.writes_transferred),
+        }
+    }
+}
+
+impl std::ops::Div<u64> for TransferProgress {
+    type Output = Self;
+    fn div(mut self, rhs: u64) -> Self::Output {
         self.bytes_transferred /= rhs;
         self.lines_transferred /= rhs;
         self.nulls_transferred /= rhs;
+        self.words_transferred /= rhs;
+        self.writes_transferred /= rhs;
         self
     }
 }
@@ -50,10 +158,10 @@ impl std::iter::Sum for TransferProgress {
     }
 }
 
-pub struct TransferProgressMonitor(WatchReceiver<TransferProgress>);
+pub struct TransferProgressMonitor(StateWatcher<TransferProgress>);
 
 impl TransferProgressMonitor {
-    pub fn new(rx: WatchReceiver<TransferProgress>) -> Self {
+    pub fn new(rx: StateWatcher<TransferProgress>) -> Self {
         Self(rx)
     }
     pub fn get(&mut self) -> TransferProgress {
@@ -61,14 +169,299 @@ impl TransferProgressMonitor {
     }
 }
 
-#[derive(Clone, Copy)]
+/// Counters a writer updates in place, which any number of threads can read
+/// at any time without subscribing to its progress channel.
+///
+/// Each counter is read on its own, so counters read while a write is being
+/// counted may be one write apart from each other.
+#[derive(Debug, Clone, Default)]
+pub struct SharedCounters(Arc<Counters>);
+
+#[derive(Debug, Default)]
+struct Counters {
+    bytes: AtomicU64,
+    lines: AtomicU64,
+    nulls: AtomicU64,
+    words: AtomicU64,
+    writes: AtomicU64,
+}
+
+impl SharedCounters {
+    pub fn bytes(&self) -> u64 {
+        self.0.bytes.load(Ordering::Relaxed)
+    }
+    pub fn lines(&self) -> u64 {
+        self.0.lines.load(Ordering::Relaxed)
+    }
+    pub fn nulls(&self) -> u64 {
+        self.0.nulls.load(Ordering::Relaxed)
+    }
+    pub fn words(&self) -> u64 {
+        self.0.words.load(Ordering::Relaxed)
+    }
+    pub fn writes(&self) -> u64 {
+        self.0.writes.load(Ordering::Relaxed)
+    }
+    /// Every counter at once.
+    pub fn get(&self) -> TransferProgress {
+        TransferProgress {
+            bytes_transferred: self.bytes(),
+            lines_transferred: self.lines(),
+            nulls_transferred: self.nulls(),
+            words_transferred: self.words(),
+            writes_transferred: self.writes(),
+        }
+    }
+    /// Replace every counter with those of `progress`; only the writer which
+    /// owns the counters calls this.
+    pub(crate) fn store(&self, progress: TransferProgress) {
+        self.0.bytes.store(progress.bytes_transferred, Ordering::Relaxed);
+        self.0.lines.store(progress.lines_transferred, Ordering::Relaxed);
+        self.0.nulls.store(progress.nulls_transferred, Ordering::Relaxed);
+        self.0.words.store(progress.words_transferred, Ordering::Relaxed);
+        self.0.writes.store(progress.writes_transferred, Ordering::Relaxed);
+    }
+}
+
+/// Cumulative progress along with when the transfer started, how long it has
+/// spent paused, and when it ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct CumulativeTransferProgress {
     pub start_time: Instant,
+    pub end_time: Option<Instant>,
+    pub paused_since: Option<Instant>,
+    pub paused_total: Duration,
     pub progress: TransferProgress,
 }
 
 impl CumulativeTransferProgress {
+    pub fn started(start_time: Instant) -> Self {
+        Self {
+            start_time,
+            end_time: None,
+            paused_since: None,
+            paused_total: Duration::ZERO,
+            progress: TransferProgress::default(),
+        }
+    }
+    /// Wall-clock time since the transfer started, up until it ended.
     pub fn elapsed(&self) -> Duration {
-        self.start_time.elapsed()
+        self.elapsed_at(Instant::now())
+    }
+    /// Time spent transferring, excluding any time spent paused.
+    pub fn active_duration(&self) -> Duration {
+        self.active_duration_at(Instant::now())
     }
+    pub fn elapsed_at(&self, now: Instant) -> Duration {
+        self.end_time
+            .unwrap_or(now)
+            .saturating_duration_since(self.start_time)
+    }
+    pub fn active_duration_at(&self, now: Instant) -> Duration {
+        let now = self.end_time.unwrap_or(now);
+        let paused = self.paused_since
+            .map(|since| now.saturating_duration_since(since))
+            .unwrap_or_default();
+        self.elapsed_at(now)
+            .saturating_sub(self.paused_total)
+            .saturating_sub(paused)
+    }
+    /// Rate per second averaged over the time spent transferring up until
+    /// `now`, so that time spent paused does not drag it down.
+    pub fn average_rate_at(&self, now: Instant) -> TransferProgress {
+        let seconds = self.active_duration_at(now).as_secs_f64();
+        if seconds <= 0f64 {
+            return TransferProgress::default();
+        }
+        let per_second = |count: u64| (count as f64 / seconds) as u64;
+        TransferProgress {
+            bytes_transferred: per_second(self.progress.bytes_transferred),
+            lines_transferred: per_second(self.progress.lines_transferred),
+            nulls_transferred: per_second(self.progress.nulls_transferred),
+            words_transferred: per_second(self.progress.words_transferred),
+            writes_transferred: per_second(self.progress.writes_transferred),
+        }
+    }
+    pub fn pause(&mut self, now: Instant) {
+        if self.paused_since.is_none() {
+            self.paused_since = Some(now);
+        }
+    }
+    pub fn resume(&mut self, now: Instant) {
+        if let Some(since) = self.paused_since.take() {
+            self.paused_total += now.saturating_duration_since(since);
+        }
+    }
+    pub fn end(&mut self, now: Instant) {
+        self.resume(now);
+        self.end_time.get_or_insert(now);
+    }
+}
+
+pub struct CumulativeProgressMonitor(StateWatcher<CumulativeTransferProgress>);
+
+impl CumulativeProgressMonitor {
+    pub fn new(rx: StateWatcher<CumulativeTransferProgress>) -> Self {
+        Self(rx)
+    }
+    pub fn get(&mut self) -> CumulativeTransferProgress {
+        self.0.get()
+    }
+    /// The next update, or `None` if there was none within `timeout`.
+    pub fn wait_timeout(&mut self, timeout: Duration) -> Option<CumulativeTransferProgress> {
+        self.0.wait_changed(timeout)
+    }
+}
+
+/// One of several inputs read in turn, with its size if known.
+///
+/// The size of a regular file is taken when it is opened and corrected to
+/// what was actually read once the file has been read to its end.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputFile {
+    pub name: String,
+    pub size: Option<u64>,
+}
+
+/// How far the transfer has got through the input it is currently reading.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileProgress {
+    /// Position of the input among all of them, counting from zero.
+    pub index: usize,
+    pub count: usize,
+    pub name: String,
+    pub transferred: u64,
+    pub size: Option<u64>,
+}
+
+impl FileProgress {
+    /// Find the input which `bytes` transferred so far lands in.
+    ///
+    /// Inputs are only passed over once their size is known and fully
+    /// transferred, so an input of unknown size stays current until the
+    /// reader finds its end. The last input stays current when all is done.
+    pub fn locate(files: &[InputFile], bytes: u64) -> Option<Self> {
+        let mut start = 0u64;
+        for (index, file) in files.iter().enumerate() {
+            let last = index + 1 == files.len();
+            match file.size {
+                Some(size) if !last && bytes >= start.saturating_add(size) => {
+                    start = start.saturating_add(size);
+                },
+                size => return Some(Self {
+                    index,
+                    count: files.len(),
+                    name: file.name.clone(),
+                    transferred: bytes.saturating_sub(start),
+                    size,
+                }),
+            }
+        }
+        None
+    }
+    /// Fraction of the input transferred, if its size is known.
+    pub fn ratio(&self) -> Option<f64> {
+        match self.size? {
+            0 => Some(1f64),
+            size => Some((self.transferred as f64 / size as f64).min(1f64)),
+        }
+    }
+}
+
+pub struct InputFilesMonitor(StateWatcher<Vec<InputFile>>);
+
+impl InputFilesMonitor {
+    pub fn new(rx: StateWatcher<Vec<InputFile>>) -> Self {
+        Self(rx)
+    }
+    pub fn get(&mut self) -> Vec<InputFile> {
+        self.0.get()
+    }
+    /// The input `bytes` transferred so far lands in.
+    pub fn locate(&mut self, bytes: u64) -> Option<FileProgress> {
+        FileProgress::locate(&self.get(), bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inputs(sizes: &[Option<u64>]) -> Vec<InputFile> {
+        sizes.iter()
+            .enumerate()
+            .map(|(i, &size)| InputFile { name: format!("f{i}"), size })
+            .collect()
+    }
+
+    #[test]
+    fn bytes_are_located_in_the_input_they_came_from() {
+        let files = inputs(&[Some(10), Some(0), Some(5)]);
+        let at = |bytes| {
+            let file = FileProgress::locate(&files, bytes).unwrap();
+            (file.index, file.transferred)
+        };
+        assert_eq!(at(0), (0, 0));
+        assert_eq!(at(9), (0, 9));
+        assert_eq!(at(10), (2, 0));
+        assert_eq!(at(15), (2, 5));
+        assert_eq!(at(20), (2, 10));
+        let located = FileProgress::locate(&files, 12).unwrap();
+        assert_eq!(located.name, "f2");
+        assert_eq!(located.count, 3);
+        assert_eq!(located.ratio(), Some(0.4));
+        assert_eq!(FileProgress::locate(&[], 0), None);
+    }
+
+    #[test]
+    fn an_input_of_unknown_size_stays_current() {
+        let files = inputs(&[Some(4), None, Some(4)]);
+        let file = FileProgress::locate(&files, 100).unwrap();
+        assert_eq!((file.index, file.transferred, file.ratio()), (1, 96, None));
+    }
+
+    #[test]
+    fn active_duration_excludes_time_spent_paused() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut progress = CumulativeTransferProgress::started(start);
+        progress.pause(at(2));
+        assert_eq!(progress.active_duration_at(at(5)), Duration::from_secs(2));
+        progress.resume(at(5));
+        progress.pause(at(7));
+        progress.end(at(10));
+        assert_eq!(progress.elapsed_at(at(20)), Duration::from_secs(10));
+        assert_eq!(progress.active_duration_at(at(20)), Duration::from_secs(4));
+    }
+
+    #[test]
+    fn counters_grow_past_four_gibibytes() {
+        let chunk = TransferProgress {
+            bytes_transferred: u32::MAX as u64,
+            ..Default::default()
+        };
+        let total: TransferProgress = std::iter::repeat_n(chunk, 4).sum();
+        assert_eq!(total.bytes_transferred, 4 * u32::MAX as u64);
+        assert!(!total.saturated());
+    }
+
+    #[test]
+    fn counters_saturate_instead_of_wrapping() {
+        let mut progress = TransferProgress {
+            bytes_transferred: u64::MAX - 1,
+            ..Default::default()
+        };
+        let one = TransferProgress {
+            bytes_transferred: 2,
+            ..Default::default()
+        };
+        assert_eq!(progress.checked_add(one), None);
+        assert_eq!((progress + one).bytes_transferred, u64::MAX);
+        assert!((progress + one).saturated());
+        progress.add_bytes(10);
+        assert_eq!(progress.bytes_transferred, u64::MAX);
+        assert_eq!(TransferProgress::default() - one, TransferProgress::default());
+        assert_eq!(TransferProgress::default().checked_sub(one), None);
+    }
+
 }
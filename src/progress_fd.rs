@@ -0,0 +1,92 @@
+use std::io::{
+    self,
+    BufRead,
+};
+
+use super::ipc::ProgressMessage;
+
+/// Parse one line written to `--progress-fd`.
+pub fn parse_line(line: &str) -> io::Result<ProgressMessage> {
+    serde_json::from_str(line.trim())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Messages read from the other end of `--progress-fd`, for programs which
+/// draw their own progress for a pvalve they run.
+///
+/// Blank lines are skipped, and the messages end when pvalve closes the
+/// descriptor.
+pub struct ProgressFeed<R> {
+    lines: io::Lines<R>,
+}
+
+impl <R: BufRead> ProgressFeed<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            lines: reader.lines(),
+        }
+    }
+}
+
+impl <R: BufRead> Iterator for ProgressFeed<R> {
+    type Item = io::Result<ProgressMessage>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.lines.next()? {
+                Ok(line) if line.trim().is_empty() => continue,
+                Ok(line) => return Some(parse_line(&line)),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        progress::TransferProgress,
+        sink::{
+            EndCause,
+            TransferState,
+        },
+    };
+
+    #[test]
+    fn feed_reads_back_what_the_reporter_writes() {
+        let message = ProgressMessage {
+            state: TransferState::Finished,
+            progress: TransferProgress {
+                bytes_transferred: 4096,
+                lines_transferred: 12,
+                ..Default::default()
+            },
+            elapsed_seconds: 1.5,
+            unit: "line".into(),
+            rate: 8,
+            expected_size: Some(8192),
+            buffer_percent: Some(25.0),
+            end: Some(EndCause::Eof),
+        };
+        let text = format!("{}\n\n{}\n", serde_json::to_string(&message).unwrap(), "{}");
+        let mut feed = ProgressFeed::new(text.as_bytes());
+        let parsed = feed.next().unwrap().unwrap();
+        assert_eq!(parsed, message);
+        assert_eq!(parsed.ratio(), Some(0.5));
+        assert_eq!(feed.next().unwrap().unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert!(feed.next().is_none());
+    }
+
+    #[test]
+    fn unknown_fields_and_a_missing_size_are_accepted() {
+        let line = r#"{"state":"running","bytes_transferred":1,"lines_transferred":0,"nulls_transferred":0,"words_transferred":0,"elapsed_seconds":0.5,"unit":"byte","rate":2,"future":true}"#;
+        let message = parse_line(line).unwrap();
+        assert_eq!(message.state, TransferState::Running);
+        assert_eq!(message.expected_size, None);
+        assert_eq!(message.buffer_percent, None);
+        assert_eq!(message.end, None);
+        assert_eq!(message.ratio(), None);
+    }
+
+}
@@ -0,0 +1,261 @@
+use std::{
+    io::{
+        BufRead,
+        BufReader,
+        Error,
+        ErrorKind,
+        Read,
+        Result,
+        Write,
+    },
+    num::NonZeroUsize,
+};
+
+use nonzero_ext::nonzero;
+
+use super::{
+    config::LatchMonitor,
+    progress::TransferProgress,
+    syncio::cancelled_error,
+    unit::Scanner,
+};
+
+/// What a [`Pump`] counted in a chunk while reading it, so that writers
+/// which report progress do not have to scan it again.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkMeta {
+    /// Every unit in the chunk.
+    pub progress: TransferProgress,
+    /// State of a scanner which has seen everything up to and including
+    /// the chunk.
+    pub scanner: Scanner,
+}
+
+impl ChunkMeta {
+    /// Count `buf`, which follows whatever `scanner` has seen, in every unit
+    /// or in bytes alone.
+    pub fn scan(scanner: &mut Scanner, buf: &[u8], bytes_only: bool) -> Self {
+        let progress = if bytes_only {
+            TransferProgress::bytes_only(buf)
+        } else {
+            TransferProgress::scan(scanner, buf)
+        };
+        Self {
+            progress,
+            scanner: *scanner,
+        }
+    }
+}
+
+/// Writer which can make use of a [`ChunkMeta`] describing what it is asked
+/// to write.
+pub trait ChunkWrite: Write {
+    /// Write some of `buf`, all of which is described by `meta`.
+    fn write_chunk(&mut self, buf: &[u8], meta: &ChunkMeta) -> Result<usize> {
+        let _ = meta;
+        self.write(buf)
+    }
+}
+
+impl ChunkWrite for Vec<u8> {}
+impl ChunkWrite for std::io::Sink {}
+
+/// Copies everything from a reader to a writer, like [`std::io::copy`], but
+/// in chunks of a known size and with a chance to stop between them.
+///
+/// Reads and writes interrupted by a signal are retried, and short writes
+/// are continued until the whole chunk has been accepted. Each chunk is
+/// counted once as it is read and handed down as a [`ChunkMeta`].
+#[derive(Clone)]
+pub struct Pump {
+    chunk_size: NonZeroUsize,
+    cancelled: Option<LatchMonitor>,
+    bytes_only: bool,
+}
+
+impl Default for Pump {
+    fn default() -> Self {
+        Self {
+            chunk_size: Self::DEFAULT_CHUNK_SIZE,
+            cancelled: None,
+            bytes_only: false,
+        }
+    }
+}
+
+impl Pump {
+    /// Same as the buffer [`std::io::copy`] uses.
+    pub const DEFAULT_CHUNK_SIZE: NonZeroUsize = nonzero!(8 * 1024usize);
+
+    /// Read at most `chunk_size` bytes at a time.
+    pub fn chunk_size(mut self, chunk_size: NonZeroUsize) -> Self {
+        self.chunk_size = chunk_size;
+        self
+    }
+    /// Stop with an error before the next read once `cancelled` is active.
+    pub fn cancel_on(mut self, cancelled: LatchMonitor) -> Self {
+        self.cancelled = Some(cancelled);
+        self
+    }
+    /// Count only bytes and writes, skipping the scan for every other unit.
+    pub fn count_bytes_only(mut self, enabled: bool) -> Self {
+        self.bytes_only = enabled;
+        self
+    }
+    /// Copy until `reader` runs out, returning how many bytes were copied.
+    pub fn copy<R, W>(&mut self, reader: &mut R, writer: &mut W) -> Result<u64>
+    where
+        R: Read + ?Sized,
+        W: ChunkWrite + ?Sized,
+    {
+        let mut reader = BufReader::with_capacity(self.chunk_size.get(), reader);
+        let mut scanner = Scanner::default();
+        let mut copied = 0;
+        loop {
+            if self.cancelled.as_mut().is_some_and(LatchMonitor::active) {
+                return Err(cancelled_error());
+            }
+            let chunk = match reader.fill_buf() {
+                Ok([]) => return Ok(copied),
+                Ok(chunk) => chunk,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            let n = chunk.len();
+            let meta = ChunkMeta::scan(&mut scanner, chunk, self.bytes_only);
+            write_all(writer, chunk, &meta)?;
+            reader.consume(n);
+            copied += n as u64;
+        }
+    }
+}
+
+/// Like [`Write::write_all`], spelled out so that every write goes through
+/// the full writer chain one call at a time, and so that `meta` is only
+/// handed down along with the whole chunk it describes.
+fn write_all<W>(writer: &mut W, mut buf: &[u8], meta: &ChunkMeta) -> Result<()>
+where
+    W: ChunkWrite + ?Sized,
+{
+    let whole = buf.len();
+    while !buf.is_empty() {
+        let result = if buf.len() == whole {
+            writer.write_chunk(buf, meta)
+        } else {
+            writer.write(buf)
+        };
+        match result {
+            Ok(0) => return Err(Error::new(
+                ErrorKind::WriteZero,
+                "failed to write whole buffer",
+            )),
+            Ok(n) => buf = &buf[n..],
+            Err(e) if e.kind() == ErrorKind::Interrupted => {},
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Latch;
+
+    /// Fails every other call with [`ErrorKind::Interrupted`] and otherwise
+    /// transfers at most two bytes.
+    #[derive(Default)]
+    struct Flaky {
+        data: Vec<u8>,
+        calls: usize,
+    }
+
+    impl Flaky {
+        fn interrupt(&mut self) -> bool {
+            self.calls += 1;
+            self.calls % 2 == 1
+        }
+    }
+
+    impl Read for Flaky {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            if self.interrupt() {
+                return Err(ErrorKind::Interrupted.into());
+            }
+            let n = buf.len().min(self.data.len()).min(2);
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data.drain(..n);
+            Ok(n)
+        }
+    }
+
+    impl Write for Flaky {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            if self.interrupt() {
+                return Err(ErrorKind::Interrupted.into());
+            }
+            let n = buf.len().min(2);
+            self.data.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    impl ChunkWrite for Flaky {}
+
+    #[test]
+    fn interruptions_and_short_transfers_are_retried() {
+        let mut reader = Flaky { data: b"hello, world".to_vec(), calls: 0 };
+        let mut writer = Flaky::default();
+        let mut pump = Pump::default().chunk_size(nonzero!(5usize));
+        assert_eq!(pump.copy(&mut reader, &mut writer).unwrap(), 12);
+        assert_eq!(writer.data, b"hello, world");
+    }
+
+    #[test]
+    fn cancellation_stops_before_the_next_read() {
+        let mut cancelled = Latch::new();
+        let mut pump = Pump::default().cancel_on(cancelled.watch());
+        cancelled.on();
+        let mut writer = vec![];
+        let error = pump.copy(&mut &b"data"[..], &mut writer).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::BrokenPipe);
+        assert!(writer.is_empty());
+    }
+
+    /// Collects the progress every chunk was described with.
+    #[derive(Default)]
+    struct Described(Vec<TransferProgress>);
+
+    impl Write for Described {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    impl ChunkWrite for Described {
+        fn write_chunk(&mut self, buf: &[u8], meta: &ChunkMeta) -> Result<usize> {
+            self.0.push(meta.progress);
+            Ok(buf.len())
+        }
+    }
+
+    #[test]
+    fn counting_bytes_only_leaves_every_other_unit_at_zero() {
+        let mut writer = Described::default();
+        let mut pump = Pump::default().count_bytes_only(true);
+        pump.copy(&mut &b"one two\nthree\0"[..], &mut writer).unwrap();
+        let progress = writer.0.into_iter().sum::<TransferProgress>();
+        assert_eq!(progress, TransferProgress {
+            bytes_transferred: 14,
+            writes_transferred: 1,
+            ..Default::default()
+        });
+    }
+
+}
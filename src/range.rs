@@ -0,0 +1,122 @@
+use std::{
+    fmt,
+    io::{
+        self,
+        Seek,
+        SeekFrom,
+    },
+    str::FromStr,
+};
+
+use thiserror::Error;
+
+use super::size::ByteSize;
+
+/// Part of the input to copy, from `start` up to but not including `end`,
+/// written as `START-END` with either bound optional, such as `1Gi-2Gi`,
+/// `100M-` or `-4k`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: Option<u64>,
+}
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum ParseByteRangeError {
+    #[error("{0:?} is not a range such as 1Gi-2Gi, 100M- or -4k")]
+    Invalid(String),
+    #[error("range {0:?} ends before it starts")]
+    Empty(String),
+}
+
+impl ByteRange {
+    /// Number of bytes in the range, if it is bounded.
+    pub fn len(&self) -> Option<u64> {
+        self.end.map(|end| end - self.start)
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0)
+    }
+    /// Number of bytes the range covers in an input of `size` bytes.
+    pub fn len_within(&self, size: u64) -> u64 {
+        self.end
+            .unwrap_or(u64::MAX)
+            .min(size)
+            .saturating_sub(self.start)
+    }
+    /// Move `input` to the start of the range; inputs which cannot seek are
+    /// only accepted when the range starts at the beginning.
+    pub fn seek(&self, input: &mut impl Seek) -> io::Result<()> {
+        if self.start > 0 {
+            input.seek(SeekFrom::Start(self.start))?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for ByteRange {
+    type Err = ParseByteRangeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ParseByteRangeError::Invalid(s.to_owned());
+        let (start, end) = s.split_once('-').ok_or_else(invalid)?;
+        let bound = |bound: &str| match bound.trim() {
+            "" => Ok(None),
+            bound => bound.parse::<ByteSize>()
+                .map(|ByteSize(size)| Some(size))
+                .map_err(|_| invalid()),
+        };
+        let start = bound(start)?.unwrap_or(0);
+        let end = bound(end)?;
+        if end.is_some_and(|end| end < start) {
+            return Err(ParseByteRangeError::Empty(s.to_owned()));
+        }
+        Ok(Self { start, end })
+    }
+}
+
+impl fmt::Display for ByteRange {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "{}-", self.start)?;
+        if let Some(end) = self.end {
+            write!(fmt, "{}", end)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounds_accept_suffixes_and_may_be_left_out() {
+        assert_eq!("1Gi-2Gi".parse(), Ok(ByteRange {
+            start: 1 << 30,
+            end: Some(2 << 30),
+        }));
+        assert_eq!("100M-".parse(), Ok(ByteRange {
+            start: 100_000_000,
+            end: None,
+        }));
+        assert_eq!("-4k".parse::<ByteRange>().map(|r| r.len()), Ok(Some(4000)));
+        for bad in ["", "10", "a-b", "1-2-3"] {
+            assert!(bad.parse::<ByteRange>().is_err(), "{:?} parsed", bad);
+        }
+        assert_eq!(
+            "2k-1k".parse::<ByteRange>(),
+            Err(ParseByteRangeError::Empty("2k-1k".into())),
+        );
+    }
+
+    #[test]
+    fn length_is_clipped_to_the_input() {
+        let range: ByteRange = "10-20".parse().unwrap();
+        assert_eq!(range.len_within(100), 10);
+        assert_eq!(range.len_within(15), 5);
+        assert_eq!(range.len_within(5), 0);
+        let open: ByteRange = "10-".parse().unwrap();
+        assert_eq!(open.len_within(100), 90);
+    }
+
+}
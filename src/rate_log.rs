@@ -0,0 +1,189 @@
+use std::{
+    fmt,
+    io::{
+        self,
+        Write,
+    },
+    num::NonZeroU64,
+    time::{
+        Duration,
+        SystemTime,
+    },
+};
+
+use super::{
+    config::LatchMonitor,
+    control::Controls,
+    instantaneous::RateSampler,
+    progress::{
+        CumulativeProgressMonitor,
+        TransferProgress,
+    },
+};
+
+/// One row of a rate log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateRow {
+    /// Time since the Unix epoch.
+    pub timestamp: Duration,
+    pub progress: TransferProgress,
+    /// Instantaneous rate per second in the limiting unit.
+    pub rate: u64,
+    pub limit: Option<NonZeroU64>,
+    pub paused: bool,
+}
+
+/// Writes a [`RateRow`] as CSV every second until shutdown, and once more
+/// after it, for graphing throughput once the transfer is over.
+pub struct RateLog<W> {
+    out: W,
+    cumulative: CumulativeProgressMonitor,
+    rate: RateSampler,
+    controls: Controls,
+    shutdown: LatchMonitor,
+}
+
+impl RateRow {
+    pub const HEADER: &'static str = "timestamp,bytes,lines,nulls,rate,limit,paused";
+}
+
+impl fmt::Display for RateRow {
+    /// Comma-separated, with the timestamp in seconds to the millisecond and
+    /// an empty limit when there is none.
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            fmt,
+            "{}.{:03},{},{},{},{},",
+            self.timestamp.as_secs(),
+            self.timestamp.subsec_millis(),
+            self.progress.bytes_transferred,
+            self.progress.lines_transferred,
+            self.progress.nulls_transferred,
+            self.rate,
+        )?;
+        if let Some(limit) = self.limit {
+            write!(fmt, "{}", limit)?;
+        }
+        write!(fmt, ",{}", self.paused)
+    }
+}
+
+impl <W: Write> RateLog<W> {
+    const INTERVAL: Duration = Duration::from_secs(1);
+
+    pub fn new(
+        out: W,
+        cumulative: CumulativeProgressMonitor,
+        rate: RateSampler,
+        controls: Controls,
+        shutdown: LatchMonitor,
+    ) -> Self {
+        Self {
+            out,
+            cumulative,
+            rate,
+            controls,
+            shutdown,
+        }
+    }
+    pub fn run(mut self) -> io::Result<()> {
+        writeln!(self.out, "{}", RateRow::HEADER)?;
+        self.log()?;
+        while !self.shutdown.wait_timeout(Self::INTERVAL) {
+            self.log()?;
+        }
+        self.log()
+    }
+    fn log(&mut self) -> io::Result<()> {
+        let config = self.controls.config();
+        let row = RateRow {
+            timestamp: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default(),
+            progress: self.cumulative.get().progress,
+            rate: config.unit.progress(&self.rate.sample()),
+            limit: config.limit(),
+            paused: self.controls.paused(),
+        };
+        writeln!(self.out, "{}", row)?;
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Instant;
+
+    use nonzero_ext::nonzero;
+    use crate::state::channel;
+
+    use crate::{
+        config::{
+            Config,
+            ConfigMonitor,
+            Latch,
+        },
+        instantaneous::RateEstimator,
+        progress::{
+            CumulativeTransferProgress,
+            TransferProgressMonitor,
+        },
+    };
+
+    #[test]
+    fn rows_leave_the_limit_empty_when_there_is_none() {
+        let row = RateRow {
+            timestamp: Duration::from_millis(1_700_000_000_042),
+            progress: TransferProgress {
+                bytes_transferred: 4096,
+                lines_transferred: 12,
+                nulls_transferred: 1,
+                ..Default::default()
+            },
+            rate: 512,
+            limit: Some(nonzero!(1024u64)),
+            paused: false,
+        };
+        assert_eq!(row.to_string(), "1700000000.042,4096,12,1,512,1024,false");
+        let row = RateRow {
+            limit: None,
+            paused: true,
+            ..row
+        };
+        assert_eq!(row.to_string(), "1700000000.042,4096,12,1,512,,true");
+    }
+
+    #[test]
+    fn log_starts_with_a_header_and_ends_with_a_row_after_shutdown() {
+        let (cumulative, _) = channel(CumulativeTransferProgress::started(Instant::now()));
+        let (progress, _) = channel(TransferProgress::default());
+        let config = Config {
+            limit: Some(nonzero!(100u64)).into(),
+            ..Default::default()
+        };
+        let (config_tx, _) = ConfigMonitor::new(config);
+        let controls = Controls::new(config, config_tx, Latch::new(), Latch::new());
+        let mut shutdown = Latch::new();
+        let mut out = vec![];
+        let log = RateLog::new(
+            &mut out,
+            CumulativeProgressMonitor::new(cumulative.subscribe()),
+            RateSampler::new(
+                TransferProgressMonitor::new(progress.subscribe()),
+                RateEstimator::new(Duration::from_secs(1)),
+            ),
+            controls,
+            shutdown.watch(),
+        );
+        shutdown.on();
+        log.run().unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], RateRow::HEADER);
+        assert!(lines[2].ends_with(",0,0,0,0,100,false"), "{}", lines[2]);
+    }
+
+}
@@ -0,0 +1,357 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    io::{
+        self,
+        ErrorKind,
+        Read,
+        Result,
+    },
+    num::NonZeroUsize,
+    str::FromStr,
+    sync::{
+        Arc,
+        Condvar,
+        Mutex,
+        MutexGuard,
+    },
+    thread,
+};
+
+use thiserror::Error;
+use super::state::{
+    channel,
+    StateWatcher,
+    StateCell,
+};
+
+/// How full a [`ReadAhead`] buffer gets before its reader stops, and how far
+/// it drains before the reader starts again, as percentages of its size,
+/// written `LOW:HIGH` such as `50:100`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watermarks {
+    pub low: u8,
+    pub high: u8,
+}
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+#[error("{0:?} is not a pair of watermarks such as 50:100, with LOW below HIGH and HIGH at most 100")]
+pub struct ParseWatermarksError(String);
+
+/// How much of a [`ReadAhead`] buffer is held.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Occupancy {
+    pub held: usize,
+    pub capacity: usize,
+}
+
+/// Reader which fills a buffer of a fixed size from its source on a thread
+/// of its own, for `--buffer-size`, so that a bursty source is read as fast
+/// as it produces while a throttled writer drains the buffer at its own
+/// pace.
+///
+/// Once the buffer fills to the high watermark, reading stops until it has
+/// drained to the low one, so the source is read in long runs rather than a
+/// chunk at a time as room appears. A read never fills the buffer past its
+/// size. An error from the source is handed on once everything read before
+/// it has been.
+///
+/// The thread is left behind when the reader is dropped, since it may be
+/// blocked on a source nothing can interrupt; it stops as soon as its
+/// current read returns.
+pub struct ReadAhead {
+    shared: Arc<Shared>,
+    occupancy: StateWatcher<Occupancy>,
+}
+
+/// Which part of the buffer is held, if any.
+#[derive(Clone)]
+pub struct OccupancyMonitor(StateWatcher<Occupancy>);
+
+struct Shared {
+    ring: Mutex<Ring>,
+    /// Signalled when there is something to read, or nothing more coming.
+    readable: Condvar,
+    /// Signalled when the buffer has drained, or nobody is reading anymore.
+    writable: Condvar,
+}
+
+struct Ring {
+    data: VecDeque<u8>,
+    capacity: usize,
+    low: usize,
+    high: usize,
+    /// The source is being read, between draining to the low watermark and
+    /// filling to the high one.
+    filling: bool,
+    eof: bool,
+    error: Option<io::Error>,
+    /// The reader has been dropped.
+    closed: bool,
+    occupancy: StateCell<Occupancy>,
+}
+
+impl Watermarks {
+    /// The default: read until the buffer is full, then let it drain halfway.
+    pub const DEFAULT: Self = Self {
+        low: 50,
+        high: 100,
+    };
+}
+
+impl Default for Watermarks {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl FromStr for Watermarks {
+    type Err = ParseWatermarksError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let invalid = || ParseWatermarksError(s.to_owned());
+        let percent = |percent: &str| {
+            let percent = percent.trim();
+            percent.strip_suffix('%').unwrap_or(percent).parse::<u8>().ok()
+        };
+        let (low, high) = s.split_once(':').ok_or_else(invalid)?;
+        let low = percent(low).ok_or_else(invalid)?;
+        let high = percent(high).ok_or_else(invalid)?;
+        if low >= high || high > 100 {
+            return Err(invalid());
+        }
+        Ok(Self { low, high })
+    }
+}
+
+impl fmt::Display for Watermarks {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "{}:{}", self.low, self.high)
+    }
+}
+
+impl Occupancy {
+    /// Share of the buffer held, from 0 to 100.
+    pub fn percentage(&self) -> f64 {
+        match self.capacity {
+            0 => 0f64,
+            capacity => 100f64 * self.held as f64 / capacity as f64,
+        }
+    }
+}
+
+impl ReadAhead {
+    /// Start reading `source` into a buffer of `size` bytes.
+    pub fn spawn<R>(source: R, size: NonZeroUsize, watermarks: Watermarks) -> Result<Self>
+    where
+        R: Read + Send + 'static,
+    {
+        let capacity = size.get();
+        let mark = |percent: u8| capacity * usize::from(percent) / 100;
+        let (occupancy, rx) = channel(Occupancy {
+            held: 0,
+            capacity,
+        });
+        let shared = Arc::new(Shared {
+            ring: Mutex::new(Ring {
+                data: VecDeque::with_capacity(capacity),
+                capacity,
+                low: mark(watermarks.low),
+                // Always leave room for something to be read.
+                high: mark(watermarks.high).max(1),
+                filling: true,
+                eof: false,
+                error: None,
+                closed: false,
+                occupancy,
+            }),
+            readable: Condvar::new(),
+            writable: Condvar::new(),
+        });
+        let filler = Arc::clone(&shared);
+        thread::Builder::new()
+            .name("read-ahead".into())
+            .spawn(move || filler.fill(source))?;
+        Ok(Self {
+            shared,
+            occupancy: rx,
+        })
+    }
+    pub fn occupancy(&self) -> OccupancyMonitor {
+        OccupancyMonitor(self.occupancy.clone())
+    }
+}
+
+impl Read for ReadAhead {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mut ring = self.shared.lock();
+        while ring.data.is_empty() {
+            if let Some(e) = ring.error.take() {
+                return Err(e);
+            }
+            if ring.eof {
+                return Ok(0);
+            }
+            ring = self.shared.readable.wait(ring).unwrap_or_else(|e| e.into_inner());
+        }
+        let read = ring.data.read(buf)?;
+        ring.publish();
+        if !ring.filling && ring.data.len() <= ring.low {
+            ring.filling = true;
+            self.shared.writable.notify_one();
+        }
+        Ok(read)
+    }
+}
+
+impl Drop for ReadAhead {
+    fn drop(&mut self) {
+        self.shared.lock().closed = true;
+        self.shared.writable.notify_one();
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Ring> {
+        self.ring.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn fill(&self, mut source: impl Read) {
+        let mut chunk = vec![];
+        loop {
+            let room = {
+                let mut ring = self.lock();
+                while !ring.filling && !ring.closed {
+                    ring = self.writable.wait(ring).unwrap_or_else(|e| e.into_inner());
+                }
+                if ring.closed {
+                    return;
+                }
+                ring.capacity - ring.data.len()
+            };
+            chunk.resize(room, 0);
+            let result = source.read(&mut chunk);
+            let mut ring = self.lock();
+            match result {
+                Ok(0) => ring.eof = true,
+                Ok(n) => {
+                    ring.data.extend(&chunk[..n]);
+                    if ring.data.len() >= ring.high {
+                        ring.filling = false;
+                    }
+                    ring.publish();
+                },
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => ring.error = Some(e),
+            }
+            let done = ring.eof || ring.error.is_some();
+            drop(ring);
+            self.readable.notify_one();
+            if done {
+                return;
+            }
+        }
+    }
+}
+
+impl Ring {
+    fn publish(&self) {
+        self.occupancy.send(Occupancy {
+            held: self.data.len(),
+            capacity: self.capacity,
+        });
+    }
+}
+
+impl OccupancyMonitor {
+    pub fn get(&mut self) -> Occupancy {
+        self.0.get()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        sync::mpsc,
+        time::Duration,
+    };
+
+    use nonzero_ext::nonzero;
+
+    /// Hands out whatever arrives on a channel, one message per read, and
+    /// reports each read on another.
+    struct Bursts(mpsc::Receiver<Result<Vec<u8>>>, mpsc::Sender<usize>);
+
+    impl Read for Bursts {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            let _ = self.1.send(buf.len());
+            match self.0.recv() {
+                Ok(Ok(burst)) => {
+                    buf[..burst.len()].copy_from_slice(&burst);
+                    Ok(burst.len())
+                },
+                Ok(Err(e)) => Err(e),
+                Err(_) => Ok(0),
+            }
+        }
+    }
+
+    #[test]
+    fn watermarks_round_trip() {
+        let watermarks: Watermarks = "25%:75%".parse().unwrap();
+        assert_eq!(watermarks, Watermarks { low: 25, high: 75 });
+        assert_eq!(watermarks.to_string(), "25:75");
+        assert_eq!(Watermarks::default().to_string().parse(), Ok(Watermarks::DEFAULT));
+        for bad in ["", "50", "75:25", "50:50", "50:101", "a:b"] {
+            assert!(bad.parse::<Watermarks>().is_err(), "{:?} parsed", bad);
+        }
+    }
+
+    #[test]
+    fn everything_is_read_through_the_buffer() {
+        let source: Vec<u8> = (0..100_000u32).map(|i| i as u8).collect();
+        let mut reader = ReadAhead::spawn(
+            io::Cursor::new(source.clone()),
+            nonzero!(1000usize),
+            Watermarks { low: 10, high: 90 },
+        ).unwrap();
+        let mut read = vec![];
+        reader.read_to_end(&mut read).unwrap();
+        assert_eq!(read, source);
+    }
+
+    #[test]
+    fn reading_stops_at_the_high_watermark_until_drained_to_the_low() {
+        let (bursts, source) = mpsc::channel();
+        let (reads_tx, reads) = mpsc::channel();
+        let mut reader = ReadAhead::spawn(
+            Bursts(source, reads_tx),
+            nonzero!(10usize),
+            Watermarks { low: 20, high: 60 },
+        ).unwrap();
+        let mut occupancy = reader.occupancy();
+        let timeout = Duration::from_secs(5);
+        assert_eq!(reads.recv_timeout(timeout), Ok(10));
+        bursts.send(Ok(b"abcdef".to_vec())).unwrap();
+        // Full to the high watermark, so nothing more is read for now.
+        let mut buf = [0; 3];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(occupancy.get(), Occupancy { held: 3, capacity: 10 });
+        assert!(reads.recv_timeout(Duration::from_millis(50)).is_err());
+        // Drained to the low watermark, so reading starts again with all
+        // the room there is.
+        reader.read_exact(&mut [0; 1]).unwrap();
+        assert_eq!(reads.recv_timeout(timeout), Ok(8));
+        bursts.send(Err(io::Error::other("gone"))).unwrap();
+        let mut rest = vec![];
+        let e = reader.read_to_end(&mut rest).unwrap_err();
+        assert_eq!(e.to_string(), "gone");
+        assert_eq!(rest, b"ef");
+    }
+
+}
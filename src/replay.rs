@@ -0,0 +1,228 @@
+use std::{
+    io::{
+        self,
+        BufRead,
+        Result,
+        Write,
+    },
+    thread::sleep,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use super::{
+    rate_log::RateRow,
+    syncio::Finish,
+};
+
+/// Wait of `delay` before `bytes` more of the stream go out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Step {
+    pub delay: Duration,
+    pub bytes: u64,
+}
+
+/// When each part of a recorded stream originally went out, read from a
+/// timing file written by `script -t` or `script -T`, or by `--rate-log`.
+///
+/// `script` writes a line per chunk of output, giving the seconds since
+/// the one before and how many bytes it held, such as `0.104282 12`; its
+/// advanced format starts the line with the stream, of which only output,
+/// `O`, is kept. A rate log is recognized by its header, and each of its
+/// rows is turned into the bytes which arrived since the row before.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Timing {
+    pub steps: Vec<Step>,
+}
+
+/// Writer which holds each part of the stream back until the [`Timing`]
+/// says it went out, to reproduce how a recording arrived rather than
+/// flattening it to a rate.
+///
+/// Each wait counts from when the part before it went out, not from the
+/// start, so a transfer held back by a pause or a slow reader carries on
+/// from where it was instead of rushing to catch up. Anything beyond what
+/// the timing covers passes straight through.
+pub struct ReplayWriter<W> {
+    inner: W,
+    steps: std::vec::IntoIter<Step>,
+    /// Bytes of the current step not yet written.
+    remaining: u64,
+    /// When the current step went out.
+    released: Option<Instant>,
+}
+
+impl Timing {
+    pub fn read(reader: impl BufRead) -> Result<Self> {
+        let mut lines = reader.lines().enumerate().peekable();
+        let rate_log = match lines.peek() {
+            Some((_, Ok(header))) => header.trim() == RateRow::HEADER,
+            _ => false,
+        };
+        if rate_log {
+            lines.next();
+        }
+        let mut steps = vec![];
+        // Time and bytes of the row before, for rate logs.
+        let mut last: Option<(f64, u64)> = None;
+        for (number, line) in lines {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = || io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("timing line {}: {:?} is not understood", number + 1, line),
+            );
+            if rate_log {
+                let mut fields = line.split(',');
+                let at = fields.next()
+                    .and_then(|at| at.parse::<f64>().ok())
+                    .ok_or_else(invalid)?;
+                let bytes = fields.next()
+                    .and_then(|bytes| bytes.parse::<u64>().ok())
+                    .ok_or_else(invalid)?;
+                let (last_at, last_bytes) = last.unwrap_or((at, 0));
+                last = Some((at, bytes));
+                steps.push(Step {
+                    delay: Duration::try_from_secs_f64(at - last_at).map_err(|_| invalid())?,
+                    bytes: bytes.saturating_sub(last_bytes),
+                });
+                continue;
+            }
+            let fields: Vec<_> = line.split_whitespace().collect();
+            let (delay, bytes) = match *fields {
+                [delay, bytes] => (delay, bytes),
+                ["O", delay, bytes] => (delay, bytes),
+                [_, _, _] => continue,
+                _ => return Err(invalid()),
+            };
+            steps.push(Step {
+                delay: delay.parse::<f64>().ok()
+                    .and_then(|delay| Duration::try_from_secs_f64(delay).ok())
+                    .ok_or_else(invalid)?,
+                bytes: bytes.parse().map_err(|_| invalid())?,
+            });
+        }
+        Ok(Self { steps })
+    }
+    /// How long the recording took.
+    pub fn duration(&self) -> Duration {
+        self.steps.iter().map(|step| step.delay).sum()
+    }
+    /// How many bytes the recording holds.
+    pub fn size(&self) -> u64 {
+        self.steps.iter().map(|step| step.bytes).sum()
+    }
+}
+
+impl <W> ReplayWriter<W> {
+    /// Replay `timing` onto `inner`, or pass everything straight through
+    /// without one.
+    pub fn new(inner: W, timing: Option<Timing>) -> Self {
+        Self {
+            inner,
+            steps: timing.unwrap_or_default().steps.into_iter(),
+            remaining: 0,
+            released: None,
+        }
+    }
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+    /// Wait for the next step which has anything in it to come due, if
+    /// there is one.
+    fn next_step(&mut self) -> bool {
+        for step in self.steps.by_ref() {
+            let now = Instant::now();
+            let due = self.released.unwrap_or(now) + step.delay;
+            if let Some(wait) = due.checked_duration_since(now) {
+                sleep(wait);
+            }
+            self.released = Some(due.max(now));
+            if step.bytes > 0 {
+                self.remaining = step.bytes;
+                return true;
+            }
+        }
+        false
+    }
+}
+
+impl <W: Write> Write for ReplayWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.remaining == 0 && !self.next_step() {
+            return self.inner.write(buf);
+        }
+        let end = buf.len().min(self.remaining.try_into().unwrap_or(usize::MAX));
+        let written = self.inner.write(&buf[..end])?;
+        self.remaining -= written as u64;
+        Ok(written)
+    }
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl <W: Finish> Finish for ReplayWriter<W> {
+    fn finish(&mut self) -> Result<()> {
+        self.inner.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(millis: u64, bytes: u64) -> Step {
+        Step {
+            delay: Duration::from_millis(millis),
+            bytes,
+        }
+    }
+
+    #[test]
+    fn script_timing_keeps_only_output() {
+        let timing = Timing::read("0.5 12\n1.25 3\n".as_bytes()).unwrap();
+        assert_eq!(timing.steps, [step(500, 12), step(1250, 3)]);
+        let advanced = Timing::read("O 0.5 12\nI 0.1 1\nO 0.25 3\nS 0.0 SIGWINCH\n".as_bytes());
+        assert_eq!(advanced.unwrap().steps, [step(500, 12), step(250, 3)]);
+        assert!(Timing::read("0.5\n".as_bytes()).is_err());
+        assert!(Timing::read("-1 12\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn rate_logs_become_what_arrived_since_the_row_before() {
+        let log = format!(
+            "{}\n100.000,0,0,0,0,,false\n101.000,4096,0,0,4096,,false\n103.500,5000,0,0,0,,true\n",
+            RateRow::HEADER,
+        );
+        let timing = Timing::read(log.as_bytes()).unwrap();
+        assert_eq!(timing.steps, [step(0, 0), step(1000, 4096), step(2500, 904)]);
+        assert_eq!(timing.duration(), Duration::from_millis(3500));
+        assert_eq!(timing.size(), 5000);
+    }
+
+    #[test]
+    fn writes_are_split_and_held_back_at_step_boundaries() {
+        let timing = Timing {
+            steps: vec![step(0, 3), step(30, 0), step(30, 2)],
+        };
+        let mut writer = ReplayWriter::new(Vec::new(), Some(timing));
+        let start = Instant::now();
+        assert_eq!(writer.write(b"abcdefg").unwrap(), 3);
+        assert!(start.elapsed() < Duration::from_millis(30));
+        assert_eq!(writer.write(b"defg").unwrap(), 2);
+        assert!(start.elapsed() >= Duration::from_millis(60));
+        // Past the end of the timing, nothing is held back.
+        assert_eq!(writer.write(b"fg").unwrap(), 2);
+        assert_eq!(writer.get_ref(), b"abcdefg");
+    }
+
+}
@@ -0,0 +1,195 @@
+use std::{
+    fmt,
+    io::{
+        self,
+        Write,
+    },
+    time::{
+        Duration,
+        Instant,
+        SystemTime,
+    },
+};
+
+use super::{
+    config::LatchMonitor,
+    format::NumberFormat,
+    history::utc,
+    progress::{
+        CumulativeProgressMonitor,
+        CumulativeTransferProgress,
+    },
+    sink::TransferState,
+    state::StateWatcher,
+    unit::Unit,
+};
+
+/// One line of an interval report, such as
+///
+/// ```text
+/// 2023-11-14 22:13:20  3.00MiB in 90.0s at 34.1KiB/s  running
+/// ```
+///
+/// with the time in UTC, the total so far, how long the transfer has been
+/// going and its average rate, leaving out time spent paused.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReportLine {
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub amount: u64,
+    pub elapsed_seconds: f64,
+    pub average_rate: u64,
+    pub unit: Unit,
+    pub state: TransferState,
+    pub format: NumberFormat,
+}
+
+/// Writes a [`ReportLine`] every interval until shutdown, and once more
+/// after it, for `--report-interval`, so that batch job logs keep a record
+/// of how the transfer went much as `dd` does with `status=progress`.
+pub struct IntervalReport<W> {
+    out: W,
+    cumulative: CumulativeProgressMonitor,
+    state: StateWatcher<TransferState>,
+    unit: Unit,
+    format: NumberFormat,
+    interval: Duration,
+    shutdown: LatchMonitor,
+}
+
+impl ReportLine {
+    /// Line for `progress` as it stands at `now`, measured in `unit`.
+    pub fn new(
+        progress: &CumulativeTransferProgress,
+        now: Instant,
+        unit: Unit,
+        state: TransferState,
+        format: NumberFormat,
+    ) -> Self {
+        Self {
+            timestamp: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            amount: unit.progress(&progress.progress),
+            elapsed_seconds: progress.elapsed_at(now).as_secs_f64(),
+            average_rate: unit.progress(&progress.average_rate_at(now)),
+            unit,
+            state,
+            format,
+        }
+    }
+}
+
+impl fmt::Display for ReportLine {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            fmt,
+            "{}  {} in {:.1}s at {}  {}",
+            utc(self.timestamp),
+            self.format.amount(self.amount, self.unit, Some(2)),
+            self.elapsed_seconds,
+            self.format.rate(self.average_rate, self.unit),
+            self.state,
+        )
+    }
+}
+
+impl <W: Write> IntervalReport<W> {
+    pub fn new(
+        out: W,
+        cumulative: CumulativeProgressMonitor,
+        state: StateWatcher<TransferState>,
+        unit: Unit,
+        interval: Duration,
+        shutdown: LatchMonitor,
+    ) -> Self {
+        Self {
+            out,
+            cumulative,
+            state,
+            unit,
+            format: NumberFormat::default(),
+            interval,
+            shutdown,
+        }
+    }
+    /// Write numbers as `format` says.
+    pub fn with_format(mut self, format: NumberFormat) -> Self {
+        self.format = format;
+        self
+    }
+    pub fn run(mut self) -> io::Result<()> {
+        while !self.shutdown.wait_timeout(self.interval) {
+            self.report()?;
+        }
+        self.report()
+    }
+    fn report(&mut self) -> io::Result<()> {
+        let line = ReportLine::new(
+            &self.cumulative.get(),
+            Instant::now(),
+            self.unit,
+            self.state.get(),
+            self.format,
+        );
+        writeln!(self.out, "{}", line)?;
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        config::Latch,
+        progress::TransferProgress,
+        state::channel,
+    };
+
+    #[test]
+    fn lines_show_when_how_much_how_fast_and_how_it_stands() {
+        let line = ReportLine {
+            timestamp: 1_700_000_000,
+            amount: 3 << 20,
+            elapsed_seconds: 90.04,
+            average_rate: 34_952,
+            unit: Unit::Byte,
+            state: TransferState::Paused,
+            format: NumberFormat::default(),
+        };
+        assert_eq!(line.to_string(), "2023-11-14 22:13:20  3.00MiB in 90.0s at 34.1KiB/s  paused");
+    }
+
+    #[test]
+    fn reports_end_with_a_line_after_shutdown() {
+        let start = Instant::now() - Duration::from_secs(2);
+        let (cumulative, _) = channel(CumulativeTransferProgress {
+            progress: TransferProgress {
+                lines_transferred: 10,
+                ..Default::default()
+            },
+            ..CumulativeTransferProgress::started(start)
+        });
+        let (state, _) = channel(TransferState::Finished);
+        let mut shutdown = Latch::new();
+        let mut out = vec![];
+        let report = IntervalReport::new(
+            &mut out,
+            CumulativeProgressMonitor::new(cumulative.subscribe()),
+            state.subscribe(),
+            Unit::Line,
+            Duration::from_secs(10),
+            shutdown.watch(),
+        );
+        shutdown.on();
+        report.run().unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].ends_with("  finished"), "{}", lines[0]);
+        assert!(lines[0].contains("  10L in 2.0s at "), "{}", lines[0]);
+    }
+
+}
@@ -0,0 +1,212 @@
+use std::{
+    fmt,
+    io::{
+        Result,
+        Write,
+    },
+    num::NonZeroU64,
+    str::FromStr,
+};
+
+use thiserror::Error;
+
+use super::state::{
+    channel,
+    StateCell,
+};
+
+use super::{
+    progress::{
+        TransferProgress,
+        TransferProgressMonitor,
+    },
+    syncio::Finish,
+    unit::{
+        Scanner,
+        Unit,
+    },
+};
+
+/// Fraction of records to forward, written `K/N` for the first `K` records
+/// out of every `N`, such as `1/100`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SampleRate {
+    pub kept: u64,
+    pub every: NonZeroU64,
+}
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+#[error("{0:?} is not a fraction such as 1/100")]
+pub struct ParseSampleRateError(String);
+
+/// Writer which forwards only a sample of the records written to it while
+/// still accepting, and counting, all of them.
+///
+/// Records are delimited according to a record unit, so a record split
+/// across several writes is either forwarded or dropped as a whole. Without
+/// a rate, or with a unit which does not delimit records, everything is
+/// forwarded.
+pub struct SamplingWriter<W> {
+    inner: W,
+    sampling: Option<(u8, SampleRate)>,
+    /// Index of the record currently being written.
+    record: u64,
+    scanner: Scanner,
+    forwarded: TransferProgress,
+    tx: StateCell<TransferProgress>,
+    /// Count only bytes and writes of what is forwarded.
+    bytes_only: bool,
+}
+
+impl SampleRate {
+    fn keeps(&self, record: u64) -> bool {
+        record % self.every.get() < self.kept
+    }
+}
+
+impl FromStr for SampleRate {
+    type Err = ParseSampleRateError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let invalid = || ParseSampleRateError(s.to_owned());
+        let (kept, every) = s.split_once('/').ok_or_else(invalid)?;
+        let kept = kept.trim().parse::<u64>().map_err(|_| invalid())?;
+        let every = every.trim().parse::<NonZeroU64>().map_err(|_| invalid())?;
+        if kept > every.get() {
+            return Err(invalid());
+        }
+        Ok(Self { kept, every })
+    }
+}
+
+impl fmt::Display for SampleRate {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "{}/{}", self.kept, self.every)
+    }
+}
+
+impl <W> SamplingWriter<W> {
+    /// Forward `rate` of the records of `unit`.
+    pub fn new(inner: W, unit: Unit, rate: Option<SampleRate>) -> Self {
+        let (tx, _) = channel(TransferProgress::default());
+        Self {
+            inner,
+            sampling: unit.delimiter().zip(rate),
+            record: 0,
+            scanner: Scanner::default(),
+            forwarded: TransferProgress::default(),
+            tx,
+            bytes_only: false,
+        }
+    }
+    /// Count only the bytes and writes forwarded, skipping the scan for
+    /// every other unit.
+    pub fn count_bytes_only(mut self, enabled: bool) -> Self {
+        self.bytes_only = enabled;
+        self
+    }
+    /// Progress counting only what was forwarded.
+    pub fn forwarded_progress(&self) -> TransferProgressMonitor {
+        TransferProgressMonitor::new(self.tx.subscribe())
+    }
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+}
+
+impl <W: Write> SamplingWriter<W> {
+    fn forward(&mut self, part: &[u8]) -> Result<()> {
+        self.inner.write_all(part)?;
+        self.count(part);
+        Ok(())
+    }
+    fn count(&mut self, part: &[u8]) {
+        let progress = if self.bytes_only {
+            TransferProgress::bytes_only(part)
+        } else {
+            TransferProgress::scan(&mut self.scanner, part)
+        };
+        self.forwarded = self.forwarded + progress;
+    }
+}
+
+impl <W: Write> Write for SamplingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let Some((delimiter, rate)) = self.sampling else {
+            let bytes_transferred = self.inner.write(buf)?;
+            self.count(&buf[..bytes_transferred]);
+            self.tx.send(self.forwarded);
+            return Ok(bytes_transferred);
+        };
+        let mut forwarded_any = false;
+        for part in buf.split_inclusive(|b| *b == delimiter) {
+            if rate.keeps(self.record) {
+                self.forward(part)?;
+                forwarded_any = true;
+            }
+            if part.last() == Some(&delimiter) {
+                self.record += 1;
+            }
+        }
+        if forwarded_any {
+            self.tx.send(self.forwarded);
+        }
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl <W: Finish> Finish for SamplingWriter<W> {
+    fn finish(&mut self) -> Result<()> {
+        self.inner.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rates_are_fractions_of_at_most_one() {
+        assert_eq!("1/100".parse::<SampleRate>().map(|r| r.to_string()), Ok("1/100".into()));
+        for bad in ["", "1", "2/1", "1/0", "x/2", "-1/2"] {
+            assert!(bad.parse::<SampleRate>().is_err(), "{:?} parsed", bad);
+        }
+    }
+
+    #[test]
+    fn records_split_across_writes_are_kept_or_dropped_whole() {
+        let rate = "1/2".parse().unwrap();
+        let mut writer = SamplingWriter::new(Vec::new(), Unit::Line, Some(rate));
+        let mut forwarded = writer.forwarded_progress();
+        writer.write_all(b"a1\nb").unwrap();
+        writer.write_all(b"2\nc3").unwrap();
+        writer.write_all(b"\nd4\ne5").unwrap();
+        assert_eq!(writer.get_ref(), b"a1\nc3\ne5");
+        assert_eq!(forwarded.get().lines_transferred, 2);
+        assert_eq!(forwarded.get().bytes_transferred, 8);
+    }
+
+    #[test]
+    fn everything_is_forwarded_without_record_sampling() {
+        let rate = "1/2".parse().unwrap();
+        for (unit, rate) in [(Unit::Byte, Some(rate)), (Unit::Line, None)] {
+            let mut writer = SamplingWriter::new(Vec::new(), unit, rate);
+            writer.write_all(b"a\nb\n").unwrap();
+            assert_eq!(writer.get_ref(), b"a\nb\n");
+            assert_eq!(writer.forwarded_progress().get().lines_transferred, 2);
+        }
+    }
+
+    #[test]
+    fn counting_bytes_only_skips_every_other_unit() {
+        let mut writer = SamplingWriter::new(Vec::new(), Unit::Byte, None)
+            .count_bytes_only(true);
+        writer.write_all(b"a\nb\n").unwrap();
+        let forwarded = writer.forwarded_progress().get();
+        assert_eq!((forwarded.bytes_transferred, forwarded.lines_transferred), (4, 0));
+    }
+
+}
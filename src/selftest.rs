@@ -0,0 +1,209 @@
+use std::{
+    fmt,
+    io::{self, Read as _, Write as _},
+    num::NonZeroU64,
+    time::{Duration, Instant},
+};
+
+use nonzero_ext::nonzero;
+
+use super::{
+    config::{Config, ConfigMonitor},
+    format::NumberFormat,
+    pump::Pump,
+    syncio::WriteExt as _,
+    throttled::Throttled,
+    unit::{Scanner, Unit},
+};
+
+/// Benchmarks of the pieces a copy is made of, to tell whether pvalve
+/// itself could be what holds a transfer back.
+#[derive(Debug, Clone)]
+pub struct SelfTest {
+    /// Bytes pushed through each throughput benchmark.
+    pub size: u64,
+    /// Line rates to check the limiter against, taking about a second each.
+    pub rates: Vec<NonZeroU64>,
+}
+
+impl Default for SelfTest {
+    fn default() -> Self {
+        Self {
+            size: 256 * 1024 * 1024,
+            rates: vec![nonzero!(100u64), nonzero!(1000u64), nonzero!(10000u64)],
+        }
+    }
+}
+
+/// Bytes moved in some time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Throughput {
+    pub bytes: u64,
+    pub elapsed: Duration,
+}
+
+impl Throughput {
+    fn measure(bytes: u64, f: impl FnOnce()) -> Self {
+        let start = Instant::now();
+        f();
+        Self {
+            bytes,
+            elapsed: start.elapsed(),
+        }
+    }
+    pub fn bytes_per_second(&self) -> u64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds <= 0f64 {
+            return u64::MAX;
+        }
+        (self.bytes as f64 / seconds) as u64
+    }
+}
+
+/// How closely the limiter held a transfer to its limit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LimiterPrecision {
+    pub limit: NonZeroU64,
+    /// Rate actually achieved once the initial burst was spent.
+    pub achieved: f64,
+}
+
+impl LimiterPrecision {
+    /// Achieved rate as a fraction of the limit.
+    pub fn ratio(&self) -> f64 {
+        self.achieved / self.limit.get() as f64
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SelfTestReport {
+    /// Copying without counting or limiting anything.
+    pub passthrough: Throughput,
+    /// Counting each unit on its own.
+    pub counting: Vec<(Unit, Throughput)>,
+    /// Copying through the whole writer chain without a limit.
+    pub full_chain: Throughput,
+    pub limiter: Vec<LimiterPrecision>,
+}
+
+/// A line of text with a few words on it, which every unit finds something
+/// to count in.
+const SAMPLE_LINE: &[u8] = b"the quick brown fox jumps over the lazy dog\0 0123456789abcdef\n";
+
+impl SelfTest {
+    pub fn run(&self) -> io::Result<SelfTestReport> {
+        Ok(SelfTestReport {
+            passthrough: self.passthrough()?,
+            counting: Unit::ALL
+                .into_iter()
+                .map(|unit| (unit, self.counting(unit)))
+                .collect(),
+            full_chain: self.full_chain()?,
+            limiter: self.rates
+                .iter()
+                .map(|rate| Self::limiter(*rate))
+                .collect::<io::Result<_>>()?,
+        })
+    }
+    fn input(&self) -> impl io::Read {
+        io::repeat(b'x').take(self.size)
+    }
+    fn passthrough(&self) -> io::Result<Throughput> {
+        let mut result = Ok(0);
+        let throughput = Throughput::measure(self.size, || {
+            result = Pump::default().copy(&mut self.input(), &mut io::sink());
+        });
+        result.map(|_| throughput)
+    }
+    fn counting(&self, unit: Unit) -> Throughput {
+        let chunk = SAMPLE_LINE.repeat(Pump::DEFAULT_CHUNK_SIZE.get() / SAMPLE_LINE.len());
+        let chunks = self.size.div_ceil(chunk.len() as u64);
+        let mut scanner = Scanner::default();
+        let mut counted = 0;
+        let throughput = Throughput::measure(chunks * chunk.len() as u64, || {
+            for _ in 0..chunks {
+                counted += scanner.count(unit, &chunk);
+            }
+        });
+        assert!(counted > 0, "every unit occurs in the sample");
+        throughput
+    }
+    fn full_chain(&self) -> io::Result<Throughput> {
+        let (_, config) = ConfigMonitor::new(Config::default());
+        let mut writer = Throttled::new(io::sink(), config);
+        let mut result = Ok(0);
+        let throughput = Throughput::measure(self.size, || {
+            result = Pump::default().copy(&mut self.input(), &mut writer);
+        });
+        result.map(|_| throughput)
+    }
+    /// Spend the initial burst of `limit` lines, then time a second's worth.
+    fn limiter(limit: NonZeroU64) -> io::Result<LimiterPrecision> {
+        let (_, config) = ConfigMonitor::new(Config {
+            limit: Some(limit).into(),
+            unit: Unit::Line,
+            ..Default::default()
+        });
+        let mut writer = io::sink().limited(config);
+        let lines = SAMPLE_LINE.repeat(limit.get() as usize);
+        let chunk_size = Pump::DEFAULT_CHUNK_SIZE.get();
+        for chunk in lines.chunks(chunk_size) {
+            writer.write_all(chunk)?;
+        }
+        let start = Instant::now();
+        for chunk in lines.chunks(chunk_size) {
+            writer.write_all(chunk)?;
+        }
+        let seconds = start.elapsed().as_secs_f64();
+        Ok(LimiterPrecision {
+            limit,
+            achieved: limit.get() as f64 / seconds,
+        })
+    }
+}
+
+impl fmt::Display for SelfTestReport {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let format = NumberFormat::Auto;
+        let rate = |throughput: &Throughput| {
+            format.rate(throughput.bytes_per_second(), Unit::Byte)
+        };
+        writeln!(fmt, "passthrough: {}", rate(&self.passthrough))?;
+        writeln!(fmt, "full chain: {}", rate(&self.full_chain))?;
+        writeln!(fmt, "counting:")?;
+        for (unit, throughput) in &self.counting {
+            writeln!(fmt, "  {:>6} {}", unit.to_string(), rate(throughput))?;
+        }
+        writeln!(fmt, "limiter precision:")?;
+        for precision in &self.limiter {
+            writeln!(
+                fmt,
+                "  {:>8} achieved {:.1}{}/s ({:.1}%)",
+                format.rate(precision.limit.get(), Unit::Line),
+                precision.achieved,
+                Unit::Line.abbreviation(),
+                precision.ratio() * 100f64,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_covers_every_benchmark() {
+        let report = SelfTest {
+            size: 64 * 1024,
+            rates: vec![],
+        }.run().unwrap();
+        assert_eq!(report.passthrough.bytes, 64 * 1024);
+        assert_eq!(report.counting.len(), Unit::ALL.len());
+        let text = report.to_string();
+        assert!(text.contains("passthrough: "));
+        assert!(text.contains("    op "));
+    }
+
+}
@@ -0,0 +1,322 @@
+use std::{
+    ffi::CString,
+    io,
+    mem,
+    ptr::{self, NonNull},
+    sync::atomic::{fence, AtomicU64, Ordering},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+
+use super::{
+    config::LatchMonitor,
+    progress::{CumulativeProgressMonitor, TransferProgress},
+};
+
+/// Identifies a region written by this version of pvalve.
+const MAGIC: u64 = u64::from_le_bytes(*b"pvalve01");
+
+/// How often a [`ShmPublisher`] checks for shutdown between updates.
+const POLL: Duration = Duration::from_millis(100);
+
+/// How long a reader waits for an update to finish before deciding that its
+/// writer died part way through, far longer than any update takes.
+const READ_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// What the shared region holds, in this order, as native-endian 64-bit
+/// integers.
+///
+/// Readers take `sequence` before and after reading the counters and retry
+/// unless both are the same even number, so they never see half an update.
+#[repr(C)]
+struct Layout {
+    magic: AtomicU64,
+    sequence: AtomicU64,
+    bytes: AtomicU64,
+    lines: AtomicU64,
+    nulls: AtomicU64,
+    words: AtomicU64,
+    writes: AtomicU64,
+    /// Nanoseconds since the Unix epoch at the last update.
+    timestamp: AtomicU64,
+}
+
+/// One consistent reading of a shared progress region.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct SharedSnapshot {
+    #[serde(flatten)]
+    pub progress: TransferProgress,
+    pub updated_unix_nanos: u64,
+}
+
+impl SharedSnapshot {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self)
+            .expect("snapshot is always serializable")
+    }
+}
+
+/// Progress counters in a POSIX shared memory object, see shm_overview(7).
+///
+/// The writer creates the object and removes it again when dropped; readers
+/// map it read-only.
+pub struct SharedProgress {
+    layout: NonNull<Layout>,
+    name: CString,
+    owner: bool,
+}
+
+// SAFETY: every access to the mapping goes through atomics.
+unsafe impl Send for SharedProgress {}
+
+/// The object name for `name`, which may not contain a slash.
+fn object_name(name: &str) -> io::Result<CString> {
+    if name.is_empty() || name.contains('/') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{:?} is not a shared memory name, which needs no slashes", name),
+        ));
+    }
+    CString::new(format!("/{}", name))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+impl SharedProgress {
+    /// Create the object called `name`, or take over a stale one, even one
+    /// whose writer died mid-update.
+    pub fn create(name: &str) -> io::Result<Self> {
+        let name = object_name(name)?;
+        let shared = Self::map(name, true)?;
+        shared.layout().sequence.store(0, Ordering::Relaxed);
+        shared.layout().magic.store(MAGIC, Ordering::Release);
+        Ok(shared)
+    }
+    /// Map the object called `name` written by another process.
+    pub fn open(name: &str) -> io::Result<Self> {
+        let shared = Self::map(object_name(name)?, false)?;
+        if shared.layout().magic.load(Ordering::Acquire) != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "shared memory was not written by this version of pvalve",
+            ));
+        }
+        Ok(shared)
+    }
+    fn map(name: CString, owner: bool) -> io::Result<Self> {
+        let size = mem::size_of::<Layout>();
+        let (flags, protection) = if owner {
+            (libc::O_RDWR | libc::O_CREAT, libc::PROT_READ | libc::PROT_WRITE)
+        } else {
+            (libc::O_RDONLY, libc::PROT_READ)
+        };
+        // SAFETY: `name` is a valid C string for the duration of the call.
+        let fd = unsafe { libc::shm_open(name.as_ptr(), flags, 0o644) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: `fd` was just opened and is closed before returning; the
+        // mapping outlives it.
+        let mapped = unsafe {
+            let result = if owner && libc::ftruncate(fd, size as libc::off_t) != 0 {
+                Err(io::Error::last_os_error())
+            } else if let Err(e) = (!owner).then(|| Self::check_size(fd, size)).transpose() {
+                Err(e)
+            } else {
+                let addr = libc::mmap(ptr::null_mut(), size, protection, libc::MAP_SHARED, fd, 0);
+                if addr == libc::MAP_FAILED {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(addr)
+                }
+            };
+            libc::close(fd);
+            result?
+        };
+        Ok(Self {
+            layout: NonNull::new(mapped.cast()).expect("mmap never maps address zero"),
+            name,
+            owner,
+        })
+    }
+    /// Fail unless the object open as `fd` holds at least `size` bytes, as
+    /// one written by another process must before it is mapped, or if its
+    /// size cannot be told.
+    ///
+    /// # Safety
+    ///
+    /// `fd` must be open.
+    unsafe fn check_size(fd: libc::c_int, size: usize) -> io::Result<()> {
+        let mut stat = mem::zeroed::<libc::stat>();
+        if libc::fstat(fd, &mut stat) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if (stat.st_size as usize) < size {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "shared memory is too small"));
+        }
+        Ok(())
+    }
+    fn layout(&self) -> &Layout {
+        // SAFETY: the mapping is as large as the layout and lives as long as
+        // `self`.
+        unsafe { self.layout.as_ref() }
+    }
+    /// Replace the counters with `progress`, stamped with the current time.
+    pub fn publish(&self, progress: &TransferProgress) {
+        let layout = self.layout();
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_nanos() as u64)
+            .unwrap_or_default();
+        layout.sequence.fetch_add(1, Ordering::Relaxed);
+        fence(Ordering::Release);
+        layout.bytes.store(progress.bytes_transferred, Ordering::Relaxed);
+        layout.lines.store(progress.lines_transferred, Ordering::Relaxed);
+        layout.nulls.store(progress.nulls_transferred, Ordering::Relaxed);
+        layout.words.store(progress.words_transferred, Ordering::Relaxed);
+        layout.writes.store(progress.writes_transferred, Ordering::Relaxed);
+        layout.timestamp.store(timestamp, Ordering::Relaxed);
+        layout.sequence.fetch_add(1, Ordering::Release);
+    }
+    /// The counters as last published, failing if an update is still
+    /// under way after [`READ_TIMEOUT`], as when the writer died during one.
+    pub fn read(&self) -> io::Result<SharedSnapshot> {
+        let layout = self.layout();
+        let deadline = Instant::now() + READ_TIMEOUT;
+        loop {
+            if Instant::now() > deadline {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "shared memory stayed mid-update, as if its writer died",
+                ));
+            }
+            let before = layout.sequence.load(Ordering::Acquire);
+            if before % 2 == 1 {
+                std::hint::spin_loop();
+                continue;
+            }
+            let snapshot = SharedSnapshot {
+                progress: TransferProgress {
+                    bytes_transferred: layout.bytes.load(Ordering::Relaxed),
+                    lines_transferred: layout.lines.load(Ordering::Relaxed),
+                    nulls_transferred: layout.nulls.load(Ordering::Relaxed),
+                    words_transferred: layout.words.load(Ordering::Relaxed),
+                    writes_transferred: layout.writes.load(Ordering::Relaxed),
+                },
+                updated_unix_nanos: layout.timestamp.load(Ordering::Relaxed),
+            };
+            fence(Ordering::Acquire);
+            if layout.sequence.load(Ordering::Relaxed) == before {
+                return Ok(snapshot);
+            }
+        }
+    }
+}
+
+impl Drop for SharedProgress {
+    fn drop(&mut self) {
+        // SAFETY: the mapping was made with this size and is not used again.
+        unsafe {
+            libc::munmap(self.layout.as_ptr().cast(), mem::size_of::<Layout>());
+            if self.owner {
+                libc::shm_unlink(self.name.as_ptr());
+            }
+        }
+    }
+}
+
+/// Copies every update of the cumulative progress into shared memory as it
+/// happens, until shutdown.
+pub struct ShmPublisher {
+    shared: SharedProgress,
+    cumulative: CumulativeProgressMonitor,
+    shutdown: LatchMonitor,
+}
+
+impl ShmPublisher {
+    pub fn new(
+        shared: SharedProgress,
+        cumulative: CumulativeProgressMonitor,
+        shutdown: LatchMonitor,
+    ) -> Self {
+        Self {
+            shared,
+            cumulative,
+            shutdown,
+        }
+    }
+    pub fn run(mut self) {
+        self.shared.publish(&self.cumulative.get().progress);
+        while !self.shutdown.active() {
+            if let Some(cumulative) = self.cumulative.wait_timeout(POLL) {
+                self.shared.publish(&cumulative.progress);
+            }
+        }
+        self.shared.publish(&self.cumulative.get().progress);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    #[test]
+    fn readers_see_what_the_writer_published() {
+        let name = format!("pvalve-test-{}", process::id());
+        let writer = SharedProgress::create(&name).unwrap();
+        let reader = SharedProgress::open(&name).unwrap();
+        assert_eq!(reader.read().unwrap().progress, TransferProgress::default());
+        let progress = TransferProgress {
+            bytes_transferred: 4096,
+            lines_transferred: 12,
+            nulls_transferred: 1,
+            words_transferred: 30,
+            writes_transferred: 2,
+        };
+        writer.publish(&progress);
+        let snapshot = reader.read().unwrap();
+        assert_eq!(snapshot.progress, progress);
+        assert!(snapshot.updated_unix_nanos > 0);
+        drop(writer);
+        drop(reader);
+        assert!(SharedProgress::open(&name).is_err());
+    }
+
+    #[test]
+    fn reads_fail_once_an_update_never_finishes() {
+        let name = format!("pvalve-test-stuck-{}", process::id());
+        let writer = SharedProgress::create(&name).unwrap();
+        let reader = SharedProgress::open(&name).unwrap();
+        // As a writer dying half way through publishing would leave it.
+        writer.layout().sequence.fetch_add(1, Ordering::Release);
+        let e = reader.read().unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn creating_takes_over_an_object_left_mid_update() {
+        let name = format!("pvalve-test-stale-{}", process::id());
+        let stale = SharedProgress::create(&name).unwrap();
+        stale.layout().sequence.fetch_add(1, Ordering::Release);
+        // Left behind without being removed, as by a writer which died.
+        mem::forget(stale);
+        let writer = SharedProgress::create(&name).unwrap();
+        let reader = SharedProgress::open(&name).unwrap();
+        let progress = TransferProgress {
+            bytes_transferred: 10,
+            ..Default::default()
+        };
+        writer.publish(&progress);
+        assert_eq!(reader.read().unwrap().progress, progress);
+    }
+
+    #[test]
+    fn names_may_not_contain_slashes() {
+        assert!(object_name("transfer-1").is_ok());
+        for name in ["", "/transfer", "a/b"] {
+            assert!(object_name(name).is_err(), "{:?}", name);
+        }
+    }
+
+}
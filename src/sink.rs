@@ -0,0 +1,541 @@
+use std::{
+    fmt,
+    io::{
+        ErrorKind,
+        Result,
+        Write,
+    },
+    time::Instant,
+};
+
+use serde::{Deserialize, Serialize};
+use super::state::{
+    channel,
+    StateWatcher,
+    StateCell,
+};
+
+use super::{
+    config::LatchMonitor,
+    progress::{
+        CumulativeProgressMonitor,
+        CumulativeTransferProgress,
+        TransferProgress,
+        TransferProgressMonitor,
+    },
+    pump::{
+        ChunkMeta,
+        ChunkWrite,
+    },
+    syncio::Finish,
+    unit::{
+        Scanner,
+        Unit,
+    },
+};
+
+/// Lifecycle of a transfer as seen by a [`ProgressSink`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferState {
+    #[default]
+    Running,
+    Paused,
+    Cancelled,
+    Finished,
+    /// Stopped early because reading or writing failed.
+    Failed,
+}
+
+impl fmt::Display for TransferState {
+    /// The same lowercase name it is serialized as.
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str(match self {
+            Self::Running => "running",
+            Self::Paused => "paused",
+            Self::Cancelled => "cancelled",
+            Self::Finished => "finished",
+            Self::Failed => "failed",
+        })
+    }
+}
+
+/// Why a transfer ended, as reported once it has.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EndCause {
+    /// The source ran out.
+    Eof,
+    /// Cancelled from the keyboard, the control socket or a signal.
+    Cancelled,
+    /// Whatever the stream was written to stopped reading it.
+    DownstreamClosed,
+    /// The source was read as far as `--range` allows.
+    QuotaReached,
+    /// Reading or writing failed for any other reason.
+    Error,
+}
+
+impl EndCause {
+    /// Classify how a copy ended, given whether the transfer had been
+    /// cancelled by then and how many bytes it was meant to stop at, if
+    /// any.
+    ///
+    /// Cancellation fails writes with [`ErrorKind::BrokenPipe`] too, so it
+    /// is told apart by the latch rather than by the error.
+    pub fn classify(result: &Result<u64>, cancelled: bool, quota: Option<u64>) -> Self {
+        match result {
+            Err(_) if cancelled => Self::Cancelled,
+            Err(e) if e.kind() == ErrorKind::BrokenPipe => Self::DownstreamClosed,
+            Err(_) => Self::Error,
+            Ok(copied) if quota == Some(*copied) => Self::QuotaReached,
+            Ok(_) => Self::Eof,
+        }
+    }
+    /// Whether the transfer went as far as it was meant to.
+    pub fn is_success(&self) -> bool {
+        matches!(self, Self::Eof | Self::QuotaReached)
+    }
+    /// Status pvalve exits with: 0 once everything asked for went out, 130
+    /// when cancelled and 141 when the downstream closed, as a shell
+    /// reports a process killed by SIGINT or SIGPIPE, and 1 otherwise.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Eof | Self::QuotaReached => 0,
+            Self::Cancelled => 130,
+            Self::DownstreamClosed => 141,
+            Self::Error => 1,
+        }
+    }
+    /// The state a transfer which ended this way is left in.
+    pub fn state(&self) -> TransferState {
+        match self {
+            Self::Eof | Self::QuotaReached => TransferState::Finished,
+            Self::Cancelled => TransferState::Cancelled,
+            Self::DownstreamClosed | Self::Error => TransferState::Failed,
+        }
+    }
+}
+
+/// Receives updates about a transfer as data flows through the writer chain.
+pub trait ProgressSink {
+    /// Cumulative progress and timing, called after every successful write
+    /// and every change of state.
+    fn on_progress(&mut self, progress: CumulativeTransferProgress);
+    /// Instantaneous rate per second, called whenever a
+    /// [`RateSampler`](crate::instantaneous::RateSampler) feeding this sink
+    /// takes a sample.
+    fn on_rate_change(&mut self, rate: TransferProgress) {
+        let _ = rate;
+    }
+    /// Called whenever the transfer enters a new state.
+    fn on_state_change(&mut self, state: TransferState);
+    /// Called once the transfer has ended, after its final state change.
+    fn on_end(&mut self, cause: EndCause) {
+        let _ = cause;
+    }
+}
+
+/// Tracks progress and state on behalf of a [`ProgressSink`], only notifying
+/// it when something changes.
+///
+/// Rates are deliberately not computed here since this sits on the write
+/// path; sample the cumulative progress with a
+/// [`RateSampler`](crate::instantaneous::RateSampler) instead.
+pub struct Reporter<S> {
+    sink: S,
+    progress: CumulativeTransferProgress,
+    scanner: Scanner,
+    state: TransferState,
+    count_final_partial: Option<Unit>,
+    bytes_only: bool,
+}
+
+impl <S: ProgressSink> Reporter<S> {
+    pub fn new(mut sink: S) -> Self {
+        let progress = CumulativeTransferProgress::started(Instant::now());
+        sink.on_progress(progress);
+        Self {
+            sink,
+            progress,
+            scanner: Scanner::default(),
+            state: TransferState::default(),
+            count_final_partial: None,
+            bytes_only: false,
+        }
+    }
+    /// Which unit, if any, a trailing unterminated record is counted in
+    /// once finished.
+    pub fn set_count_final_partial(&mut self, unit: Option<Unit>) {
+        self.count_final_partial = unit;
+    }
+    /// Whether only bytes and writes are counted, leaving the other units
+    /// at zero.
+    pub fn set_bytes_only(&mut self, enabled: bool) {
+        self.bytes_only = enabled;
+    }
+    pub fn progress(&self) -> CumulativeTransferProgress {
+        self.progress
+    }
+    pub fn state(&self) -> TransferState {
+        self.state
+    }
+    /// Carry on counting from `progress`, made by an earlier transfer.
+    pub fn resume_from(&mut self, progress: TransferProgress) {
+        self.progress.progress = progress;
+        self.sink.on_progress(self.progress);
+    }
+    pub fn sink(&self) -> &S {
+        &self.sink
+    }
+    pub fn sink_mut(&mut self) -> &mut S {
+        &mut self.sink
+    }
+    pub fn into_sink(self) -> S {
+        self.sink
+    }
+    /// Account for `buf` having been written.
+    pub fn record(&mut self, buf: &[u8]) {
+        let sample = if self.bytes_only {
+            TransferProgress::bytes_only(buf)
+        } else {
+            TransferProgress::scan(&mut self.scanner, buf)
+        };
+        self.progress.progress = self.progress.progress + sample;
+        self.sink.on_progress(self.progress);
+    }
+    /// Account for `written` bytes of a chunk described by `meta` having
+    /// been written, only scanning them when the chunk was cut short.
+    pub fn record_chunk(&mut self, buf: &[u8], written: usize, meta: &ChunkMeta) {
+        if written < buf.len() {
+            return self.record(&buf[..written]);
+        }
+        self.scanner = meta.scanner;
+        self.progress.progress = self.progress.progress + meta.progress;
+        self.sink.on_progress(self.progress);
+    }
+    pub fn transition(&mut self, state: TransferState) {
+        if self.state == state {
+            return;
+        }
+        let now = Instant::now();
+        match state {
+            TransferState::Running => self.progress.resume(now),
+            TransferState::Paused => self.progress.pause(now),
+            TransferState::Cancelled | TransferState::Failed => {
+                self.progress.end(now)
+            },
+            TransferState::Finished => {
+                if let Some(unit) = self.count_final_partial {
+                    self.progress.progress = self.progress.progress
+                        + self.scanner.unterminated(unit);
+                }
+                self.progress.end(now);
+            },
+        }
+        self.state = state;
+        self.sink.on_state_change(state);
+        self.sink.on_progress(self.progress);
+    }
+    /// End the transfer for `cause`, leaving a cancelled one cancelled
+    /// whatever the copy made of it.
+    pub fn end(&mut self, cause: EndCause) {
+        if self.state != TransferState::Cancelled {
+            self.transition(cause.state());
+        }
+        self.sink.on_end(cause);
+    }
+    /// Transition according to the current value of the given latches.
+    pub fn observe(
+        &mut self,
+        paused: Option<&mut LatchMonitor>,
+        cancelled: Option<&mut LatchMonitor>,
+    ) {
+        let cancelled = cancelled
+            .map(|latch| latch.active())
+            .unwrap_or(false);
+        let paused = paused
+            .map(|latch| latch.active())
+            .unwrap_or(false);
+        if cancelled {
+            self.transition(TransferState::Cancelled);
+        } else if paused {
+            self.transition(TransferState::Paused);
+        } else {
+            self.transition(TransferState::Running);
+        }
+    }
+}
+
+/// Writer which reports everything passing through it to a [`ProgressSink`].
+pub struct SinkWriter<W, S> {
+    inner: W,
+    reporter: Reporter<S>,
+    paused: Option<LatchMonitor>,
+    cancelled: Option<LatchMonitor>,
+}
+
+impl <W, S: ProgressSink> SinkWriter<W, S> {
+    pub fn new(inner: W, sink: S) -> Self {
+        Self {
+            inner,
+            reporter: Reporter::new(sink),
+            paused: None,
+            cancelled: None,
+        }
+    }
+    /// Report pauses signalled through `paused`.
+    pub fn with_paused(self, paused: LatchMonitor) -> Self {
+        Self {
+            paused: Some(paused),
+            ..self
+        }
+    }
+    /// Report cancellation signalled through `cancelled`.
+    pub fn with_cancelled(self, cancelled: LatchMonitor) -> Self {
+        Self {
+            cancelled: Some(cancelled),
+            ..self
+        }
+    }
+    pub fn sink(&self) -> &S {
+        self.reporter.sink()
+    }
+    pub fn sink_mut(&mut self) -> &mut S {
+        self.reporter.sink_mut()
+    }
+    /// Report that the transfer has finished.
+    pub fn finish(&mut self) {
+        self.reporter.transition(TransferState::Finished);
+    }
+    /// Report that the transfer failed, unless it was cancelled already.
+    pub fn fail(&mut self) {
+        if self.reporter.state() != TransferState::Cancelled {
+            self.reporter.transition(TransferState::Failed);
+        }
+    }
+    fn observe_latches(&mut self) {
+        self.reporter.observe(self.paused.as_mut(), self.cancelled.as_mut());
+    }
+}
+
+impl <W: Write, S: ProgressSink> Write for SinkWriter<W, S> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.observe_latches();
+        let result = self.inner.write(buf);
+        self.observe_latches();
+        let bytes_transferred = result?;
+        self.reporter.record(&buf[..bytes_transferred]);
+        Ok(bytes_transferred)
+    }
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl <W: Finish, S: ProgressSink> Finish for SinkWriter<W, S> {
+    fn finish(&mut self) -> Result<()> {
+        self.inner.finish()
+    }
+}
+
+impl <W: Write, S: ProgressSink> ChunkWrite for SinkWriter<W, S> {
+    fn write_chunk(&mut self, buf: &[u8], meta: &ChunkMeta) -> Result<usize> {
+        self.observe_latches();
+        let result = self.inner.write(buf);
+        self.observe_latches();
+        let bytes_transferred = result?;
+        self.reporter.record_chunk(buf, bytes_transferred, meta);
+        Ok(bytes_transferred)
+    }
+}
+
+/// Sink which republishes every update over watch channels, so that other
+/// threads such as the interactive interface can poll for them.
+#[derive(Clone)]
+pub struct WatchSink {
+    cumulative: StateCell<CumulativeTransferProgress>,
+    progress: StateCell<TransferProgress>,
+    rate: StateCell<TransferProgress>,
+    state: StateCell<TransferState>,
+    end: StateCell<Option<EndCause>>,
+}
+
+impl WatchSink {
+    pub fn new() -> Self {
+        let (cumulative, _) = channel(
+            CumulativeTransferProgress::started(Instant::now())
+        );
+        let (progress, _) = channel(TransferProgress::default());
+        let (rate, _) = channel(TransferProgress::default());
+        let (state, _) = channel(TransferState::default());
+        let (end, _) = channel(None);
+        Self {
+            cumulative,
+            progress,
+            rate,
+            state,
+            end,
+        }
+    }
+    pub fn cumulative_progress(&self) -> CumulativeProgressMonitor {
+        CumulativeProgressMonitor::new(self.cumulative.subscribe())
+    }
+    pub fn transfer_progress(&self) -> TransferProgressMonitor {
+        TransferProgressMonitor::new(self.progress.subscribe())
+    }
+    pub fn instantaneous_progress(&self) -> TransferProgressMonitor {
+        TransferProgressMonitor::new(self.rate.subscribe())
+    }
+    pub fn transfer_state(&self) -> StateWatcher<TransferState> {
+        self.state.subscribe()
+    }
+    /// Why the transfer ended, once it has.
+    pub fn end_cause(&self) -> StateWatcher<Option<EndCause>> {
+        self.end.subscribe()
+    }
+    /// A handle to subscribe monitors from later on, without being able to
+    /// publish anything.
+    pub fn registry(&self) -> ProgressRegistry {
+        ProgressRegistry(self.clone())
+    }
+}
+
+/// Read-only handle on a [`WatchSink`], from which monitors can be
+/// subscribed at any point of the transfer, such as for a control socket
+/// client asking how it is going. A monitor subscribed late starts from the
+/// totals so far rather than from nothing.
+#[derive(Clone)]
+pub struct ProgressRegistry(WatchSink);
+
+impl ProgressRegistry {
+    pub fn cumulative_progress(&self) -> CumulativeProgressMonitor {
+        self.0.cumulative_progress()
+    }
+    pub fn transfer_progress(&self) -> TransferProgressMonitor {
+        self.0.transfer_progress()
+    }
+    pub fn transfer_state(&self) -> StateWatcher<TransferState> {
+        self.0.transfer_state()
+    }
+    pub fn end_cause(&self) -> StateWatcher<Option<EndCause>> {
+        self.0.end_cause()
+    }
+}
+
+impl Default for WatchSink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProgressSink for WatchSink {
+    fn on_progress(&mut self, progress: CumulativeTransferProgress) {
+        self.cumulative.send(progress);
+        self.progress.send(progress.progress);
+    }
+    fn on_rate_change(&mut self, rate: TransferProgress) {
+        self.rate.send(rate);
+    }
+    fn on_state_change(&mut self, state: TransferState) {
+        self.state.send(state);
+    }
+    fn on_end(&mut self, cause: EndCause) {
+        self.end.send(Some(cause));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Latch;
+
+    #[derive(Default)]
+    struct RecordingSink {
+        progress: Vec<TransferProgress>,
+        states: Vec<TransferState>,
+    }
+
+    impl ProgressSink for RecordingSink {
+        fn on_progress(&mut self, progress: CumulativeTransferProgress) {
+            let progress = progress.progress;
+            if self.progress.last() != Some(&progress) {
+                self.progress.push(progress);
+            }
+        }
+        fn on_state_change(&mut self, state: TransferState) {
+            self.states.push(state);
+        }
+    }
+
+    #[test]
+    fn sink_receives_cumulative_progress_and_state_changes() {
+        let mut cancelled = Latch::new();
+        let mut writer = SinkWriter::new(
+            Vec::new(),
+            RecordingSink::default(),
+        ).with_cancelled(cancelled.watch());
+        writer.write_all(b"a\n").unwrap();
+        writer.write_all(b"b\n").unwrap();
+        cancelled.on();
+        writer.write_all(b"c\n").unwrap();
+        writer.finish();
+        let RecordingSink { progress, states } = writer.reporter.into_sink();
+        let lines: Vec<_> = progress.iter()
+            .map(|p| p.lines_transferred)
+            .collect();
+        assert_eq!(lines, vec![0, 1, 2, 3]);
+        assert_eq!(states, vec![
+            TransferState::Cancelled,
+            TransferState::Finished,
+        ]);
+    }
+    #[test]
+    fn end_causes_are_told_apart() {
+        use std::io::Error;
+        let closed = Err(Error::from(ErrorKind::BrokenPipe));
+        assert_eq!(EndCause::classify(&Ok(10), false, None), EndCause::Eof);
+        assert_eq!(EndCause::classify(&Ok(10), false, Some(10)), EndCause::QuotaReached);
+        assert_eq!(EndCause::classify(&Ok(4), false, Some(10)), EndCause::Eof);
+        assert_eq!(EndCause::classify(&closed, false, None), EndCause::DownstreamClosed);
+        assert_eq!(EndCause::classify(&closed, true, None), EndCause::Cancelled);
+        let failed = Err(Error::from(ErrorKind::PermissionDenied));
+        assert_eq!(EndCause::classify(&failed, false, None), EndCause::Error);
+        let codes: Vec<_> = [
+            EndCause::Eof,
+            EndCause::QuotaReached,
+            EndCause::Cancelled,
+            EndCause::DownstreamClosed,
+            EndCause::Error,
+        ].iter().map(EndCause::exit_code).collect();
+        assert_eq!(codes, [0, 0, 130, 141, 1]);
+    }
+
+    #[test]
+    fn ending_reports_the_cause_after_the_final_state() {
+        let sink = WatchSink::new();
+        let mut state = sink.transfer_state();
+        let mut end = sink.end_cause();
+        let mut reporter = Reporter::new(sink);
+        assert_eq!(end.get(), None);
+        reporter.end(EndCause::DownstreamClosed);
+        assert_eq!(state.get(), TransferState::Failed);
+        assert_eq!(end.get(), Some(EndCause::DownstreamClosed));
+        reporter.transition(TransferState::Cancelled);
+        reporter.end(EndCause::Error);
+        assert_eq!(state.get(), TransferState::Cancelled);
+    }
+
+    #[test]
+    fn monitors_subscribed_late_start_from_the_totals_so_far() {
+        let sink = WatchSink::new();
+        let registry = sink.registry();
+        let mut writer = SinkWriter::new(vec![], sink);
+        writer.write_all(b"one\ntwo\n").unwrap();
+        writer.finish();
+        assert_eq!(registry.transfer_progress().get().lines_transferred, 2);
+        assert_eq!(registry.cumulative_progress().get().progress.bytes_transferred, 8);
+        assert_eq!(registry.transfer_state().get(), TransferState::Finished);
+    }
+
+}
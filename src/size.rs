@@ -0,0 +1,149 @@
+use std::{
+    fmt,
+    num::NonZeroU64,
+    str::FromStr,
+};
+
+use thiserror::Error;
+
+/// An amount written with an optional SI (`k`, `M`, `G`, ...) or binary
+/// (`Ki`, `Mi`, `Gi`, ...) multiplier, such as `700M` or `4.7Gi`.
+///
+/// Plain suffixes are always powers of 1000 and `i` suffixes always powers
+/// of 1024, so `1M` is 1000000 while `1Mi` is 1048576. A trailing `B` is
+/// accepted and ignored, and fractional amounts are rounded to the nearest
+/// whole unit.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ByteSize(pub u64);
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum ParseByteSizeError {
+    #[error("{0:?} is not a number with an optional suffix such as 700M or 4.7Gi")]
+    Invalid(String),
+    #[error("{0:?} is too large")]
+    TooLarge(String),
+    #[error("{0:?} must be greater than zero")]
+    Zero(String),
+}
+
+const PREFIXES: [char; 6] = ['K', 'M', 'G', 'T', 'P', 'E'];
+
+impl ByteSize {
+    pub fn get(&self) -> u64 {
+        self.0
+    }
+    /// Characters which may appear in a size, for input fields which filter
+    /// keystrokes.
+    pub fn is_size_char(c: char) -> bool {
+        c.is_ascii_digit()
+            || matches!(c, '.' | 'i' | 'B' | 'k')
+            || PREFIXES.contains(&c)
+    }
+}
+
+fn multiplier(suffix: &str) -> Option<u64> {
+    let suffix = suffix.strip_suffix('B').unwrap_or(suffix);
+    if suffix.is_empty() {
+        return Some(1);
+    }
+    let (prefix, binary) = match suffix.strip_suffix('i') {
+        Some(prefix) => (prefix, true),
+        None => (suffix, false),
+    };
+    let mut chars = prefix.chars();
+    let prefix = chars.next()?.to_ascii_uppercase();
+    if chars.next().is_some() {
+        return None;
+    }
+    let power = PREFIXES.iter().position(|p| *p == prefix)? as u32 + 1;
+    let base: u64 = if binary { 1024 } else { 1000 };
+    Some(base.pow(power))
+}
+
+impl FromStr for ByteSize {
+    type Err = ParseByteSizeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ParseByteSizeError::Invalid(s.to_owned());
+        let trimmed = s.trim();
+        let split = trimmed
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(trimmed.len());
+        let (number, suffix) = trimmed.split_at(split);
+        let multiplier = multiplier(suffix.trim_start()).ok_or_else(invalid)?;
+        if let Ok(whole) = number.parse::<u64>() {
+            return whole.checked_mul(multiplier)
+                .map(Self)
+                .ok_or_else(|| ParseByteSizeError::TooLarge(s.to_owned()));
+        }
+        let fraction = number.parse::<f64>().map_err(|_| invalid())?;
+        let amount = (fraction * multiplier as f64).round();
+        if amount >= u64::MAX as f64 {
+            return Err(ParseByteSizeError::TooLarge(s.to_owned()));
+        }
+        Ok(Self(amount as u64))
+    }
+}
+
+impl fmt::Display for ByteSize {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "{}", self.0)
+    }
+}
+
+/// Parse a size which must not be zero.
+pub fn parse_nonzero(s: &str) -> Result<NonZeroU64, ParseByteSizeError> {
+    let ByteSize(size) = s.parse()?;
+    NonZeroU64::new(size).ok_or_else(|| ParseByteSizeError::Zero(s.to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn size(s: &str) -> u64 {
+        s.parse::<ByteSize>().unwrap().get()
+    }
+
+    #[test]
+    fn plain_numbers_are_taken_as_is() {
+        assert_eq!(size("123"), 123);
+        assert_eq!(size("0"), 0);
+    }
+
+    #[test]
+    fn plain_suffixes_are_si_and_i_suffixes_are_binary() {
+        assert_eq!(size("700M"), 700_000_000);
+        assert_eq!(size("1k"), 1000);
+        assert_eq!(size("1K"), 1000);
+        assert_eq!(size("1Ki"), 1024);
+        assert_eq!(size("1Mi"), 1 << 20);
+        assert_eq!(size("1MiB"), 1 << 20);
+        assert_eq!(size("1MB"), 1_000_000);
+        assert_eq!(size("2 Gi"), 2 << 30);
+        assert_eq!(size("1Ei"), 1 << 60);
+    }
+
+    #[test]
+    fn fractions_are_rounded() {
+        assert_eq!(size("4.7Gi"), 5_046_586_573);
+        assert_eq!(size("1.5k"), 1500);
+        assert_eq!(size("0.4"), 0);
+    }
+
+    #[test]
+    fn malformed_and_oversized_amounts_are_rejected() {
+        for bad in ["", "M", "1X", "1Mb", "1.2.3", "1KiKi", "-1"] {
+            assert!(bad.parse::<ByteSize>().is_err(), "{:?} parsed", bad);
+        }
+        assert_eq!(
+            "20Ei".parse::<ByteSize>(),
+            Err(ParseByteSizeError::TooLarge("20Ei".into())),
+        );
+        assert_eq!(
+            parse_nonzero("0k"),
+            Err(ParseByteSizeError::Zero("0k".into())),
+        );
+    }
+
+}
@@ -0,0 +1,73 @@
+use std::{
+    fs::File,
+    io,
+    time::Duration,
+};
+
+use super::platform;
+
+/// Filesystems a transfer writes files to, whose free space `--min-free`
+/// keeps an eye on.
+#[derive(Default)]
+pub struct FreeSpace {
+    files: Vec<File>,
+}
+
+impl FreeSpace {
+    /// How often `--min-free` checks, which is often enough to pause well
+    /// before a filesystem with the margin to spare fills up.
+    pub const INTERVAL: Duration = Duration::from_secs(1);
+
+    /// Also watch the filesystem `file` is on, if it is a regular file;
+    /// pipes and terminals never fill up.
+    pub fn with_file(mut self, file: File) -> Self {
+        if file.metadata().is_ok_and(|metadata| metadata.is_file()) {
+            self.files.push(file);
+        }
+        self
+    }
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+    /// Bytes left for unprivileged writers on the fullest filesystem, or
+    /// none when no file is watched.
+    pub fn available(&self) -> io::Result<Option<u64>> {
+        let mut least = None;
+        for file in &self.files {
+            let available = platform::available_space(file)?;
+            least = Some(least.map_or(available, |least: u64| least.min(available)));
+        }
+        Ok(least)
+    }
+    /// Whether at least `min` bytes are left everywhere. Space which cannot
+    /// be found out counts as enough, so as not to hold the transfer up for
+    /// good.
+    pub fn at_least(&self, min: u64) -> bool {
+        match self.available() {
+            Ok(Some(available)) => available >= min,
+            Ok(None) | Err(_) => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+
+    #[test]
+    fn only_regular_files_are_watched() {
+        assert_eq!(FreeSpace::default().available().unwrap(), None);
+        let path = env::temp_dir().join(format!("pvalve-space-{}", std::process::id()));
+        let space = FreeSpace::default()
+            .with_file(File::create(&path).unwrap())
+            .with_file(File::open("/dev/null").unwrap());
+        assert_eq!(space.files.len(), 1);
+        assert!(space.available().unwrap().is_some());
+        assert!(space.at_least(0));
+        assert!(!space.at_least(u64::MAX));
+        std::fs::remove_file(path).unwrap();
+    }
+
+}
@@ -0,0 +1,208 @@
+use std::{
+    sync::{
+        Arc,
+        Condvar,
+        Mutex,
+        MutexGuard,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+/// Shared value which any number of producers can replace and any number of
+/// watchers can read, block on, or check for changes since they last looked.
+///
+/// Every replacement bumps a version counter, whether or not the value is
+/// any different, so a watcher sees each update it has not yet seen exactly
+/// once through [`StateWatcher::get_if_changed`] or
+/// [`StateWatcher::wait_changed`]. Cloning a cell adds another producer.
+pub struct StateCell<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// Reading end of a [`StateCell`], remembering the version it last saw.
+pub struct StateWatcher<T> {
+    shared: Arc<Shared<T>>,
+    seen: u64,
+}
+
+struct Shared<T> {
+    slot: Mutex<Slot<T>>,
+    changed: Condvar,
+}
+
+struct Slot<T> {
+    value: T,
+    version: u64,
+}
+
+/// A cell holding `value`, and a watcher which has yet to see it.
+pub fn channel<T>(value: T) -> (StateCell<T>, StateWatcher<T>) {
+    let cell = StateCell::new(value);
+    let watcher = StateWatcher {
+        shared: Arc::clone(&cell.shared),
+        seen: 0,
+    };
+    (cell, watcher)
+}
+
+impl <T> Shared<T> {
+    fn lock(&self) -> MutexGuard<'_, Slot<T>> {
+        self.slot.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl <T> StateCell<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                slot: Mutex::new(Slot {
+                    value,
+                    version: 1,
+                }),
+                changed: Condvar::new(),
+            }),
+        }
+    }
+    /// Replace the value, waking every watcher blocked on it.
+    pub fn send(&self, value: T) {
+        self.update(|current| *current = value);
+    }
+    /// Change the value in place, waking every watcher blocked on it.
+    pub fn update(&self, f: impl FnOnce(&mut T)) {
+        {
+            let mut slot = self.shared.lock();
+            f(&mut slot.value);
+            slot.version = slot.version.wrapping_add(1);
+        }
+        self.shared.changed.notify_all();
+    }
+    /// A watcher which has seen the value as it is now.
+    pub fn subscribe(&self) -> StateWatcher<T> {
+        StateWatcher {
+            shared: Arc::clone(&self.shared),
+            seen: self.version(),
+        }
+    }
+    /// How many times the value has been set, counting the first.
+    pub fn version(&self) -> u64 {
+        self.shared.lock().version
+    }
+}
+
+impl <T> Clone for StateCell<T> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl <T> StateWatcher<T> {
+    /// Look at the value without cloning it, marking it seen.
+    pub fn with<R>(&mut self, f: impl FnOnce(&T) -> R) -> R {
+        let slot = self.shared.lock();
+        self.seen = slot.version;
+        f(&slot.value)
+    }
+    /// Whether the value has been set since this watcher last looked.
+    pub fn has_changed(&self) -> bool {
+        self.shared.lock().version != self.seen
+    }
+    /// Version of the value this watcher last looked at.
+    pub fn version(&self) -> u64 {
+        self.seen
+    }
+}
+
+impl <T: Clone> StateWatcher<T> {
+    pub fn get(&mut self) -> T {
+        self.with(T::clone)
+    }
+    /// The value, if it has been set since this watcher last looked.
+    pub fn get_if_changed(&mut self) -> Option<T> {
+        let slot = self.shared.lock();
+        if slot.version == self.seen {
+            return None;
+        }
+        self.seen = slot.version;
+        Some(slot.value.clone())
+    }
+    /// Block until the value is set again, however long that takes.
+    pub fn wait(&mut self) -> T {
+        let mut slot = self.shared.lock();
+        while slot.version == self.seen {
+            slot = self.shared.changed.wait(slot).unwrap_or_else(|e| e.into_inner());
+        }
+        self.seen = slot.version;
+        slot.value.clone()
+    }
+    /// Block until the value is set again or `timeout` passes, returning it
+    /// if it was. A timeout too long to reach waits for good.
+    pub fn wait_changed(&mut self, timeout: Duration) -> Option<T> {
+        let Some(deadline) = Instant::now().checked_add(timeout) else {
+            return Some(self.wait());
+        };
+        let mut slot = self.shared.lock();
+        while slot.version == self.seen {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return None;
+            }
+            slot = self.shared.changed
+                .wait_timeout(slot, left)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+        self.seen = slot.version;
+        Some(slot.value.clone())
+    }
+}
+
+impl <T> Clone for StateWatcher<T> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: Arc::clone(&self.shared),
+            seen: self.seen,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    #[test]
+    fn watchers_see_each_update_once() {
+        let (cell, mut first) = channel(1);
+        assert_eq!(first.get_if_changed(), Some(1));
+        assert_eq!(first.get_if_changed(), None);
+        let mut later = cell.subscribe();
+        assert!(!later.has_changed());
+        cell.clone().send(2);
+        cell.update(|value| *value += 1);
+        assert_eq!(cell.version(), 3);
+        assert!(later.has_changed());
+        assert_eq!(later.get_if_changed(), Some(3));
+        assert_eq!(later.version(), 3);
+        assert_eq!(first.with(|value| value * 10), 30);
+        assert_eq!(first.get_if_changed(), None);
+    }
+
+    #[test]
+    fn waits_end_with_the_next_update_or_the_timeout() {
+        let (cell, mut watcher) = channel("before");
+        watcher.get();
+        assert_eq!(watcher.wait_changed(Duration::from_millis(10)), None);
+        let mut waiting = watcher.clone();
+        let waiter = thread::spawn(move || waiting.wait_changed(Duration::MAX));
+        cell.send("after");
+        assert_eq!(waiter.join().unwrap(), Some("after"));
+        assert_eq!(watcher.wait_changed(Duration::ZERO), Some("after"));
+    }
+
+}
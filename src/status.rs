@@ -0,0 +1,270 @@
+use std::{
+    env,
+    fmt,
+    io::{
+        self,
+        Write,
+    },
+    str::FromStr,
+    sync::Arc,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use crossterm::{
+    style::{
+        Attribute,
+        Color,
+        ContentStyle,
+        StyledContent,
+    },
+    terminal,
+};
+use thiserror::Error;
+use tui::style::{
+    Color as TuiColor,
+    Modifier,
+};
+
+use super::{
+    alarm::RateAlarm,
+    config::{
+        Config,
+        LatchMonitor,
+    },
+    template::ProgressTemplate,
+    tui::Monitors,
+    readahead::OccupancyMonitor,
+    window::WindowMonitor,
+    widgets::{
+        FileView,
+        Segment,
+        TransferProgressView,
+    },
+};
+
+/// Width assumed when the terminal cannot be asked for its size.
+const FALLBACK_WIDTH: u16 = 80;
+
+/// How progress is drawn on the terminal.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Render {
+    /// Simple inside tmux or GNU screen, full everywhere else.
+    #[default]
+    Auto,
+    /// The full-screen interface on the alternate screen.
+    Full,
+    /// A [`StatusLine`], which needs neither raw mode nor the alternate
+    /// screen and so survives nested multiplexers.
+    Simple,
+}
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+#[error("unknown rendering mode {0:?}, expected auto, full or simple")]
+pub struct ParseRenderError(String);
+
+impl Render {
+    /// Whether to draw a status line rather than the full-screen interface.
+    pub fn is_simple(self) -> bool {
+        let multiplexed = ["TMUX", "STY"]
+            .into_iter()
+            .any(|name| env::var_os(name).is_some_and(|value| !value.is_empty()));
+        self.is_simple_within(multiplexed)
+    }
+    fn is_simple_within(self, multiplexed: bool) -> bool {
+        match self {
+            Self::Auto => multiplexed,
+            Self::Full => false,
+            Self::Simple => true,
+        }
+    }
+}
+
+impl FromStr for Render {
+    type Err = ParseRenderError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "full" => Ok(Self::Full),
+            "simple" => Ok(Self::Simple),
+            _ => Err(ParseRenderError(s.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for Render {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Auto => "auto",
+            Self::Full => "full",
+            Self::Simple => "simple",
+        };
+        fmt.write_str(name)
+    }
+}
+
+/// Progress reported on a single line of stderr which is redrawn in place,
+/// for use where a full-screen interface is unwanted.
+pub struct StatusLine<W = io::Stderr> {
+    out: W,
+    shutdown: LatchMonitor,
+    config: Config,
+    monitors: Monitors,
+    refresh: Duration,
+    alarm: Option<RateAlarm>,
+    template: Option<Arc<ProgressTemplate>>,
+    /// What was drawn last, so that an unchanged line is not drawn again.
+    drawn: Vec<Segment>,
+}
+
+impl StatusLine {
+    pub fn new(config: Config, monitors: Monitors, shutdown: LatchMonitor) -> Self {
+        Self::with_output(io::stderr(), config, monitors, shutdown)
+    }
+}
+
+impl <W: Write> StatusLine<W> {
+    pub fn with_output(
+        out: W,
+        config: Config,
+        monitors: Monitors,
+        shutdown: LatchMonitor,
+    ) -> Self {
+        Self {
+            out,
+            shutdown,
+            config,
+            monitors,
+            refresh: Duration::from_secs(1),
+            alarm: config.min_rate.map(RateAlarm::new),
+            template: None,
+            drawn: vec![],
+        }
+    }
+    /// Redraw at most this often.
+    pub fn with_refresh(mut self, refresh: Duration) -> Self {
+        self.refresh = refresh;
+        self
+    }
+    /// Lay the line out as `template` says rather than as usual.
+    pub fn with_template(mut self, template: Option<ProgressTemplate>) -> Self {
+        self.template = template.map(Arc::new);
+        self
+    }
+
+    /// Redraw the line every refresh until shutdown, then leave it in place.
+    pub fn run(mut self) -> io::Result<()> {
+        self.draw()?;
+        while !self.shutdown.wait_timeout(self.refresh) {
+            self.draw()?;
+        }
+        self.draw()?;
+        writeln!(self.out)
+    }
+
+    fn draw(&mut self) -> io::Result<()> {
+        let width = terminal::size()
+            .map(|(columns, _)| columns)
+            .unwrap_or(FALLBACK_WIDTH);
+        let segments = self.line(width.saturating_sub(1) as usize)?;
+        if segments == self.drawn {
+            return Ok(());
+        }
+        self.drawn = segments.clone();
+        write!(self.out, "\r")?;
+        for (i, segment) in segments.into_iter().enumerate() {
+            if i > 0 {
+                write!(self.out, " ")?;
+            }
+            write!(self.out, "{}", styled(segment))?;
+        }
+        write!(self.out, "\x1b[K")?;
+        self.out.flush()
+    }
+
+    fn line(&mut self, width: usize) -> io::Result<Vec<Segment>> {
+        let Monitors {
+            cumulative,
+            instantaneous,
+            limiter,
+            forwarded,
+            files,
+            window,
+            buffer,
+            ..
+        } = &mut self.monitors;
+        let instantaneous = instantaneous.sample();
+        let now = Instant::now();
+        let mut below_minimum = false;
+        if let Some(alarm) = &mut self.alarm {
+            if alarm.observe(&instantaneous, self.config.unit, now)
+                && alarm.min_rate().bell
+            {
+                write!(self.out, "\x07")?;
+            }
+            below_minimum = alarm.raised();
+        }
+        let cumulative = cumulative.get();
+        let file = files.as_mut()
+            .and_then(|files| files.locate(cumulative.progress.bytes_transferred));
+        let mut line = TransferProgressView {
+            cumulative,
+            at: now,
+            expected_size: self.config.expected_size,
+            instantaneous,
+            limit: self.config.limit(),
+            paused: false,
+            gated: false,
+            waiting_for_space: false,
+            window: window.as_mut().and_then(WindowMonitor::get),
+            buffered: buffer.as_mut().map(OccupancyMonitor::get),
+            unit: self.config.shown_unit(),
+            limit_unit: self.config.unit,
+            format: self.config.number_format,
+            show_all_units: self.config.show_all_units,
+            below_minimum,
+            limiter_active: limiter.active_at(now),
+            forwarded: forwarded.as_mut().map(|forwarded| forwarded.get()),
+            mouse: false,
+            colored_gauge: false,
+            template: self.template.clone(),
+            rate_gauge: self.config.rate_gauge,
+        }
+            .status();
+        if let Some(file) = file {
+            FileView { file, format: self.config.number_format }.append_to(&mut line);
+        }
+        Ok(line.compose(width))
+    }
+}
+
+/// A segment with the subset of its style a plain terminal line can show.
+fn styled(segment: Segment) -> StyledContent<String> {
+    let mut style = ContentStyle::new();
+    if segment.style.add_modifier.contains(Modifier::BOLD) {
+        style.attributes.set(Attribute::Bold);
+    }
+    if segment.style.fg == Some(TuiColor::Red) {
+        style.foreground_color = Some(Color::Red);
+    }
+    StyledContent::new(style, segment.text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auto_rendering_is_simple_only_inside_a_multiplexer() {
+        assert!(Render::Auto.is_simple_within(true));
+        assert!(!Render::Auto.is_simple_within(false));
+        assert!(!Render::Full.is_simple_within(true));
+        assert!(Render::Simple.is_simple_within(false));
+        assert_eq!("simple".parse(), Ok(Render::Simple));
+        assert!("fancy".parse::<Render>().is_err());
+    }
+
+}
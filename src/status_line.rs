@@ -0,0 +1,369 @@
+use std::{
+    fmt,
+    num::{NonZeroU64, NonZeroUsize},
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use thiserror::Error;
+
+use super::{
+    format::NumberFormat,
+    progress::{
+        CumulativeTransferProgress,
+        TransferProgress,
+    },
+    size,
+    unit::Unit,
+};
+
+/// Part of a [`StatusLine`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Field {
+    /// Amount transferred in the shown unit, and in bytes if that differs,
+    /// or in every unit seen so far.
+    Transferred,
+    /// Time since the transfer started, as `H:MM:SS`.
+    Elapsed,
+    /// What was passed downstream, when sampling or filtering.
+    Forwarded,
+    /// Share of the expected size transferred, once that is known.
+    Percentage,
+    /// Instantaneous rate, in brackets.
+    Rate,
+    /// The limit, if one applies.
+    Limit,
+    /// Time left at the current rate, or how far past the expected size
+    /// the transfer went.
+    Eta,
+}
+
+/// What a gauge of the rate is full at, given with `--rate-gauge-max` as a
+/// rate in the shown unit per second, such as `10M`, or as `limit`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateScale {
+    Fixed(NonZeroU64),
+    /// Whatever the limit is at the time, with no gauge while unlimited.
+    Limit,
+}
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+#[error("{0:?} is not a rate such as 10M, or limit")]
+pub struct ParseRateScaleError(String);
+
+/// Concise one-line description of a transfer, such as
+///
+/// ```text
+/// 1.2GiB 0:03:12 37% [4.1MiB/s] ETA 0:05:20
+/// ```
+///
+/// built from one sample of its progress, for programs embedding pvalve to
+/// show the same line as the status line, the full-screen interface and
+/// its copy to the clipboard do. Fields with nothing to show, such as the
+/// ETA of a transfer of unknown size, are left out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatusLine {
+    cumulative: CumulativeTransferProgress,
+    at: Instant,
+    rate: TransferProgress,
+    expected_size: Option<NonZeroUsize>,
+    limit: Option<(NonZeroU64, Unit)>,
+    forwarded: Option<TransferProgress>,
+    unit: Unit,
+    format: NumberFormat,
+    show_all_units: bool,
+    fields: Vec<Field>,
+}
+
+impl StatusLine {
+    /// Every field, in the order pvalve shows them.
+    pub const DEFAULT_FIELDS: [Field; 7] = [
+        Field::Transferred,
+        Field::Elapsed,
+        Field::Forwarded,
+        Field::Percentage,
+        Field::Rate,
+        Field::Limit,
+        Field::Eta,
+    ];
+
+    /// Line for `cumulative` progress as of `at`, which every duration and
+    /// rate is worked out from.
+    pub fn new(cumulative: CumulativeTransferProgress, at: Instant) -> Self {
+        Self {
+            cumulative,
+            at,
+            rate: TransferProgress::default(),
+            expected_size: None,
+            limit: None,
+            forwarded: None,
+            unit: Unit::default(),
+            format: NumberFormat::default(),
+            show_all_units: false,
+            fields: Self::DEFAULT_FIELDS.to_vec(),
+        }
+    }
+    /// Instantaneous rate per second.
+    pub fn with_rate(mut self, rate: TransferProgress) -> Self {
+        self.rate = rate;
+        self
+    }
+    /// Bytes the transfer is expected to amount to.
+    pub fn with_expected_size(mut self, expected_size: Option<NonZeroUsize>) -> Self {
+        self.expected_size = expected_size;
+        self
+    }
+    /// Limit per second, charged in `unit`.
+    pub fn with_limit(mut self, limit: Option<NonZeroU64>, unit: Unit) -> Self {
+        self.limit = limit.map(|limit| (limit, unit));
+        self
+    }
+    /// What was passed downstream, when that differs from what was read.
+    pub fn with_forwarded(mut self, forwarded: Option<TransferProgress>) -> Self {
+        self.forwarded = forwarded;
+        self
+    }
+    /// Unit amounts and rates are shown in.
+    pub fn with_unit(mut self, unit: Unit) -> Self {
+        self.unit = unit;
+        self
+    }
+    pub fn with_format(mut self, format: NumberFormat) -> Self {
+        self.format = format;
+        self
+    }
+    /// Show every counter rather than only the shown unit and bytes.
+    pub fn with_all_units(mut self, enabled: bool) -> Self {
+        self.show_all_units = enabled;
+        self
+    }
+    /// Show only `fields`, in the order given.
+    pub fn with_fields(mut self, fields: impl IntoIterator<Item = Field>) -> Self {
+        self.fields = fields.into_iter().collect();
+        self
+    }
+
+    /// Fraction of the expected size transferred so far, if it is known,
+    /// which goes past one once more than expected arrives.
+    pub fn ratio(&self) -> Option<f64> {
+        self.expected_size.map(|expected_size| {
+            self.cumulative.progress.bytes_transferred as f64
+                / expected_size.get() as f64
+        })
+    }
+
+    /// Bytes transferred beyond the expected size, if any.
+    pub fn overrun(&self) -> Option<u64> {
+        let expected = self.expected_size?.get() as u64;
+        self.cumulative.progress.bytes_transferred
+            .checked_sub(expected)
+            .filter(|excess| *excess > 0)
+    }
+
+    /// Time left until the expected size is reached at the current rate, or
+    /// at the average rate while nothing is moving.
+    /// None once more than expected has arrived.
+    pub fn eta(&self) -> Option<Duration> {
+        if self.overrun().is_some() {
+            return None;
+        }
+        let expected = self.expected_size?.get() as u64;
+        let rate = match self.rate.bytes_transferred {
+            0 => self.cumulative.average_rate_at(self.at).bytes_transferred,
+            rate => rate,
+        };
+        if rate == 0 {
+            return None;
+        }
+        let remaining = expected
+            .saturating_sub(self.cumulative.progress.bytes_transferred);
+        Some(Duration::from_secs(remaining.div_ceil(rate)))
+    }
+
+    /// Text of `field`, if it has anything to show.
+    pub fn field(&self, field: Field) -> Option<String> {
+        let Self { format, unit, .. } = *self;
+        let text = match field {
+            Field::Transferred => self.transferred(),
+            Field::Elapsed => format_duration(&self.cumulative.elapsed_at(self.at)),
+            Field::Forwarded => {
+                let forwarded = unit.progress(&self.forwarded?);
+                format!("fwd {}", format.amount(forwarded, unit, Some(2)))
+            },
+            Field::Percentage => match self.ratio()? {
+                _ if self.overrun().is_some() => ">100%".to_owned(),
+                ratio => format!("{}%", (ratio * 100f64) as u16),
+            },
+            Field::Rate => format!("[{}]", format.rate(unit.progress(&self.rate), unit)),
+            Field::Limit => {
+                let (limit, limit_unit) = self.limit?;
+                format!("limit {}", format.rate(limit.get(), limit_unit))
+            },
+            Field::Eta => match self.overrun() {
+                Some(excess) => {
+                    format!("overrun +{}", format.amount(excess, Unit::Byte, Some(2)))
+                },
+                None => format!("ETA {}", format_duration(&self.eta()?)),
+            },
+        };
+        Some(text)
+    }
+
+    /// Every field with something to show and its text, in order.
+    pub fn fields(&self) -> Vec<(Field, String)> {
+        self.fields.iter()
+            .filter_map(|&field| Some((field, self.field(field)?)))
+            .collect()
+    }
+
+    fn transferred(&self) -> String {
+        let Self { format, unit, .. } = *self;
+        let progress = self.cumulative.progress;
+        let amount = |unit: Unit| format.amount(unit.progress(&progress), unit, Some(2));
+        if self.show_all_units {
+            return Unit::ALL
+                .into_iter()
+                .filter(|u| *u == Unit::Byte || *u == unit || u.progress(&progress) > 0)
+                .map(amount)
+                .collect::<Vec<_>>()
+                .join(" ");
+        }
+        match unit {
+            Unit::Byte => amount(Unit::Byte),
+            _ => format!("{} ({})", amount(unit), amount(Unit::Byte)),
+        }
+    }
+}
+
+impl fmt::Display for StatusLine {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (_, text)) in self.fields().into_iter().enumerate() {
+            if i > 0 {
+                fmt.write_str(" ")?;
+            }
+            fmt.write_str(&text)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for RateScale {
+    type Err = ParseRateScaleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "limit" => Ok(Self::Limit),
+            rate => size::parse_nonzero(rate)
+                .map(Self::Fixed)
+                .map_err(|_| ParseRateScaleError(s.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for RateScale {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Fixed(max) => write!(fmt, "{}", max),
+            Self::Limit => fmt.write_str("limit"),
+        }
+    }
+}
+
+/// `duration` as `H:MM:SS`, with as many hours as it takes.
+pub fn format_duration(duration: &Duration) -> String {
+    let secs = duration.as_secs();
+    let hours = secs / 3600;
+    let minutes = (secs / 60) % 60;
+    let seconds = secs % 60;
+    format!("{}:{:02}:{:02}", hours, minutes, seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ended(progress: TransferProgress, elapsed: Duration) -> (CumulativeTransferProgress, Instant) {
+        let start = Instant::now();
+        let mut cumulative = CumulativeTransferProgress::started(start);
+        cumulative.progress = progress;
+        cumulative.end(start + elapsed);
+        (cumulative, start + elapsed)
+    }
+
+    fn bytes(bytes_transferred: u64) -> TransferProgress {
+        TransferProgress {
+            bytes_transferred,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn default_line_reads_like_pv() {
+        let (cumulative, at) = ended(bytes(3 << 20), Duration::from_secs(192));
+        let line = StatusLine::new(cumulative, at)
+            .with_rate(bytes(1 << 20))
+            .with_expected_size(NonZeroUsize::new(8 << 20));
+        assert_eq!(line.to_string(), "3.00MiB 0:03:12 37% [1.0MiB/s] ETA 0:00:05");
+        let line = line.with_fields([Field::Transferred, Field::Rate, Field::Eta]);
+        assert_eq!(line.to_string(), "3.00MiB [1.0MiB/s] ETA 0:00:05");
+    }
+
+    #[test]
+    fn fields_without_anything_to_show_are_left_out() {
+        let (cumulative, at) = ended(TransferProgress::default(), Duration::ZERO);
+        let line = StatusLine::new(cumulative, at).with_format(NumberFormat::Raw);
+        assert_eq!(line.to_string(), "0B 0:00:00 [0B/s]");
+        assert_eq!(line.field(Field::Eta), None);
+        assert_eq!(line.with_fields([]).to_string(), "");
+    }
+
+    #[test]
+    fn overruns_replace_the_eta_and_cap_the_percentage() {
+        let (cumulative, at) = ended(bytes(1250), Duration::from_secs(1));
+        let line = StatusLine::new(cumulative, at)
+            .with_format(NumberFormat::Raw)
+            .with_expected_size(NonZeroUsize::new(1000))
+            .with_fields([Field::Percentage, Field::Eta]);
+        assert_eq!(line.to_string(), ">100% overrun +250B");
+    }
+
+    #[test]
+    fn all_units_lists_every_nonzero_counter_and_the_selected_unit() {
+        let progress = TransferProgress {
+            bytes_transferred: 2048,
+            lines_transferred: 3,
+            ..Default::default()
+        };
+        let (cumulative, at) = ended(progress, Duration::ZERO);
+        let text = |unit, show_all_units| StatusLine::new(cumulative, at)
+            .with_unit(unit)
+            .with_format(NumberFormat::Raw)
+            .with_all_units(show_all_units)
+            .with_fields([Field::Transferred, Field::Elapsed])
+            .to_string();
+        assert_eq!(text(Unit::Line, false), "3L (2048B) 0:00:00");
+        assert_eq!(text(Unit::Line, true), "2048B 3L 0:00:00");
+        assert_eq!(text(Unit::Word, true), "2048B 3L 0W 0:00:00");
+    }
+
+    #[test]
+    fn limits_are_shown_in_the_unit_they_are_charged_in() {
+        let (cumulative, at) = ended(TransferProgress::default(), Duration::ZERO);
+        let line = StatusLine::new(cumulative, at)
+            .with_unit(Unit::Line)
+            .with_format(NumberFormat::Raw)
+            .with_limit(NonZeroU64::new(500), Unit::Byte)
+            .with_forwarded(Some(TransferProgress::default()))
+            .with_fields([Field::Forwarded, Field::Limit]);
+        assert_eq!(line.to_string(), "fwd 0L limit 500B/s");
+    }
+    #[test]
+    fn rate_scales_are_a_rate_or_the_limit() {
+        assert_eq!("limit".parse(), Ok(RateScale::Limit));
+        assert_eq!("10k".parse(), Ok(RateScale::Fixed(NonZeroU64::new(10_000).unwrap())));
+        assert_eq!(RateScale::Fixed(NonZeroU64::new(500).unwrap()).to_string(), "500");
+        assert!("0".parse::<RateScale>().is_err());
+        assert!("fast".parse::<RateScale>().is_err());
+    }
+
+}
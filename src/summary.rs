@@ -0,0 +1,111 @@
+use serde::Serialize;
+
+use super::{
+    backpressure::{
+        Backpressure,
+        BackpressureBreakdown,
+        WaitBreakdown,
+    },
+    format::NumberFormat,
+    progress::{
+        CumulativeTransferProgress,
+        TransferProgress,
+    },
+    sink::EndCause,
+    unit::Unit,
+};
+
+/// Machine-readable report of a finished transfer.
+#[derive(Debug, Clone, Serialize)]
+pub struct Summary {
+    #[serde(flatten)]
+    pub progress: TransferProgress,
+    /// Whether any counter overflowed and stopped at its maximum.
+    pub saturated: bool,
+    pub elapsed_seconds: f64,
+    pub active_seconds: f64,
+    pub backpressure: BackpressureBreakdown,
+    /// How often and for how long the limiter, pauses and the output held
+    /// writes back.
+    pub waits: WaitBreakdown,
+    /// What was passed downstream, when only a sample of it was.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub forwarded: Option<TransferProgress>,
+    /// Why the transfer ended.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end: Option<EndCause>,
+    /// Bytes of records other than their delimiters and headers, with
+    /// `--net-bytes`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display: Option<SummaryDisplay>,
+}
+
+/// Human-readable totals, written in the requested [`NumberFormat`].
+#[derive(Debug, Clone, Serialize)]
+pub struct SummaryDisplay {
+    pub transferred: String,
+    pub average_rate: String,
+}
+
+impl Summary {
+    pub fn new(
+        progress: CumulativeTransferProgress,
+        backpressure: Backpressure,
+    ) -> Self {
+        Self {
+            progress: progress.progress,
+            saturated: progress.progress.saturated(),
+            elapsed_seconds: progress.elapsed().as_secs_f64(),
+            active_seconds: progress.active_duration().as_secs_f64(),
+            backpressure: backpressure.breakdown(),
+            waits: backpressure.waits(),
+            forwarded: None,
+            end: None,
+            payload_bytes: None,
+            display: None,
+        }
+    }
+    /// Include what was forwarded downstream, if sampling or filtering.
+    pub fn with_forwarded(self, forwarded: Option<TransferProgress>) -> Self {
+        Self {
+            forwarded,
+            ..self
+        }
+    }
+    /// Include the payload bytes counted, if they were.
+    pub fn with_payload(self, payload_bytes: Option<u64>) -> Self {
+        Self {
+            payload_bytes,
+            ..self
+        }
+    }
+    /// Include why the transfer ended.
+    pub fn with_end(self, end: EndCause) -> Self {
+        Self {
+            end: Some(end),
+            ..self
+        }
+    }
+    /// Include the amount transferred and the average rate in `unit`.
+    pub fn with_display(self, format: NumberFormat, unit: Unit) -> Self {
+        let amount = unit.progress(&self.progress);
+        let average = if self.active_seconds > 0f64 {
+            (amount as f64 / self.active_seconds).round() as u64
+        } else {
+            0
+        };
+        Self {
+            display: Some(SummaryDisplay {
+                transferred: format.amount(amount, unit, Some(2)),
+                average_rate: format.rate(average, unit),
+            }),
+            ..self
+        }
+    }
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self)
+            .expect("summary is always serializable")
+    }
+}
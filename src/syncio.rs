@@ -1,18 +1,33 @@
 use std::{
+    fs::File,
     io::{
+        self,
         Error,
         ErrorKind,
         Result,
         Write,
     },
-    num::NonZeroU32,
+    num::{
+        NonZeroU32,
+        NonZeroU64,
+    },
+    sync::{
+        Arc,
+        atomic::{
+            AtomicBool,
+            Ordering,
+        },
+    },
     thread::sleep,
-    time::Duration,
+    time::{
+        Duration,
+        Instant,
+    },
 };
 
 use governor::{
     clock::{
-        Clock as _,
+        Clock,
         DefaultClock,
     },
     state::{
@@ -20,42 +35,144 @@ use governor::{
         NotKeyed,
     },
     NegativeMultiDecision,
+    NotUntil,
     Quota,
     RateLimiter as GovernorRateLimiter,
 };
 
-use watch::{
+use super::state::{
     channel,
-    WatchSender,
+    StateCell,
 };
 
 use crate::{
     progress::{
+        SharedCounters,
         TransferProgress,
         TransferProgressMonitor,
     },
-    unit::Unit,
+    unit::{
+        Scanner,
+        Unit,
+    },
     config::{
+        Config,
         ConfigMonitor,
         LatchMonitor,
     },
     instantaneous::InstantaneousProgressWriter,
+    sink::{
+        ProgressSink,
+        SinkWriter,
+    },
+    backpressure::{
+        timed,
+        Backpressure,
+        BackpressureMonitor,
+    },
+    buffering::{
+        Buffering,
+        BufferedWriter,
+    },
+    audit::AuditedWriter,
+    checkpoint::{
+        Checkpoint,
+        CheckpointWriter,
+        StateFile,
+    },
+    fanout::{
+        FanOut,
+        Outputs,
+    },
+    sample::{
+        SampleRate,
+        SamplingWriter,
+    },
+    delay::{
+        DelayWriter,
+        Latency,
+    },
+    annotate::AnnotatingWriter,
+    replay::{
+        ReplayWriter,
+        Timing,
+    },
+    atomic::RecordAtomicWriter,
+    payload::{
+        PayloadScanner,
+        PayloadWriter,
+    },
+    watchdog::WatchedWriter,
 };
 
-const NUL: u8 = 0x0;
-const LF: u8 = 0xA;
-
 pub trait WriteExt<W> {
     /// Wrap any writer into one which can report progress.
     fn progress(self) -> ProgressWriter<W>;
     /// Wrap any writer into one which reports instantaneous transfer rates.
     fn instantaneous(self, window: Duration) -> InstantaneousProgressWriter<W>;
+    /// Wrap any writer into one which reports to a progress sink.
+    fn observe<S: ProgressSink>(self, sink: S) -> SinkWriter<W, S>;
     /// Wrap any writer into one which can be paused and resumed.
     fn pauseable(self, paused: LatchMonitor) -> PauseableWriter<W>;
     /// Wrap any writer into one which can be cancelled.
     fn cancellable(self, cancelled: LatchMonitor) -> CancellableWriter<W>;
     /// Wrap any writer into one with a throughput limit.
     fn limited(self, config: ConfigMonitor) -> RateLimitedWriter<W, DynamicRateLimiter>;
+    /// Wrap any writer into one with the given output buffering.
+    fn buffered(self, buffering: Buffering) -> BufferedWriter<W> where W: Write;
+    /// Wrap any writer into one which logs the pacing of its writes to `log`.
+    fn audited<L: Write>(self, log: Option<L>) -> AuditedWriter<W, L>;
+    /// Wrap any writer into one which saves how far it got to `state`.
+    fn checkpointed(self, state: Option<StateFile>, resumed: Checkpoint) -> CheckpointWriter<W>;
+    /// Wrap any writer into one which also copies everything to `outputs`.
+    fn fan_out(self, outputs: Outputs) -> FanOut<W>;
+    /// Wrap any writer into one which forwards only `rate` of the records.
+    fn sampled(self, unit: Unit, rate: Option<SampleRate>) -> SamplingWriter<W>;
+    /// Wrap any writer into one which can start each record with its number
+    /// or the time.
+    fn annotated(self, unit: Unit) -> AnnotatingWriter<W>;
+    /// Wrap any writer into one which pauses between records.
+    fn delayed(self, unit: Unit, latency: Option<Latency>) -> DelayWriter<W>;
+    /// Wrap any writer into one which reproduces the timing of a recording.
+    fn replayed(self, timing: Option<Timing>) -> ReplayWriter<W>;
+    /// Wrap any writer into one which only passes on whole records.
+    fn record_atomic(self, config: ConfigMonitor) -> RecordAtomicWriter<W>;
+    /// Wrap any writer into one which counts the payload bytes of records
+    /// through `scanner`, if given.
+    fn net_bytes(self, scanner: Option<PayloadScanner>) -> PayloadWriter<W>;
+    /// Wrap any writer into one whose blocked writes a watchdog can see.
+    fn watched(self) -> WatchedWriter<W>;
+}
+
+/// Writer which may hold back some of what it is given until it knows no
+/// more will follow.
+///
+/// Finishing a writer writes out what it holds back and then finishes the
+/// writer it wraps, so finishing the outermost writer of a chain drains
+/// every layer of it down to the output, where flushing would leave behind
+/// whatever a layer holds back deliberately.
+pub trait Finish: Write {
+    /// Write out everything held back, once no more will be written.
+    fn finish(&mut self) -> Result<()> {
+        self.flush()
+    }
+}
+
+impl Finish for File {}
+impl Finish for Vec<u8> {}
+impl Finish for io::Sink {}
+impl Finish for io::Stdout {}
+
+impl <W: Finish + ?Sized> Finish for &mut W {
+    fn finish(&mut self) -> Result<()> {
+        (**self).finish()
+    }
+}
+
+impl <W: Finish + ?Sized> Finish for Box<W> {
+    fn finish(&mut self) -> Result<()> {
+        (**self).finish()
+    }
 }
 
 impl <W: Write> WriteExt<W> for W {
@@ -65,28 +182,75 @@ impl <W: Write> WriteExt<W> for W {
     fn instantaneous(self, window: Duration) -> InstantaneousProgressWriter<W> {
         InstantaneousProgressWriter::new(self, window)
     }
+    fn observe<S: ProgressSink>(self, sink: S) -> SinkWriter<W, S> {
+        SinkWriter::new(self, sink)
+    }
     fn pauseable(self, paused: LatchMonitor) -> PauseableWriter<W> {
         PauseableWriter {
             inner: self,
             paused,
         }
     }
-    fn cancellable(self, cancelled: LatchMonitor) -> CancellableWriter<W> {
+    fn cancellable(self, mut cancelled: LatchMonitor) -> CancellableWriter<W> {
+        let flag = Arc::new(AtomicBool::new(false));
+        let notified = Arc::clone(&flag);
+        cancelled.notify(move |active| if active {
+            notified.store(true, Ordering::Relaxed);
+        });
+        // Set before watching counts as well.
+        if cancelled.active() {
+            flag.store(true, Ordering::Relaxed);
+        }
         CancellableWriter {
             inner: self,
-            cancelled,
+            cancelled: flag,
         }
     }
     fn limited(self, config: ConfigMonitor) -> RateLimitedWriter<W, DynamicRateLimiter> {
         RateLimitedWriter::writer_with_config(self, config)
     }
+    fn buffered(self, buffering: Buffering) -> BufferedWriter<W> {
+        BufferedWriter::new(self, buffering)
+    }
+    fn audited<L: Write>(self, log: Option<L>) -> AuditedWriter<W, L> {
+        AuditedWriter::new(self, log)
+    }
+    fn checkpointed(self, state: Option<StateFile>, resumed: Checkpoint) -> CheckpointWriter<W> {
+        CheckpointWriter::new(self, state, resumed)
+    }
+    fn fan_out(self, outputs: Outputs) -> FanOut<W> {
+        FanOut::new(self, outputs)
+    }
+    fn sampled(self, unit: Unit, rate: Option<SampleRate>) -> SamplingWriter<W> {
+        SamplingWriter::new(self, unit, rate)
+    }
+    fn annotated(self, unit: Unit) -> AnnotatingWriter<W> {
+        AnnotatingWriter::new(self, unit)
+    }
+    fn delayed(self, unit: Unit, latency: Option<Latency>) -> DelayWriter<W> {
+        DelayWriter::new(self, unit, latency)
+    }
+    fn replayed(self, timing: Option<Timing>) -> ReplayWriter<W> {
+        ReplayWriter::new(self, timing)
+    }
+    fn record_atomic(self, config: ConfigMonitor) -> RecordAtomicWriter<W> {
+        RecordAtomicWriter::new(self, config)
+    }
+    fn net_bytes(self, scanner: Option<PayloadScanner>) -> PayloadWriter<W> {
+        PayloadWriter::new(self, scanner)
+    }
+    fn watched(self) -> WatchedWriter<W> {
+        WatchedWriter::new(self)
+    }
 }
 
 #[derive(Clone)]
 pub struct ProgressWriter<W> {
     inner: W,
     transfer_progress: TransferProgress,
-    tx: WatchSender<TransferProgress>,
+    tx: StateCell<TransferProgress>,
+    counters: SharedCounters,
+    scanner: Scanner,
 }
 
 impl <W> ProgressWriter<W> {
@@ -97,20 +261,29 @@ impl <W> ProgressWriter<W> {
             inner,
             transfer_progress,
             tx,
+            counters: SharedCounters::default(),
+            scanner: Scanner::default(),
         }
     }
     pub fn transfer_progress(&mut self) -> TransferProgressMonitor {
         TransferProgressMonitor::new(self.tx.subscribe())
     }
+    /// Counters which can be read without a monitor; subscribe with
+    /// [`ProgressWriter::transfer_progress`] instead to be told of changes.
+    pub fn shared_counters(&self) -> SharedCounters {
+        self.counters.clone()
+    }
 }
 
 impl <W: Write> Write for ProgressWriter<W> {
     fn write(&mut self, buf: &[u8]) -> Result<usize> {
         let bytes_transferred = self.inner.write(buf)?;
         let slice = &buf[..bytes_transferred];
-        self.transfer_progress.add_bytes(bytes_transferred);
-        self.transfer_progress.add_lines(annotate_lines(slice).len());
-        self.transfer_progress.add_nulls(annotate_nulls(slice).len());
+        self.transfer_progress = self.transfer_progress + TransferProgress::scan(
+            &mut self.scanner,
+            slice,
+        );
+        self.counters.store(self.transfer_progress);
         self.tx.send(self.transfer_progress);
         Ok(bytes_transferred)
     }
@@ -119,96 +292,336 @@ impl <W: Write> Write for ProgressWriter<W> {
     }
 }
 
-type DirectRateLimiter<C> = GovernorRateLimiter<NotKeyed, InMemoryState, C>;
+impl <W: Finish> Finish for ProgressWriter<W> {
+    fn finish(&mut self) -> Result<()> {
+        self.inner.finish()
+    }
+}
+
+pub(crate) type DirectRateLimiter<C> = GovernorRateLimiter<NotKeyed, InMemoryState, C>;
 
 pub struct RateLimitedWriter<W, R> {
     inner: W,
     config: ConfigMonitor,
     rate_limiter: R,
+    scanner: Scanner,
+    /// Bytes written since the last point a token was charged for.
+    uncharged: usize,
+    /// Tokens granted for points which have not been written yet, because
+    /// the wrapped writer accepted less than it was offered.
+    credit: u32,
+    backpressure: Backpressure,
+    backpressure_tx: StateCell<Backpressure>,
+    /// Turn writes away rather than wait for the limiter.
+    nonblocking: bool,
+    /// When the limiter will next have a token, since it last turned a
+    /// write away.
+    ready_at: Option<Instant>,
+}
+
+/// Tokens handed out by a [`RateLimiter`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Grant {
+    pub tokens: u32,
+    /// None were available at the time of the request, so the limiter
+    /// blocked until some were.
+    pub blocked: bool,
+}
+
+impl Grant {
+    pub fn immediate(tokens: u32) -> Self {
+        Self {
+            tokens,
+            blocked: false,
+        }
+    }
 }
 
 pub trait RateLimiter {
     /// Request the desired amount of tokens.
     ///
-    /// Returns the amount of tokens granted, which is always less than or equal
-    /// to what was requested.
+    /// Grants an amount of tokens which is always less than or equal to what
+    /// was requested.
     ///
     /// If none are available at the time of request, it blocks until there is
     /// at least one token available and acquires whatever portion of the
     /// requested amount that it can.
-    fn request(&mut self, tokens: u32) -> u32;
+    fn request(&mut self, tokens: u32) -> Grant;
+    /// Like [`RateLimiter::request`], but without blocking: if no tokens are
+    /// available, how long until there will be.
+    fn try_request(&mut self, tokens: u32) -> std::result::Result<Grant, Duration> {
+        Ok(self.request(tokens))
+    }
+    /// Replace the rate at which tokens become available.
+    fn set_limit(&mut self, limit: Option<NonZeroU64>);
 }
 
 pub struct DynamicRateLimiter {
-    limiter: Option<DirectRateLimiter<DefaultClock>>,
+    limiter: Option<Scaled<DirectRateLimiter<DefaultClock>>>,
 }
 
 impl DynamicRateLimiter {
-    pub fn new(limit: Option<NonZeroU32>) -> Self {
+    pub fn new(limit: Option<NonZeroU64>) -> Self {
         Self {
-            limiter: Self::limiter(limit)
+            limiter: direct_limiter(limit)
         }
     }
-    fn swapout(&mut self, limit: Option<NonZeroU32>) {
-        self.limiter = Self::limiter(limit);
-    }
-    fn limiter(
-        limit: Option<NonZeroU32>
-    ) -> Option<DirectRateLimiter<DefaultClock>> {
-        limit.map(Quota::per_second)
-            .map(DirectRateLimiter::direct)
+    fn swapout(&mut self, limit: Option<NonZeroU64>) {
+        self.limiter = direct_limiter(limit);
     }
 }
 
+/// Most cells a governor quota can replenish per second before its
+/// replenishment interval drops below one nanosecond.
+const MAX_CELLS_PER_SECOND: u64 = 1_000_000_000;
+
+/// How long the cells of a full bucket last at its rate.
+///
+/// A bucket holding a whole second's worth lets the stream out in bursts of
+/// a second's worth at a time, which at a few bytes per second is all of it
+/// at once followed by a long wait.
+const BURST_PERIOD: Duration = Duration::from_millis(100);
+
+/// Quota allowing `limit` tokens per second, along with how many tokens each
+/// of its cells stands for.
+///
+/// A cell is replenished every `1 / limit` seconds and the bucket holds at
+/// most [`BURST_PERIOD`]'s worth of them, but never less than one, so that
+/// the stream goes out evenly rather than a second's worth at a time.
+/// Limits above [`MAX_CELLS_PER_SECOND`] are split so that every cell covers
+/// several tokens instead.
+pub(crate) fn scaled_quota(limit: NonZeroU64) -> (Quota, NonZeroU64) {
+    let scale = limit.get().div_ceil(MAX_CELLS_PER_SECOND);
+    let cells = (limit.get() / scale) as u32;
+    let period = Duration::from_secs(1) / cells;
+    let burst = (u128::from(cells) * BURST_PERIOD.as_nanos())
+        .div_ceil(Duration::from_secs(1).as_nanos());
+    let burst = NonZeroU32::new(burst as u32)
+        .expect("a quota always has at least one cell");
+    let scale = NonZeroU64::new(scale)
+        .expect("a quota always has a scale of at least one");
+    let quota = Quota::with_period(period)
+        .expect("a cell is never replenished in under a nanosecond")
+        .allow_burst(burst);
+    (quota, scale)
+}
+
+pub(crate) fn direct_limiter(
+    limit: Option<NonZeroU64>
+) -> Option<Scaled<DirectRateLimiter<DefaultClock>>> {
+    limit.map(|limit| {
+        let (quota, scale) = scaled_quota(limit);
+        Scaled::new(DirectRateLimiter::direct(quota), scale)
+    })
+}
+
 impl RateLimiter for DynamicRateLimiter {
-    fn request(&mut self, tokens: u32) -> u32 {
+    fn request(&mut self, tokens: u32) -> Grant {
         if tokens < 1 {
-            return 0;
+            return Grant::default();
         }
         if let Some(limiter) = &mut self.limiter {
-            wait_for_at_most(limiter, tokens)
+            limiter.wait_for_at_most(tokens)
         } else {
-            tokens
+            Grant::immediate(tokens)
         }
     }
+    fn try_request(&mut self, tokens: u32) -> std::result::Result<Grant, Duration> {
+        if tokens < 1 {
+            return Ok(Grant::default());
+        }
+        match &mut self.limiter {
+            Some(limiter) => limiter.try_for_at_most(tokens).map(Grant::immediate),
+            None => Ok(Grant::immediate(tokens)),
+        }
+    }
+    fn set_limit(&mut self, limit: Option<NonZeroU64>) {
+        self.swapout(limit);
+    }
 }
 
 impl <W> RateLimitedWriter<W, DynamicRateLimiter> {
-
-    pub fn writer_with_config(writer: W, mut config: ConfigMonitor) -> Self {
+    pub fn writer_with_config(writer: W, config: ConfigMonitor) -> Self {
         let rate_limiter = DynamicRateLimiter::new(config.limit());
+        Self::writer_with_limiter(writer, config, rate_limiter)
+    }
+    /// Limit `writer` to `rate` bytes per second for good.
+    pub fn with_rate(writer: W, rate: NonZeroU64) -> Self {
+        Self::with_rate_and_unit(writer, rate, Unit::Byte)
+    }
+    /// Limit `writer` to `rate` of `unit` per second for good, without a
+    /// channel to change it through.
+    pub fn with_rate_and_unit(writer: W, rate: NonZeroU64, unit: Unit) -> Self {
+        Self::writer_with_config(writer, ConfigMonitor::fixed(Config {
+            limit: Some(rate).into(),
+            unit,
+            ..Default::default()
+        }))
+    }
+    /// Pass every write straight through, only keeping track of how long
+    /// `writer` takes over them.
+    pub fn unlimited(writer: W) -> Self {
+        Self::writer_with_config(writer, ConfigMonitor::fixed(Config::default()))
+    }
+}
+
+impl <W, R: RateLimiter> RateLimitedWriter<W, R> {
+
+    pub fn writer_with_limiter(
+        writer: W,
+        config: ConfigMonitor,
+        rate_limiter: R,
+    ) -> Self {
+        let backpressure = Backpressure::default();
+        let (backpressure_tx, _) = channel(backpressure);
         Self {
             inner: writer,
             rate_limiter,
             config,
+            scanner: Scanner::default(),
+            uncharged: 0,
+            credit: 0,
+            backpressure,
+            backpressure_tx,
+            nonblocking: false,
+            ready_at: None,
         }
     }
 
-    fn get_largest_slice<'a>(&mut self, buf: &'a [u8]) -> &'a [u8] {
+    /// Fail writes with [`ErrorKind::WouldBlock`] instead of waiting for the
+    /// limiter, see [`Self::ready_at`] for how long to wait instead.
+    pub fn set_nonblocking(&mut self, nonblocking: bool) {
+        self.nonblocking = nonblocking;
+    }
+
+    /// When the limiter will have a token for the write it last turned away,
+    /// if it still holds writes back.
+    pub fn ready_at(&self) -> Option<Instant> {
+        self.ready_at
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Time spent waiting on the limiter and on the wrapped writer.
+    pub fn backpressure(&mut self) -> BackpressureMonitor {
+        BackpressureMonitor::new(self.backpressure_tx.subscribe())
+    }
+
+    /// Count a pause which held a write back for `waited`, alongside the
+    /// writer's own waits.
+    pub fn record_pause(&mut self, waited: Duration) {
+        self.backpressure.pausing += waited;
+        self.backpressure.pauses += 1;
+        self.backpressure_tx.send(self.backpressure);
+    }
+
+    /// Longest run of bytes which may pass without being charged a token,
+    /// so that records longer than this are paced in pieces rather than
+    /// passing for free until their delimiter shows up.
+    pub const MAX_UNCHARGED: usize = 64 * 1024;
+
+    /// The largest prefix of `buf` the limiter allows through, along with
+    /// the points in `buf` which cost a token each.
+    ///
+    /// Tokens left over from a short write are spent before asking the
+    /// limiter for more. Without any, a nonblocking writer gives up with
+    /// [`ErrorKind::WouldBlock`] rather than wait.
+    fn get_largest_slice<'a>(&mut self, buf: &'a [u8]) -> Result<(&'a [u8], Vec<usize>)> {
         let points = self.annotate(buf);
-        let buffer_cost = points.len().min(u32::MAX as usize) as u32;
-        let tokens_granted = self.rate_limiter.request(buffer_cost);
-        if buffer_cost <= tokens_granted {
-            buf
+        let mut buffer_cost = points.len().min(u32::MAX as usize) as u32;
+        if self.config.flush_every_record() && self.config.unit().delimiter().is_some() {
+            buffer_cost = buffer_cost.min(1);
+        }
+        let credited = self.credit.min(buffer_cost);
+        let wanted = buffer_cost - credited;
+        let (grant, elapsed) = timed(|| if self.nonblocking {
+            self.rate_limiter.try_request(wanted)
         } else {
-            let end = points[tokens_granted as usize];
-            &buf[..end]
+            Ok(self.rate_limiter.request(wanted))
+        });
+        self.backpressure.limiting += elapsed;
+        let grant = match grant {
+            Ok(grant) => grant,
+            Err(_) if credited > 0 => Grant::default(),
+            Err(wait) => {
+                // One wait for the limiter, however often it is asked.
+                if self.ready_at.replace(Instant::now() + wait).is_none() {
+                    self.backpressure.limiter_waits += 1;
+                    self.backpressure_tx.send(self.backpressure);
+                }
+                return Err(ErrorKind::WouldBlock.into());
+            },
+        };
+        self.ready_at = None;
+        if grant.blocked {
+            self.backpressure.limiter_waits += 1;
         }
+        let requested = grant.tokens;
+        self.credit += requested;
+        let tokens_granted = credited + requested;
+        let slice = match tokens_granted as usize {
+            granted if points.len() <= granted => buf,
+            // Always let at least one unit through, so as not to stall.
+            0 => &buf[..points[0]],
+            granted => &buf[..points[granted - 1]],
+        };
+        Ok((slice, points))
     }
 
+    /// Points just past every unit in `buf`, with extra points splitting
+    /// runs longer than [`Self::MAX_UNCHARGED`] between them.
+    ///
+    /// Ops cost one token per write however long it is, so they are never
+    /// split.
     fn annotate(&mut self, buf: &[u8]) -> Vec<usize> {
-        match self.config.unit() {
-            Unit::Byte => annotate_bytes(buf),
-            Unit::Line => annotate_lines(buf),
-            Unit::Null => annotate_nulls(buf),
+        let unit = self.config.unit();
+        let units = self.scanner.annotate(unit, buf);
+        if unit == Unit::Op {
+            return units;
+        }
+        let mut points = Vec::with_capacity(units.len());
+        let mut next = Self::MAX_UNCHARGED - self.uncharged.min(Self::MAX_UNCHARGED - 1);
+        for end in units.into_iter().chain(std::iter::once(buf.len() + 1)) {
+            while next < end {
+                points.push(next);
+                next += Self::MAX_UNCHARGED;
+            }
+            if end <= buf.len() {
+                points.push(end);
+                next = end + Self::MAX_UNCHARGED;
+            }
+        }
+        points
+    }
+
+    fn consume(&mut self, buf: &[u8], points: &[usize]) {
+        if self.config.unit() == Unit::Word {
+            self.scanner.advance(buf);
         }
+        let spent = points.iter()
+            .take_while(|point| **point <= buf.len())
+            .count();
+        self.credit = self.credit.saturating_sub(spent.min(u32::MAX as usize) as u32);
+        let charged = spent.checked_sub(1).map(|last| points[last]);
+        self.uncharged = match charged {
+            Some(point) => buf.len() - point,
+            None => self.uncharged + buf.len(),
+        };
     }
 
-    fn set_rate(&mut self, rate: NonZeroU32) {
-        self.rate_limiter.swapout(rate.into());
+    fn set_rate(&mut self, rate: NonZeroU64) {
+        self.credit = 0;
+        self.ready_at = None;
+        self.rate_limiter.set_limit(rate.into());
     }
 
-    fn poll_for_config_update(&mut self) -> Option<NonZeroU32> {
+    fn poll_for_config_update(&mut self) -> Option<NonZeroU64> {
         if let Some(new_rate) = self.config.limit_if_new() {
             self.set_rate(new_rate);
             Some(new_rate)
@@ -218,16 +631,32 @@ impl <W> RateLimitedWriter<W, DynamicRateLimiter> {
     }
 }
 
-impl <W: Write> Write for RateLimitedWriter<W, DynamicRateLimiter> {
+impl <W: Write, R: RateLimiter> Write for RateLimitedWriter<W, R> {
     fn write(&mut self, buf: &[u8]) -> Result<usize> {
         let limit = self.poll_for_config_update();
+        let (slice, points) = if limit.is_none() {
+            self.ready_at = None;
+            (buf, vec![])
+        } else {
+            self.get_largest_slice(buf)?
+        };
+        let (result, elapsed) = timed(|| self.inner.write(slice));
+        self.backpressure.writing += elapsed;
+        if elapsed > Backpressure::BLOCKED_WRITE {
+            self.backpressure.write_waits += 1;
+        }
+        self.backpressure_tx.send(self.backpressure);
+        let bytes_transferred = result?;
         if limit.is_none() {
-            return self.inner.write(buf);
+            return Ok(bytes_transferred);
         }
-        let slice = self.get_largest_slice(buf);
-        let bytes_transferred = self.inner.write(slice)?;
-        if bytes_transferred < buf.len() {
-            self.flush()?;
+        self.consume(&slice[..bytes_transferred], &points);
+        if bytes_transferred < buf.len() || self.config.flush_every_record() {
+            match self.flush() {
+                // What was written stays written, the rest goes out later.
+                Err(e) if self.nonblocking && e.kind() == ErrorKind::WouldBlock => {},
+                result => result?,
+            }
         }
         Ok(bytes_transferred)
     }
@@ -237,50 +666,119 @@ impl <W: Write> Write for RateLimitedWriter<W, DynamicRateLimiter> {
     }
 }
 
-fn annotate_bytes(buf: &[u8]) -> Vec<usize> {
-    buf.iter()
-        .enumerate()
-        .map(|(i, _)| i)
-        .collect()
+impl <W: Finish, R: RateLimiter> Finish for RateLimitedWriter<W, R> {
+    /// Finish without asking the limiter, which only paces what is written
+    /// through it.
+    fn finish(&mut self) -> Result<()> {
+        self.inner.finish()
+    }
+}
+
+pub(crate) type DefaultInstant = <DefaultClock as Clock>::Instant;
+
+/// Any governor rate limiter which can be asked for one or more tokens.
+pub(crate) trait TokenBucket {
+    fn check_one(&self) -> std::result::Result<(), NotUntil<DefaultInstant>>;
+    fn check_many(
+        &self,
+        n: NonZeroU32,
+    ) -> std::result::Result<(), NegativeMultiDecision<NotUntil<DefaultInstant>>>;
 }
 
-fn annotate_lines(buf: &[u8]) -> Vec<usize> {
-    buf.iter()
-        .enumerate()
-        .filter(|(_, b)| LF == **b)
-        .map(|(i, _)| i)
-        .collect()
+impl TokenBucket for DirectRateLimiter<DefaultClock> {
+    fn check_one(&self) -> std::result::Result<(), NotUntil<DefaultInstant>> {
+        self.check()
+    }
+    fn check_many(
+        &self,
+        n: NonZeroU32,
+    ) -> std::result::Result<(), NegativeMultiDecision<NotUntil<DefaultInstant>>> {
+        self.check_n(n)
+    }
 }
 
-fn annotate_nulls(buf: &[u8]) -> Vec<usize> {
-    buf.iter()
-        .enumerate()
-        .filter(|(_, b)| NUL == **b)
-        .map(|(i, _)| i)
-        .collect()
+/// Token bucket whose every cell stands for `scale` tokens.
+pub(crate) struct Scaled<L> {
+    bucket: L,
+    scale: NonZeroU64,
+}
+
+impl <L: TokenBucket> Scaled<L> {
+    pub(crate) fn new(bucket: L, scale: NonZeroU64) -> Self {
+        Self { bucket, scale }
+    }
+    /// Like [`wait_for_at_most`], but counted in tokens rather than cells.
+    pub(crate) fn wait_for_at_most(&self, goal: u32) -> Grant {
+        let granted = wait_for_at_most(&self.bucket, self.cells(goal));
+        Grant {
+            tokens: self.tokens(granted.tokens, goal),
+            ..granted
+        }
+    }
+    /// Like [`try_for_at_most`], but counted in tokens rather than cells.
+    pub(crate) fn try_for_at_most(&self, goal: u32) -> std::result::Result<u32, Duration> {
+        try_for_at_most(&self.bucket, self.cells(goal))
+            .map(|granted| self.tokens(granted, goal))
+    }
+    fn cells(&self, tokens: u32) -> u32 {
+        u64::from(tokens).div_ceil(self.scale.get()) as u32
+    }
+    fn tokens(&self, cells: u32, goal: u32) -> u32 {
+        (u64::from(cells) * self.scale.get()).min(u64::from(goal)) as u32
+    }
+}
+
+/// Like [`wait_for_at_most`], but rather than wait for a token when there
+/// are none, how long until there will be.
+pub(crate) fn try_for_at_most(
+    limiter: &impl TokenBucket,
+    goal: u32,
+) -> std::result::Result<u32, Duration> {
+    let Some(goal_value) = NonZeroU32::new(goal) else {
+        return Ok(0);
+    };
+    if goal <= 2 {
+        let now = DefaultClock::default().now();
+        return limiter.check_one()
+            .map(|_| 1)
+            .map_err(|not_until| not_until.wait_time_from(now));
+    }
+    match limiter.check_many(goal_value) {
+        Ok(_) => Ok(goal),
+        Err(NegativeMultiDecision::InsufficientCapacity(part)) => {
+            try_for_at_most(limiter, part)
+        }
+        Err(NegativeMultiDecision::BatchNonConforming(_, _)) => {
+            try_for_at_most(limiter, goal / 2)
+        }
+    }
 }
 
 /// Should never take more than ~32 recursive steps to terminate.
-fn wait_for_at_most(limiter: &DirectRateLimiter<DefaultClock>, goal: u32) -> u32 {
+pub(crate) fn wait_for_at_most(limiter: &impl TokenBucket, goal: u32) -> Grant {
     if goal <= 2 {
         let clock = DefaultClock::default();
         let now = clock.now();
-        if let Err(not_until) = limiter.check() {
+        let blocked = limiter.check_one().is_err_and(|not_until| {
             let delay = not_until.wait_time_from(now);
             sleep(delay);
             wait_for_one(limiter);
-        }
-        return 1;
+            true
+        });
+        return Grant {
+            tokens: 1,
+            blocked,
+        };
     }
 
     let goal_value = NonZeroU32::new(goal);
 
     if goal_value.is_none() {
-        return 0;
+        return Grant::default();
     }
 
-    match limiter.check_n(goal_value.unwrap()) {
-        Ok(_) => goal,
+    match limiter.check_many(goal_value.unwrap()) {
+        Ok(_) => Grant::immediate(goal),
         Err(NegativeMultiDecision::InsufficientCapacity(part)) => {
             wait_for_at_most(limiter, part)
         }
@@ -290,10 +788,10 @@ fn wait_for_at_most(limiter: &DirectRateLimiter<DefaultClock>, goal: u32) -> u32
     }
 }
 
-fn wait_for_one(limiter: &DirectRateLimiter<DefaultClock>) {
+fn wait_for_one(limiter: &impl TokenBucket) {
     let clock = DefaultClock::default();
     let now = clock.now();
-    while let Err(not_until) = limiter.check() {
+    while let Err(not_until) = limiter.check_one() {
         let delay = not_until.wait_time_from(now);
         sleep(delay);
     }
@@ -304,17 +802,19 @@ pub struct PauseableWriter<W> {
     paused: LatchMonitor,
 }
 
-impl <W> PauseableWriter<W> {
-    fn paused(&mut self) -> bool {
-        self.paused.active()
-    }
+/// Block for as long as `paused` is active.
+pub(crate) fn wait_while_paused(paused: &mut LatchMonitor) {
+    paused.wait_for(false, Duration::MAX);
+}
+
+/// Error returned by writes attempted after cancellation.
+pub(crate) fn cancelled_error() -> Error {
+    Error::new(ErrorKind::BrokenPipe, "cancelled")
 }
 
 impl <W: Write> Write for PauseableWriter<W> {
     fn write(&mut self, buf: &[u8]) -> Result<usize> {
-        while self.paused() {
-            sleep(Duration::from_millis(500));
-        }
+        wait_while_paused(&mut self.paused);
         self.inner.write(buf)
     }
     fn flush(&mut self) -> Result<()> {
@@ -322,21 +822,28 @@ impl <W: Write> Write for PauseableWriter<W> {
     }
 }
 
+impl <W: Finish> Finish for PauseableWriter<W> {
+    fn finish(&mut self) -> Result<()> {
+        self.inner.finish()
+    }
+}
+
 pub struct CancellableWriter<W> {
     inner: W,
-    cancelled: LatchMonitor,
+    /// Set once the latch is, so that writes need not lock it.
+    cancelled: Arc<AtomicBool>,
 }
 
 impl <W> CancellableWriter<W> {
     fn cancelled(&mut self) -> bool {
-        self.cancelled.active()
+        self.cancelled.load(Ordering::Relaxed)
     }
 }
 
 impl <W: Write> Write for CancellableWriter<W> {
     fn write(&mut self, buf: &[u8]) -> Result<usize> {
         if self.cancelled() {
-            Err(Error::new(ErrorKind::BrokenPipe, "cancelled"))
+            Err(cancelled_error())
         } else {
             self.inner.write(buf)
         }
@@ -347,47 +854,400 @@ impl <W: Write> Write for CancellableWriter<W> {
     }
 }
 
+impl <W: Finish> Finish for CancellableWriter<W> {
+    fn finish(&mut self) -> Result<()> {
+        self.inner.finish()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use governor::clock::FakeRelativeClock;
+    use nonzero_ext::nonzero;
+    use proptest::prelude::*;
+    use crate::config::{Config, Latch};
+
+    #[derive(Default)]
+    struct RecordingWriter {
+        writes: Vec<Vec<u8>>,
+        flushes: usize,
+    }
+
+    impl Write for RecordingWriter {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            self.writes.push(buf.to_vec());
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> Result<()> {
+            self.flushes += 1;
+            Ok(())
+        }
+    }
+
+    fn limited(config: Config) -> RateLimitedWriter<RecordingWriter, DynamicRateLimiter> {
+        let (_, config) = ConfigMonitor::new(config);
+        RecordingWriter::default().limited(config)
+    }
 
     #[test]
-    fn annotate_bytes_returns_buffer_length() {
-        use std::iter::repeat;
-        let buf: Vec<u8> = repeat(NUL)
-            .take(10)
-            .chain(repeat(LF).take(10))
-            .collect();
-        assert_eq!(
-            annotate_bytes(&buf).len(),
-            buf.len(),
-        );
+    fn shared_counters_follow_every_write_without_a_monitor() {
+        let mut writer = Vec::new().progress();
+        let counters = writer.shared_counters();
+        let reader = counters.clone();
+        writer.write_all(b"one\ntwo\0").unwrap();
+        assert_eq!(reader.bytes(), 8);
+        assert_eq!(reader.lines(), 1);
+        assert_eq!(reader.nulls(), 1);
+        writer.write_all(b"three\n").unwrap();
+        assert_eq!(counters.get(), writer.transfer_progress().get());
+        assert_eq!(counters.writes(), 2);
     }
 
     #[test]
-    fn annotate_lines_returns_number_of_linefeeds() {
-        use std::iter::repeat;
-        let buf: Vec<u8> = repeat(LF)
-            .take(10)
-            .chain(vec![NUL].into_iter())
-            .collect();
-        assert_eq!(
-            annotate_lines(&buf).len(),
-            10,
+    fn fixed_rate_writers_limit_their_unit_without_a_config_channel() {
+        let lines = "line\n".repeat(120);
+        let mut writer = RateLimitedWriter::with_rate_and_unit(
+            RecordingWriter::default(),
+            nonzero!(100u64),
+            Unit::Line,
         );
+        let mut backpressure = writer.backpressure();
+        writer.write_all(lines.as_bytes()).unwrap();
+        assert!(backpressure.get().limiter_waits > 0);
+        let mut writer = RateLimitedWriter::unlimited(RecordingWriter::default());
+        let mut backpressure = writer.backpressure();
+        writer.write_all(lines.as_bytes()).unwrap();
+        assert_eq!(backpressure.get().limiter_waits, 0);
+        assert_eq!(writer.get_ref().writes.concat(), lines.as_bytes());
     }
 
     #[test]
-    fn annotate_nulls_returns_number_of_nulls() {
-        use std::iter::repeat;
-        let buf: Vec<u8> = repeat(NUL)
-            .take(10)
-            .chain(vec![LF].into_iter())
-            .collect();
-        assert_eq!(
-            annotate_nulls(&buf).len(),
-            10,
-        );
+    fn flush_every_record_writes_and_flushes_one_line_at_a_time() {
+        let mut writer = limited(Config {
+            limit: Some(nonzero!(1000u64)).into(),
+            unit: Unit::Line,
+            flush_every_record: true,
+            ..Default::default()
+        });
+        let mut buf: &[u8] = b"a\nbb\nccc\n";
+        while !buf.is_empty() {
+            let n = writer.write(buf).unwrap();
+            buf = &buf[n..];
+        }
+        let RecordingWriter { writes, flushes } = writer.inner;
+        assert_eq!(writes, vec![b"a\n".to_vec(), b"bb\n".to_vec(), b"ccc\n".to_vec()]);
+        assert_eq!(flushes, 3);
+    }
+
+    #[test]
+    fn flush_every_record_passes_whole_writes_in_byte_mode() {
+        let mut writer = limited(Config {
+            limit: Some(nonzero!(1_000_000u64)).into(),
+            unit: Unit::Byte,
+            flush_every_record: true,
+            ..Default::default()
+        });
+        let buf = b"a\nbb\nccc\n";
+        assert_eq!(writer.write(buf).unwrap(), buf.len());
+        let RecordingWriter { writes, flushes } = writer.inner;
+        assert_eq!(writes, vec![buf.to_vec()]);
+        assert_eq!(flushes, 1);
+    }
+
+    #[test]
+    fn writes_held_back_by_the_limiter_are_counted() {
+        let mut writer = limited(Config {
+            limit: Some(nonzero!(10u64)).into(),
+            unit: Unit::Line,
+            ..Default::default()
+        });
+        let mut backpressure = writer.backpressure();
+        // A tenth of a second's worth goes out straight away.
+        writer.write_all(b"x\n").unwrap();
+        assert_eq!(backpressure.get().limiter_waits, 0);
+        writer.write_all(b"x\n").unwrap();
+        assert_eq!(backpressure.get().limiter_waits, 1);
+    }
+
+    #[test]
+    fn nonblocking_writes_are_turned_away_until_the_limiter_has_tokens() {
+        let mut writer = limited(Config {
+            limit: Some(nonzero!(10u64)).into(),
+            unit: Unit::Line,
+            ..Default::default()
+        });
+        writer.set_nonblocking(true);
+        let mut backpressure = writer.backpressure();
+        writer.write_all(b"x\n").unwrap();
+        for _ in 0..2 {
+            let error = writer.write(b"x\n").unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::WouldBlock);
+        }
+        let ready_at = writer.ready_at().expect("the limiter should be waited for");
+        assert!(ready_at > Instant::now());
+        assert_eq!(backpressure.get().limiter_waits, 1);
+        sleep(ready_at - Instant::now());
+        assert_eq!(writer.write(b"x\n").unwrap(), 2);
+        assert_eq!(writer.ready_at(), None);
+    }
+
+    /// Grants at most `per_request` tokens, remembering what was asked for.
+    struct FixedGrants {
+        per_request: u32,
+        requested: Vec<u32>,
+    }
+
+    impl RateLimiter for &mut FixedGrants {
+        fn request(&mut self, tokens: u32) -> Grant {
+            self.requested.push(tokens);
+            Grant::immediate(tokens.min(self.per_request))
+        }
+        fn set_limit(&mut self, _: Option<NonZeroU64>) {}
+    }
+
+    fn limited_by(
+        limiter: &mut FixedGrants,
+        unit: Unit,
+    ) -> RateLimitedWriter<RecordingWriter, &mut FixedGrants> {
+        let (_, config) = ConfigMonitor::new(Config {
+            limit: Some(nonzero!(1u64)).into(),
+            unit,
+            ..Default::default()
+        });
+        RateLimitedWriter::writer_with_limiter(RecordingWriter::default(), config, limiter)
+    }
+
+    const MAX_UNCHARGED: usize =
+        RateLimitedWriter::<RecordingWriter, DynamicRateLimiter>::MAX_UNCHARGED;
+
+    #[test]
+    fn delimiter_free_streams_are_charged_per_max_uncharged_bytes() {
+        let mut limiter = FixedGrants { per_request: u32::MAX, requested: vec![] };
+        let mut writer = limited_by(&mut limiter, Unit::Line);
+        let chunk = vec![b'x'; 1000];
+        let total = 3 * MAX_UNCHARGED + 10;
+        let mut written = 0;
+        while written < total {
+            let n = (total - written).min(chunk.len());
+            writer.write_all(&chunk[..n]).unwrap();
+            written += n;
+        }
+        writer.write_all(b"\n").unwrap();
+        assert_eq!(limiter.requested.iter().sum::<u32>(), 4);
+    }
+
+    #[test]
+    fn long_records_are_split_where_the_grant_runs_out() {
+        let mut limiter = FixedGrants { per_request: 1, requested: vec![] };
+        let mut writer = limited_by(&mut limiter, Unit::Null);
+        let buf = vec![b'x'; 2 * MAX_UNCHARGED + 1];
+        assert_eq!(writer.write(&buf).unwrap(), MAX_UNCHARGED);
+        assert_eq!(writer.write(&buf[MAX_UNCHARGED..]).unwrap(), MAX_UNCHARGED + 1);
+        assert_eq!(writer.get_ref().writes.len(), 2);
+        assert_eq!(limiter.requested, vec![2, 1]);
+    }
+
+    #[test]
+    fn ops_cost_one_token_per_write_whatever_its_size() {
+        let mut limiter = FixedGrants { per_request: 1, requested: vec![] };
+        let mut writer = limited_by(&mut limiter, Unit::Op);
+        let buf = vec![b'x'; 3 * MAX_UNCHARGED];
+        assert_eq!(writer.write(&buf).unwrap(), buf.len());
+        assert_eq!(writer.write(b"a\nb\n").unwrap(), 4);
+        assert_eq!(limiter.requested, vec![1, 1]);
+    }
+
+    /// Accepts at most `max` bytes per write.
+    struct ShortWriter {
+        max: usize,
+        written: Vec<u8>,
+    }
+
+    impl Write for ShortWriter {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            let n = buf.len().min(self.max);
+            self.written.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Mostly short runs of every kind of delimiter, now and then a run
+    /// too long to pass uncharged.
+    fn arbitrary_stream() -> impl Strategy<Value = Vec<u8>> {
+        let run = prop_oneof![
+            59 => 0..8usize,
+            1 => MAX_UNCHARGED..2 * MAX_UNCHARGED,
+        ];
+        let record = (run, prop::sample::select(b"\n\0 \tx".to_vec()));
+        prop::collection::vec(record, 0..40).prop_map(|records| {
+            let mut stream = vec![];
+            for (len, delimiter) in records {
+                stream.extend(std::iter::repeat_n(b'x', len));
+                stream.push(delimiter);
+            }
+            stream
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn grants_only_ever_pass_a_paid_for_prefix_and_lose_nothing(
+            stream in arbitrary_stream(),
+            unit in prop::sample::select(Unit::ALL.to_vec()),
+            // Bytes cost a token each, so they are granted in bulk to keep
+            // long runs quick to get through.
+            bulk in 1024u32..5120,
+            few in 1u32..5,
+            max in 1..MAX_UNCHARGED,
+            cuts in prop::collection::vec(any::<prop::sample::Index>(), 1..16),
+        ) {
+            let per_request = match unit {
+                Unit::Byte => bulk,
+                _ => few,
+            };
+            let mut limiter = FixedGrants { per_request, requested: vec![] };
+            let short = ShortWriter { max, written: vec![] };
+            let (_, config) = ConfigMonitor::new(Config {
+                limit: Some(nonzero!(1u64)).into(),
+                unit,
+                ..Default::default()
+            });
+            let mut writer = RateLimitedWriter::writer_with_limiter(short, config, &mut limiter);
+            let mut rest = stream.as_slice();
+            let mut cuts = cuts.iter().cycle();
+            while !rest.is_empty() {
+                let cut = cuts.next().unwrap();
+                let buf = &rest[..1 + cut.index(rest.len().min(2 * MAX_UNCHARGED))];
+                let credit = writer.credit;
+                let (slice, points) = writer.get_largest_slice(buf).unwrap();
+                let granted = credit.min(points.len() as u32) + (writer.credit - credit);
+                let charged = points.iter().filter(|&&point| point <= slice.len()).count();
+                prop_assert!(!slice.is_empty(), "nothing passed");
+                prop_assert_eq!(slice.as_ptr(), buf.as_ptr(), "not a prefix");
+                prop_assert!(slice.len() <= buf.len(), "past the end");
+                prop_assert!(charged as u32 <= granted, "{} units for {} tokens", charged, granted);
+                prop_assert!(unit.count(slice) <= charged, "delimiters passed uncharged");
+                let n = writer.inner.write(slice).unwrap();
+                writer.consume(&slice[..n], &points);
+                rest = &rest[n..];
+            }
+            prop_assert_eq!(&writer.get_ref().written, &stream, "stream changed");
+        }
+    }
+
+    #[test]
+    fn tokens_granted_for_unwritten_records_are_spent_next_time() {
+        let mut limiter = FixedGrants { per_request: u32::MAX, requested: vec![] };
+        let (_, config) = ConfigMonitor::new(Config {
+            limit: Some(nonzero!(1u64)).into(),
+            unit: Unit::Line,
+            ..Default::default()
+        });
+        let short = ShortWriter { max: 3, written: vec![] };
+        let mut writer = RateLimitedWriter::writer_with_limiter(short, config, &mut limiter);
+        writer.write_all(b"a\nb\nc\nd\n").unwrap();
+        assert_eq!(writer.get_ref().written, b"a\nb\nc\nd\n");
+        assert_eq!(limiter.requested.iter().sum::<u32>(), 4);
+    }
+
+    #[test]
+    fn limits_beyond_governor_resolution_are_split_into_larger_cells() {
+        let (quota, scale) = scaled_quota(nonzero!(1000u64));
+        assert_eq!(quota, Quota::per_second(nonzero!(1000u32)).allow_burst(nonzero!(100u32)));
+        assert_eq!(scale.get(), 1);
+        let limit = 8 * MAX_CELLS_PER_SECOND + 1;
+        let (quota, scale) = scaled_quota(NonZeroU64::new(limit).unwrap());
+        assert_eq!(scale.get(), 9);
+        // The bucket holds a tenth of a second's worth, rounded up.
+        let effective = u64::from(quota.burst_size().get()) * 10 * scale.get();
+        assert!(effective >= limit && effective < limit + 10 * scale.get());
+    }
+
+    /// Cells a reader taking all it can every millisecond gets out of
+    /// `quota` in each tenth of a second, over `seconds` on a fake clock.
+    fn delivered(quota: Quota, seconds: u32) -> Vec<u32> {
+        let clock = FakeRelativeClock::default();
+        let limiter = GovernorRateLimiter::direct_with_clock(quota, &clock);
+        let mut windows = vec![0; seconds as usize * 10];
+        for tick in 0..seconds * 1000 {
+            while limiter.check().is_ok() {
+                windows[tick as usize / 100] += 1;
+            }
+            clock.advance(Duration::from_millis(1));
+        }
+        windows
+    }
+
+    fn variance(windows: &[u32]) -> f64 {
+        let n = windows.len() as f64;
+        let mean = windows.iter().map(|w| f64::from(*w)).sum::<f64>() / n;
+        windows.iter().map(|w| (f64::from(*w) - mean).powi(2)).sum::<f64>() / n
+    }
+
+    #[test]
+    fn low_limits_are_delivered_evenly() {
+        let (quota, _) = scaled_quota(nonzero!(3u64));
+        assert_eq!(quota.burst_size().get(), 1);
+        let windows = delivered(quota, 10);
+        assert!(windows.iter().all(|w| *w <= 1), "{:?}", windows);
+        for second in windows.chunks(10) {
+            assert_eq!(second.iter().sum::<u32>(), 3, "{:?}", windows);
+        }
+        // A second's worth at once, as a whole second's bucket allows.
+        let bursty = delivered(Quota::per_second(nonzero!(3u32)), 10);
+        assert_eq!(bursty[0], 3);
+        assert!(variance(&windows) < variance(&bursty));
+    }
+
+    #[test]
+    fn high_limits_vary_little_between_tenths_of_a_second() {
+        let (quota, _) = scaled_quota(nonzero!(1000u64));
+        let windows = delivered(quota, 5);
+        assert!(windows[1..].iter().all(|w| *w == 100), "{:?}", windows);
+        let bursty = delivered(Quota::per_second(nonzero!(1000u32)), 5);
+        assert!(variance(&windows) * 10f64 < variance(&bursty));
+    }
+
+    #[test]
+    fn scaled_limiter_grants_tokens_not_cells() {
+        let limiter = direct_limiter(NonZeroU64::new(50 * MAX_CELLS_PER_SECOND))
+            .unwrap();
+        assert_eq!(limiter.wait_for_at_most(1000).tokens, 1000);
+        assert_eq!(limiter.wait_for_at_most(u32::MAX).tokens, u32::MAX);
+    }
+
+    #[test]
+    fn paused_writes_resume_as_soon_as_the_latch_is_off() {
+        let mut paused = Latch::new();
+        let mut writer = Vec::new().pauseable(paused.watch());
+        paused.on();
+        let resume = std::thread::spawn(move || {
+            sleep(Duration::from_millis(20));
+            paused.off();
+        });
+        let started = Instant::now();
+        writer.write_all(b"a").unwrap();
+        resume.join().unwrap();
+        assert!(started.elapsed() < Duration::from_millis(400), "{:?}", started.elapsed());
+        assert_eq!(writer.inner, b"a");
+    }
+
+    #[test]
+    fn cancelled_writers_stay_cancelled() {
+        let mut cancelled = Latch::new();
+        let mut writer = Vec::new().cancellable(cancelled.watch());
+        writer.write_all(b"a").unwrap();
+        cancelled.on();
+        assert_eq!(writer.write(b"b").unwrap_err().kind(), ErrorKind::BrokenPipe);
+        cancelled.off();
+        assert!(writer.write(b"b").is_err());
+        let mut already = Latch::new();
+        already.on();
+        assert!(Vec::new().cancellable(already.watch()).write(b"a").is_err());
     }
 
 }
@@ -0,0 +1,146 @@
+use std::{
+    env,
+    io,
+    os::{
+        fd::{FromRawFd as _, RawFd},
+        linux::net::SocketAddrExt as _,
+        unix::net::{SocketAddr, UnixDatagram, UnixListener},
+    },
+    process,
+    time::Duration,
+};
+
+use super::{
+    config::LatchMonitor,
+    format::NumberFormat,
+    progress::CumulativeProgressMonitor,
+    unit::Unit,
+};
+
+/// First descriptor passed by socket activation, see sd_listen_fds(3).
+const LISTEN_FDS_START: RawFd = 3;
+
+/// The first socket systemd passed to this process, if it was started by
+/// socket activation.
+///
+/// The activation variables are removed from the environment so that they
+/// do not leak into hooks.
+pub fn activated_listener() -> Option<UnixListener> {
+    let pid = env::var("LISTEN_PID").ok()?;
+    let fds = env::var("LISTEN_FDS").ok()?;
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+    if pid.parse() != Ok(process::id()) {
+        return None;
+    }
+    let fds: RawFd = fds.parse().ok()?;
+    // SAFETY: systemd hands over descriptors from LISTEN_FDS_START onwards,
+    // and only this process was meant to receive them.
+    (fds >= 1).then(|| unsafe { UnixListener::from_raw_fd(LISTEN_FDS_START) })
+}
+
+/// Sends sd_notify(3) messages to the service manager, if there is one.
+pub struct Notifier {
+    socket: Option<(UnixDatagram, SocketAddr)>,
+}
+
+impl Notifier {
+    /// Talk to whatever `NOTIFY_SOCKET` points at; without it every message
+    /// is dropped.
+    pub fn from_env() -> io::Result<Self> {
+        let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+            return Ok(Self { socket: None });
+        };
+        let address = match path.as_encoded_bytes() {
+            [b'@', name @ ..] => SocketAddr::from_abstract_name(name)?,
+            _ => SocketAddr::from_pathname(&path)?,
+        };
+        Ok(Self {
+            socket: Some((UnixDatagram::unbound()?, address)),
+        })
+    }
+    pub fn notify(&self, state: &str) -> io::Result<()> {
+        if let Some((socket, address)) = &self.socket {
+            socket.send_to_addr(state.as_bytes(), address)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reports readiness, then how much has been transferred as the service
+/// status every refresh until shutdown.
+pub struct StatusNotifier {
+    notifier: Notifier,
+    cumulative: CumulativeProgressMonitor,
+    unit: Unit,
+    format: NumberFormat,
+    shutdown: LatchMonitor,
+    refresh: Duration,
+}
+
+impl StatusNotifier {
+    pub fn new(
+        notifier: Notifier,
+        cumulative: CumulativeProgressMonitor,
+        unit: Unit,
+        format: NumberFormat,
+        shutdown: LatchMonitor,
+    ) -> Self {
+        Self {
+            notifier,
+            cumulative,
+            unit,
+            format,
+            shutdown,
+            refresh: Duration::from_secs(1),
+        }
+    }
+    /// Report at most this often.
+    pub fn with_refresh(mut self, refresh: Duration) -> Self {
+        self.refresh = refresh;
+        self
+    }
+    pub fn run(mut self) -> io::Result<()> {
+        self.report("READY=1\n")?;
+        while !self.shutdown.wait_timeout(self.refresh) {
+            self.report("")?;
+        }
+        self.report("STOPPING=1\n")
+    }
+    /// Send the current status along with any other `assignments`.
+    fn report(&mut self, assignments: &str) -> io::Result<()> {
+        let progress = self.cumulative.get().progress;
+        let amount = self.unit.progress(&progress);
+        self.notifier.notify(&format!(
+            "{}STATUS={} transferred",
+            assignments,
+            self.format.amount(amount, self.unit, Some(2)),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn notifications_reach_the_notify_socket() {
+        let path = env::temp_dir().join(format!("pvalve-notify-{}", process::id()));
+        let _ = fs::remove_file(&path);
+        let receiver = UnixDatagram::bind(&path).unwrap();
+        let notifier = Notifier {
+            socket: Some((
+                UnixDatagram::unbound().unwrap(),
+                SocketAddr::from_pathname(&path).unwrap(),
+            )),
+        };
+        notifier.notify("READY=1").unwrap();
+        let mut buf = [0; 16];
+        let n = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"READY=1");
+        fs::remove_file(&path).unwrap();
+    }
+
+}
@@ -0,0 +1,200 @@
+use std::{
+    fmt,
+    str::FromStr,
+};
+
+use thiserror::Error;
+
+use super::{
+    readahead::Occupancy,
+    status_line::{
+        Field,
+        StatusLine,
+    },
+};
+
+/// Part of a [`ProgressTemplate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Piece {
+    Text(String),
+    Field(Field),
+    Name,
+    Buffer,
+}
+
+/// Layout of the status line given with `-F`, as in pv, such as
+/// `%N %b %t %r %p %e`.
+///
+/// `%b` is the amount transferred, `%t` the time elapsed, `%r` the rate,
+/// `%e` the time left, `%p` the percentage, `%T` how full the
+/// `--buffer-size` buffer is and `%N` the name given with `-N`; `%%` is a
+/// percent sign. Anything else is kept as it is, and a
+/// token with nothing to show, such as the ETA of a transfer of unknown
+/// size, comes out empty.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ProgressTemplate {
+    pieces: Vec<Piece>,
+    name: String,
+}
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum ParseProgressTemplateError {
+    #[error("unknown format token %{0}, expected one of %b %t %r %e %p %T %N %%")]
+    Unknown(char),
+    #[error("format ends in a lone %")]
+    Trailing,
+}
+
+impl ProgressTemplate {
+    /// What `%N` stands for.
+    pub fn with_name(mut self, name: Option<String>) -> Self {
+        self.name = name.unwrap_or_default();
+        self
+    }
+    /// The template filled in from `line` and the buffer, if there is one.
+    pub fn render(&self, line: &StatusLine, buffered: Option<Occupancy>) -> String {
+        let mut text = String::new();
+        for piece in &self.pieces {
+            match piece {
+                Piece::Text(literal) => text.push_str(literal),
+                Piece::Field(field) => text.extend(line.field(*field)),
+                Piece::Name => text.push_str(&self.name),
+                Piece::Buffer => if let Some(buffered) = buffered {
+                    text.push_str(&format!("{:.0}%", buffered.percentage()));
+                },
+            }
+        }
+        text
+    }
+}
+
+impl FromStr for ProgressTemplate {
+    type Err = ParseProgressTemplateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut pieces = vec![];
+        let mut literal = String::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                literal.push(c);
+                continue;
+            }
+            let piece = match chars.next().ok_or(ParseProgressTemplateError::Trailing)? {
+                '%' => {
+                    literal.push('%');
+                    continue;
+                },
+                'b' => Piece::Field(Field::Transferred),
+                't' => Piece::Field(Field::Elapsed),
+                'r' => Piece::Field(Field::Rate),
+                'e' => Piece::Field(Field::Eta),
+                'p' => Piece::Field(Field::Percentage),
+                'N' => Piece::Name,
+                'T' => Piece::Buffer,
+                token => return Err(ParseProgressTemplateError::Unknown(token)),
+            };
+            if !literal.is_empty() {
+                pieces.push(Piece::Text(std::mem::take(&mut literal)));
+            }
+            pieces.push(piece);
+        }
+        if !literal.is_empty() {
+            pieces.push(Piece::Text(literal));
+        }
+        Ok(Self {
+            pieces,
+            name: String::new(),
+        })
+    }
+}
+
+impl fmt::Display for ProgressTemplate {
+    /// The template as it would be given to `-F`.
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for piece in &self.pieces {
+            let token = match piece {
+                Piece::Text(literal) => {
+                    fmt.write_str(&literal.replace('%', "%%"))?;
+                    continue;
+                },
+                Piece::Field(Field::Transferred) => "%b",
+                Piece::Field(Field::Elapsed) => "%t",
+                Piece::Field(Field::Rate) => "%r",
+                Piece::Field(Field::Eta) => "%e",
+                Piece::Field(Field::Percentage) => "%p",
+                Piece::Field(_) => unreachable!("only the tokens parsed are held"),
+                Piece::Name => "%N",
+                Piece::Buffer => "%T",
+            };
+            fmt.write_str(token)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        num::NonZeroUsize,
+        time::{Duration, Instant},
+    };
+
+    use crate::{
+        format::NumberFormat,
+        progress::{
+            CumulativeTransferProgress,
+            TransferProgress,
+        },
+    };
+
+    fn line(bytes_transferred: u64, expected_size: Option<usize>) -> StatusLine {
+        let start = Instant::now();
+        let mut cumulative = CumulativeTransferProgress::started(start);
+        cumulative.progress.bytes_transferred = bytes_transferred;
+        cumulative.end(start + Duration::from_secs(65));
+        StatusLine::new(cumulative, start + Duration::from_secs(65))
+            .with_format(NumberFormat::Raw)
+            .with_rate(TransferProgress {
+                bytes_transferred: 10,
+                ..Default::default()
+            })
+            .with_expected_size(expected_size.and_then(NonZeroUsize::new))
+    }
+
+    #[test]
+    fn tokens_are_filled_in_and_text_kept() {
+        let template: ProgressTemplate = "%N: %b in %t %r %p done, %e".parse().unwrap();
+        let template = template.with_name(Some("backup".into()));
+        assert_eq!(
+            template.render(&line(500, Some(1000)), None),
+            "backup: 500B in 0:01:05 [10B/s] 50% done, ETA 0:00:50",
+        );
+        let buffered = Occupancy { held: 3, capacity: 4 };
+        let template: ProgressTemplate = "%b {%T}".parse().unwrap();
+        assert_eq!(template.render(&line(500, None), Some(buffered)), "500B {75%}");
+    }
+
+    #[test]
+    fn tokens_with_nothing_to_show_come_out_empty() {
+        let template: ProgressTemplate = "[%N] %b %e|%p %T".parse().unwrap();
+        assert_eq!(template.render(&line(500, None), None), "[] 500B | ");
+    }
+
+    #[test]
+    fn unknown_and_trailing_tokens_are_rejected() {
+        assert_eq!("%q".parse::<ProgressTemplate>(), Err(ParseProgressTemplateError::Unknown('q')));
+        assert_eq!("%b %".parse::<ProgressTemplate>(), Err(ParseProgressTemplateError::Trailing));
+    }
+
+    #[test]
+    fn templates_round_trip() {
+        for text in ["%b %t %r %p %e %T", "%N: %b (%p of 100%%)", "plain"] {
+            let template: ProgressTemplate = text.parse().unwrap();
+            assert_eq!(template.to_string(), text);
+        }
+    }
+
+}
@@ -0,0 +1,387 @@
+use std::{
+    io::{
+        ErrorKind,
+        Result,
+        Write,
+    },
+    time::Instant,
+};
+
+use crate::{
+    backpressure::{
+        timed,
+        BackpressureMonitor,
+    },
+    config::{
+        ConfigMonitor,
+        LatchMonitor,
+    },
+    progress::TransferProgress,
+    pump::{
+        ChunkMeta,
+        ChunkWrite,
+    },
+    sink::{
+        EndCause,
+        ProgressSink,
+        Reporter,
+        TransferState,
+        WatchSink,
+    },
+    syncio::{
+        cancelled_error,
+        wait_while_paused,
+        DynamicRateLimiter,
+        Finish,
+        RateLimitedWriter,
+    },
+    unit::Unit,
+};
+
+/// The whole writer chain as a single type.
+///
+/// Limiting, pausing, cancellation and progress reporting are each optional
+/// and can be switched on and off while the transfer runs.
+pub struct Throttled<W, S = WatchSink> {
+    limited: RateLimitedWriter<W, DynamicRateLimiter>,
+    reporter: Reporter<S>,
+    paused: Option<LatchMonitor>,
+    cancelled: Option<LatchMonitor>,
+    features: Features,
+    /// Turn writes away while paused or limited rather than wait.
+    nonblocking: bool,
+}
+
+/// Which layers of a [`Throttled`] writer are currently active.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Features {
+    pub limiting: bool,
+    pub pausing: bool,
+    pub cancelling: bool,
+    pub reporting: bool,
+}
+
+impl Default for Features {
+    fn default() -> Self {
+        Self {
+            limiting: true,
+            pausing: true,
+            cancelling: true,
+            reporting: true,
+        }
+    }
+}
+
+impl <W> Throttled<W> {
+    pub fn new(inner: W, config: ConfigMonitor) -> Self {
+        Self::with_sink(inner, config, WatchSink::new())
+    }
+}
+
+impl <W, S: ProgressSink> Throttled<W, S> {
+    pub fn with_sink(inner: W, config: ConfigMonitor, sink: S) -> Self {
+        Self {
+            limited: RateLimitedWriter::writer_with_config(inner, config),
+            reporter: Reporter::new(sink),
+            paused: None,
+            cancelled: None,
+            features: Features::default(),
+            nonblocking: false,
+        }
+    }
+    /// Pause writing whenever `paused` is active.
+    pub fn pauseable(self, paused: LatchMonitor) -> Self {
+        Self {
+            paused: Some(paused),
+            ..self
+        }
+    }
+    /// Fail every write once `cancelled` becomes active.
+    pub fn cancellable(self, cancelled: LatchMonitor) -> Self {
+        Self {
+            cancelled: Some(cancelled),
+            ..self
+        }
+    }
+    /// Count a trailing unterminated record of `unit`, if given, once the
+    /// transfer finishes.
+    pub fn count_final_partial(mut self, unit: Option<Unit>) -> Self {
+        self.reporter.set_count_final_partial(unit);
+        self
+    }
+    /// Count only bytes and writes, for a transfer which shows nothing else.
+    pub fn count_bytes_only(mut self, enabled: bool) -> Self {
+        self.reporter.set_bytes_only(enabled);
+        self
+    }
+    /// Carry on counting from `progress`, made by an earlier transfer.
+    pub fn resumed_from(mut self, progress: TransferProgress) -> Self {
+        self.reporter.resume_from(progress);
+        self
+    }
+    pub fn features(&self) -> Features {
+        self.features
+    }
+    pub fn set_features(&mut self, features: Features) {
+        self.features = features;
+    }
+    pub fn set_limiting(&mut self, enabled: bool) {
+        self.features.limiting = enabled;
+    }
+    pub fn set_pausing(&mut self, enabled: bool) {
+        self.features.pausing = enabled;
+    }
+    pub fn set_cancelling(&mut self, enabled: bool) {
+        self.features.cancelling = enabled;
+    }
+    pub fn set_reporting(&mut self, enabled: bool) {
+        self.features.reporting = enabled;
+    }
+    /// Fail writes with [`ErrorKind::WouldBlock`] instead of waiting while
+    /// paused or for the limiter, leaving the caller to wait as it sees fit.
+    pub fn set_nonblocking(&mut self, nonblocking: bool) {
+        self.nonblocking = nonblocking;
+        self.limited.set_nonblocking(nonblocking);
+    }
+    /// Whether writes are held back by a pause.
+    pub fn is_paused(&mut self) -> bool {
+        self.paused().is_some_and(|paused| paused.active())
+    }
+    /// When the limiter will let through the write it last turned away.
+    pub fn ready_at(&self) -> Option<Instant> {
+        self.limited.ready_at().filter(|_| self.features.limiting)
+    }
+    pub fn sink(&self) -> &S {
+        self.reporter.sink()
+    }
+    pub fn sink_mut(&mut self) -> &mut S {
+        self.reporter.sink_mut()
+    }
+    pub fn get_ref(&self) -> &W {
+        self.limited.get_ref()
+    }
+    pub fn get_mut(&mut self) -> &mut W {
+        self.limited.get_mut()
+    }
+    /// Time spent waiting on the limiter and on the wrapped writer.
+    pub fn backpressure(&mut self) -> BackpressureMonitor {
+        self.limited.backpressure()
+    }
+    /// Write out everything the writers it wraps hold back, whether or not
+    /// the transfer went well, without waiting on pauses or the limiter.
+    pub fn drain(&mut self) -> Result<()>
+    where
+        W: Finish,
+    {
+        self.limited.finish()
+    }
+    /// Report that the transfer has finished.
+    pub fn finish(&mut self) {
+        self.reporter.transition(TransferState::Finished);
+    }
+    /// Report that the transfer failed, unless it was cancelled already.
+    pub fn fail(&mut self) {
+        if self.reporter.state() != TransferState::Cancelled {
+            self.reporter.transition(TransferState::Failed);
+        }
+    }
+    /// Report that the transfer ended for `cause`.
+    pub fn end(&mut self, cause: EndCause) {
+        self.reporter.end(cause);
+    }
+    fn paused(&mut self) -> Option<&mut LatchMonitor> {
+        self.paused
+            .as_mut()
+            .filter(|_| self.features.pausing)
+    }
+    fn cancelled(&mut self) -> Option<&mut LatchMonitor> {
+        self.cancelled
+            .as_mut()
+            .filter(|_| self.features.cancelling)
+    }
+    fn observe_latches(&mut self) {
+        if !self.features.reporting {
+            return;
+        }
+        let paused = self.paused
+            .as_mut()
+            .filter(|_| self.features.pausing);
+        let cancelled = self.cancelled
+            .as_mut()
+            .filter(|_| self.features.cancelling);
+        self.reporter.observe(paused, cancelled);
+    }
+}
+
+impl <W: Write, S: ProgressSink> Throttled<W, S> {
+    fn write_with(&mut self, buf: &[u8], meta: Option<&ChunkMeta>) -> Result<usize> {
+        self.observe_latches();
+        if self.nonblocking {
+            if self.is_paused() {
+                return Err(ErrorKind::WouldBlock.into());
+            }
+        } else if let Some(paused) = self.paused() {
+            if paused.active() {
+                let ((), waited) = timed(|| wait_while_paused(paused));
+                self.limited.record_pause(waited);
+            }
+        }
+        if self.cancelled().is_some_and(|cancelled| cancelled.active()) {
+            self.observe_latches();
+            return Err(cancelled_error());
+        }
+        self.observe_latches();
+        let bytes_transferred = if self.features.limiting {
+            self.limited.write(buf)?
+        } else {
+            self.limited.get_mut().write(buf)?
+        };
+        if self.features.reporting {
+            match meta {
+                Some(meta) => self.reporter.record_chunk(buf, bytes_transferred, meta),
+                None => self.reporter.record(&buf[..bytes_transferred]),
+            }
+        }
+        Ok(bytes_transferred)
+    }
+}
+
+impl <W: Write, S: ProgressSink> Write for Throttled<W, S> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.write_with(buf, None)
+    }
+    fn flush(&mut self) -> Result<()> {
+        self.limited.flush()
+    }
+}
+
+impl <W: Write, S: ProgressSink> ChunkWrite for Throttled<W, S> {
+    fn write_chunk(&mut self, buf: &[u8], meta: &ChunkMeta) -> Result<usize> {
+        self.write_with(buf, Some(meta))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nonzero_ext::nonzero;
+    use crate::{
+        atomic::RecordAtomicWriter,
+        buffering::{
+            BufferedWriter,
+            Buffering,
+        },
+        config::{
+            Config,
+            Latch,
+        },
+        pump::Pump,
+        syncio::WriteExt as _,
+    };
+
+    fn throttled() -> Throttled<Vec<u8>> {
+        let (_, config) = ConfigMonitor::new(Config {
+            limit: Some(nonzero!(1000u64)).into(),
+            ..Default::default()
+        });
+        Throttled::new(Vec::new(), config)
+    }
+
+    #[test]
+    fn reports_progress_only_while_reporting_is_enabled() {
+        let mut writer = throttled();
+        let mut progress = writer.sink().transfer_progress();
+        writer.write_all(b"ab").unwrap();
+        writer.set_reporting(false);
+        writer.write_all(b"cd").unwrap();
+        assert_eq!(progress.get().bytes_transferred, 2);
+        assert_eq!(writer.get_ref(), b"abcd");
+    }
+
+    #[test]
+    fn cancellation_can_be_disabled_at_runtime() {
+        let mut cancelled = Latch::new();
+        let mut writer = throttled().cancellable(cancelled.watch());
+        cancelled.on();
+        writer.write_all(b"a").expect_err("write should be cancelled");
+        writer.set_cancelling(false);
+        writer.write_all(b"a").unwrap();
+    }
+
+    #[test]
+    fn writes_held_back_by_a_pause_are_counted_as_pause_waits() {
+        let mut paused = Latch::new();
+        let mut writer = throttled().pauseable(paused.watch());
+        let mut backpressure = writer.backpressure();
+        writer.write_all(b"a").unwrap();
+        paused.on();
+        let resume = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            paused.off();
+        });
+        writer.write_all(b"b").unwrap();
+        resume.join().unwrap();
+        let waits = backpressure.get().waits();
+        assert_eq!(waits.pausing.count, 1);
+        assert!(waits.pausing.seconds >= 0.02, "{:?}", waits);
+        assert_eq!(writer.get_ref(), b"ab");
+    }
+
+    #[test]
+    fn draining_writes_out_what_every_layer_holds_even_once_cancelled() {
+        let (_, config) = ConfigMonitor::new(Config {
+            unit: Unit::Line,
+            record_atomic: true,
+            ..Default::default()
+        });
+        let mut cancelled = Latch::new();
+        let mut writer = Throttled::new(
+            Vec::new()
+                .buffered(Buffering::Block(nonzero!(64usize)))
+                .record_atomic(config.clone()),
+            config,
+        )
+            .cancellable(cancelled.watch());
+        let output = |writer: &Throttled<RecordAtomicWriter<BufferedWriter<Vec<u8>>>>| match writer.get_ref().get_ref() {
+            BufferedWriter::Block(inner) => inner.get_ref().clone(),
+            _ => unreachable!("output is block buffered"),
+        };
+        writer.write_all(b"a\nb").unwrap();
+        assert_eq!(output(&writer), b"");
+        writer.flush().unwrap();
+        assert_eq!(output(&writer), b"a\n");
+        cancelled.on();
+        writer.write_all(b"c").expect_err("write should be cancelled");
+        writer.drain().unwrap();
+        assert_eq!(output(&writer), b"a\nb");
+    }
+
+    #[test]
+    fn pumped_chunks_are_counted_without_rescanning_the_same() {
+        let data = "one two\nthree\u{3000}four\nfive".as_bytes();
+        let mut writer = throttled();
+        let mut progress = writer.sink().transfer_progress();
+        Pump::default()
+            .chunk_size(nonzero!(3usize))
+            .copy(&mut &data[..], &mut writer)
+            .unwrap();
+        assert_eq!(progress.get(), TransferProgress {
+            writes_transferred: data.len().div_ceil(3) as u64,
+            ..TransferProgress::of(data)
+        });
+        assert_eq!(writer.get_ref(), data);
+    }
+
+    #[test]
+    fn final_partial_line_is_counted_when_finished() {
+        for (unit, lines) in [(Some(Unit::Line), 2), (Some(Unit::Null), 1), (None, 1)] {
+            let mut writer = throttled().count_final_partial(unit);
+            let mut progress = writer.sink().transfer_progress();
+            writer.write_all(b"a\nb").unwrap();
+            assert_eq!(progress.get().lines_transferred, 1);
+            writer.finish();
+            assert_eq!(progress.get().lines_transferred, lines);
+        }
+    }
+
+}
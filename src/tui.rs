@@ -1,12 +1,16 @@
 use std::{
     borrow::Borrow,
+    collections::VecDeque,
     fs::{File, OpenOptions},
-    io, iter,
-    num::NonZeroU32,
-    time::{
-        Duration,
-        Instant,
+    io::{self, Write as _},
+    iter,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc,
     },
+    thread,
+    time::{Duration, Instant},
 };
 
 use tui::{
@@ -16,34 +20,65 @@ use tui::{
 };
 
 use crossterm::{
-    event::{poll, read, Event as InputEvent, KeyCode, KeyEvent, KeyModifiers},
+    event::{
+        poll,
+        read,
+        DisableMouseCapture,
+        EnableMouseCapture,
+        Event as InputEvent,
+        KeyCode,
+        KeyEvent,
+        KeyModifiers,
+        MouseButton,
+        MouseEvent,
+        MouseEventKind,
+    },
     execute, terminal,
 };
 
 use thiserror::Error;
 
-use watch::WatchSender;
-
 use super::{
-    config::{Config, Latch, LatchMonitor},
+    alarm::RateAlarm,
+    backpressure::{BackpressureMonitor, LimiterActivity},
+    fanout::OutputMonitor,
+    instantaneous::RateSampler,
+    config::{LatchMonitor, SpeedLimit},
+    control::Controls,
+    ipc::Message,
+    keys,
+    platform,
     progress::{
         TransferProgress,
         TransferProgressMonitor,
-        CumulativeTransferProgress,
+        CumulativeProgressMonitor,
+        InputFilesMonitor,
     },
+    payload::PayloadMonitor,
+    template::ProgressTemplate,
+    readahead::OccupancyMonitor,
+    window::WindowMonitor,
     widgets::{
         InteractiveWidget as _,
         KeyboardInput as _,
+        ConfirmAbortView,
         EditRateView,
         EditRateState,
         EditRateResponse,
+        DetailView,
+        FileView,
+        Hit,
+        OutputRow,
+        OutputsView,
+        ToastView,
+        TooltipView,
         TransferProgressView,
     },
 };
 
 #[derive(Debug, Error)]
 pub enum UserInterfaceError {
-    #[error("I/O error talking to terminal")]
+    #[error("I/O error talking to terminal: {0}")]
     IO(#[from] io::Error),
 }
 
@@ -51,12 +86,9 @@ pub enum UserInterfaceError {
 enum TuiMode {
     Progress,
     Edit,
-}
-
-#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Eq, Ord)]
-struct TransferMode {
-    paused: bool,
-    limit: NonZeroU32,
+    /// Waiting for Ctrl-C to be pressed again before aborting, until the
+    /// given time.
+    ConfirmAbort(Instant),
 }
 
 type Result<T> = std::result::Result<T, UserInterfaceError>;
@@ -67,39 +99,73 @@ enum Event {
     Input(InputEvent),
 }
 
-struct Events;
+/// Input events, with a tick whenever none arrives for `timeout` and
+/// another as soon as the interface is told to shut down, so that it does
+/// not wait out the refresh to notice.
+///
+/// Input is read on a thread of its own, which stops shortly after the
+/// events are dropped.
+struct Events {
+    rx: mpsc::Receiver<Event>,
+    timeout: Duration,
+    closed: Arc<AtomicBool>,
+}
 
-impl Iterator for Events {
-    type Item = Event;
+impl Events {
+    /// How long the input thread waits for input before checking whether
+    /// it is still wanted.
+    const INPUT_POLL: Duration = Duration::from_millis(100);
 
-    fn next(&mut self) -> Option<Self::Item> {
-        match poll(Duration::from_secs(1)) {
-            Ok(true) => {
-                let event = read().unwrap();
-                Some(Event::Input(event))
+    fn spawn(timeout: Duration, shutdown: &LatchMonitor) -> io::Result<Self> {
+        let (tx, rx) = mpsc::channel();
+        let ticks = tx.clone();
+        shutdown.notify(move |active| if active {
+            let _ = ticks.send(Event::Tick);
+        });
+        let closed = Arc::new(AtomicBool::new(false));
+        let stop = Arc::clone(&closed);
+        thread::Builder::new()
+            .name("input".into())
+            .spawn(move || Self::read_input(tx, &stop))?;
+        Ok(Self {
+            rx,
+            timeout,
+            closed,
+        })
+    }
+
+    fn read_input(tx: mpsc::Sender<Event>, closed: &AtomicBool) {
+        while !closed.load(Ordering::Relaxed) {
+            match poll(Self::INPUT_POLL) {
+                Ok(true) => match read() {
+                    Ok(event) => if tx.send(Event::Input(event)).is_err() {
+                        return;
+                    },
+                    Err(_) => return,
+                },
+                Ok(false) => {},
+                Err(_) => return,
             }
-            Ok(false) => Some(Event::Tick),
-            _ => unreachable!("failed to iterate input events"),
         }
     }
 }
 
-fn checked_add(value: Option<NonZeroU32>, increment: u32) -> Option<NonZeroU32> {
-    value?.get()
-        .checked_add(increment)
-        .or(Some(0))
-        .map(|n| n - (n % increment))
-        .map(|n| 1.max(n))
-        .and_then(NonZeroU32::new)
+impl Iterator for Events {
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.rx.recv_timeout(self.timeout) {
+            Ok(event) => Some(event),
+            Err(RecvTimeoutError::Timeout) => Some(Event::Tick),
+            Err(RecvTimeoutError::Disconnected) => None,
+        }
+    }
 }
 
-fn checked_sub(value: Option<NonZeroU32>, increment: u32) -> Option<NonZeroU32> {
-    value?.get()
-        .checked_sub(increment)
-        .or(Some(0))
-        .map(|n| n - (n % increment))
-        .map(|n| 1.max(n))
-        .and_then(NonZeroU32::new)
+impl Drop for Events {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::Relaxed);
+    }
 }
 
 type CrossTerminal = Terminal<CrosstermBackend<File>>;
@@ -107,64 +173,177 @@ type CrossTerminal = Terminal<CrosstermBackend<File>>;
 pub struct UserInterface {
     terminal: CrossTerminal,
     shutdown: LatchMonitor,
-    config: Config,
-    config_tx: WatchSender<Config>,
-    paused: Latch,
-    aborted: Latch,
-    cumulative: TransferProgressMonitor,
-    instantaneous: TransferProgressMonitor,
+    controls: Controls,
+    monitors: Monitors,
+    show_details: bool,
+    alarm: Option<RateAlarm>,
+    /// Most recently sampled rate, which arrow keys start from when there
+    /// is no limit yet.
+    observed: TransferProgress,
+    mouse: bool,
+    /// Last drawn progress view, for the mouse to hit-test against.
+    view: Option<TransferProgressView>,
+    tooltip: Option<TooltipView>,
+    /// The last change made and until when it is shown.
+    toast: Option<(ToastView, Instant)>,
+    history: LimitHistory,
+    /// Ask for Ctrl-C twice before aborting.
+    confirm_abort: bool,
+    /// Pause the transfer when Ctrl-Z suspends the interface.
+    pause_on_suspend: bool,
+    /// Layout of the gauge's label given with `-F`.
+    template: Option<Arc<ProgressTemplate>>,
+    refresh: Duration,
+}
+
+/// Everything the interface observes about the running transfer.
+pub struct Monitors {
+    pub cumulative: CumulativeProgressMonitor,
+    pub instantaneous: RateSampler,
+    pub backpressure: BackpressureMonitor,
+    /// Whether the limiter is holding the transfer back.
+    pub limiter: LimiterActivity,
+    pub outputs: Vec<OutputMonitor>,
+    /// Progress forwarded downstream, when sampling records or filtering.
+    pub forwarded: Option<TransferProgressMonitor>,
+    /// Inputs read in turn, when reading files rather than stdin.
+    pub files: Option<InputFilesMonitor>,
+    /// Whether the `--gate-cmd` gate is closed, when there is one.
+    pub gate: Option<LatchMonitor>,
+    /// Whether the transfer waits for `--min-free` space, when watching it.
+    pub space: Option<LatchMonitor>,
+    /// Which `--window` is active, when there are any.
+    pub window: Option<WindowMonitor>,
+    /// How full the read-ahead buffer is, when there is one.
+    pub buffer: Option<OccupancyMonitor>,
+    /// Payload bytes written, when counting them with `--net-bytes`.
+    pub payload: Option<PayloadMonitor>,
+}
+
+/// State of the transfer at the moment a frame is drawn.
+#[derive(Clone, PartialEq)]
+struct Snapshot {
+    progress: TransferProgressView,
+    file: Option<FileView>,
+    outputs: OutputsView,
+    details: Option<DetailView>,
+    toast: Option<ToastView>,
+    tooltip: Option<TooltipView>,
 }
 
+/// Limits as they were before each change made from the interface, the
+/// latest last, for `u` to put back one at a time.
+#[derive(Default)]
+struct LimitHistory(VecDeque<SpeedLimit>);
+
 pub struct Cleanup();
 
+/// Restores the terminal as well as it still can; if it has gone away there
+/// is nothing left to restore.
 impl Drop for Cleanup {
     fn drop(&mut self) {
         if let Ok(mut tty) =
             OpenOptions::new().read(true).write(true).open("/dev/tty")
         {
-            execute!(tty, terminal::LeaveAlternateScreen)
-                .expect("failed to leave alternate screen");
-            terminal::disable_raw_mode().expect("failed to disable raw mode");
+            let _ = execute!(tty, DisableMouseCapture, terminal::LeaveAlternateScreen);
         }
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// The controlling terminal in raw mode on the alternate screen, ready for a
+/// [`UserInterface`] to draw on.
+pub struct Screen(CrosstermBackend<File>);
+
+impl Screen {
+    /// Take over the controlling terminal, failing if there is none, as in
+    /// many containers.
+    pub fn enter() -> Result<Self> {
+        let mut tty =
+            OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+        terminal::enable_raw_mode()?;
+        if let Err(e) = execute!(tty, terminal::EnterAlternateScreen) {
+            let _ = terminal::disable_raw_mode();
+            return Err(e.into());
+        }
+        Ok(Self(CrosstermBackend::new(tty)))
     }
 }
 
 impl UserInterface {
+    pub const DEFAULT_REFRESH: Duration = Duration::from_secs(1);
+    /// How long Ctrl-C can be pressed again in to confirm an abort.
+    const CONFIRM_ABORT: Duration = Duration::from_secs(3);
+
     pub fn new(
-        paused: Latch,
-        aborted: Latch,
+        screen: Screen,
+        controls: Controls,
         shutdown: LatchMonitor,
-        config: Config,
-        cumulative: TransferProgressMonitor,
-        instantaneous: TransferProgressMonitor,
-        config_tx: WatchSender<Config>,
+        monitors: Monitors,
     ) -> Result<Self> {
-        let backend = Self::initialize_backend()?;
-        let terminal = Terminal::new(backend)?;
+        let terminal = Terminal::new(screen.0)?;
         Ok(Self {
             terminal,
             shutdown,
-            config,
-            config_tx,
-            paused,
-            aborted,
-            cumulative,
-            instantaneous,
+            alarm: controls.config().min_rate.map(RateAlarm::new),
+            controls,
+            monitors,
+            show_details: false,
+            observed: TransferProgress::default(),
+            mouse: false,
+            view: None,
+            tooltip: None,
+            toast: None,
+            history: LimitHistory::default(),
+            confirm_abort: false,
+            pause_on_suspend: false,
+            template: None,
+            refresh: Self::DEFAULT_REFRESH,
         })
     }
-    fn initialize_backend() -> Result<CrosstermBackend<File>> {
-        let mut tty =
-            OpenOptions::new().read(true).write(true).open("/dev/tty")?;
-        terminal::enable_raw_mode()?;
-        execute!(tty, terminal::EnterAlternateScreen)?;
-        Ok(CrosstermBackend::new(tty))
+    /// Redraw at most this often while no input arrives.
+    pub fn with_refresh(mut self, refresh: Duration) -> Self {
+        self.refresh = refresh;
+        self
+    }
+    /// Ask for Ctrl-C to be pressed again within a few seconds before
+    /// aborting, so that a stray one does not end a long transfer.
+    pub fn with_confirm_abort(mut self, enabled: bool) -> Self {
+        self.confirm_abort = enabled;
+        self
+    }
+    /// Pause the transfer on Ctrl-Z, so that it is still paused once the
+    /// interface is brought back to the foreground.
+    pub fn with_pause_on_suspend(mut self, enabled: bool) -> Self {
+        self.pause_on_suspend = enabled;
+        self
+    }
+    /// Label the gauge as `template` says rather than as usual.
+    pub fn with_template(mut self, template: Option<ProgressTemplate>) -> Self {
+        self.template = template.map(Arc::new);
+        self
+    }
+    /// Let the mouse pause, adjust the rate and point along the gauge.
+    pub fn with_mouse(mut self, enabled: bool) -> Result<Self> {
+        if enabled {
+            execute!(self.terminal.backend_mut(), EnableMouseCapture)?;
+        }
+        self.mouse = enabled;
+        Ok(self)
     }
-    pub fn run(mut self, start_time: Instant) -> Result<Cleanup> {
-        let events = iter::once(Event::Tick).chain(Events);
+    pub fn run(mut self) -> Result<Cleanup> {
+        let events = iter::once(Event::Tick)
+            .chain(Events::spawn(self.refresh, &self.shutdown)?);
         let mut mode = TuiMode::Progress;
         let mut rate = EditRateState::new();
+        let mut drawn: Option<Snapshot> = None;
         self.terminal.clear()?;
         for event in events {
+            // Input may change the mode, the text being edited or the size
+            // of the terminal, none of which the snapshot holds.
+            if matches!(event, Event::Input(_)) {
+                drawn = None;
+            }
             match mode {
                 TuiMode::Progress => match event {
                     Event::Input(InputEvent::Key(KeyEvent {
@@ -174,45 +353,45 @@ impl UserInterface {
                         mode = TuiMode::Edit;
                     },
                     Event::Input(InputEvent::Key(KeyEvent {
-                        code: KeyCode::Tab,
-                        ..
-                    })) => { self.cycle_unit(); },
-                    Event::Input(InputEvent::Key(KeyEvent {
-                        code: KeyCode::Char('`'),
-                        ..
-                    })) => { self.toggle_speed_limit(); },
-                    Event::Input(InputEvent::Key(KeyEvent {
-                        code: KeyCode::Left,
+                        code: KeyCode::Char('d'),
                         ..
                     })) => {
-                        self.decrease_rate();
+                        self.show_details = !self.show_details;
                     },
                     Event::Input(InputEvent::Key(KeyEvent {
-                        code: KeyCode::Right,
+                        code: KeyCode::Char('z'),
+                        modifiers: KeyModifiers::CONTROL,
                         ..
                     })) => {
-                        self.increase_rate();
+                        self.suspend()?;
                     },
+                    #[cfg(feature = "clipboard")]
                     Event::Input(InputEvent::Key(KeyEvent {
-                        code: KeyCode::Char(' '),
+                        code: KeyCode::Char('y'),
                         ..
                     })) => {
-                        self.toggle_paused();
+                        self.copy_status()?;
                     },
-                    Event::Input(InputEvent::Key(KeyEvent {
-                        code: KeyCode::Char('c'),
-                        modifiers: KeyModifiers::CONTROL,
-                        ..
-                    })) => {
-                        self.aborted.on();
-                        break;
+                    Event::Input(InputEvent::Key(key)) => {
+                        match keys::message(key, &self.observed) {
+                            Some(Message::Abort) if self.confirm_abort => {
+                                mode = TuiMode::ConfirmAbort(
+                                    Instant::now() + Self::CONFIRM_ABORT,
+                                );
+                            },
+                            Some(message) => self.dispatch(message),
+                            None => {},
+                        }
+                    },
+                    Event::Input(InputEvent::Mouse(event)) => {
+                        self.mouse_input(event)?;
                     },
                     _ => {},
                 },
                 TuiMode::Edit => if let Event::Input(event) = event {
                     match rate.input(event) {
                         Some(EditRateResponse::NewRate(rate)) => {
-                            self.set_limit(Some(rate));
+                            self.dispatch(Message::Limit(Some(rate)));
                             mode = TuiMode::Progress;
                         },
                         Some(_) => {
@@ -221,80 +400,269 @@ impl UserInterface {
                         _ => {},
                     }
                 },
+                // Any other key carries on with the transfer.
+                TuiMode::ConfirmAbort(until) => match event {
+                    Event::Input(InputEvent::Key(key)) => {
+                        let abort = keys::message(key, &self.observed) == Some(Message::Abort);
+                        if abort && Instant::now() < until {
+                            self.dispatch(Message::Abort);
+                        }
+                        mode = TuiMode::Progress;
+                    },
+                    // Redraw the time left, which the snapshot does not hold.
+                    Event::Tick => {
+                        if Instant::now() >= until {
+                            mode = TuiMode::Progress;
+                        }
+                        drawn = None;
+                    },
+                    _ => {},
+                },
             }
-            if self.shutdown.active() {
+            if self.shutdown.active() || self.controls.aborted() {
                 break;
             }
-            let cumulative_progress = CumulativeTransferProgress {
-                start_time,
-                progress: self.cumulative.get(),
-            };
-            let config = self.config;
-            let paused = self.paused.active();
-            let speed = self.instantaneous.get();
+            let snapshot = self.snapshot()?;
+            if drawn.as_ref() == Some(&snapshot) {
+                continue;
+            }
+            self.view = Some(snapshot.progress.clone());
+            drawn = Some(snapshot.clone());
             self.terminal.draw(|f| Self::draw(
                     f,
                     mode,
-                    config,
-                    paused,
-                    cumulative_progress,
-                    speed,
+                    snapshot,
                     rate.borrow(),
             ))?;
         }
         Ok(Cleanup())
     }
 
-    fn toggle_paused(&mut self) {
-        self.paused.toggle();
-    }
-
-    fn toggle_speed_limit(&mut self) {
-        self.config.toggle_limit();
-        self.config_tx.send(self.config);
+    /// Give the terminal back and stop, as Ctrl-Z would outside raw mode,
+    /// then take it over again and redraw once continued.
+    ///
+    /// The whole process stops, so the transfer waits for `fg` as well.
+    fn suspend(&mut self) -> Result<()> {
+        if self.pause_on_suspend && !self.controls.paused() {
+            self.dispatch(Message::Pause);
+        }
+        let backend = self.terminal.backend_mut();
+        if self.mouse {
+            execute!(backend, DisableMouseCapture)?;
+        }
+        execute!(backend, terminal::LeaveAlternateScreen)?;
+        terminal::disable_raw_mode()?;
+        platform::suspend()?;
+        terminal::enable_raw_mode()?;
+        let backend = self.terminal.backend_mut();
+        execute!(backend, terminal::EnterAlternateScreen)?;
+        if self.mouse {
+            execute!(backend, EnableMouseCapture)?;
+        }
+        self.terminal.clear()?;
+        Ok(())
     }
 
-    fn set_limit(&mut self, limit: Option<NonZeroU32>) {
-        self.config = Config {
-            limit: limit.into(),
-            ..self.config
+    /// Put the whole status line last drawn on the clipboard.
+    #[cfg(feature = "clipboard")]
+    fn copy_status(&mut self) -> Result<()> {
+        let Some(view) = &self.view else {
+            return Ok(());
         };
-        self.config_tx.send(self.config);
+        let text = view.status().to_text(usize::MAX);
+        crate::clipboard::copy(self.terminal.backend_mut(), &text)?;
+        let toast = ToastView { text: "copied to clipboard".to_owned() };
+        self.toast = Some((toast, Instant::now() + ToastView::LIFETIME));
+        Ok(())
     }
 
-    fn increase_rate(&mut self) {
-        let limit = checked_add(self.config.limit(), 10);
-        self.set_limit(limit);
+    /// Act on `message`, then tell what it did for a little while.
+    ///
+    /// A change of limit is remembered for undoing, unless it is an undo.
+    fn dispatch(&mut self, message: Message) {
+        let message = match message {
+            Message::Undo => match self.history.undo() {
+                Some(restore) => restore,
+                None => return,
+            },
+            message => message,
+        };
+        let before = self.controls.config().limit;
+        self.controls.send(message);
+        let config = self.controls.config();
+        self.history.record(message, before, config.limit);
+        self.toast = ToastView::after(message, &config, self.controls.paused())
+            .map(|toast| (toast, Instant::now() + ToastView::LIFETIME));
     }
 
-    fn decrease_rate(&mut self) {
-        let limit = checked_sub(self.config.limit(), 10);
-        self.set_limit(limit);
+    fn snapshot(&mut self) -> Result<Snapshot> {
+        let Monitors {
+            cumulative,
+            instantaneous,
+            backpressure,
+            limiter,
+            outputs,
+            forwarded,
+            files,
+            gate,
+            space,
+            window,
+            buffer,
+            payload,
+        } = &mut self.monitors;
+        let gated = gate.as_mut().is_some_and(LatchMonitor::active);
+        let waiting_for_space = space.as_mut().is_some_and(LatchMonitor::active);
+        let window = window.as_mut().and_then(WindowMonitor::get);
+        let buffered = buffer.as_mut().map(OccupancyMonitor::get);
+        let forwarded = forwarded.as_mut().map(TransferProgressMonitor::get);
+        let cumulative = cumulative.get();
+        let file = files.as_mut()
+            .and_then(|files| files.locate(cumulative.progress.bytes_transferred))
+            .map(|file| FileView {
+                file,
+                format: self.controls.config().number_format,
+            });
+        let now = Instant::now();
+        let limiter_active = limiter.active_at(now);
+        let instantaneous = instantaneous.sample();
+        self.observed = instantaneous;
+        let outputs = OutputsView {
+            rows: outputs.iter_mut()
+                .map(|output| OutputRow {
+                    name: output.name.clone(),
+                    written: output.written.get(),
+                    rate: output.rate.sample(),
+                    dropped: output.dropped.get(),
+                    limit: output.limit,
+                    limiter_active: output.limiter.active_at(now),
+                    lossy: output.lossy,
+                })
+                .collect(),
+            unit: self.controls.config().shown_unit(),
+            limit_unit: self.controls.config().unit,
+            format: self.controls.config().number_format,
+            offset: file.is_some() as u16,
+        };
+        let details = self.show_details.then(|| DetailView {
+            backpressure: backpressure.get(),
+            payload: payload.as_mut().map(PayloadMonitor::get),
+            format: self.controls.config().number_format,
+            offset: outputs.offset + outputs.height(),
+        });
+        let paused = self.controls.paused();
+        let mut below_minimum = false;
+        if let Some(alarm) = &mut self.alarm {
+            if paused {
+                alarm.reset();
+            } else if alarm.observe(&instantaneous, self.controls.config().unit, now)
+                && alarm.min_rate().bell
+            {
+                self.terminal.backend_mut().write_all(b"\x07")?;
+            }
+            below_minimum = alarm.raised();
+        }
+        let config = self.controls.config();
+        Ok(Snapshot {
+            progress: TransferProgressView {
+                paused,
+                gated,
+                waiting_for_space,
+                window,
+                buffered,
+                unit: config.shown_unit(),
+                limit_unit: config.unit,
+                format: config.number_format,
+                show_all_units: config.show_all_units,
+                limit: config.limit(),
+                expected_size: config.expected_size,
+                cumulative,
+                at: now,
+                instantaneous,
+                below_minimum,
+                limiter_active,
+                forwarded,
+                mouse: self.mouse,
+                colored_gauge: config.colored_gauge,
+                rate_gauge: config.rate_gauge,
+                template: self.template.clone(),
+            },
+            file,
+            outputs,
+            details,
+            toast: self.toast.as_ref()
+                .filter(|(_, until)| now < *until)
+                .map(|(toast, _)| toast.clone()),
+            tooltip: self.tooltip.clone(),
+        })
     }
 
-    fn cycle_unit(&mut self) {
-        self.config.unit.cycle();
-        self.config_tx.send(self.config);
+    fn mouse_input(&mut self, event: MouseEvent) -> Result<()> {
+        let Some(view) = &self.view else {
+            return Ok(());
+        };
+        let area = self.terminal.size()?;
+        let hit = view.hit(area, event.column, event.row);
+        match (event.kind, hit) {
+            (MouseEventKind::Down(MouseButton::Left), Some(Hit::Pause)) => {
+                self.dispatch(Message::TogglePause);
+            },
+            (MouseEventKind::ScrollUp, Some(Hit::Rate)) => {
+                self.dispatch(Message::Faster(self.observed));
+            },
+            (MouseEventKind::ScrollDown, Some(Hit::Rate)) => {
+                self.dispatch(Message::Slower(self.observed));
+            },
+            (
+                MouseEventKind::Down(MouseButton::Left)
+                | MouseEventKind::Drag(MouseButton::Left),
+                Some(Hit::Gauge(ratio)),
+            ) => {
+                self.tooltip = view.position_at(ratio).map(|text| TooltipView {
+                    column: event.column,
+                    text,
+                });
+            },
+            (MouseEventKind::Up(_), _) => self.tooltip = None,
+            _ => {},
+        }
+        Ok(())
     }
 
     fn draw(
         frame: &mut Frame,
         mode: TuiMode,
-        config: Config,
-        paused: bool,
-        cumulative: CumulativeTransferProgress,
-        instantaneous: TransferProgress,
+        snapshot: Snapshot,
         input: &str,
     ) {
+        let Snapshot {
+            progress,
+            file,
+            outputs,
+            details,
+            toast,
+            tooltip,
+        } = snapshot;
         match mode {
-            TuiMode::Progress => TransferProgressView {
-                paused,
-                unit: config.unit,
-                limit: config.limit(),
-                expected_size: config.expected_size,
-                cumulative,
-                instantaneous,
-            }.render(frame),
+            TuiMode::Progress | TuiMode::ConfirmAbort(_) => {
+                progress.render(frame);
+                if let Some(file) = file {
+                    file.render(frame);
+                }
+                outputs.render(frame);
+                if let Some(details) = details {
+                    details.render(frame);
+                }
+                if let Some(toast) = toast {
+                    toast.render(frame);
+                }
+                if let Some(tooltip) = tooltip {
+                    tooltip.render(frame);
+                }
+                if let TuiMode::ConfirmAbort(until) = mode {
+                    let remaining = until.saturating_duration_since(Instant::now());
+                    ConfirmAbortView(remaining).render(frame);
+                }
+            },
             TuiMode::Edit => EditRateView(input).render(frame),
         }
     }
@@ -306,3 +674,52 @@ impl Drop for UserInterface {
         Cleanup();
     }
 }
+
+impl LimitHistory {
+    /// How many limit changes can be undone.
+    const DEPTH: usize = 16;
+
+    /// Remember `before` if `message` changed the limit from it, unless
+    /// the change was itself an undo.
+    fn record(&mut self, message: Message, before: SpeedLimit, after: SpeedLimit) {
+        if before == after || matches!(message, Message::RestoreLimit(_)) {
+            return;
+        }
+        if self.0.len() == Self::DEPTH {
+            self.0.pop_front();
+        }
+        self.0.push_back(before);
+    }
+    /// The message putting back the limit from before the latest change,
+    /// if there are any left.
+    fn undo(&mut self) -> Option<Message> {
+        self.0.pop_back().map(Message::RestoreLimit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::num::NonZeroU64;
+
+    fn limit(n: u64) -> SpeedLimit {
+        NonZeroU64::new(n).into()
+    }
+
+    #[test]
+    fn history_keeps_the_latest_changes_and_no_restores() {
+        let mut history = LimitHistory::default();
+        for n in 1..=20 {
+            history.record(Message::Limit(NonZeroU64::new(n + 1)), limit(n), limit(n + 1));
+        }
+        history.record(Message::CycleUnit, limit(21), limit(21));
+        history.record(Message::RestoreLimit(limit(5)), limit(21), limit(5));
+        let undone: Vec<_> = std::iter::from_fn(|| history.undo()).collect();
+        let expected: Vec<_> = (5..=20).rev()
+            .map(|n| Message::RestoreLimit(limit(n)))
+            .collect();
+        assert_eq!(undone, expected);
+    }
+
+}
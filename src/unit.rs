@@ -1,22 +1,392 @@
-#[derive(Debug, Clone, Copy, PartialOrd, Ord, PartialEq, Eq)]
+use std::{
+    fmt,
+    str::FromStr,
+};
+
+use thiserror::Error;
+
+use super::progress::TransferProgress;
+
+const NUL: u8 = 0x0;
+const LF: u8 = 0xA;
+
+#[derive(Debug, Default, Clone, Copy, PartialOrd, Ord, PartialEq, Eq)]
 pub enum Unit {
+    #[default]
     Byte,
     Line,
     Null,
+    Word,
+    /// One call to write, whatever its size.
+    Op,
 }
 
-impl Default for Unit {
-    fn default() -> Self {
-        Self::Byte
-    }
+/// Incremental scanner which carries state across buffer boundaries.
+///
+/// Only needed for units whose boundaries cannot be found by looking at a
+/// single byte, such as whitespace-delimited words which may be separated by
+/// multi-byte UTF-8 whitespace split across two writes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Scanner {
+    in_word: bool,
+    partial: [u8; 4],
+    partial_len: usize,
+    last: Option<u8>,
 }
 
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+#[error("unknown unit {0:?}, expected one of: b, l, 0, w, o")]
+pub struct ParseUnitError(String);
+
 impl Unit {
+    pub const ALL: [Unit; 5] = [Self::Byte, Self::Line, Self::Null, Self::Word, Self::Op];
+
     pub fn cycle(&mut self) {
         *self = match self {
             Self::Byte => Self::Line,
             Self::Line => Self::Null,
-            Self::Null => Self::Byte,
+            Self::Null => Self::Word,
+            Self::Word => Self::Op,
+            Self::Op => Self::Byte,
+        }
+    }
+
+    /// Short suffix used when displaying amounts and rates.
+    pub fn abbreviation(&self) -> &'static str {
+        match self {
+            Self::Byte => "B",
+            Self::Line => "L",
+            Self::Null => "#",
+            Self::Word => "W",
+            Self::Op => "op",
+        }
+    }
+
+    /// The delimiter terminating each record, if this is a record unit.
+    pub fn delimiter(&self) -> Option<u8> {
+        match self {
+            Self::Byte | Self::Word | Self::Op => None,
+            Self::Line => Some(LF),
+            Self::Null => Some(NUL),
+        }
+    }
+
+    /// Select the counter of `progress` which is measured in this unit.
+    pub fn progress(&self, progress: &TransferProgress) -> u64 {
+        match self {
+            Self::Byte => progress.bytes_transferred,
+            Self::Line => progress.lines_transferred,
+            Self::Null => progress.nulls_transferred,
+            Self::Word => progress.words_transferred,
+            Self::Op => progress.writes_transferred,
+        }
+    }
+
+    /// Offsets just past the end of every unit in `buf`.
+    pub fn annotate(&self, buf: &[u8]) -> Vec<usize> {
+        Scanner::default().annotate(*self, buf)
+    }
+
+    /// Number of units contained in `buf`.
+    pub fn count(&self, buf: &[u8]) -> usize {
+        Scanner::default().count(*self, buf)
+    }
+
+    fn annotate_stateless(&self, buf: &[u8]) -> Vec<usize> {
+        if *self == Self::Op {
+            return (!buf.is_empty()).then_some(buf.len()).into_iter().collect();
+        }
+        match self.delimiter() {
+            None => (1..=buf.len()).collect(),
+            Some(delimiter) => buf.iter()
+                .enumerate()
+                .filter(|(_, b)| delimiter == **b)
+                .map(|(i, _)| i + 1)
+                .collect(),
+        }
+    }
+
+    fn count_stateless(&self, buf: &[u8]) -> usize {
+        if *self == Self::Op {
+            return !buf.is_empty() as usize;
+        }
+        match self.delimiter() {
+            None => buf.len(),
+            Some(delimiter) => buf.iter()
+                .filter(|b| delimiter == **b)
+                .count(),
+        }
+    }
+}
+
+impl Scanner {
+    /// Offsets just past the end of every unit in `buf` without consuming it.
+    pub fn annotate(&self, unit: Unit, buf: &[u8]) -> Vec<usize> {
+        match unit {
+            Unit::Word => {
+                let mut points = vec![];
+                self.scan_words(buf, |i| points.push(i));
+                points
+            },
+            _ => unit.annotate_stateless(buf),
+        }
+    }
+
+    /// Number of units contained in `buf`, consuming it.
+    pub fn count(&mut self, unit: Unit, buf: &[u8]) -> usize {
+        match unit {
+            Unit::Word => {
+                let mut count = 0;
+                *self = self.scan_words(buf, |_| count += 1);
+                count
+            },
+            _ => unit.count_stateless(buf),
+        }
+    }
+
+    /// Consume `buf` without counting anything.
+    pub fn advance(&mut self, buf: &[u8]) {
+        *self = self.scan_words(buf, |_| {});
+    }
+
+    /// Remember how `buf` ended so that [`Scanner::unterminated`] can tell
+    /// whether a record is still open.
+    pub fn observe_end(&mut self, buf: &[u8]) {
+        if let Some(&last) = buf.last() {
+            self.last = Some(last);
+        }
+    }
+
+    /// The unit of `unit` which has begun in the data seen so far but was
+    /// never terminated, such as the final line of input lacking a newline,
+    /// counted in that unit alone.
+    pub fn unterminated(&self, unit: Unit) -> TransferProgress {
+        let open = |delimiter| self.last
+            .is_some_and(|last| last != delimiter) as u64;
+        let mut progress = TransferProgress::default();
+        match unit {
+            Unit::Line => progress.lines_transferred = open(LF),
+            Unit::Null => progress.nulls_transferred = open(NUL),
+            Unit::Word => {
+                progress.words_transferred = (self.in_word || self.partial_len > 0) as u64
+            },
+            Unit::Byte | Unit::Op => {},
+        }
+        progress
+    }
+
+    /// Reports the offset just past each whitespace character ending a word.
+    fn scan_words(mut self, buf: &[u8], mut on_boundary: impl FnMut(usize)) -> Self {
+        let mut i = 0;
+        if self.partial_len > 0 {
+            let width = utf8_width(self.partial[0]);
+            while self.partial_len < width
+                && i < buf.len()
+                && is_continuation(buf[i])
+            {
+                self.partial[self.partial_len] = buf[i];
+                self.partial_len += 1;
+                i += 1;
+            }
+            if self.partial_len < width && i == buf.len() {
+                return self;
+            }
+            let partial = &self.partial[..self.partial_len];
+            let whitespace = std::str::from_utf8(partial)
+                .map(|s| s.chars().all(char::is_whitespace))
+                .unwrap_or(false);
+            self.partial_len = 0;
+            self.visit(whitespace, i, &mut on_boundary);
         }
+        while i < buf.len() {
+            let width = utf8_width(buf[i]);
+            if i + width > buf.len()
+                && buf[i + 1..].iter().copied().all(is_continuation)
+            {
+                let rest = &buf[i..];
+                self.partial[..rest.len()].copy_from_slice(rest);
+                self.partial_len = rest.len();
+                break;
+            }
+            let (whitespace, width) = std::str::from_utf8(
+                &buf[i..(i + width).min(buf.len())]
+            )
+                .ok()
+                .and_then(|s| s.chars().next())
+                .map(|c| (c.is_whitespace(), width))
+                .unwrap_or((false, 1));
+            i += width;
+            self.visit(whitespace, i, &mut on_boundary);
+        }
+        self
+    }
+
+    fn visit(
+        &mut self,
+        whitespace: bool,
+        end: usize,
+        on_boundary: &mut impl FnMut(usize),
+    ) {
+        if whitespace && self.in_word {
+            on_boundary(end);
+        }
+        self.in_word = !whitespace;
+    }
+}
+
+fn utf8_width(lead: u8) -> usize {
+    match lead {
+        0xC2..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        0xF0..=0xF4 => 4,
+        _ => 1,
+    }
+}
+
+fn is_continuation(b: u8) -> bool {
+    (0x80..=0xBF).contains(&b)
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Byte => "byte",
+            Self::Line => "line",
+            Self::Null => "null",
+            Self::Word => "word",
+            Self::Op => "op",
+        };
+        fmt.write_str(name)
+    }
+}
+
+impl FromStr for Unit {
+    type Err = ParseUnitError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "b" | "byte" | "bytes" => Ok(Self::Byte),
+            "l" | "line" | "lines" => Ok(Self::Line),
+            "0" | "null" | "nulls" => Ok(Self::Null),
+            "w" | "word" | "words" => Ok(Self::Word),
+            "o" | "op" | "ops" => Ok(Self::Op),
+            _ => Err(ParseUnitError(s.to_owned())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::iter::repeat_n;
+
+    #[test]
+    fn annotate_bytes_returns_buffer_length() {
+        let buf: Vec<u8> = repeat_n(NUL, 10)
+            .chain(repeat_n(LF, 10))
+            .collect();
+        assert_eq!(
+            Unit::Byte.annotate(&buf).len(),
+            buf.len(),
+        );
+    }
+
+    #[test]
+    fn annotate_lines_returns_number_of_linefeeds() {
+        let buf: Vec<u8> = repeat_n(LF, 10)
+            .chain(vec![NUL])
+            .collect();
+        assert_eq!(
+            Unit::Line.annotate(&buf).len(),
+            10,
+        );
     }
+
+    #[test]
+    fn annotate_nulls_returns_number_of_nulls() {
+        let buf: Vec<u8> = repeat_n(NUL, 10)
+            .chain(vec![LF])
+            .collect();
+        assert_eq!(
+            Unit::Null.annotate(&buf).len(),
+            10,
+        );
+    }
+
+    #[test]
+    fn annotate_points_past_each_delimiter() {
+        let buf = b"a\nbc\n";
+        assert_eq!(Unit::Line.annotate(buf), vec![2, 5]);
+        assert_eq!(Unit::Byte.annotate(buf), vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn count_agrees_with_annotate() {
+        let buf = b"a\nb\0c\n\0";
+        for unit in Unit::ALL {
+            assert_eq!(unit.count(buf), unit.annotate(buf).len());
+        }
+    }
+
+    #[test]
+    fn every_write_is_one_op_whatever_its_size() {
+        assert_eq!(Unit::Op.annotate(b"a\nbc\n"), vec![5]);
+        assert_eq!(Unit::Op.count(b"x"), 1);
+        assert_eq!(Unit::Op.count(b""), 0);
+    }
+
+    #[test]
+    fn annotate_words_returns_number_of_terminated_words() {
+        let buf = b"  one two\tthree\n four";
+        assert_eq!(Unit::Word.annotate(buf), vec![6, 10, 16]);
+    }
+
+    #[test]
+    fn words_are_separated_by_multibyte_whitespace() {
+        let buf = "one\u{3000}two\u{a0}three ".as_bytes();
+        assert_eq!(Unit::Word.count(buf), 3);
+    }
+
+    #[test]
+    fn words_split_across_buffers_are_counted_once() {
+        let buf = "alpha\u{2003}beta gamma ".as_bytes();
+        for split in 0..=buf.len() {
+            let mut scanner = Scanner::default();
+            let (head, tail) = buf.split_at(split);
+            let count = scanner.count(Unit::Word, head)
+                + scanner.count(Unit::Word, tail);
+            assert_eq!(count, 3, "split at {}", split);
+        }
+    }
+
+    #[test]
+    fn unterminated_counts_the_open_record() {
+        let mut scanner = Scanner::default();
+        assert_eq!(scanner.unterminated(Unit::Line), TransferProgress::default());
+        let progress = TransferProgress::scan(&mut scanner, b"a\nb");
+        assert_eq!(progress.lines_transferred, 1);
+        let open = scanner.unterminated(Unit::Line);
+        assert_eq!(open, TransferProgress { lines_transferred: 1, ..Default::default() });
+        let open = scanner.unterminated(Unit::Word);
+        assert_eq!(open, TransferProgress { words_transferred: 1, ..Default::default() });
+        for unit in [Unit::Byte, Unit::Op] {
+            assert_eq!(scanner.unterminated(unit), TransferProgress::default(), "{}", unit);
+        }
+        TransferProgress::scan(&mut scanner, b"\n");
+        assert_eq!(scanner.unterminated(Unit::Line).lines_transferred, 0);
+        assert_eq!(scanner.unterminated(Unit::Word).words_transferred, 0);
+    }
+
+    #[test]
+    fn parse_roundtrips_display() {
+        for unit in Unit::ALL {
+            assert_eq!(unit.to_string().parse::<Unit>(), Ok(unit));
+        }
+        assert_eq!("b".parse::<Unit>(), Ok(Unit::Byte));
+        assert_eq!("l".parse::<Unit>(), Ok(Unit::Line));
+        assert_eq!("0".parse::<Unit>(), Ok(Unit::Null));
+        assert_eq!("w".parse::<Unit>(), Ok(Unit::Word));
+        assert!("x".parse::<Unit>().is_err());
+    }
+
 }
@@ -0,0 +1,302 @@
+use std::{
+    io::{
+        Result,
+        Write,
+    },
+    num::NonZeroU64,
+    sync::{
+        atomic::{
+            AtomicU64,
+            Ordering,
+        },
+        Arc,
+        Condvar,
+        Mutex,
+        MutexGuard,
+        RwLock,
+    },
+};
+
+use governor::clock::DefaultClock;
+
+use super::state::{
+    channel,
+    StateCell,
+};
+
+use crate::{
+    config::ConfigMonitor,
+    progress::{
+        TransferProgress,
+        TransferProgressMonitor,
+    },
+    syncio::{
+        direct_limiter,
+        DirectRateLimiter,
+        Grant,
+        Scaled,
+        RateLimitedWriter,
+        RateLimiter,
+    },
+    unit::Scanner,
+};
+
+/// A rate budget shared by any number of writers, possibly across threads.
+///
+/// Writers take turns acquiring tokens in the order they asked for them, so a
+/// busy writer cannot starve the others of the shared budget.
+#[derive(Clone)]
+pub struct SharedValve(Arc<Shared>);
+
+struct Shared {
+    config: ConfigMonitor,
+    limit: Mutex<Option<NonZeroU64>>,
+    limiter: RwLock<Option<Arc<Scaled<DirectRateLimiter<DefaultClock>>>>>,
+    turnstile: Turnstile,
+    progress: Mutex<TransferProgress>,
+    tx: Mutex<StateCell<TransferProgress>>,
+}
+
+/// First-come, first-served ordering of token requests.
+#[derive(Default)]
+struct Turnstile {
+    next: AtomicU64,
+    serving: Mutex<u64>,
+    turn: Condvar,
+}
+
+/// Writer drawing from a [`SharedValve`]'s budget.
+pub struct SharedValveWriter<W> {
+    inner: RateLimitedWriter<W, SharedValve>,
+    valve: SharedValve,
+    scanner: Scanner,
+}
+
+impl SharedValve {
+    pub fn new(config: ConfigMonitor) -> Self {
+        let limit = config.limit();
+        let progress = TransferProgress::default();
+        let (tx, _) = channel(progress);
+        Self(Arc::new(Shared {
+            config,
+            limit: Mutex::new(limit),
+            limiter: RwLock::new(direct_limiter(limit).map(Arc::new)),
+            turnstile: Turnstile::default(),
+            progress: Mutex::new(progress),
+            tx: Mutex::new(tx),
+        }))
+    }
+    /// Wrap `writer` into one which shares this valve's budget.
+    pub fn writer<W>(&self, writer: W) -> SharedValveWriter<W> {
+        let config = self.config();
+        SharedValveWriter {
+            inner: RateLimitedWriter::writer_with_limiter(
+                writer,
+                config,
+                self.clone(),
+            ),
+            valve: self.clone(),
+            scanner: Scanner::default(),
+        }
+    }
+    pub(crate) fn config(&self) -> ConfigMonitor {
+        self.0.config.clone()
+    }
+    /// Combined progress of every writer sharing this valve.
+    pub fn transfer_progress(&self) -> TransferProgressMonitor {
+        let tx = self.0.tx.lock().unwrap_or_else(|e| e.into_inner());
+        TransferProgressMonitor::new(tx.subscribe())
+    }
+    pub(crate) fn add_progress(&self, progress: TransferProgress) {
+        let mut total = self.0.progress.lock().unwrap_or_else(|e| e.into_inner());
+        *total = *total + progress;
+        self.0.tx.lock().unwrap_or_else(|e| e.into_inner()).send(*total);
+    }
+}
+
+impl RateLimiter for SharedValve {
+    fn request(&mut self, tokens: u32) -> Grant {
+        if tokens < 1 {
+            return Grant::default();
+        }
+        let limiter = self.0.limiter
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        if let Some(limiter) = limiter {
+            self.0.turnstile.pass(|| limiter.wait_for_at_most(tokens))
+        } else {
+            Grant::immediate(tokens)
+        }
+    }
+    fn set_limit(&mut self, limit: Option<NonZeroU64>) {
+        let mut current = self.0.limit.lock().unwrap_or_else(|e| e.into_inner());
+        if *current == limit {
+            return;
+        }
+        *current = limit;
+        *self.0.limiter.write().unwrap_or_else(|e| e.into_inner()) =
+            direct_limiter(limit).map(Arc::new);
+    }
+}
+
+impl Turnstile {
+    fn pass<T>(&self, f: impl FnOnce() -> T) -> T {
+        let ticket = self.next.fetch_add(1, Ordering::SeqCst);
+        let mut serving = self.serving();
+        while *serving != ticket {
+            serving = self.turn.wait(serving).unwrap_or_else(|e| e.into_inner());
+        }
+        drop(serving);
+        // Let the next writer through even if this one panics in `f`.
+        let _turn = Turn(self);
+        f()
+    }
+    fn serving(&self) -> MutexGuard<'_, u64> {
+        self.serving.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Ends the current turn at a [`Turnstile`] once dropped.
+struct Turn<'a>(&'a Turnstile);
+
+impl Drop for Turn<'_> {
+    fn drop(&mut self) {
+        *self.0.serving() += 1;
+        self.0.turn.notify_all();
+    }
+}
+
+impl <W> SharedValveWriter<W> {
+    pub fn get_ref(&self) -> &W {
+        self.inner.get_ref()
+    }
+}
+
+impl <W: Write> Write for SharedValveWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let bytes_transferred = self.inner.write(buf)?;
+        let slice = &buf[..bytes_transferred];
+        let progress = TransferProgress::scan(&mut self.scanner, slice);
+        self.valve.add_progress(progress);
+        Ok(bytes_transferred)
+    }
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        thread,
+        time::{
+            Duration,
+            Instant,
+        },
+    };
+    use nonzero_ext::nonzero;
+    use crate::config::Config;
+
+    #[test]
+    fn writers_on_separate_threads_share_progress() {
+        let (_tx, config) = ConfigMonitor::new(Config {
+            limit: Some(nonzero!(100_000u64)).into(),
+            ..Default::default()
+        });
+        let valve = SharedValve::new(config);
+        let mut progress = valve.transfer_progress();
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let mut writer = valve.writer(Vec::new());
+                thread::spawn(move || {
+                    writer.write_all(&[b'x'; 1000]).unwrap();
+                    writer.get_ref().len()
+                })
+            })
+            .collect();
+        for thread in threads {
+            assert_eq!(thread.join().unwrap(), 1000);
+        }
+        assert_eq!(progress.get().bytes_transferred, 4000);
+    }
+
+    #[test]
+    fn writers_on_separate_threads_share_one_budget() {
+        let (_tx, config) = ConfigMonitor::new(Config {
+            limit: Some(nonzero!(10_000u64)).into(),
+            ..Default::default()
+        });
+        let valve = SharedValve::new(config);
+        let started = Instant::now();
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let mut writer = valve.writer(Vec::new());
+                thread::spawn(move || writer.write_all(&[b'x'; 1500]).unwrap())
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        // Each writer alone would be through in a twentieth of a second,
+        // but together they take as long as 6000 at the one limit, less
+        // the tenth of a second's worth allowed at once.
+        assert!(started.elapsed() >= Duration::from_millis(400), "{:?}", started.elapsed());
+    }
+
+    #[test]
+    fn turnstile_serves_requests_in_the_order_they_arrived() {
+        let turnstile = Arc::new(Turnstile::default());
+        let served = Arc::new(Mutex::new(vec![]));
+        let (release, held) = std::sync::mpsc::channel::<()>();
+        let first = {
+            let turnstile = Arc::clone(&turnstile);
+            thread::spawn(move || turnstile.pass(|| held.recv().unwrap()))
+        };
+        // Tickets are taken one at a time, so the order they are served in
+        // is known.
+        while turnstile.next.load(Ordering::SeqCst) < 1 {
+            thread::yield_now();
+        }
+        let waiting: Vec<_> = (1..=3)
+            .map(|i| {
+                let entrant = Arc::clone(&turnstile);
+                let served = Arc::clone(&served);
+                let waiter = thread::spawn(move || {
+                    entrant.pass(|| served.lock().unwrap().push(i))
+                });
+                while turnstile.next.load(Ordering::SeqCst) < i + 1 {
+                    thread::yield_now();
+                }
+                waiter
+            })
+            .collect();
+        assert!(served.lock().unwrap().is_empty());
+        release.send(()).unwrap();
+        first.join().unwrap();
+        for waiter in waiting {
+            waiter.join().unwrap();
+        }
+        assert_eq!(*served.lock().unwrap(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn turnstile_lets_others_through_after_a_panic() {
+        let turnstile = Arc::new(Turnstile::default());
+        let panicked = {
+            let turnstile = Arc::clone(&turnstile);
+            thread::spawn(move || turnstile.pass(|| panic!("writer failed")))
+        };
+        assert!(panicked.join().is_err());
+        // Poison the lock too, as a panic while holding it would.
+        let poisoner = Arc::clone(&turnstile);
+        let _ = thread::spawn(move || {
+            let _serving = poisoner.serving.lock();
+            panic!("poisoned");
+        }).join();
+        assert!(turnstile.serving.is_poisoned());
+        assert_eq!(turnstile.pass(|| 7), 7);
+    }
+
+}
@@ -0,0 +1,140 @@
+use std::{
+    io::{
+        Result,
+        Write,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use thiserror::Error;
+
+use super::state::{
+    channel,
+    StateWatcher,
+    StateCell,
+};
+
+use super::{
+    config::LatchMonitor,
+    syncio::Finish,
+};
+
+/// A single write to the output blocked for longer than `--write-timeout`
+/// allows, such as on a hung network mount.
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+#[error("a write to the output blocked for more than {}s", .0.as_secs_f64())]
+pub struct WriteTimedOut(pub Duration);
+
+impl WriteTimedOut {
+    /// Exit status for a timed out write, the same as timeout(1) uses.
+    pub const EXIT_CODE: i32 = 124;
+}
+
+/// Writer which publishes when the write or flush it is blocked in began,
+/// for a [`Watchdog`] to keep an eye on.
+pub struct WatchedWriter<W> {
+    inner: W,
+    tx: StateCell<Option<Instant>>,
+}
+
+impl <W> WatchedWriter<W> {
+    pub fn new(inner: W) -> Self {
+        let (tx, _) = channel(None);
+        Self { inner, tx }
+    }
+    /// When the write in progress began, if there is one.
+    pub fn in_flight(&self) -> StateWatcher<Option<Instant>> {
+        self.tx.subscribe()
+    }
+    fn timed<T>(&mut self, f: impl FnOnce(&mut W) -> T) -> T {
+        self.tx.send(Some(Instant::now()));
+        let result = f(&mut self.inner);
+        self.tx.send(None);
+        result
+    }
+}
+
+impl <W: Write> Write for WatchedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.timed(|inner| inner.write(buf))
+    }
+    fn flush(&mut self) -> Result<()> {
+        self.timed(Write::flush)
+    }
+}
+
+impl <W: Finish> Finish for WatchedWriter<W> {
+    fn finish(&mut self) -> Result<()> {
+        self.timed(Finish::finish)
+    }
+}
+
+/// Notices a write which has been blocked for too long.
+///
+/// A blocked write cannot be interrupted, so all the watchdog can do is
+/// report it and leave the caller to give up on the whole process.
+pub struct Watchdog {
+    in_flight: StateWatcher<Option<Instant>>,
+    timeout: Duration,
+    shutdown: LatchMonitor,
+}
+
+impl Watchdog {
+    pub fn new(
+        in_flight: StateWatcher<Option<Instant>>,
+        timeout: Duration,
+        shutdown: LatchMonitor,
+    ) -> Self {
+        Self {
+            in_flight,
+            timeout,
+            shutdown,
+        }
+    }
+
+    /// Check on writes a few times per timeout until shutdown, failing as
+    /// soon as one has been blocked for too long.
+    pub fn run(mut self) -> std::result::Result<(), WriteTimedOut> {
+        loop {
+            self.check(Instant::now())?;
+            if self.shutdown.wait_timeout(self.timeout / 4) {
+                return Ok(());
+            }
+        }
+    }
+
+    fn check(&mut self, now: Instant) -> std::result::Result<(), WriteTimedOut> {
+        match self.in_flight.get() {
+            Some(since) if now.saturating_duration_since(since) > self.timeout => {
+                Err(WriteTimedOut(self.timeout))
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Latch;
+
+    #[test]
+    fn only_a_write_blocked_past_the_timeout_trips_the_watchdog() {
+        let mut writer = WatchedWriter::new(Vec::new());
+        let mut watchdog = Watchdog::new(
+            writer.in_flight(),
+            Duration::from_secs(1),
+            Latch::new().watch(),
+        );
+        writer.write_all(b"done").unwrap();
+        let later = Instant::now() + Duration::from_secs(5);
+        assert_eq!(watchdog.check(later), Ok(()));
+        writer.tx.send(Some(Instant::now()));
+        assert_eq!(watchdog.check(Instant::now()), Ok(()));
+        assert_eq!(watchdog.check(later), Err(WriteTimedOut(Duration::from_secs(1))));
+    }
+
+}
@@ -1,6 +1,7 @@
 use std::borrow::Borrow;
-use std::time::Duration;
-use std::num::{NonZeroU32, NonZeroUsize};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::num::{NonZeroU64, NonZeroUsize};
 
 use tui::{
     Frame,
@@ -16,9 +17,12 @@ use tui::{
         Color,
         Modifier,
     },
+    text::{
+        Line,
+        Span,
+    },
     widgets::{
         Widget,
-        Gauge,
         Paragraph,
     },
 };
@@ -29,14 +33,30 @@ use crossterm::event::{
     KeyCode,
 };
 
-use size_format::{
-    SizeFormatterBinary,
-    SizeFormatterSI,
-};
 
 use super::unit::Unit;
+use super::config::Config;
+use super::ipc::Message;
+use super::format::NumberFormat;
+use super::size::{self, ByteSize};
+use super::backpressure::{
+    Backpressure,
+    BackpressureBreakdown,
+    WaitBreakdown,
+    Waits,
+};
+use super::status_line::{
+    format_duration,
+    Field,
+    RateScale,
+    StatusLine,
+};
+use super::template::ProgressTemplate;
+use super::readahead::Occupancy;
+use super::window::Window;
 use super::progress::{
     CumulativeTransferProgress,
+    FileProgress,
     TransferProgress,
 };
 
@@ -49,85 +69,281 @@ pub trait KeyboardInput {
     fn input(&mut self, event: Event) -> Option<Self::Response>;
 }
 
-pub struct ObservedRateView(pub TransferProgress, pub Unit, Option<NonZeroU32>);
+/// The instantaneous rate, emphasized while the limiter holds it back and
+/// flagged when it has stayed below the minimum for too long, along with a
+/// small gauge of it when given the rate the gauge is full at.
+pub struct ObservedRateView(
+    pub TransferProgress,
+    pub Unit,
+    bool,
+    NumberFormat,
+    bool,
+    Option<NonZeroU64>,
+);
+
+/// Part of a status line, kept or dropped as a whole.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub text: String,
+    pub priority: u8,
+    pub style: Style,
+    /// What clicking or scrolling over the segment acts on.
+    pub hit: Option<Hit>,
+}
+
+/// Part of a [`TransferProgressView`] which responds to the mouse.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Hit {
+    Pause,
+    Rate,
+    /// A point along the gauge, as a fraction of the expected size.
+    Gauge(f64),
+}
+
+/// Status line which drops its least important segments until it fits.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct LineComposer {
+    segments: Vec<Segment>,
+}
+
+impl LineComposer {
+    pub fn push(&mut self, text: impl Into<String>, priority: u8) -> &mut Self {
+        self.push_styled(text, priority, Style::default())
+    }
+    pub fn push_styled(
+        &mut self,
+        text: impl Into<String>,
+        priority: u8,
+        style: Style,
+    ) -> &mut Self {
+        self.segments.push(Segment {
+            text: text.into(),
+            priority,
+            style,
+            hit: None,
+        });
+        self
+    }
+    /// Mark the most recently pushed segment as a target for the mouse.
+    pub fn tag(&mut self, hit: Hit) -> &mut Self {
+        if let Some(segment) = self.segments.last_mut() {
+            segment.hit = Some(hit);
+        }
+        self
+    }
+    fn width_of(segments: &[Segment]) -> usize {
+        let text: usize = segments.iter()
+            .map(|segment| segment.text.chars().count())
+            .sum();
+        text + segments.len().saturating_sub(1)
+    }
+    /// The segments which fit within `width` columns, in their original order.
+    ///
+    /// Lower priority segments are dropped first, the latest one first among
+    /// equals. If even the most important segment does not fit, it is cut
+    /// short and ends with an ellipsis.
+    pub fn compose(&self, width: usize) -> Vec<Segment> {
+        let mut kept = self.segments.clone();
+        while kept.len() > 1 && Self::width_of(&kept) > width {
+            let least = kept.iter()
+                .enumerate()
+                .rev()
+                .min_by_key(|(_, segment)| segment.priority)
+                .map(|(i, _)| i)
+                .expect("there is always a segment left");
+            kept.remove(least);
+        }
+        if let [only] = &mut kept[..] {
+            if only.text.chars().count() > width {
+                only.text = only.text
+                    .chars()
+                    .take(width.saturating_sub(1))
+                    .chain((width > 0).then_some('…'))
+                    .collect();
+            }
+        }
+        kept
+    }
+    /// Target of the segment at `column` once composed within `width`.
+    pub fn hit(&self, width: usize, column: usize) -> Option<Hit> {
+        let mut start = 0;
+        for segment in self.compose(width) {
+            let end = start + segment.text.chars().count();
+            if (start..end).contains(&column) {
+                return segment.hit;
+            }
+            start = end + 1;
+        }
+        None
+    }
+    /// Plain text of the segments which fit within `width` columns.
+    pub fn to_text(&self, width: usize) -> String {
+        self.compose(width)
+            .into_iter()
+            .map(|segment| segment.text)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+    /// Styled line of the segments which fit within `width` columns.
+    pub fn to_line(&self, width: usize) -> Line<'static> {
+        let mut spans = vec![];
+        for segment in self.compose(width) {
+            if !spans.is_empty() {
+                spans.push(Span::raw(" "));
+            }
+            spans.push(Span::styled(segment.text, segment.style));
+        }
+        Line::from(spans)
+    }
+}
 
 impl ObservedRateView {
-    const RELATIVE_TOLERANCE: f32 = 0.1f32;
-    const ABSOLUTE_TOLERANCE: usize = 1;
-    fn text_byte(progress: &TransferProgress) -> String {
-        format!(
-            "[{}B/s]",
-            SizeFormatterBinary::new(progress.bytes_transferred as u64)
-        )
-    }
-    fn text_line(progress: &TransferProgress) -> String {
-        format!(
-            "[{}L/s]",
-            SizeFormatterSI::new(progress.lines_transferred as u64)
-        )
-    }
-    fn text_null(progress: &TransferProgress) -> String {
-        format!(
-            "[{}#/s]",
-            SizeFormatterSI::new(progress.nulls_transferred as u64)
-        )
-    }
-    fn scalar_progress(&self) -> usize {
+    /// Cells the gauge of the rate takes up, between its ends.
+    const GAUGE_WIDTH: usize = 8;
+
+    fn scalar_progress(&self) -> u64 {
         let Self(progress, unit, ..) = self;
-        match *unit {
-            Unit::Byte => progress.bytes_transferred,
-            Unit::Line => progress.lines_transferred,
-            Unit::Null => progress.nulls_transferred,
-        }
-    }
-    fn distance_from_limit(&self) -> Option<(bool, usize, f32)> {
-        let Self(_, _, limit) = self;
-        if let Some(limit) = limit.map(NonZeroU32::get) {
-            let scalar_progress = self.scalar_progress();
-            let exceeded = scalar_progress >= limit as usize;
-            let distance = ((limit as isize) - (scalar_progress as isize)).abs();
-            let relative = ((distance as f32) / (limit as f32)).abs();
-            Some((exceeded, distance as usize, relative))
-        } else {
-            None
-        }
+        unit.progress(progress)
+    }
+    fn limiter_active(&self) -> bool {
+        let Self(_, _, limiter_active, ..) = self;
+        *limiter_active
+    }
+
+    fn below_minimum(&self) -> bool {
+        let Self(_, _, _, _, below_minimum, _) = self;
+        *below_minimum
     }
-    fn saturated(&self) -> bool {
-        if let Some((exceeded, absolute, relative)) = self.distance_from_limit() {
-            exceeded
-            ||
-            absolute <= Self::ABSOLUTE_TOLERANCE
-            ||
-            relative <= Self::RELATIVE_TOLERANCE
+    fn style(&self) -> Style {
+        if self.below_minimum() {
+            Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)
+        } else if self.limiter_active() {
+            Style::default().add_modifier(Modifier::BOLD)
         } else {
-            false
+            Style::default()
         }
     }
 
     pub fn as_text(&self) -> String {
-        let ObservedRateView(progress, unit, ..) = self;
-        match unit {
-            Unit::Byte => Self::text_byte(progress),
-            Unit::Line => Self::text_line(progress),
-            Unit::Null => Self::text_null(progress),
+        let Self(_, unit, _, format, ..) = self;
+        let text = format!("[{}]", format.rate(self.scalar_progress(), *unit));
+        match self.gauge() {
+            Some(gauge) => format!("{} {}", text, gauge),
+            None => text,
+        }
+    }
+
+    /// The rate as a bar [`Self::GAUGE_WIDTH`] cells wide, filled to the
+    /// nearest eighth of a cell and full at the gauge's maximum or beyond.
+    pub fn gauge(&self) -> Option<String> {
+        let Self(.., max) = self;
+        let ratio = self.scalar_progress() as f64 / max.as_ref()?.get() as f64;
+        let eighths = (ratio.clamp(0f64, 1f64) * (Self::GAUGE_WIDTH * 8) as f64) as usize;
+        let (full, partial) = (eighths / 8, eighths % 8);
+        let mut bar = ProgressBar::FULL.repeat(full);
+        if partial > 0 {
+            bar.push_str(ProgressBar::PARTIAL[partial - 1]);
         }
+        Some(format!("▕{:<width$}▏", bar, width = Self::GAUGE_WIDTH))
     }
 }
 
 impl Widget for ObservedRateView {
     fn render(self, area: Rect, buf: &mut Buffer) {
-        let saturated = self.saturated();
+        let style = self.style();
         let text = self.as_text();
-        let style = if saturated {
-            Style::default().add_modifier(Modifier::BOLD)
-        } else {
-            Style::default()
-        };
         let para = Paragraph::new(text).style(style);
         para.render(area, buf);
     }
 }
 
+/// Progress bar filled to a precision of an eighth of a cell, with a label
+/// centered over it.
+pub struct ProgressBar {
+    pub ratio: f64,
+    pub label: String,
+    pub fill: Color,
+}
+
+impl ProgressBar {
+    /// Blocks filling the left eighths of a cell, from one to seven.
+    const PARTIAL: [&'static str; 7] = ["▏", "▎", "▍", "▌", "▋", "▊", "▉"];
+    const FULL: &'static str = "█";
+    const EMPTY: Color = Color::Black;
+}
+
+impl Widget for ProgressBar {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        if area.area() == 0 {
+            return;
+        }
+        let eighths = (self.ratio.clamp(0f64, 1f64) * area.width as f64 * 8f64) as u32;
+        let full = (eighths / 8) as u16;
+        let partial = (eighths % 8) as usize;
+        let label_width = (self.label.chars().count() as u16).min(area.width);
+        let label_start = area.x + (area.width - label_width) / 2;
+        let mut label = self.label.chars().take(label_width as usize);
+        for offset in 0..area.width {
+            let x = area.x + offset;
+            let filled = offset < full || (offset == full && partial > 0);
+            let mut style = Style::default().bg(Self::EMPTY);
+            let cell = buf.get_mut(x, area.y);
+            if (label_start..label_start + label_width).contains(&x) {
+                let symbol = label.next().unwrap_or(' ');
+                style = if filled {
+                    style.fg(Self::EMPTY).bg(self.fill)
+                } else {
+                    style.fg(Color::White)
+                };
+                cell.set_char(symbol);
+            } else if offset < full {
+                style = style.fg(self.fill);
+                cell.set_symbol(Self::FULL);
+            } else if offset == full && partial > 0 {
+                style = style.fg(self.fill);
+                cell.set_symbol(Self::PARTIAL[partial - 1]);
+            } else {
+                cell.set_char(' ');
+            }
+            cell.set_style(style);
+        }
+    }
+}
+
+/// Gauge for a transfer of unknown size, on a log scale which starts over
+/// each time the amount grows tenfold, like the wheels of an odometer.
+pub struct Odometer {
+    pub amount: u64,
+    pub label: String,
+    pub fill: Color,
+}
+
+impl Odometer {
+    /// How far `amount` is through its power of ten.
+    pub fn ratio(amount: u64) -> f64 {
+        match amount {
+            0 => 0f64,
+            amount => (amount as f64).log10().fract(),
+        }
+    }
+    /// The amount shown `ratio` of the way along the gauge while it is
+    /// filling with `amount`.
+    pub fn amount_at(amount: u64, ratio: f64) -> u64 {
+        let decade = (amount.max(1) as f64).log10().floor();
+        10f64.powf(decade + ratio.clamp(0f64, 1f64)) as u64
+    }
+}
+
+impl Widget for Odometer {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        ProgressBar {
+            ratio: Self::ratio(self.amount),
+            label: self.label,
+            fill: self.fill,
+        }.render(area, buf)
+    }
+}
+
 pub struct DurationView(Duration);
 
 impl DurationView {
@@ -166,16 +382,16 @@ impl Borrow<str> for EditRateState {
 
 pub enum EditRateResponse {
     Cancelled,
-    NewRate(NonZeroU32),
+    NewRate(NonZeroU64),
 }
 
-impl From<NonZeroU32> for EditRateResponse {
-    fn from(rate: NonZeroU32) -> Self {
+impl From<NonZeroU64> for EditRateResponse {
+    fn from(rate: NonZeroU64) -> Self {
         Self::NewRate(rate)
     }
 }
 
-impl From<&EditRateResponse> for Option<NonZeroU32> {
+impl From<&EditRateResponse> for Option<NonZeroU64> {
     fn from(val: &EditRateResponse) -> Self {
         if let EditRateResponse::NewRate(rate) = *val {
             Some(rate)
@@ -198,9 +414,9 @@ impl KeyboardInput for EditRateState {
                 Some(Self::Response::Cancelled)
             },
             Event::Key(KeyEvent {
-                code: KeyCode::Char(code @ '0'..='9'),
+                code: KeyCode::Char(code),
                 ..
-            }) => {
+            }) if ByteSize::is_size_char(code) => {
                 input.push(code);
                 None
             },
@@ -215,9 +431,8 @@ impl KeyboardInput for EditRateState {
                 code: KeyCode::Enter,
                 ..
             }) => {
-                let rate = input.parse::<u32>()
+                let rate = size::parse_nonzero(input)
                     .ok()
-                    .and_then(NonZeroU32::new)
                     .map(Self::Response::from);
                 input.clear();
                 rate
@@ -264,148 +479,978 @@ impl <'a> InteractiveWidget for EditRateView<'a> {
     }
 }
 
-fn abbreviate(unit: Unit) -> &'static str {
-    match unit {
-        Unit::Byte => "B",
-        Unit::Line => "L",
-        Unit::Null => "#",
-    }
+#[derive(Clone)]
+pub struct TransferProgressView {
+    pub cumulative: CumulativeTransferProgress,
+    /// When the counters were sampled; every duration and rate shown is
+    /// worked out as of this moment rather than whenever it is drawn.
+    pub at: Instant,
+    pub expected_size: Option<NonZeroUsize>,
+    pub instantaneous: TransferProgress,
+    pub limit: Option<NonZeroU64>,
+    pub paused: bool,
+    /// The `--gate-cmd` command is failing, which holds the transfer paused.
+    pub gated: bool,
+    /// Free space is below `--min-free`, which holds the transfer paused.
+    pub waiting_for_space: bool,
+    /// The `--window` whose limit applies, if any.
+    pub window: Option<Window>,
+    /// How full the `--buffer-size` buffer is, when reading ahead.
+    pub buffered: Option<Occupancy>,
+    /// What the gauge next to the rate is full at, if there is one.
+    pub rate_gauge: Option<RateScale>,
+    /// Unit amounts and rates are shown in.
+    pub unit: Unit,
+    /// Unit the limit is charged in, which may differ from the one shown.
+    pub limit_unit: Unit,
+    pub format: NumberFormat,
+    /// Show every counter rather than only the selected unit and bytes.
+    pub show_all_units: bool,
+    /// The rate has stayed below the minimum for too long.
+    pub below_minimum: bool,
+    /// The limiter held writes back within the last rate window.
+    pub limiter_active: bool,
+    /// How much was passed downstream, when only a sample of it is.
+    pub forwarded: Option<TransferProgress>,
+    /// Show a pause button even while running, for the mouse to click.
+    pub mouse: bool,
+    /// Color the gauge by whether the limiter holds the rate back.
+    pub colored_gauge: bool,
+    /// Layout given with `-F`, drawn in place of the usual status line.
+    pub template: Option<Arc<ProgressTemplate>>,
 }
 
-fn format_duration(duration: &Duration) -> String {
-    let secs = duration.as_secs();
-    let hours = secs / 3600;
-    let minutes = (secs / 60) % 60;
-    let seconds = secs % 60;
-    format!("{}:{:02}:{:02}", hours, minutes, seconds)
-}
+impl TransferProgressView {
+    /// Priorities of the status line's segments; ETA goes first, then rate.
+    const ETA: u8 = 0;
+    const RATE: u8 = 1;
+    const FORWARDED: u8 = 2;
+    const LIMIT: u8 = 3;
+    const PERCENTAGE: u8 = 4;
+    const PROGRESS: u8 = 5;
+    const PAUSED: u8 = 6;
+
+    /// The text of the status line, without any of the interface's styling.
+    pub fn status_line(&self) -> StatusLine {
+        StatusLine::new(self.cumulative, self.at)
+            .with_rate(self.instantaneous)
+            .with_expected_size(self.expected_size)
+            .with_limit(self.limit, self.limit_unit)
+            .with_forwarded(self.forwarded)
+            .with_unit(self.unit)
+            .with_format(self.format)
+            .with_all_units(self.show_all_units)
+    }
+
+    /// Fraction of the expected size transferred so far, if it is known,
+    /// which goes past one once more than expected arrives.
+    pub fn ratio(&self) -> Option<f64> {
+        self.status_line().ratio()
+    }
+
+    /// Bytes transferred beyond the expected size, if any.
+    pub fn overrun(&self) -> Option<u64> {
+        self.status_line().overrun()
+    }
+
+    /// What the gauge of the rate is full at, if it has one. A gauge scaled
+    /// to the limit goes while there is none, or while it is charged in a
+    /// unit other than the one shown.
+    fn rate_gauge_max(&self) -> Option<NonZeroU64> {
+        match self.rate_gauge? {
+            RateScale::Fixed(max) => Some(max),
+            RateScale::Limit => self.limit.filter(|_| self.unit == self.limit_unit),
+        }
+    }
 
-#[derive(Clone, Copy)]
-struct AbsoluteTransferProgress(CumulativeTransferProgress, Unit);
+    /// Time left at the current rate; see [`StatusLine::eta`].
+    pub fn eta(&self) -> Option<Duration> {
+        self.status_line().eta()
+    }
 
-impl std::fmt::Display for AbsoluteTransferProgress {
-    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let Self(progress, unit) = *self;
-        let bytes_transferred = SizeFormatterBinary::new(
-            progress.progress.bytes_transferred as u64
+    /// The status line without the pause marker.
+    pub fn status(&self) -> LineComposer {
+        let mut line = LineComposer::default();
+        if let Some(template) = &self.template {
+            line.push(template.render(&self.status_line(), self.buffered), Self::PROGRESS);
+            return line;
+        }
+        let speed = ObservedRateView(
+            self.instantaneous,
+            self.unit,
+            self.limiter_active,
+            self.format,
+            self.below_minimum,
+            self.rate_gauge_max(),
         );
-        let unit_abbreviation = abbreviate(unit);
-        let duration = format_duration(&progress.elapsed());
-        let CumulativeTransferProgress { progress, .. } = progress;
-        match unit {
-            Unit::Byte => write!(
-                fmt,
-                "{:.2}{unit} {}",
-                bytes_transferred,
-                duration,
-                unit=unit_abbreviation,
-            ),
-            Unit::Line => write!(
-                fmt,
-                "{:.2}{unit} ({}B) {}",
-                SizeFormatterSI::new(progress.lines_transferred as u64),
-                bytes_transferred,
-                duration,
-                unit=unit_abbreviation,
-            ),
-            Unit::Null => write!(
-                fmt,
-                "{:.2}{unit} ({}B) {}",
-                SizeFormatterSI::new(progress.nulls_transferred as u64),
-                bytes_transferred,
-                duration,
-                unit=unit_abbreviation,
-            ),
+        for (field, text) in self.status_line().fields() {
+            match field {
+                Field::Transferred | Field::Elapsed => line.push(text, Self::PROGRESS),
+                Field::Forwarded => line.push(text, Self::FORWARDED),
+                Field::Percentage => line.push(text, Self::PERCENTAGE),
+                Field::Rate => {
+                    let text = match speed.gauge() {
+                        Some(gauge) => format!("{} {}", text, gauge),
+                        None => text,
+                    };
+                    line.push_styled(text, Self::RATE, speed.style()).tag(Hit::Rate)
+                },
+                // While paused, this is what applies once the transfer resumes.
+                Field::Limit if self.paused => line.push(text, Self::PAUSED),
+                Field::Limit => line.push(text, Self::LIMIT),
+                Field::Eta => line.push(text, Self::ETA),
+            };
+        }
+        if let Some(window) = self.window {
+            line.push(format!("window {}", window.span()), Self::LIMIT);
+        }
+        if let Some(buffered) = self.buffered {
+            line.push(format!("buf {:.0}%", buffered.percentage()), Self::FORWARDED);
+        }
+        line
+    }
+
+    /// The status line including the pause marker, if paused.
+    pub fn status_with_pause(&self) -> LineComposer {
+        let mut line = self.status();
+        let marker = self.pause_marker();
+        if !marker.is_empty() {
+            line.push_styled(marker, Self::PAUSED, self.pause_style())
+                .tag(Hit::Pause);
+        }
+        line
+    }
+
+    /// Marker shown while paused, or the button to pause with the mouse.
+    fn pause_marker(&self) -> &'static str {
+        match (self.paused, self.mouse) {
+            (true, _) if self.waiting_for_space => "[WAITING FOR SPACE]",
+            (true, _) if self.gated => "[GATED]",
+            (true, _) => "[PAUSED]",
+            (false, true) => "[PAUSE]",
+            (false, false) => "",
+        }
+    }
+
+    /// Green while running freely, amber while the limiter holds the rate
+    /// back and red while paused.
+    fn fill(&self) -> Color {
+        if !self.colored_gauge {
+            return Color::White;
+        }
+        let speed = ObservedRateView(
+            self.instantaneous,
+            self.unit,
+            self.limiter_active,
+            self.format,
+            self.below_minimum,
+            None,
+        );
+        if self.paused {
+            Color::Red
+        } else if speed.limiter_active() {
+            Color::Yellow
+        } else {
+            Color::Green
+        }
+    }
+
+    fn pause_style(&self) -> Style {
+        if self.paused {
+            Style::default().add_modifier(Modifier::RAPID_BLINK)
+        } else {
+            Style::default()
+        }
+    }
+
+    /// Gauge, padding and pause marker.
+    fn gauge_layout(&self, row: Rect) -> Option<(Rect, Rect, Rect)> {
+        let layout = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([
+                Constraint::Max(64),
+                Constraint::Length(1),
+                Constraint::Length(self.pause_marker().len() as u16),
+            ])
+            .split(row);
+        match *layout {
+            [l, pad, r] => Some((l, pad, r)),
+            _ => None,
         }
     }
+
+    /// What lies at `column` and `row` when drawn within `area`.
+    pub fn hit(&self, area: Rect, column: u16, row: u16) -> Option<Hit> {
+        if row != area.y {
+            return None;
+        }
+        let line = Rect {
+            height: 1,
+            ..area
+        };
+        let Some((gauge, _, pause)) = self.gauge_layout(line) else {
+            let line = self.status_with_pause();
+            return line.hit(area.width as usize, column.saturating_sub(area.x) as usize);
+        };
+        if pause.width > 0 && (pause.left()..pause.right()).contains(&column) {
+            return Some(Hit::Pause);
+        }
+        if !(gauge.left()..gauge.right()).contains(&column) {
+            return None;
+        }
+        // The gauge centers its label.
+        let status = self.status();
+        let label_width = status.to_text(gauge.width as usize).chars().count() as u16;
+        let label_start = gauge.x + gauge.width.saturating_sub(label_width) / 2;
+        let in_label = column.checked_sub(label_start)
+            .and_then(|offset| status.hit(gauge.width as usize, offset as usize));
+        in_label.or_else(|| Some(Hit::Gauge(
+            (column - gauge.x) as f64 / gauge.width.max(1) as f64
+        )))
+    }
+
+    /// How far into the expected size a point along the gauge is, or what
+    /// amount it stands for on the odometer.
+    pub fn position_at(&self, ratio: f64) -> Option<String> {
+        let Some(expected) = self.expected_size else {
+            let amount = self.unit.progress(&self.cumulative.progress);
+            let at = Odometer::amount_at(amount, ratio);
+            return Some(self.format.amount(at, self.unit, Some(2)));
+        };
+        let bytes = (expected.get() as f64 * ratio.clamp(0f64, 1f64)) as u64;
+        Some(self.format.amount(bytes, Unit::Byte, Some(2)))
+    }
 }
 
-pub struct TransferProgressView {
-    pub cumulative: CumulativeTransferProgress,
-    pub expected_size: Option<NonZeroUsize>,
-    pub instantaneous: TransferProgress,
-    pub limit: Option<NonZeroU32>,
-    pub paused: bool,
-    pub unit: Unit,
+/// Views are equal when they would be drawn the same, so the moment they
+/// were sampled at only matters to the second.
+impl PartialEq for TransferProgressView {
+    fn eq(&self, other: &Self) -> bool {
+        let shown = |view: &Self| (
+            view.cumulative.elapsed_at(view.at).as_secs(),
+            view.eta(),
+        );
+        shown(self) == shown(other)
+            && self.cumulative == other.cumulative
+            && self.expected_size == other.expected_size
+            && self.instantaneous == other.instantaneous
+            && self.limit == other.limit
+            && self.paused == other.paused
+            && self.gated == other.gated
+            && self.waiting_for_space == other.waiting_for_space
+            && self.window == other.window
+            && self.buffered == other.buffered
+            && self.rate_gauge == other.rate_gauge
+            && self.unit == other.unit
+            && self.limit_unit == other.limit_unit
+            && self.format == other.format
+            && self.show_all_units == other.show_all_units
+            && self.below_minimum == other.below_minimum
+            && self.limiter_active == other.limiter_active
+            && self.forwarded == other.forwarded
+            && self.mouse == other.mouse
+            && self.colored_gauge == other.colored_gauge
+            && self.template == other.template
+    }
 }
 
 impl InteractiveWidget for TransferProgressView {
     fn render(self, frame: &mut Frame) {
-        let Self {
-            cumulative,
-            expected_size,
-            instantaneous,
-            limit,
-            paused,
-            unit,
-        } = self;
+        let row = Rect {
+            height: 1,
+            ..frame.size()
+        };
+
+        let pause = Paragraph::new(self.pause_marker())
+            .style(self.pause_style());
 
-        let pause = if paused { "[PAUSED]" } else { "" };
-        let pause_len = pause.len() as u16;
+        if let Some((l, pad, r)) = self.gauge_layout(row) {
+            let label = self.status().to_text(l.width as usize);
+            match self.ratio() {
+                Some(ratio) => frame.render_widget(
+                    ProgressBar {
+                        ratio,
+                        label,
+                        fill: self.fill(),
+                    },
+                    l,
+                ),
+                None => frame.render_widget(
+                    Odometer {
+                        amount: self.unit.progress(&self.cumulative.progress),
+                        label,
+                        fill: self.fill(),
+                    },
+                    l,
+                ),
+            }
+            frame.render_widget(Paragraph::new(" "), pad);
+            frame.render_widget(pause, r);
+        } else {
+            let line = self.status_with_pause().to_line(row.width as usize);
+            frame.render_widget(Paragraph::new(line), row);
+        }
+    }
+}
+
+/// Where along the gauge the mouse points, shown just below it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TooltipView {
+    pub column: u16,
+    pub text: String,
+}
+
+impl InteractiveWidget for TooltipView {
+    fn render(self, frame: &mut Frame) {
+        let size = frame.size();
+        if size.height < 2 {
+            return;
+        }
+        let width = (self.text.chars().count() as u16).min(size.width);
+        let area = Rect {
+            x: self.column.min(size.width - width),
+            y: size.y + 1,
+            width,
+            height: 1,
+        };
+        let text = Paragraph::new(self.text)
+            .style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_widget(text, area);
+    }
+}
+
+/// The last change made to the transfer, shown on the bottom row for a
+/// couple of seconds so that it is not missed.
+#[derive(Clone, PartialEq)]
+pub struct ToastView {
+    pub text: String,
+}
+
+impl ToastView {
+    /// How long a toast stays up.
+    pub const LIFETIME: Duration = Duration::from_secs(2);
+
+    /// What `message` did, given the configuration and pause state it left
+    /// behind, or None if there is nothing worth telling.
+    pub fn after(message: Message, config: &Config, paused: bool) -> Option<Self> {
+        let text = match message {
+            Message::Pause | Message::Resume | Message::TogglePause => {
+                if paused { "paused" } else { "resumed" }.to_owned()
+            },
+            Message::Limit(_)
+            | Message::ToggleLimit(_)
+            | Message::Faster(_)
+            | Message::Slower(_)
+            | Message::RestoreLimit(_)
+            | Message::Undo => match config.limit() {
+                Some(limit) => format!(
+                    "limit \u{2192} {}",
+                    config.number_format.rate(limit.get(), config.unit),
+                ),
+                None => "limit off".to_owned(),
+            },
+            Message::CycleUnit => format!("limiting {}s", config.unit),
+            Message::CycleDisplayUnit => format!("showing {}s", config.shown_unit()),
+            Message::ToggleAllUnits if config.show_all_units => "showing every unit".to_owned(),
+            Message::ToggleAllUnits => format!("showing {}s", config.shown_unit()),
+            Message::Abort => return None,
+        };
+        Some(Self { text })
+    }
+}
+
+impl InteractiveWidget for ToastView {
+    fn render(self, frame: &mut Frame) {
+        let size = frame.size();
+        if size.height < 2 {
+            return;
+        }
+        let area = Rect {
+            y: size.y + size.height - 1,
+            height: 1,
+            ..size
+        };
+        let text = Paragraph::new(self.text)
+            .style(Style::default().add_modifier(Modifier::BOLD));
+        frame.render_widget(text, area);
+    }
+}
+
+/// Asks for Ctrl-C again before aborting, over the bottom row, along with
+/// how long is left to press it in.
+#[derive(Clone, Copy, PartialEq)]
+pub struct ConfirmAbortView(pub Duration);
+
+impl InteractiveWidget for ConfirmAbortView {
+    fn render(self, frame: &mut Frame) {
+        let size = frame.size();
+        if size.height < 2 {
+            return;
+        }
+        let area = Rect {
+            y: size.y + size.height - 1,
+            height: 1,
+            ..size
+        };
+        let seconds = self.0.as_secs_f64().ceil() as u64;
+        let text = format!("press Ctrl-C again within {}s to abort", seconds);
+        let text = Paragraph::new(text)
+            .style(
+                Style::default()
+                .bg(Color::Red)
+                .fg(Color::White)
+                .add_modifier(Modifier::BOLD)
+            );
+        frame.render_widget(text, area);
+    }
+}
+
+/// Progress through the input file being read, on its own gauge below the
+/// status line while the status line's gauge shows every file together.
+#[derive(Clone, PartialEq)]
+pub struct FileView {
+    pub file: FileProgress,
+    pub format: NumberFormat,
+}
+
+impl FileView {
+    /// Priority of the file among the status line's segments, dropped
+    /// along with what was forwarded.
+    const PRIORITY: u8 = TransferProgressView::FORWARDED;
+
+    /// Position, name and how much of the file was read, such as
+    /// "[2/5] b.log 1.50MiB/3.00MiB".
+    pub fn label(&self) -> String {
+        let FileProgress { index, count, name, transferred, size } = &self.file;
+        let mut label = format!(
+            "[{}/{}] {} {}",
+            index + 1,
+            count,
+            name,
+            self.format.amount(*transferred, Unit::Byte, Some(2)),
+        );
+        if let Some(size) = size {
+            label.push('/');
+            label.push_str(&self.format.amount(*size, Unit::Byte, Some(2)));
+        }
+        label
+    }
+
+    /// Add the file's position and how far through it the transfer is to a
+    /// status line with no room for a second gauge.
+    pub fn append_to(&self, line: &mut LineComposer) {
+        let FileProgress { index, count, name, .. } = &self.file;
+        let mut text = format!("[{}/{} {}", index + 1, count, name);
+        if let Some(ratio) = self.file.ratio() {
+            text.push_str(&format!(" {}%", (ratio * 100f64) as u16));
+        }
+        text.push(']');
+        line.push(text, Self::PRIORITY);
+    }
+}
 
+impl InteractiveWidget for FileView {
+    fn render(self, frame: &mut Frame) {
+        let area = frame.size();
+        if area.height < 2 {
+            return;
+        }
         let row = Rect {
+            y: area.y + 1,
             height: 1,
-            ..frame.size()
+            width: area.width.min(64),
+            ..area
         };
+        let label = self.label();
+        match self.file.ratio() {
+            Some(ratio) => frame.render_widget(
+                ProgressBar { ratio, label, fill: Color::White },
+                row,
+            ),
+            None => frame.render_widget(
+                Odometer { amount: self.file.transferred, label, fill: Color::White },
+                row,
+            ),
+        }
+    }
+}
 
-        let progress = format!("{}", AbsoluteTransferProgress(cumulative, unit));
-        let progress_len = progress.len() as u16;
+/// Progress of every extra output, one row each below the status line.
+#[derive(Clone, PartialEq)]
+pub struct OutputsView {
+    pub rows: Vec<OutputRow>,
+    pub unit: Unit,
+    /// Unit the outputs' limits are charged in.
+    pub limit_unit: Unit,
+    pub format: NumberFormat,
+    /// Rows below the status line already taken by other views.
+    pub offset: u16,
+}
+
+#[derive(Clone, PartialEq)]
+pub struct OutputRow {
+    pub name: String,
+    pub written: TransferProgress,
+    pub rate: TransferProgress,
+    pub dropped: TransferProgress,
+    pub limit: Option<NonZeroU64>,
+    /// The output's own limiter held its writes back recently.
+    pub limiter_active: bool,
+    pub lossy: bool,
+}
+
+impl OutputsView {
+    /// Priorities of each row's segments; the name is kept the longest.
+    const LIMIT: u8 = 0;
+    const DROPPED: u8 = 1;
+    const RATE: u8 = 2;
+    const WRITTEN: u8 = 3;
+    const NAME: u8 = 4;
 
-        let speed = ObservedRateView(instantaneous, unit, limit);
-        let pause = Paragraph::new(pause)
-            .style(Style::default().add_modifier(Modifier::RAPID_BLINK));
+    pub fn height(&self) -> u16 {
+        self.rows.len() as u16
+    }
 
-        if let Some(expected_size) = expected_size {
-            let ratio = f64::min(
-                1f64,
-                cumulative.progress.bytes_transferred as f64 / (expected_size.get() as
-                f64)
+    fn status(&self, row: &OutputRow) -> LineComposer {
+        let mut line = LineComposer::default();
+        line.push(format!("{}:", row.name), Self::NAME);
+        line.push(
+            self.format.amount(self.unit.progress(&row.written), self.unit, Some(2)),
+            Self::WRITTEN,
+        );
+        let rate = ObservedRateView(
+            row.rate,
+            self.unit,
+            row.limiter_active,
+            self.format,
+            false,
+            None,
+        );
+        line.push_styled(rate.as_text(), Self::RATE, rate.style());
+        if row.lossy {
+            let dropped = self.unit.progress(&row.dropped);
+            line.push(
+                format!("dropped {}", self.format.amount(dropped, self.unit, Some(2))),
+                Self::DROPPED,
             );
-            let percentage = (ratio * 100f64) as u16;
-            let label = format!(
-                "{} {}% {}",
-                progress,
-                percentage,
-                speed.as_text(),
+        }
+        if let Some(limit) = row.limit {
+            line.push(
+                format!("limit {}", self.format.rate(limit.get(), self.limit_unit)),
+                Self::LIMIT,
             );
-            let gauge = Gauge::default()
-                .gauge_style(Style::default().fg(Color::White).bg(Color::Black))
-                .label(label)
-                .use_unicode(true)
-                .ratio(ratio);
-
-            let layout = Layout::default()
-                .direction(Direction::Horizontal)
-                .constraints([
-                    Constraint::Max(64),
-                    Constraint::Length(1),
-                    Constraint::Length(pause_len),
-                ])
-                .split(row);
-
-                if let [l, pad, r] = *layout {
-                    frame.render_widget(gauge, l);
-                    frame.render_widget(Paragraph::new(" "), pad);
-                    frame.render_widget(pause, r);
-                }
-        } else {
-            let progress = Paragraph::new(progress);
-            let layout = Layout::default()
-                .direction(Direction::Horizontal)
-                .constraints([
-                    Constraint::Length(progress_len),
-                    Constraint::Max(1),
-                    Constraint::Length(10),
-                    Constraint::Length(pause_len),
-                ])
-                .split(row);
-
-                if let [l, pad, c, r] = *layout {
-                    frame.render_widget(progress, l);
-                    frame.render_widget(Paragraph::new(" "), pad);
-                    frame.render_widget(speed, c);
-                    frame.render_widget(pause, r);
-                }
         }
+        line
+    }
+}
+
+impl InteractiveWidget for OutputsView {
+    fn render(self, frame: &mut Frame) {
+        let area = frame.size();
+        for (i, row) in self.rows.iter().enumerate() {
+            let y = area.y + 1 + self.offset + i as u16;
+            if y >= area.bottom() {
+                break;
+            }
+            let line = self.status(row).to_line(area.width as usize);
+            let area = Rect {
+                y,
+                height: 1,
+                ..area
+            };
+            frame.render_widget(Paragraph::new(line), area);
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct DetailView {
+    pub backpressure: Backpressure,
+    /// Bytes transferred leaving out record delimiters and headers, for
+    /// `--net-bytes`.
+    pub payload: Option<u64>,
+    pub format: NumberFormat,
+    /// Rows below the status line already taken by other views.
+    pub offset: u16,
+}
+
+impl DetailView {
+    fn lines(&self) -> Vec<String> {
+        let BackpressureBreakdown {
+            reading,
+            limiting,
+            writing,
+        } = self.backpressure.breakdown();
+        let WaitBreakdown {
+            limiting: limiter_waits,
+            pausing: pauses,
+            writing: write_waits,
+        } = self.backpressure.waits();
+        let waits = |waits: Waits| format!("{} ({:.1}s)", waits.count, waits.seconds);
+        let mut lines = vec![
+            format!(
+                "waiting: read {:.0}% limit {:.0}% write {:.0}%",
+                reading,
+                limiting,
+                writing,
+            ),
+            format!(
+                "waits: limit {} pause {} write {}",
+                waits(limiter_waits),
+                waits(pauses),
+                waits(write_waits),
+            ),
+        ];
+        if let Some(payload) = self.payload {
+            lines.push(format!("payload: {}", self.format.amount(payload, Unit::Byte, Some(2))));
+        }
+        lines
+    }
+}
+
+impl InteractiveWidget for DetailView {
+    fn render(self, frame: &mut Frame) {
+        let area = frame.size();
+        let skip = 1 + self.offset;
+        if area.height <= skip {
+            return;
+        }
+        let area = Rect {
+            y: area.y + skip,
+            height: area.height - skip,
+            ..area
+        };
+        let para = Paragraph::new(self.lines().join("\n"));
+        frame.render_widget(para, area);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line() -> LineComposer {
+        let mut line = LineComposer::default();
+        line.push("1.00MiB 0:00:10", 3)
+            .push("50%", 2)
+            .push("[100KiB/s]", 1)
+            .push("ETA 0:00:10", 0);
+        line
+    }
+
+    #[test]
+    fn composer_keeps_everything_that_fits() {
+        assert_eq!(line().to_text(80), "1.00MiB 0:00:10 50% [100KiB/s] ETA 0:00:10");
+    }
+
+    #[test]
+    fn composer_drops_eta_before_rate() {
+        assert_eq!(line().to_text(35), "1.00MiB 0:00:10 50% [100KiB/s]");
+        assert_eq!(line().to_text(25), "1.00MiB 0:00:10 50%");
+        assert_eq!(line().to_text(15), "1.00MiB 0:00:10");
+    }
+
+    #[test]
+    fn composer_elides_the_last_segment_standing() {
+        assert_eq!(line().to_text(8), "1.00MiB…");
+        assert_eq!(line().to_text(0), "");
+    }
+
+    #[test]
+    fn current_file_is_labelled_with_its_position_and_size() {
+        let view = |size| FileView {
+            file: FileProgress {
+                index: 1,
+                count: 5,
+                name: "b.log".into(),
+                transferred: 250,
+                size,
+            },
+            format: NumberFormat::Raw,
+        };
+        assert_eq!(view(Some(1000)).label(), "[2/5] b.log 250B/1000B");
+        assert_eq!(view(None).label(), "[2/5] b.log 250B");
+        let mut line = LineComposer::default();
+        line.push("1000B 0:00:01", TransferProgressView::PROGRESS);
+        view(Some(1000)).append_to(&mut line);
+        assert_eq!(line.to_text(80), "1000B 0:00:01 [2/5 b.log 25%]");
+        assert_eq!(line.to_text(20), "1000B 0:00:01");
     }
+
+    #[test]
+    fn configured_limit_is_shown_and_kept_while_paused() {
+        let view = |paused| TransferProgressView {
+            cumulative: CumulativeTransferProgress::started(Instant::now()),
+            at: Instant::now(),
+            expected_size: None,
+            instantaneous: TransferProgress::default(),
+            limit: NonZeroU64::new(500),
+            paused,
+            unit: Unit::Byte,
+            limit_unit: Unit::Byte,
+            format: NumberFormat::Raw,
+            show_all_units: false,
+            below_minimum: false,
+            limiter_active: false,
+            forwarded: None,
+            mouse: false,
+            colored_gauge: false,
+            template: None,
+            gated: false,
+            waiting_for_space: false,
+            window: None,
+            buffered: None,
+            rate_gauge: None,
+        };
+        let running = view(false).status_with_pause().to_text(80);
+        assert!(running.ends_with("[0B/s] limit 500B/s"), "{}", running);
+        let paused = view(true).status_with_pause().to_text(30);
+        assert!(paused.ends_with("limit 500B/s [PAUSED]"), "{}", paused);
+        let lines = TransferProgressView { unit: Unit::Line, ..view(false) };
+        let lines = lines.status_with_pause().to_text(80);
+        assert!(lines.ends_with("[0L/s] limit 500B/s"), "{}", lines);
+        let buffered = TransferProgressView {
+            buffered: Some(Occupancy { held: 45, capacity: 100 }),
+            ..view(false)
+        };
+        let buffered = buffered.status_with_pause().to_text(80);
+        assert!(buffered.ends_with("limit 500B/s buf 45%"), "{}", buffered);
+        let gauged = TransferProgressView {
+            rate_gauge: Some(RateScale::Limit),
+            ..view(false)
+        };
+        let gauged = gauged.status_with_pause().to_text(80);
+        assert!(gauged.contains("[0B/s] ▕        ▏ limit"), "{}", gauged);
+        let unlimited = TransferProgressView {
+            rate_gauge: Some(RateScale::Limit),
+            limit: None,
+            ..view(false)
+        };
+        assert!(!unlimited.status_with_pause().to_text(80).contains('▕'));
+    }
+
+    #[test]
+    fn rate_gauge_fills_to_the_nearest_eighth_up_to_its_maximum() {
+        let gauge = |bytes_transferred, max| ObservedRateView(
+            TransferProgress {
+                bytes_transferred,
+                ..Default::default()
+            },
+            Unit::Byte,
+            false,
+            NumberFormat::Raw,
+            false,
+            NonZeroU64::new(max),
+        ).gauge();
+        assert_eq!(gauge(50, 0), None);
+        assert_eq!(gauge(0, 100).as_deref(), Some("▕        ▏"));
+        assert_eq!(gauge(50, 100).as_deref(), Some("▕████    ▏"));
+        assert_eq!(gauge(30, 100).as_deref(), Some("▕██▍     ▏"));
+        assert_eq!(gauge(500, 100).as_deref(), Some("▕████████▏"));
+    }
+
+    #[test]
+    fn mouse_targets_are_found_in_the_status_line_and_gauge() {
+        let mut view = TransferProgressView {
+            cumulative: CumulativeTransferProgress::started(Instant::now()),
+            at: Instant::now(),
+            expected_size: None,
+            instantaneous: TransferProgress::default(),
+            limit: None,
+            paused: false,
+            unit: Unit::Byte,
+            limit_unit: Unit::Byte,
+            format: NumberFormat::Raw,
+            show_all_units: false,
+            below_minimum: false,
+            limiter_active: false,
+            forwarded: None,
+            mouse: true,
+            colored_gauge: false,
+            template: None,
+            gated: false,
+            waiting_for_space: false,
+            window: None,
+            buffered: None,
+            rate_gauge: None,
+        };
+        let area = Rect::new(0, 0, 80, 10);
+        let (gauge, _, marker) = view.gauge_layout(Rect { height: 1, ..area }).unwrap();
+        let label = view.status().to_text(gauge.width as usize);
+        let label_start = gauge.x + (gauge.width - label.chars().count() as u16) / 2;
+        let rate = label_start + label.find("[0B/s]").unwrap() as u16;
+        assert_eq!(view.hit(area, rate, 0), Some(Hit::Rate));
+        assert_eq!(view.hit(area, marker.x + 1, 0), Some(Hit::Pause));
+        assert_eq!(view.hit(area, 0, 0), Some(Hit::Gauge(0f64)));
+        assert_eq!(view.hit(area, rate, 1), None);
+        view.expected_size = NonZeroUsize::new(1000);
+        let (_, _, marker) = view.gauge_layout(Rect { height: 1, ..area }).unwrap();
+        assert_eq!(view.hit(area, marker.x, 0), Some(Hit::Pause));
+        assert_eq!(view.hit(area, 16, 0), Some(Hit::Gauge(0.25)));
+        assert_eq!(view.position_at(0.25).as_deref(), Some("250B"));
+    }
+
+    #[test]
+    fn odometer_starts_over_at_every_power_of_ten() {
+        assert_eq!(Odometer::ratio(0), 0f64);
+        assert_eq!(Odometer::ratio(1000), 0f64);
+        assert!((Odometer::ratio(5000) - 0.699).abs() < 0.001);
+        assert_eq!(Odometer::amount_at(5000, 0f64), 1000);
+        assert_eq!(Odometer::amount_at(5000, 0.5), 3162);
+        let area = Rect::new(0, 0, 4, 1);
+        let mut buf = Buffer::empty(area);
+        Odometer {
+            amount: 50,
+            label: String::new(),
+            fill: Color::Green,
+        }.render(area, &mut buf);
+        let symbols = (0..4).map(|x| buf.get(x, 0).symbol().to_owned()).collect::<String>();
+        assert_eq!(symbols, "██▊ ");
+    }
+
+    #[test]
+    fn progress_bar_fills_to_the_nearest_eighth_of_a_cell() {
+        let symbols = |ratio, label: &str| {
+            let area = Rect::new(0, 0, 4, 1);
+            let mut buf = Buffer::empty(area);
+            ProgressBar {
+                ratio,
+                label: label.into(),
+                fill: Color::Green,
+            }.render(area, &mut buf);
+            (0..4).map(|x| buf.get(x, 0).symbol().to_owned()).collect::<String>()
+        };
+        assert_eq!(symbols(0.0, ""), "    ");
+        assert_eq!(symbols(0.5, ""), "██  ");
+        assert_eq!(symbols(0.3, ""), "█▏  ");
+        assert_eq!(symbols(1.0, "ab"), "█ab█");
+        assert_eq!(symbols(0.99, ""), "███▉");
+    }
+
+    #[test]
+    fn eta_falls_back_to_the_average_rate_excluding_pauses() {
+        let start = Instant::now();
+        let mut cumulative = CumulativeTransferProgress::started(start);
+        cumulative.progress.bytes_transferred = 500;
+        cumulative.pause(start + Duration::from_secs(5));
+        let view = TransferProgressView {
+            cumulative,
+            at: start + Duration::from_secs(3600),
+            expected_size: NonZeroUsize::new(1000),
+            instantaneous: TransferProgress::default(),
+            limit: None,
+            paused: true,
+            unit: Unit::Byte,
+            limit_unit: Unit::Byte,
+            format: NumberFormat::Raw,
+            show_all_units: false,
+            below_minimum: false,
+            limiter_active: false,
+            forwarded: None,
+            mouse: false,
+            colored_gauge: false,
+            template: None,
+            gated: false,
+            waiting_for_space: false,
+            window: None,
+            buffered: None,
+            rate_gauge: None,
+        };
+        assert_eq!(view.eta(), Some(Duration::from_secs(5)));
+        assert!(view.status().to_text(80).starts_with("500B 1:00:00"));
+    }
+
+    #[test]
+    fn transfers_past_the_expected_size_show_the_overrun() {
+        let start = Instant::now();
+        let mut cumulative = CumulativeTransferProgress::started(start);
+        cumulative.progress.bytes_transferred = 1250;
+        let view = TransferProgressView {
+            cumulative,
+            at: start,
+            expected_size: NonZeroUsize::new(1000),
+            instantaneous: TransferProgress::default(),
+            limit: None,
+            paused: false,
+            unit: Unit::Byte,
+            limit_unit: Unit::Byte,
+            format: NumberFormat::Raw,
+            show_all_units: false,
+            below_minimum: false,
+            limiter_active: false,
+            forwarded: None,
+            mouse: false,
+            colored_gauge: false,
+            template: None,
+            gated: false,
+            waiting_for_space: false,
+            window: None,
+            buffered: None,
+            rate_gauge: None,
+        };
+        assert_eq!(view.ratio(), Some(1.25));
+        assert_eq!(view.overrun(), Some(250));
+        assert_eq!(view.eta(), None);
+        let status = view.status().to_text(80);
+        assert!(status.contains(" >100% "), "{}", status);
+        assert!(status.ends_with("overrun +250B"), "{}", status);
+    }
+
+    #[test]
+    fn views_sampled_within_the_same_second_are_equal() {
+        let start = Instant::now();
+        let view = |at: u64| TransferProgressView {
+            cumulative: CumulativeTransferProgress::started(start),
+            at: start + Duration::from_millis(at),
+            expected_size: None,
+            instantaneous: TransferProgress::default(),
+            limit: None,
+            paused: false,
+            unit: Unit::Byte,
+            limit_unit: Unit::Byte,
+            format: NumberFormat::Raw,
+            show_all_units: false,
+            below_minimum: false,
+            limiter_active: false,
+            forwarded: None,
+            mouse: false,
+            colored_gauge: false,
+            template: None,
+            gated: false,
+            waiting_for_space: false,
+            window: None,
+            buffered: None,
+            rate_gauge: None,
+        };
+        assert!(view(100) == view(900));
+        assert!(view(900) != view(1100));
+        let paused = TransferProgressView { paused: true, ..view(100) };
+        assert!(view(100) != paused);
+        assert_eq!(paused.pause_marker(), "[PAUSED]");
+        let gated = TransferProgressView { gated: true, ..paused.clone() };
+        assert!(gated != paused);
+        assert_eq!(gated.pause_marker(), "[GATED]");
+        let waiting = TransferProgressView { waiting_for_space: true, ..gated };
+        assert_eq!(waiting.pause_marker(), "[WAITING FOR SPACE]");
+    }
+
+    #[test]
+    fn toasts_tell_what_the_last_action_left_behind() {
+        let toast = |message, config: &Config, paused| {
+            ToastView::after(message, config, paused).map(|toast| toast.text)
+        };
+        let mut config = Config {
+            limit: NonZeroU64::new(2 << 20).into(),
+            ..Default::default()
+        };
+        let observed = TransferProgress::default();
+        assert_eq!(toast(Message::Faster(observed), &config, false).unwrap(), "limit \u{2192} 2.0MiB/s");
+        assert_eq!(toast(Message::TogglePause, &config, true).unwrap(), "paused");
+        config.toggle_limit(None);
+        assert_eq!(toast(Message::ToggleLimit(observed), &config, false).unwrap(), "limit off");
+        config.unit = Unit::Line;
+        assert_eq!(toast(Message::CycleUnit, &config, false).unwrap(), "limiting lines");
+        assert_eq!(toast(Message::Abort, &config, false), None);
+    }
+
 }
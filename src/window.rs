@@ -0,0 +1,271 @@
+use std::{
+    fmt,
+    num::NonZeroU64,
+    str::FromStr,
+    time::Duration,
+};
+
+use thiserror::Error;
+use super::state::{
+    channel,
+    StateWatcher,
+    StateCell,
+};
+
+use super::{
+    config::LatchMonitor,
+    control::Controls,
+    ipc::Message,
+    platform,
+    size,
+};
+
+/// Minutes in a day, which is also how `24:00` is held.
+const DAY: u16 = 24 * 60;
+
+/// Limit which applies between two times of day, written `06:00-22:00=500k`
+/// or `22:00-06:00=unlimited`.
+///
+/// A window whose end comes before its start runs past midnight, and one
+/// which ends where it starts lasts all day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Window {
+    /// Minutes since midnight, local time.
+    pub start: u16,
+    pub end: u16,
+    pub limit: Option<NonZeroU64>,
+}
+
+/// Windows given with `--window`, of which the first one covering the time
+/// of day applies.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Windows(pub Vec<Window>);
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+#[error("{0:?} is not a window such as 06:00-22:00=500k or 22:00-06:00=unlimited")]
+pub struct ParseWindowError(String);
+
+/// Changes the limit as the local time of day moves from one [`Window`]
+/// to the next, for `--window`.
+///
+/// The limit is only set as a window starts, so one changed by hand holds
+/// until the next window does. Outside every window, the limit goes back
+/// to the one the transfer started with.
+pub struct WindowScheduler {
+    windows: Windows,
+    /// The limit outside every window.
+    base: Option<NonZeroU64>,
+    controls: Controls,
+    active: StateCell<Option<Window>>,
+    shutdown: LatchMonitor,
+}
+
+/// Which window is active, if any.
+pub struct WindowMonitor(StateWatcher<Option<Window>>);
+
+impl Window {
+    /// Whether `minute` of the day falls within the window.
+    pub fn contains(&self, minute: u16) -> bool {
+        match self.start.cmp(&self.end) {
+            std::cmp::Ordering::Less => (self.start..self.end).contains(&minute),
+            std::cmp::Ordering::Greater => minute >= self.start || minute < self.end,
+            std::cmp::Ordering::Equal => true,
+        }
+    }
+    /// The times the window runs between, such as `06:00-22:00`.
+    pub fn span(&self) -> String {
+        let time = |minute: u16| format!("{:02}:{:02}", minute / 60, minute % 60);
+        format!("{}-{}", time(self.start), time(self.end))
+    }
+}
+
+impl Windows {
+    /// The window covering `minute` of the day, if there is one.
+    pub fn at(&self, minute: u16) -> Option<Window> {
+        self.0.iter().copied().find(|window| window.contains(minute))
+    }
+}
+
+impl FromStr for Window {
+    type Err = ParseWindowError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ParseWindowError(s.to_owned());
+        let time = |time: &str| {
+            let (hours, minutes) = time.trim().split_once(':')?;
+            let hours = hours.parse::<u16>().ok().filter(|hours| *hours <= 24)?;
+            let minutes = minutes.parse::<u16>().ok().filter(|minutes| *minutes < 60)?;
+            Some(hours * 60 + minutes).filter(|minute| *minute <= DAY)
+        };
+        let (span, limit) = s.split_once('=').ok_or_else(invalid)?;
+        let (start, end) = span.split_once('-').ok_or_else(invalid)?;
+        let limit = match limit.trim() {
+            "unlimited" => None,
+            limit => Some(size::parse_nonzero(limit).map_err(|_| invalid())?),
+        };
+        Ok(Self {
+            start: time(start).ok_or_else(invalid)? % DAY,
+            end: time(end).ok_or_else(invalid)?,
+            limit,
+        })
+    }
+}
+
+impl fmt::Display for Window {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.limit {
+            Some(limit) => write!(fmt, "{}={}", self.span(), limit),
+            None => write!(fmt, "{}=unlimited", self.span()),
+        }
+    }
+}
+
+impl FromStr for Windows {
+    type Err = ParseWindowError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+impl fmt::Display for Windows {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, window) in self.0.iter().enumerate() {
+            if i > 0 {
+                fmt.write_str(",")?;
+            }
+            write!(fmt, "{}", window)?;
+        }
+        Ok(())
+    }
+}
+
+impl WindowScheduler {
+    const INTERVAL: Duration = Duration::from_secs(1);
+
+    pub fn new(
+        windows: Windows,
+        base: Option<NonZeroU64>,
+        controls: Controls,
+        shutdown: LatchMonitor,
+    ) -> Self {
+        let (active, _) = channel(None);
+        Self {
+            windows,
+            base,
+            controls,
+            active,
+            shutdown,
+        }
+    }
+    pub fn active(&self) -> WindowMonitor {
+        WindowMonitor(self.active.subscribe())
+    }
+
+    /// Check the time straight away, then every second until shutdown.
+    pub fn run(mut self) {
+        let mut current = None;
+        loop {
+            // Without the local time, stay in whatever window applies.
+            if let Ok(time) = platform::local_time_of_day() {
+                let minute = (time.as_secs() / 60) as u16;
+                self.enter(&mut current, self.windows.at(minute));
+            }
+            if self.shutdown.wait_timeout(Self::INTERVAL) {
+                break;
+            }
+        }
+    }
+
+    /// Move from the `current` window to `next`, setting its limit if it is
+    /// a different one.
+    fn enter(&mut self, current: &mut Option<Option<Window>>, next: Option<Window>) {
+        if *current == Some(next) {
+            return;
+        }
+        *current = Some(next);
+        let limit = match next {
+            Some(window) => window.limit,
+            None => self.base,
+        };
+        self.controls.send(Message::Limit(limit));
+        self.active.send(next);
+    }
+}
+
+impl WindowMonitor {
+    pub fn get(&mut self) -> Option<Window> {
+        self.0.get()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use nonzero_ext::nonzero;
+
+    use crate::config::{
+        Config,
+        ConfigMonitor,
+        Latch,
+    };
+
+    #[test]
+    fn windows_round_trip() {
+        let text = "22:00-06:00=unlimited,06:00-22:00=500000";
+        let windows: Windows = text.parse().unwrap();
+        assert_eq!(windows.0[1].limit, Some(nonzero!(500000u64)));
+        assert_eq!(windows.to_string(), text);
+        let kilo: Window = "06:00-22:00=500k".parse().unwrap();
+        assert_eq!(kilo.limit, Some(nonzero!(500000u64)));
+        for bad in ["", "06:00-22:00", "6-22=1k", "06:00-25:00=1k", "06:60-22:00=1k", "06:00-22:00=0"] {
+            assert!(bad.parse::<Windows>().is_err(), "{:?} parsed", bad);
+        }
+    }
+
+    #[test]
+    fn windows_may_run_past_midnight() {
+        let windows: Windows = "22:00-06:00=1k,06:00-12:00=2k".parse().unwrap();
+        let limit = |hour: u16| windows.at(hour * 60).and_then(|window| window.limit);
+        assert_eq!(limit(23), Some(nonzero!(1000u64)));
+        assert_eq!(limit(0), Some(nonzero!(1000u64)));
+        assert_eq!(limit(6), Some(nonzero!(2000u64)));
+        assert_eq!(limit(13), None);
+        let all_day: Window = "00:00-24:00=1k".parse().unwrap();
+        assert!(all_day.contains(0) && all_day.contains(DAY - 1));
+        assert_eq!(all_day.to_string(), "00:00-24:00=1000");
+    }
+
+    #[test]
+    fn limits_change_only_as_windows_start() {
+        let config = Config {
+            limit: Some(nonzero!(7u64)).into(),
+            ..Default::default()
+        };
+        let (config_tx, _) = ConfigMonitor::new(config);
+        let controls = Controls::new(config, config_tx, Latch::new(), Latch::new());
+        let windows: Windows = "06:00-22:00=500".parse().unwrap();
+        let mut scheduler = WindowScheduler::new(
+            windows.clone(),
+            Some(nonzero!(7u64)),
+            controls.clone(),
+            Latch::new().watch(),
+        );
+        let mut active = scheduler.active();
+        let mut current = None;
+        scheduler.enter(&mut current, windows.at(8 * 60));
+        assert_eq!(controls.config().limit(), Some(nonzero!(500u64)));
+        assert_eq!(active.get().map(|window| window.span()).as_deref(), Some("06:00-22:00"));
+        controls.send(Message::Limit(Some(nonzero!(900u64))));
+        scheduler.enter(&mut current, windows.at(9 * 60));
+        assert_eq!(controls.config().limit(), Some(nonzero!(900u64)));
+        scheduler.enter(&mut current, windows.at(23 * 60));
+        assert_eq!(controls.config().limit(), Some(nonzero!(7u64)));
+        assert_eq!(active.get(), None);
+    }
+
+}